@@ -0,0 +1,8 @@
+-- Separate "removed" from "disabled": enabled now only controls whether we serve answers,
+-- deleted_at marks domains that were removed and should no longer be loaded.
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE;
+
+-- Rows disabled before this migration were removals
+UPDATE domains SET deleted_at = updated_at WHERE enabled = false AND deleted_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_domains_deleted_at ON domains(deleted_at);
@@ -0,0 +1,59 @@
+-- Append-only change feed consumed by the mail backend via GET /feed/domains
+CREATE TABLE IF NOT EXISTS domain_events (
+    id BIGSERIAL PRIMARY KEY, -- Doubles as the feed cursor
+    domain VARCHAR(255) NOT NULL,
+    event_type VARCHAR(20) NOT NULL, -- 'added', 'removed', 'enabled', 'disabled', 'verified', 'unverified'
+    enabled BOOLEAN NOT NULL, -- State after the event
+    verified BOOLEAN NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_domain_events_domain ON domain_events(domain);
+
+-- Derive events from row changes so edits made directly in SQL show up in the feed too
+CREATE OR REPLACE FUNCTION record_domain_event()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO domain_events (domain, event_type, enabled, verified)
+        VALUES (NEW.domain, 'added', COALESCE(NEW.enabled, true), COALESCE(NEW.verified, false));
+        RETURN NEW;
+    END IF;
+
+    IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        INSERT INTO domain_events (domain, event_type, enabled, verified)
+        VALUES (NEW.domain, 'removed', false, COALESCE(NEW.verified, false));
+        RETURN NEW;
+    END IF;
+
+    IF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        INSERT INTO domain_events (domain, event_type, enabled, verified)
+        VALUES (NEW.domain, 'added', COALESCE(NEW.enabled, true), COALESCE(NEW.verified, false));
+        RETURN NEW;
+    END IF;
+
+    IF OLD.enabled IS DISTINCT FROM NEW.enabled THEN
+        INSERT INTO domain_events (domain, event_type, enabled, verified)
+        VALUES (NEW.domain, CASE WHEN NEW.enabled THEN 'enabled' ELSE 'disabled' END,
+                COALESCE(NEW.enabled, false), COALESCE(NEW.verified, false));
+    END IF;
+
+    IF OLD.verified IS DISTINCT FROM NEW.verified THEN
+        INSERT INTO domain_events (domain, event_type, enabled, verified)
+        VALUES (NEW.domain, CASE WHEN NEW.verified THEN 'verified' ELSE 'unverified' END,
+                COALESCE(NEW.enabled, false), COALESCE(NEW.verified, false));
+    END IF;
+
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'record_domains_events') THEN
+        CREATE TRIGGER record_domains_events 
+            AFTER INSERT OR UPDATE ON domains 
+            FOR EACH ROW 
+            EXECUTE FUNCTION record_domain_event();
+    END IF;
+END $$;
@@ -0,0 +1,10 @@
+-- One row per NS verification attempt, for flapping/history views
+CREATE TABLE IF NOT EXISTS verification_history (
+    id BIGSERIAL PRIMARY KEY,
+    domain VARCHAR(255) NOT NULL,
+    status VARCHAR(32) NOT NULL, -- VerificationStatus after the check
+    nameservers TEXT[], -- Nameservers observed during the check
+    checked_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_verification_history_domain ON verification_history(domain, checked_at DESC);
@@ -0,0 +1,50 @@
+-- Per-zone SOA serial, bumped on every record change
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS soa_serial BIGINT NOT NULL DEFAULT 1;
+
+-- Record-level audit log; before/after hold full dns_records rows so changes can be rolled back
+CREATE TABLE IF NOT EXISTS zone_changes (
+    id BIGSERIAL PRIMARY KEY, -- Rollback target
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    operation VARCHAR(10) NOT NULL, -- 'INSERT', 'UPDATE', 'DELETE'
+    record_type VARCHAR(10) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    before JSONB, -- NULL for inserts
+    after JSONB, -- NULL for deletes
+    actor VARCHAR(255) NOT NULL, -- From the cybertemp.actor setting, else the database user
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_zone_changes_domain_id ON zone_changes(domain_id, id);
+
+-- Captures changes made directly in SQL too; the API sets cybertemp.actor per transaction
+CREATE OR REPLACE FUNCTION record_zone_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    row_domain_id UUID := CASE WHEN TG_OP = 'DELETE' THEN OLD.domain_id ELSE NEW.domain_id END;
+BEGIN
+    INSERT INTO zone_changes (domain_id, operation, record_type, name, before, after, actor)
+    VALUES (
+        row_domain_id,
+        TG_OP,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.record_type ELSE NEW.record_type END,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.name ELSE NEW.name END,
+        CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) END,
+        CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) END,
+        COALESCE(NULLIF(current_setting('cybertemp.actor', true), ''), current_user)
+    );
+
+    UPDATE domains SET soa_serial = soa_serial + 1 WHERE id = row_domain_id;
+
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'record_dns_records_changes') THEN
+        CREATE TRIGGER record_dns_records_changes
+            AFTER INSERT OR UPDATE OR DELETE ON dns_records
+            FOR EACH ROW
+            EXECUTE FUNCTION record_zone_change();
+    END IF;
+END $$;
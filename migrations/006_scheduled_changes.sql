@@ -0,0 +1,14 @@
+-- Zone mutations staged through the API and applied by the scheduler once effective_at passes
+CREATE TABLE IF NOT EXISTS scheduled_changes (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    mutation JSONB NOT NULL, -- Serialized ZoneMutation, tagged by "action"
+    effective_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    actor VARCHAR(255) NOT NULL, -- Who staged it; the applied change is attributed to them too
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    applied_at TIMESTAMP WITH TIME ZONE, -- NULL while pending
+    error TEXT -- Set when applying failed
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_changes_pending ON scheduled_changes(effective_at) WHERE applied_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_scheduled_changes_domain_id ON scheduled_changes(domain_id, effective_at);
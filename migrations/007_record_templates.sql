@@ -0,0 +1,32 @@
+-- Named record sets with {domain}/{ip} placeholders, materialized into dns_records per domain
+CREATE TABLE IF NOT EXISTS record_templates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(64) UNIQUE NOT NULL, -- e.g. 'standard-mail', 'discord-mail'
+    description TEXT,
+    records JSONB NOT NULL, -- Array of {record_type, name, value, ttl, priority}
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Which templates each domain has had applied, for bulk re-application
+CREATE TABLE IF NOT EXISTS domain_templates (
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    template_id UUID REFERENCES record_templates(id) ON DELETE CASCADE,
+    applied_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (domain_id, template_id)
+);
+
+-- Records materialized from a template, replaced wholesale when it is re-applied
+ALTER TABLE dns_records ADD COLUMN IF NOT EXISTS template_id UUID REFERENCES record_templates(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_dns_records_template_id ON dns_records(template_id);
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'update_record_templates_updated_at') THEN
+        CREATE TRIGGER update_record_templates_updated_at 
+            BEFORE UPDATE ON record_templates 
+            FOR EACH ROW 
+            EXECUTE FUNCTION update_updated_at_column();
+    END IF;
+END $$;
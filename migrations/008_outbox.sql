@@ -0,0 +1,18 @@
+-- Outgoing notifications (webhooks, Discord), persisted so they survive restarts and failed deliveries
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    channel VARCHAR(20) NOT NULL, -- 'webhook' or 'discord'
+    target TEXT NOT NULL, -- URL the payload is POSTed to
+    payload JSONB NOT NULL, -- Request body, already in the channel's format
+    event_id BIGINT, -- Source domain_events row; doubles as the enqueue cursor
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- 'pending', 'delivered' or 'dead'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    delivered_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_outbox_event_target ON outbox(event_id, channel, target) WHERE event_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_outbox_status ON outbox(status, id);
@@ -0,0 +1,3 @@
+-- What each verification resolver saw, so a status change can be traced to the resolvers that agreed on it
+ALTER TABLE verification_history
+    ADD COLUMN IF NOT EXISTS resolver_results JSONB; -- [{resolver, delegated, nameservers, error}], NULL for rows before consensus checks
@@ -0,0 +1,23 @@
+-- Current abuse state per domain name; keyed by name so a ban outlives the removed domain row
+CREATE TABLE IF NOT EXISTS domain_abuse (
+    domain VARCHAR(255) PRIMARY KEY,
+    state VARCHAR(20) NOT NULL, -- 'reported', 'quarantined' or 'banned'
+    reason TEXT,
+    updated_by VARCHAR(255) NOT NULL, -- Actor of the last transition
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_domain_abuse_state ON domain_abuse(state);
+
+-- Every abuse state transition, including clearing a state
+CREATE TABLE IF NOT EXISTS abuse_events (
+    id BIGSERIAL PRIMARY KEY,
+    domain VARCHAR(255) NOT NULL,
+    from_state VARCHAR(20), -- NULL when the domain had no abuse state
+    to_state VARCHAR(20), -- NULL when the state was cleared
+    reason TEXT,
+    actor VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_abuse_events_domain ON abuse_events(domain, created_at DESC);
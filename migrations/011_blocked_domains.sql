@@ -0,0 +1,10 @@
+-- Names that may never be provisioned, whether added through the API, auto-discovered or synced from Supabase
+CREATE TABLE IF NOT EXISTS blocked_domains (
+    id BIGSERIAL PRIMARY KEY,
+    pattern VARCHAR(255) NOT NULL, -- e.g. 'paypal.com'
+    kind VARCHAR(10) NOT NULL DEFAULT 'exact', -- 'exact' matches the name only, 'suffix' also every name below it
+    reason TEXT,
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (pattern, kind)
+);
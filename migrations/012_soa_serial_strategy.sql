@@ -0,0 +1,42 @@
+-- Next SOA serial for a zone under the configured soa_serial_strategy ('counter', 'date' or
+-- 'unixtime'). Never returns a serial at or below the current one, so secondaries always see
+-- an increase, also right after switching strategies.
+CREATE OR REPLACE FUNCTION next_soa_serial(current BIGINT, strategy TEXT)
+RETURNS BIGINT AS $$
+BEGIN
+    RETURN GREATEST(
+        current + 1,
+        CASE strategy
+            WHEN 'date' THEN to_char(NOW() AT TIME ZONE 'UTC', 'YYYYMMDD')::BIGINT * 100
+            WHEN 'unixtime' THEN EXTRACT(EPOCH FROM NOW())::BIGINT
+            ELSE 0
+        END
+    );
+END;
+$$ language 'plpgsql';
+
+-- Same as in 005, but the serial follows the strategy the API sets in cybertemp.serial_strategy;
+-- changes made directly in SQL count up by one
+CREATE OR REPLACE FUNCTION record_zone_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    row_domain_id UUID := CASE WHEN TG_OP = 'DELETE' THEN OLD.domain_id ELSE NEW.domain_id END;
+BEGIN
+    INSERT INTO zone_changes (domain_id, operation, record_type, name, before, after, actor)
+    VALUES (
+        row_domain_id,
+        TG_OP,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.record_type ELSE NEW.record_type END,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.name ELSE NEW.name END,
+        CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) END,
+        CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) END,
+        COALESCE(NULLIF(current_setting('cybertemp.actor', true), ''), current_user)
+    );
+
+    UPDATE domains
+    SET soa_serial = next_soa_serial(soa_serial, COALESCE(NULLIF(current_setting('cybertemp.serial_strategy', true), ''), 'counter'))
+    WHERE id = row_domain_id;
+
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
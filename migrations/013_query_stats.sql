@@ -0,0 +1,30 @@
+-- Per-domain query counts, written in 5-minute buckets and rolled up to hourly and daily rows.
+-- Each resolution is pruned after its own retention window (stats_*_retention settings).
+CREATE TABLE IF NOT EXISTS query_stats_5m (
+    domain VARCHAR(255) NOT NULL, -- Managed domain the query name falls under
+    bucket_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    queries BIGINT NOT NULL DEFAULT 0,
+    negative BIGINT NOT NULL DEFAULT 0, -- NXDOMAIN or empty answers
+    PRIMARY KEY (domain, bucket_start)
+);
+
+CREATE TABLE IF NOT EXISTS query_stats_hourly (
+    domain VARCHAR(255) NOT NULL,
+    bucket_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    queries BIGINT NOT NULL DEFAULT 0,
+    negative BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (domain, bucket_start)
+);
+
+CREATE TABLE IF NOT EXISTS query_stats_daily (
+    domain VARCHAR(255) NOT NULL,
+    bucket_start TIMESTAMP WITH TIME ZONE NOT NULL, -- Midnight UTC
+    queries BIGINT NOT NULL DEFAULT 0,
+    negative BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (domain, bucket_start)
+);
+
+-- Rollups and pruning scan by time across all domains
+CREATE INDEX IF NOT EXISTS idx_query_stats_5m_bucket ON query_stats_5m(bucket_start);
+CREATE INDEX IF NOT EXISTS idx_query_stats_hourly_bucket ON query_stats_hourly(bucket_start);
+CREATE INDEX IF NOT EXISTS idx_query_stats_daily_bucket ON query_stats_daily(bucket_start);
@@ -0,0 +1,3 @@
+-- Share of the last 30 days each domain spent verified, refreshed after every NS check;
+-- NULL until the domain has been checked
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS uptime_30d DOUBLE PRECISION;
@@ -0,0 +1,9 @@
+-- Client-supplied Idempotency-Key values of applied writes, so a retried POST /domains is not applied twice
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key VARCHAR(255) PRIMARY KEY,
+    operation VARCHAR(50) NOT NULL, -- e.g. 'add_domain'
+    domain VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created ON idempotency_keys(created_at);
@@ -0,0 +1,11 @@
+-- IP changes across many domains, moved through TTL lowering, cutover, verification and TTL restore
+CREATE TABLE IF NOT EXISTS ip_migrations (
+    id BIGSERIAL PRIMARY KEY,
+    phase VARCHAR(30) NOT NULL, -- waiting_for_cutover, verifying, restoring_ttl, completed or cancelled
+    state JSONB NOT NULL, -- Serialized IpMigration, including per-domain progress
+    actor VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ip_migrations_active ON ip_migrations(id) WHERE phase NOT IN ('completed', 'cancelled');
@@ -0,0 +1,21 @@
+-- Domains are stored in one canonical form: lowercase, no trailing dot, and internationalized
+-- labels as xn-- A-labels. Rows with Unicode labels are renamed by the server at startup.
+-- Where several spellings of a name exist, only the oldest is renamed; the rest are left for
+-- an operator and logged at startup.
+UPDATE domains d
+SET domain = lower(rtrim(d.domain, '.')), updated_at = NOW()
+WHERE d.id IN (
+    SELECT DISTINCT ON (lower(rtrim(domain, '.'))) id
+    FROM domains
+    WHERE domain <> lower(rtrim(domain, '.'))
+    ORDER BY lower(rtrim(domain, '.')), created_at
+)
+AND NOT EXISTS (SELECT 1 FROM domains o WHERE o.domain = lower(rtrim(d.domain, '.')));
+
+-- NOT VALID keeps any leftover rows while checking every new write
+DO $$
+BEGIN
+    ALTER TABLE domains ADD CONSTRAINT domains_domain_canonical
+        CHECK (domain = lower(domain) AND domain NOT LIKE '%.') NOT VALID;
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
@@ -0,0 +1,16 @@
+-- Ownership changes of domains between Supabase users, and claim tokens waiting to be redeemed
+CREATE TABLE IF NOT EXISTS domain_transfers (
+    id BIGSERIAL PRIMARY KEY,
+    domain VARCHAR(255) NOT NULL,
+    from_user VARCHAR(255) NOT NULL,
+    to_user VARCHAR(255), -- NULL until a claim token is redeemed
+    status VARCHAR(20) NOT NULL, -- pending, completed or cancelled
+    token_hash VARCHAR(64), -- SHA-256 of the claim token; the token itself is never stored
+    actor VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_domain_transfers_domain ON domain_transfers(domain, created_at DESC);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_domain_transfers_token ON domain_transfers(token_hash) WHERE token_hash IS NOT NULL;
@@ -0,0 +1,7 @@
+-- Domains whose Supabase payment_status is not 'succeeded'; a row is deleted once payment resumes
+CREATE TABLE IF NOT EXISTS billing_holds (
+    domain VARCHAR(255) PRIMARY KEY,
+    payment_status VARCHAR(50) NOT NULL, -- Last unpaid status seen
+    unpaid_since TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    paused_at TIMESTAMP WITH TIME ZONE -- NULL during the grace window
+);
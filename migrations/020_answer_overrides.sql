@@ -0,0 +1,14 @@
+-- Emergency answers that take precedence over everything else served for a name until they expire
+CREATE TABLE IF NOT EXISTS answer_overrides (
+    id BIGSERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL, -- e.g. 'login.evil.com', or '*.evil.com' for every name below it
+    record_type VARCHAR(10), -- NULL applies to every type
+    action VARCHAR(10) NOT NULL, -- 'refuse', 'nxdomain' or 'answer'
+    value TEXT, -- The record served by 'answer'
+    reason TEXT,
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_answer_overrides_expires_at ON answer_overrides(expires_at);
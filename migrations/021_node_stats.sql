@@ -0,0 +1,14 @@
+-- Query counters each node pushes with stats_push_enabled, one row per node and transport.
+-- Counters are cumulative since the node started and overwritten on every push.
+CREATE TABLE IF NOT EXISTS node_stats (
+    node_id VARCHAR(255) NOT NULL,
+    transport VARCHAR(10) NOT NULL, -- udp, tcp, tls or doh
+    region VARCHAR(100),
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL, -- When the counters last reset
+    queries BIGINT NOT NULL DEFAULT 0,
+    edns BIGINT NOT NULL DEFAULT 0,
+    truncated BIGINT NOT NULL DEFAULT 0,
+    answer_micros BIGINT NOT NULL DEFAULT 0, -- Time spent answering, summed
+    reported_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (node_id, transport)
+);
@@ -0,0 +1,12 @@
+-- Every running instance, upserted on startup and every heartbeat. Rows of nodes that were
+-- shut down stay until removed through DELETE /nodes/{node_id}.
+CREATE TABLE IF NOT EXISTS nodes (
+    node_id VARCHAR(255) PRIMARY KEY, -- node_id setting, or the hostname
+    hostname VARCHAR(255),
+    region VARCHAR(100),
+    version VARCHAR(50) NOT NULL,
+    config_hash VARCHAR(64) NOT NULL, -- Fingerprint of the effective config
+    listen_addresses TEXT[] NOT NULL DEFAULT '{}',
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_heartbeat TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
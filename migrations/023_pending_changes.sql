@@ -0,0 +1,17 @@
+-- Records API changes held for a second API key's approval when approval_required is set
+CREATE TABLE IF NOT EXISTS pending_changes (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    change JSONB NOT NULL, -- Serialized RecordChange, tagged by "action"
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending, approved, rejected or failed
+    submitted_by VARCHAR(255) NOT NULL, -- Actor the change is applied as
+    submitter_key VARCHAR(64) NOT NULL, -- SHA-256 of the submitting API key, never the key itself
+    submitted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    reviewed_by VARCHAR(255),
+    reviewer_key VARCHAR(64),
+    reviewed_at TIMESTAMP WITH TIME ZONE,
+    error TEXT -- Set when applying an approved change failed
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_changes_pending ON pending_changes(submitted_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_pending_changes_domain_id ON pending_changes(domain_id, submitted_at);
@@ -0,0 +1,19 @@
+-- Certificates found in certificate transparency logs for hosted domains and their subdomains
+CREATE TABLE IF NOT EXISTS ct_findings (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    cert_id BIGINT NOT NULL, -- crt.sh ID of the first log entry seen for the certificate
+    issuer TEXT NOT NULL,
+    serial_number VARCHAR(128) NOT NULL,
+    names TEXT[] NOT NULL,
+    not_before TIMESTAMP WITH TIME ZONE NOT NULL,
+    not_after TIMESTAMP WITH TIME ZONE NOT NULL,
+    expected BOOLEAN, -- NULL when no expected issuers are known for the domain
+    seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_ct_findings_certificate ON ct_findings(domain_id, issuer, serial_number);
+CREATE INDEX IF NOT EXISTS idx_ct_findings_domain_id ON ct_findings(domain_id, seen_at DESC);
+
+-- Set after each CT lookup; certificates found by a domain's first lookup are stored without alerting
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS ct_checked_at TIMESTAMP WITH TIME ZONE;
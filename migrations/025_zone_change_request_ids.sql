@@ -0,0 +1,28 @@
+-- API request or background job run that made each record change, from X-Request-Id
+ALTER TABLE zone_changes ADD COLUMN IF NOT EXISTS request_id VARCHAR(128);
+
+-- Same as in 012, but also records the cybertemp.request_id setting; NULL for changes made directly in SQL
+CREATE OR REPLACE FUNCTION record_zone_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    row_domain_id UUID := CASE WHEN TG_OP = 'DELETE' THEN OLD.domain_id ELSE NEW.domain_id END;
+BEGIN
+    INSERT INTO zone_changes (domain_id, operation, record_type, name, before, after, actor, request_id)
+    VALUES (
+        row_domain_id,
+        TG_OP,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.record_type ELSE NEW.record_type END,
+        CASE WHEN TG_OP = 'DELETE' THEN OLD.name ELSE NEW.name END,
+        CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) END,
+        CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) END,
+        COALESCE(NULLIF(current_setting('cybertemp.actor', true), ''), current_user),
+        NULLIF(current_setting('cybertemp.request_id', true), '')
+    );
+
+    UPDATE domains
+    SET soa_serial = next_soa_serial(soa_serial, COALESCE(NULLIF(current_setting('cybertemp.serial_strategy', true), ''), 'counter'))
+    WHERE id = row_domain_id;
+
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
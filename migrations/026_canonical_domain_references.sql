@@ -0,0 +1,53 @@
+-- Tables that refer to domains by name get the canonical form of 017 too: lowercase, no
+-- trailing dot. Unicode labels are converted by the server at startup, with the domain row.
+
+UPDATE domain_events SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+UPDATE verification_history SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+UPDATE abuse_events SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+UPDATE idempotency_keys SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+UPDATE domain_transfers SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+
+-- Where spellings of a name collide, the most severe abuse state is kept
+DELETE FROM domain_abuse
+WHERE domain NOT IN (
+    SELECT DISTINCT ON (lower(rtrim(domain, '.'))) domain
+    FROM domain_abuse
+    ORDER BY lower(rtrim(domain, '.')),
+             CASE state WHEN 'banned' THEN 3 WHEN 'quarantined' THEN 2 ELSE 1 END DESC,
+             updated_at DESC
+);
+UPDATE domain_abuse SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+
+-- ... and the hold whose grace window started first
+DELETE FROM billing_holds
+WHERE domain NOT IN (
+    SELECT DISTINCT ON (lower(rtrim(domain, '.'))) domain
+    FROM billing_holds
+    ORDER BY lower(rtrim(domain, '.')), unpaid_since
+);
+UPDATE billing_holds SET domain = lower(rtrim(domain, '.')) WHERE domain <> lower(rtrim(domain, '.'));
+
+-- Query counts of the same bucket are added up
+INSERT INTO query_stats_5m (domain, bucket_start, queries, negative)
+SELECT lower(rtrim(domain, '.')), bucket_start, SUM(queries), SUM(negative)
+FROM query_stats_5m WHERE domain <> lower(rtrim(domain, '.'))
+GROUP BY lower(rtrim(domain, '.')), bucket_start
+ON CONFLICT (domain, bucket_start) DO UPDATE
+SET queries = query_stats_5m.queries + EXCLUDED.queries, negative = query_stats_5m.negative + EXCLUDED.negative;
+DELETE FROM query_stats_5m WHERE domain <> lower(rtrim(domain, '.'));
+
+INSERT INTO query_stats_hourly (domain, bucket_start, queries, negative)
+SELECT lower(rtrim(domain, '.')), bucket_start, SUM(queries), SUM(negative)
+FROM query_stats_hourly WHERE domain <> lower(rtrim(domain, '.'))
+GROUP BY lower(rtrim(domain, '.')), bucket_start
+ON CONFLICT (domain, bucket_start) DO UPDATE
+SET queries = query_stats_hourly.queries + EXCLUDED.queries, negative = query_stats_hourly.negative + EXCLUDED.negative;
+DELETE FROM query_stats_hourly WHERE domain <> lower(rtrim(domain, '.'));
+
+INSERT INTO query_stats_daily (domain, bucket_start, queries, negative)
+SELECT lower(rtrim(domain, '.')), bucket_start, SUM(queries), SUM(negative)
+FROM query_stats_daily WHERE domain <> lower(rtrim(domain, '.'))
+GROUP BY lower(rtrim(domain, '.')), bucket_start
+ON CONFLICT (domain, bucket_start) DO UPDATE
+SET queries = query_stats_daily.queries + EXCLUDED.queries, negative = query_stats_daily.negative + EXCLUDED.negative;
+DELETE FROM query_stats_daily WHERE domain <> lower(rtrim(domain, '.'));
@@ -55,8 +55,12 @@ def setup_database():
         )
         cursor = conn.cursor()
         
-        with open('migrations/001_initial_schema.sql', 'r') as f:
-            cursor.execute(f.read())
+        for migration in sorted(os.listdir('migrations')):
+            if not migration.endswith('.sql'):
+                continue
+            print(f"Applying migrations/{migration}...")
+            with open(os.path.join('migrations', migration), 'r') as f:
+                cursor.execute(f.read())
             
         conn.commit()
         cursor.close()
@@ -34,7 +34,10 @@ sudo -u postgres psql -c "GRANT ALL PRIVILEGES ON DATABASE $DB_NAME TO $DB_USER;
 
 # Run migrations
 export PGPASSWORD="$DB_PASS"
-psql -h $DB_HOST -p $DB_PORT -U $DB_USER -d $DB_NAME -f migrations/001_initial_schema.sql
+for migration in migrations/*.sql; do
+    echo "Applying $migration..."
+    psql -h $DB_HOST -p $DB_PORT -U $DB_USER -d $DB_NAME -f "$migration"
+done
 
 echo "Database setup complete!"
 echo "Database: $DB_NAME"
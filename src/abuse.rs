@@ -0,0 +1,72 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How far an abuse case against a domain has escalated. Variants are ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum AbuseState {
+    /// Reported and awaiting review; the domain is served as usual.
+    Reported,
+    /// Mail is cut off: MX queries for the domain and its subdomains get `NXDOMAIN`, A records still resolve.
+    Quarantined,
+    /// Removed from serving and refused if it is added or discovered again.
+    Banned,
+}
+
+impl AbuseState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AbuseState::Reported => "reported",
+            AbuseState::Quarantined => "quarantined",
+            AbuseState::Banned => "banned",
+        }
+    }
+}
+
+impl std::str::FromStr for AbuseState {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reported" => Ok(AbuseState::Reported),
+            "quarantined" => Ok(AbuseState::Quarantined),
+            "banned" => Ok(AbuseState::Banned),
+            other => Err(anyhow::anyhow!("Unknown abuse state: {}", other)),
+        }
+    }
+}
+
+/// Cases only escalate; going back down means clearing the case and reporting it again.
+pub fn check_transition(from: Option<AbuseState>, to: Option<AbuseState>) -> Result<()> {
+    match (from, to) {
+        (None, None) => Err(anyhow::anyhow!("Domain has no abuse state to clear")),
+        (Some(_), None) => Ok(()),
+        (Some(from), Some(to)) if to <= from => Err(anyhow::anyhow!(
+            "Cannot move abuse state from {} to {}, states only escalate", from.as_str(), to.as_str()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The current abuse state of a domain name, as stored in `domain_abuse`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbuseCase {
+    pub domain: String,
+    pub state: AbuseState,
+    pub reason: Option<String>,
+    pub updated_by: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One audited transition; `None` on either side means no abuse state.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbuseEvent {
+    pub id: i64,
+    pub domain: String,
+    pub from_state: Option<AbuseState>,
+    pub to_state: Option<AbuseState>,
+    pub reason: Option<String>,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+}
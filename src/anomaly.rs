@@ -0,0 +1,121 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Intervals observed before alerting, so a restart does not see every domain as new traffic.
+const WARMUP_INTERVALS: u32 = 10;
+/// Baselines that decayed below this many queries per minute are dropped, i.e. back to zero.
+const IDLE_BASELINE: f64 = 0.01;
+/// A domain that stays hot is alerted on at most once per cooldown.
+const ALERT_COOLDOWN: Duration = Duration::from_secs(3600);
+
+/// A domain whose query volume jumped well above its usual rate.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuerySpike {
+    pub domain: String,
+    pub queries_per_minute: f64,
+    pub baseline_per_minute: f64,
+    pub detected_at: DateTime<Utc>,
+}
+
+struct Baseline {
+    /// Exponentially weighted queries per minute.
+    rate: f64,
+    last_alert: Option<Instant>,
+}
+
+/// Per-domain query counts for the current interval and a rolling baseline of past ones.
+/// Domains without a baseline count as zero, so a dormant domain that suddenly gets traffic
+/// is flagged as soon as it crosses `min_queries_per_minute`.
+pub struct QueryAnomalyDetector {
+    counts: Mutex<HashMap<String, u64>>,
+    baselines: Mutex<HashMap<String, Baseline>>,
+    last_evaluated: Mutex<Instant>,
+    intervals: Mutex<u32>,
+    spike_factor: f64,
+    min_queries_per_minute: f64,
+    /// EWMA weight of each new sample, `1 / anomaly_baseline_minutes`.
+    smoothing: f64,
+}
+
+impl QueryAnomalyDetector {
+    pub fn new(spike_factor: f64, min_queries_per_minute: u64, baseline_minutes: u64) -> Self {
+        Self {
+            counts: Mutex::new(HashMap::new()),
+            baselines: Mutex::new(HashMap::new()),
+            last_evaluated: Mutex::new(Instant::now()),
+            intervals: Mutex::new(0),
+            spike_factor,
+            min_queries_per_minute: min_queries_per_minute as f64,
+            smoothing: 1.0 / baseline_minutes.max(1) as f64,
+        }
+    }
+    
+    /// Counts one query for a managed domain.
+    pub fn record(&self, domain: &str) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        match counts.get_mut(domain) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(domain.to_string(), 1);
+            }
+        }
+    }
+    
+    /// Domains with a baseline, i.e. seen since their rate last decayed to zero.
+    pub fn tracked_domains(&self) -> usize {
+        self.baselines.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+    
+    /// Closes the current interval: compares each domain's rate with its baseline, then folds
+    /// the interval into the baselines. Domains without queries decay towards zero.
+    pub fn evaluate(&self) -> Vec<QuerySpike> {
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap_or_else(PoisonError::into_inner));
+        let minutes = {
+            let mut last = self.last_evaluated.lock().unwrap_or_else(PoisonError::into_inner);
+            let elapsed = last.elapsed();
+            *last = Instant::now();
+            (elapsed.as_secs_f64() / 60.0).max(1.0 / 60.0)
+        };
+        
+        let warmed_up = {
+            let mut intervals = self.intervals.lock().unwrap_or_else(PoisonError::into_inner);
+            *intervals = intervals.saturating_add(1);
+            *intervals > WARMUP_INTERVALS
+        };
+        
+        let mut baselines = self.baselines.lock().unwrap_or_else(PoisonError::into_inner);
+        for domain in counts.keys() {
+            if !baselines.contains_key(domain) {
+                baselines.insert(domain.clone(), Baseline { rate: 0.0, last_alert: None });
+            }
+        }
+        
+        let mut spikes = Vec::new();
+        for (domain, baseline) in baselines.iter_mut() {
+            let rate = counts.get(domain).copied().unwrap_or(0) as f64 / minutes;
+            
+            let cooling_down = baseline.last_alert.is_some_and(|at| at.elapsed() < ALERT_COOLDOWN);
+            if warmed_up
+                && rate >= self.min_queries_per_minute
+                && rate >= baseline.rate * self.spike_factor
+                && !cooling_down
+            {
+                baseline.last_alert = Some(Instant::now());
+                spikes.push(QuerySpike {
+                    domain: domain.clone(),
+                    queries_per_minute: rate,
+                    baseline_per_minute: baseline.rate,
+                    detected_at: Utc::now(),
+                });
+            }
+            
+            baseline.rate += self.smoothing * (rate - baseline.rate);
+        }
+        baselines.retain(|domain, baseline| counts.contains_key(domain) || baseline.rate >= IDLE_BASELINE);
+        
+        spikes
+    }
+}
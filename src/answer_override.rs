@@ -0,0 +1,200 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use trust_dns_proto::rr::{Name, RecordType};
+
+use crate::domain_name::canonical_domain;
+use crate::txt_record;
+
+/// How often each node re-reads the overrides, so ones added through another node's API apply within seconds.
+pub const OVERRIDE_REFRESH_INTERVAL_SECONDS: u64 = 5;
+/// How long an override lasts when the request gives no `expires_at`.
+const DEFAULT_OVERRIDE_MINUTES: i64 = 60;
+/// Overrides are for incidents; anything longer belongs in the domain's own records.
+const MAX_OVERRIDE_DAYS: i64 = 7;
+/// TTL of `answer` records, kept short so resolvers let go soon after the override does.
+pub const OVERRIDE_ANSWER_TTL: u32 = 60;
+/// Record types an `answer` override can serve.
+const ANSWER_TYPES: &[&str] = &["A", "AAAA", "CNAME", "TXT"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverrideAction {
+    /// Answer REFUSED with no records.
+    Refuse,
+    /// Answer NXDOMAIN with no records.
+    Nxdomain,
+    /// Answer with `value` instead, e.g. a sinkhole address.
+    Answer,
+}
+
+impl OverrideAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverrideAction::Refuse => "refuse",
+            OverrideAction::Nxdomain => "nxdomain",
+            OverrideAction::Answer => "answer",
+        }
+    }
+}
+
+impl std::str::FromStr for OverrideAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "refuse" => Ok(OverrideAction::Refuse),
+            "nxdomain" => Ok(OverrideAction::Nxdomain),
+            "answer" => Ok(OverrideAction::Answer),
+            other => Err(anyhow::anyhow!("Unknown override action: {}", other)),
+        }
+    }
+}
+
+/// Body of `POST /overrides`.
+#[derive(Debug, Deserialize)]
+pub struct OverrideRequest {
+    /// A name, or `*.` and a name for every name below it.
+    pub name: String,
+    /// Record type the override applies to; every type when absent or `ANY`.
+    #[serde(default, rename = "type")]
+    pub record_type: Option<String>,
+    pub action: OverrideAction,
+    /// The record served by `answer`.
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Defaults to an hour from now.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A validated `OverrideRequest`, ready to be stored.
+#[derive(Debug, Clone)]
+pub struct NewOverride {
+    pub name: String,
+    pub record_type: Option<String>,
+    pub action: OverrideAction,
+    pub value: Option<String>,
+    pub reason: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl OverrideRequest {
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<NewOverride> {
+        let name = normalize_name(&self.name)?;
+        let record_type = match self.record_type.as_deref().map(|t| t.trim().to_uppercase()) {
+            None => None,
+            Some(t) if t == "ANY" => None,
+            Some(t) => {
+                t.parse::<RecordType>().map_err(|_| anyhow::anyhow!("Unknown record type: {}", t))?;
+                Some(t)
+            }
+        };
+
+        let value = match (self.action, self.value.as_deref()) {
+            (OverrideAction::Answer, Some(value)) => {
+                let record_type = record_type.as_deref()
+                    .filter(|t| ANSWER_TYPES.contains(t))
+                    .ok_or_else(|| anyhow::anyhow!("An answer override needs a type of {}", ANSWER_TYPES.join(", ")))?;
+                Some(check_value(record_type, value)?)
+            }
+            (OverrideAction::Answer, None) => return Err(anyhow::anyhow!("An answer override needs a value")),
+            (_, Some(_)) => return Err(anyhow::anyhow!("Only answer overrides take a value")),
+            (_, None) => None,
+        };
+
+        let expires_at = self.expires_at.unwrap_or(now + Duration::minutes(DEFAULT_OVERRIDE_MINUTES));
+        if expires_at <= now {
+            return Err(anyhow::anyhow!("expires_at must be in the future"));
+        }
+        if expires_at > now + Duration::days(MAX_OVERRIDE_DAYS) {
+            return Err(anyhow::anyhow!("expires_at may be at most {} days away", MAX_OVERRIDE_DAYS));
+        }
+
+        Ok(NewOverride {
+            name,
+            record_type,
+            action: self.action,
+            value,
+            reason: self.reason.clone(),
+            expires_at,
+        })
+    }
+}
+
+/// Canonicalizes a name, keeping a leading `*.`.
+fn normalize_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    match name.strip_prefix("*.") {
+        Some(parent) => Ok(format!("*.{}", canonical_domain(parent)?)),
+        None => canonical_domain(name),
+    }
+}
+
+fn check_value(record_type: &str, value: &str) -> Result<String> {
+    let value = value.trim();
+    match record_type {
+        "A" => value.parse::<Ipv4Addr>().map(|_| value.to_string())
+            .map_err(|_| anyhow::anyhow!("Invalid IPv4 address: {}", value)),
+        "AAAA" => value.parse::<Ipv6Addr>().map(|_| value.to_string())
+            .map_err(|_| anyhow::anyhow!("Invalid IPv6 address: {}", value)),
+        "CNAME" => {
+            let target = value.trim_end_matches('.').to_lowercase();
+            Name::from_ascii(&target).map_err(|_| anyhow::anyhow!("Invalid CNAME target: {}", value))?;
+            Ok(target)
+        }
+        _ => {
+            let value = txt_record::unquote(value);
+            txt_record::validate(&value)?;
+            Ok(value)
+        }
+    }
+}
+
+/// A row of `answer_overrides`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnswerOverride {
+    pub id: i64,
+    pub name: String,
+    /// `None` applies to every type.
+    #[serde(rename = "type")]
+    pub record_type: Option<String>,
+    pub action: OverrideAction,
+    pub value: Option<String>,
+    pub reason: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl AnswerOverride {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at > now
+    }
+
+    /// Whether the override covers `qname`/`qtype`. Like a zone wildcard, `*.evil.com`
+    /// covers every name below `evil.com` but not `evil.com` itself.
+    pub fn matches(&self, qname: &str, qtype: RecordType) -> bool {
+        if let Some(record_type) = &self.record_type {
+            if *record_type != qtype.to_string() {
+                return false;
+            }
+        }
+        let qname = qname.trim_end_matches('.').to_lowercase();
+        match self.name.strip_prefix("*.") {
+            Some(parent) => qname.ends_with(&format!(".{}", parent)),
+            None => qname == self.name,
+        }
+    }
+}
+
+/// The override answering `qname`/`qtype`: the most specific active one, exact names before
+/// wildcards, and for equally specific ones one limited to the type over one for every type.
+pub fn find_override<'a>(overrides: &'a [AnswerOverride], qname: &str, qtype: RecordType, now: DateTime<Utc>) -> Option<&'a AnswerOverride> {
+    overrides.iter()
+        .filter(|entry| entry.is_active(now) && entry.matches(qname, qtype))
+        .max_by_key(|entry| (!entry.name.starts_with("*."), entry.name.len(), entry.record_type.is_some()))
+}
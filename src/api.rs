@@ -0,0 +1,1569 @@
+use anyhow::Result;
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{ConnectInfo, FromRequest, FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, patch, post, put};
+use axum::{Json, Router};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{info, info_span, warn, Instrument};
+use trust_dns_proto::rr::RecordType;
+
+use crate::abuse::AbuseState;
+use crate::answer_override::OverrideRequest;
+use crate::approval::{key_fingerprint, ApprovalStatus, RecordChange};
+use crate::billing::PaymentCheckReport;
+use crate::blocklist::BlockKind;
+use crate::canary::Canary;
+use crate::config::{DmarcPolicy, DnsConfig, EffectiveConfig, MailRouting, SubdomainPolicy};
+use crate::dns_json::to_rfc8427;
+use crate::dns_server::DnsServer;
+use crate::domain_name::canonical_domain;
+use crate::domain_manager::SERVED_RECORD_TYPES;
+use crate::external_verification::{ExternalVerification, ExternalVerificationRequest};
+use crate::ip_migration::{IpMigration, IpMigrationRequest};
+use crate::node_stats::Breakdown;
+use crate::graphql::{build_schema, CybertempSchema};
+use crate::packet_capture::CaptureRequest;
+use crate::lint::LintReport;
+use crate::propagation::PropagationReport;
+use crate::registrar::DelegationInstructions;
+use crate::request_id::{accept_request_id, new_request_id, with_request_id, REQUEST_ID_HEADER};
+use crate::query_stats::{parse_window, TopStats};
+use crate::transport_stats::TransportSnapshot;
+use crate::rate_limit::RateLimiter;
+use crate::record_templates::RecordTemplate;
+use crate::runtime::RuntimeInfo;
+use crate::scheduler::ZoneMutation;
+use crate::synthetic::SyntheticRule;
+use crate::transfer::{ClaimRequest, TransferRequest};
+use crate::usage::{to_csv, DomainUptime};
+
+// API server for remote management
+pub struct DnsApiServer {
+    state: ApiState,
+}
+
+/// Per-minute request limits: `/feed/domains` per key, plus every request per API key or,
+/// without a valid key, per client IP.
+struct ApiLimiters {
+    feed: RateLimiter,
+    per_key: RateLimiter,
+    per_ip: RateLimiter,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    dns_server: Arc<DnsServer>,
+    limiters: Arc<ApiLimiters>,
+    schema: CybertempSchema,
+}
+
+impl DnsApiServer {
+    pub fn new(dns_server: Arc<DnsServer>) -> Self {
+        let limiters = Arc::new(ApiLimiters {
+            feed: RateLimiter::new(dns_server.config().feed_rate_limit_per_minute),
+            per_key: RateLimiter::new(dns_server.config().api_rate_limit_per_minute),
+            per_ip: RateLimiter::new(dns_server.config().api_ip_rate_limit_per_minute),
+        });
+        let schema = build_schema(dns_server.clone());
+        Self { state: ApiState { dns_server, limiters, schema } }
+    }
+    
+    pub async fn run(&self, bind_addr: &str, port: u16) -> Result<()> {
+        let addr: SocketAddr = format!("{}:{}", bind_addr, port).parse()?;
+        let app = self.router();
+        
+        let server = axum::Server::bind(&addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        info!("DNS API server running on http://{}", addr);
+        
+        server.await?;
+        Ok(())
+    }
+    
+    /// Every route, wrapped (outermost first) in CORS, request IDs, rate limiting, the
+    /// maintenance gate and `?effective_at=` scheduling.
+    fn router(&self) -> Router {
+        let state = self.state.clone();
+        let mut router = Router::new()
+            .route("/health", get(health))
+            .route("/maintenance", get(maintenance_status))
+            .route("/maintenance/enable", post(enable_maintenance))
+            .route("/maintenance/disable", post(disable_maintenance))
+            .route("/graphql", post(graphql))
+            .route("/feed/domains", get(domain_feed))
+            .route("/debug/capture", get(capture_status).post(start_capture).delete(stop_capture))
+            .route("/debug/runtime", get(runtime))
+            .route("/metrics", get(metrics))
+            .route("/resolve", get(resolve))
+            .route("/stats", get(stats))
+            .route("/stats/top", get(top_stats))
+            .route("/stats/transport", get(transport_stats))
+            .route("/stats/export", get(export_stats))
+            .route("/sync", post(sync))
+            .route("/reload", post(reload))
+            .route("/config", get(effective_config))
+            .route("/jobs", get(jobs))
+            .route("/verify", post(verify_domains))
+            .route("/verify/:domain", post(verify_domain))
+            .route("/cache/flush", post(flush_cache))
+            .route("/cache/flush/:domain", post(flush_cache_for))
+            .route("/domains", get(list_domains).post(add_domain))
+            .route("/domains/:domain", get(get_domain).patch(update_domain).delete(remove_domain))
+            .route("/domains/:domain/enable", post(enable_domain))
+            .route("/domains/:domain/disable", post(disable_domain))
+            .route("/domains/:domain/ip", put(set_ip))
+            .route("/domains/:domain/records/:record_type/:name", put(set_records).patch(update_record).delete(remove_records))
+            .route("/domains/:domain/bimi", put(set_bimi).delete(clear_bimi))
+            .route("/domains/:domain/dmarc", put(set_dmarc).delete(clear_dmarc))
+            .route("/domains/:domain/mail-routing", put(set_mail_routing).delete(clear_mail_routing))
+            .route("/domains/:domain/nameservers", put(set_nameservers).delete(clear_nameservers))
+            .route("/domains/:domain/nameserver-set", put(assign_nameserver_set).delete(clear_nameserver_set))
+            .route("/domains/:domain/routing-profile", put(assign_routing_profile).delete(clear_routing_profile))
+            .route("/domains/:domain/subdomains", put(set_subdomain_policy).delete(clear_subdomain_policy))
+            .route("/domains/:domain/synthetic-records", put(set_synthetic_rules).delete(clear_synthetic_rules))
+            .route("/domains/:domain/canaries", put(set_canaries).delete(clear_canaries))
+            .route("/domains/:domain/propagation", get(propagation))
+            .route("/domains/:domain/lint", get(lint_domain))
+            .route("/domains/:domain/instructions", get(delegation_instructions))
+            .route("/domains/:domain/external-verification", get(external_verifications).post(start_external_verification))
+            .route("/domains/:domain/uptime", get(uptime))
+            .route("/domains/:domain/certificates", get(certificates))
+            .route("/domains/:domain/abuse", get(domain_abuse).put(set_abuse_state).delete(clear_abuse_state))
+            .route("/domains/:domain/changes", get(zone_changes))
+            .route("/domains/:domain/rollback", post(rollback))
+            .route("/domains/:domain/scheduled", get(domain_scheduled))
+            .route("/domains/:domain/transfer", post(transfer_domain).delete(cancel_domain_transfer))
+            .route("/domains/:domain/transfers", get(domain_transfers))
+            .route("/domains/:domain/templates/:name", post(apply_template).delete(remove_template))
+            .route("/templates", get(list_templates))
+            .route("/templates/:name", get(get_template).put(save_template).delete(delete_template))
+            .route("/templates/:name/reapply", post(reapply_template))
+            .route("/scheduled", get(scheduled))
+            .route("/scheduled/:id", delete(cancel_scheduled))
+            .route("/approvals", get(approvals))
+            .route("/approvals/:id/approve", post(approve_change))
+            .route("/approvals/:id/reject", post(reject_change))
+            .route("/transfers/claim", post(claim_domain_transfer))
+            .route("/migrations", get(ip_migrations).post(start_ip_migration))
+            .route("/migrations/:id", get(ip_migration))
+            .route("/migrations/:id/cancel", post(cancel_ip_migration))
+            .route("/billing/holds", get(billing_holds))
+            .route("/billing/check", post(check_payments))
+            .route("/abuse", get(abuse_cases))
+            .route("/blocked", get(blocked_domains).post(block_domain))
+            .route("/blocked/:id", delete(unblock_domain))
+            .route("/overrides", get(answer_overrides).post(add_answer_override))
+            .route("/overrides/:id", delete(remove_answer_override))
+            .route("/nodes", get(nodes))
+            .route("/nodes/:node_id", delete(remove_node))
+            .route("/outbox", get(outbox))
+            .route("/outbox/:id/retry", post(retry_notification))
+            .fallback(not_found)
+            .layer(middleware::from_fn_with_state(state.clone(), schedule_mutation))
+            .layer(middleware::from_fn_with_state(state.clone(), maintenance_gate))
+            .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+            .layer(middleware::from_fn(tag_request))
+            .with_state(state);
+        
+        if let Some(cors) = cors_layer(self.state.dns_server.config()) {
+            router = router.layer(cors);
+        }
+        router
+    }
+}
+
+/// CORS for the origins in `cors_allowed_origins`, or `None` when the list is empty.
+/// Other origins get no `Access-Control-Allow-Origin`, so browsers block them.
+fn cors_layer(config: &DnsConfig) -> Option<CorsLayer> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+    
+    let origins = config.cors_allowed_origins.clone();
+    let methods: Vec<Method> = config.cors_allowed_methods.iter()
+        .filter_map(|m| Method::from_str(m).map_err(|_| warn!("Ignoring invalid CORS method: {}", m)).ok())
+        .collect();
+    let headers: Vec<header::HeaderName> = config.cors_allowed_headers.iter()
+        .filter_map(|h| h.parse().map_err(|_| warn!("Ignoring invalid CORS header: {}", h)).ok())
+        .collect();
+    
+    Some(CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin.to_str().map(|origin| origins.iter().any(|o| o == "*" || o == origin)).unwrap_or(false)
+        }))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers([header::RETRY_AFTER, header::HeaderName::from_static(REQUEST_ID_HEADER)])
+        .max_age(Duration::from_secs(config.cors_max_age_seconds)))
+}
+
+/// Every handler error, sent as `{"error": "..."}` with the matching status.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(String),
+    NotFound(String),
+    RateLimited(Duration),
+    Unavailable(String),
+    Internal(String),
+}
+
+impl ApiError {
+    fn bad_request(e: impl std::fmt::Display) -> Self {
+        ApiError::BadRequest(e.to_string())
+    }
+    
+    fn forbidden(e: impl std::fmt::Display) -> Self {
+        ApiError::Forbidden(e.to_string())
+    }
+    
+    fn not_found(e: impl std::fmt::Display) -> Self {
+        ApiError::NotFound(e.to_string())
+    }
+    
+    fn internal(e: impl std::fmt::Display) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match &self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message.as_str()),
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "Invalid or missing API key"),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message.as_str()),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message.as_str()),
+            ApiError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded"),
+            ApiError::Unavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message.as_str()),
+            ApiError::Internal(message) => (StatusCode::INTERNAL_SERVER_ERROR, message.as_str()),
+        };
+        let mut response = (status, Json(json!({"error": message}))).into_response();
+        if let ApiError::RateLimited(retry_after) = self {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+        }
+        response
+    }
+}
+
+type ApiResult<T = Json<Value>> = std::result::Result<T, ApiError>;
+
+/// JSON body that ignores `Content-Type`, since clients have always posted with a bare
+/// `curl -d`, and reads an empty body as `{}`.
+struct ApiJson<T>(T);
+
+#[async_trait]
+impl<S, T> FromRequest<S, Body> for ApiJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+    
+    async fn from_request(req: Request<Body>, state: &S) -> ApiResult<Self> {
+        let body = Bytes::from_request(req, state).await.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        let body = if body.is_empty() { &b"{}"[..] } else { &body[..] };
+        serde_json::from_slice(body)
+            .map(ApiJson)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid request body: {}", e)))
+    }
+}
+
+/// `Query` with its rejection mapped onto `ApiError`.
+struct ApiQuery<T>(T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for ApiQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+    
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> ApiResult<Self> {
+        let Query(query) = Query::from_request_parts(parts, state).await.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        Ok(ApiQuery(query))
+    }
+}
+
+/// `Path` with its rejection mapped onto `ApiError`.
+struct ApiPath<T>(T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for ApiPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = ApiError;
+    
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> ApiResult<Self> {
+        let Path(path) = Path::from_request_parts(parts, state).await.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        Ok(ApiPath(path))
+    }
+}
+
+/// A bearer token matching one of the configured `api_keys`; rejects with 401 otherwise.
+struct ApiKey(String);
+
+#[async_trait]
+impl FromRequestParts<ApiState> for ApiKey {
+    type Rejection = ApiError;
+    
+    async fn from_request_parts(parts: &mut Parts, state: &ApiState) -> ApiResult<Self> {
+        authorized_key(&parts.headers, state.dns_server.config())
+            .map(|key| ApiKey(key.to_string()))
+            .ok_or(ApiError::Unauthorized)
+    }
+}
+
+/// Who a change is attributed to in `zone_changes`: the `X-Actor` header, else "api".
+struct Actor(String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+    
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> std::result::Result<Self, Infallible> {
+        Ok(Actor(parts.headers
+            .get("x-actor")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(255).collect())
+            .unwrap_or_else(|| "api".to_string())))
+    }
+}
+
+/// Returns the bearer token if it matches one of the configured `api_keys`.
+fn authorized_key<'a>(headers: &'a HeaderMap, config: &DnsConfig) -> Option<&'a str> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))?;
+    
+    key_listed(&config.api_keys, token).then_some(token)
+}
+
+/// Whether `token` is one of `keys`. Keys are compared as HMAC digests in constant time, and
+/// every key is checked, so response times do not reveal how much of a key matched.
+fn key_listed(keys: &[String], token: &str) -> bool {
+    let digest = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"cybertemp-api-key").expect("HMAC accepts any key length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    let token = digest(token).finalize().into_bytes();
+    keys.iter().fold(false, |found, key| digest(key).verify_slice(&token).is_ok() | found)
+}
+
+/// Whether a request changes server state. Reads, GraphQL queries, cache flushes, reloads
+/// from the database, debug captures and the maintenance toggle itself do not.
+fn is_mutating(method: &Method, path: &str) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD)
+        && path != "/graphql"
+        && path != "/reload"
+        && !path.starts_with("/cache/")
+        && !path.starts_with("/debug/")
+        && !path.starts_with("/maintenance")
+}
+
+/// Gives the request an ID, the client's `X-Request-Id` if it sent a usable one, and returns
+/// it in `X-Request-Id`. Log lines and zone changes made while handling the request carry it.
+async fn tag_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let request_id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(accept_request_id)
+        .unwrap_or_else(new_request_id);
+    let span = info_span!("request", request_id = %request_id, method = %req.method(), path = %req.uri().path());
+    
+    let mut response = with_request_id(request_id.clone(), next.run(req)).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn rate_limit<B>(
+    State(state): State<ApiState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    req: Request<B>,
+    next: Next<B>,
+) -> ApiResult<Response> {
+    // Health checks stay unlimited so load balancers never see a 429
+    if req.uri().path() != "/health" {
+        let limited = match authorized_key(req.headers(), state.dns_server.config()) {
+            Some(key) => state.limiters.per_key.check(key),
+            None => state.limiters.per_ip.check(&client.ip().to_string()),
+        };
+        limited.map_err(ApiError::RateLimited)?;
+    }
+    Ok(next.run(req).await)
+}
+
+async fn maintenance_gate<B>(
+    State(state): State<ApiState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> ApiResult<Response> {
+    if is_mutating(req.method(), req.uri().path()) {
+        // Overrides are how incidents get handled, which maintenance windows are not exempt from
+        if state.dns_server.is_maintenance() && !req.uri().path().starts_with("/overrides") {
+            return Err(ApiError::Unavailable("Server is in maintenance mode".to_string()));
+        }
+        if !state.dns_server.database_available() {
+            return Err(ApiError::Unavailable("Database unavailable, changes are paused until it recovers".to_string()));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+#[derive(Deserialize)]
+struct ScheduleQuery {
+    effective_at: Option<String>,
+}
+
+/// Per-domain setting and records changes can be staged instead of applied by adding
+/// `?effective_at=<RFC 3339>`; `ZoneMutation::from_request` lists which.
+async fn schedule_mutation(
+    State(state): State<ApiState>,
+    actor: Actor,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> ApiResult<Response> {
+    let effective_at = Query::<ScheduleQuery>::try_from_uri(req.uri()).ok()
+        .and_then(|Query(query)| query.effective_at)
+        .filter(|_| is_mutating(req.method(), req.uri().path()));
+    let Some(effective_at) = effective_at else {
+        return Ok(next.run(req).await);
+    };
+    // The scheduler applies staged changes on its own, which would skip the second key
+    if state.dns_server.config().approval_required {
+        return Err(ApiError::forbidden("Changes cannot be scheduled while approval_required is set"));
+    }
+    
+    let (domain, resource) = req.uri().path()
+        .strip_prefix("/domains/")
+        .map(|p| p.split_once('/').unwrap_or((p, "")))
+        .map(|(domain, resource)| (domain.to_string(), resource.to_string()))
+        .ok_or_else(|| ApiError::bad_request("Only /domains/{domain}/... changes can be scheduled"))?;
+    let effective_at = DateTime::parse_from_rfc3339(&effective_at)
+        .map_err(|_| ApiError::bad_request("Invalid effective_at, use RFC 3339 e.g. 2026-01-01T02:00:00Z"))?
+        .with_timezone(&Utc);
+    
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.map_err(ApiError::bad_request)?;
+    let mutation = ZoneMutation::from_request(&parts.method, &resource, &body).map_err(ApiError::bad_request)?;
+    let id = state.dns_server.schedule_mutation(&domain, &mutation, effective_at, &actor.0).await
+        .map_err(ApiError::bad_request)?;
+    
+    Ok((StatusCode::ACCEPTED, Json(json!({"status": "scheduled", "id": id, "effective_at": effective_at}))).into_response())
+}
+
+async fn not_found() -> ApiError {
+    ApiError::not_found("Not found")
+}
+
+/// Stays 200 while the database is down, since DNS keeps answering from memory.
+async fn health(State(state): State<ApiState>) -> Json<Value> {
+    let database = state.dns_server.database_status();
+    if database.available {
+        return Json(json!({"status": "healthy"}));
+    }
+    Json(json!({
+        "status": "degraded",
+        "database": "unavailable",
+        "unavailable_since": database.unavailable_since,
+        "queued_writes": database.queued_writes,
+    }))
+}
+
+async fn maintenance_status(State(state): State<ApiState>) -> Json<Value> {
+    Json(json!({"maintenance": state.dns_server.is_maintenance()}))
+}
+
+async fn enable_maintenance(State(state): State<ApiState>) -> Json<Value> {
+    state.dns_server.set_maintenance(true);
+    Json(json!({"status": "ok", "maintenance": true}))
+}
+
+async fn disable_maintenance(State(state): State<ApiState>) -> Json<Value> {
+    state.dns_server.set_maintenance(false);
+    Json(json!({"status": "ok", "maintenance": false}))
+}
+
+async fn graphql(State(state): State<ApiState>, _key: ApiKey, body: Bytes) -> ApiResult<Json<async_graphql::Response>> {
+    let request: async_graphql::Request = serde_json::from_slice(&body)
+        .map_err(|_| ApiError::bad_request("Invalid GraphQL request"))?;
+    Ok(Json(state.schema.execute(request).await))
+}
+
+#[derive(Deserialize)]
+struct FeedQuery {
+    #[serde(default)]
+    since: i64,
+}
+
+async fn domain_feed(
+    State(state): State<ApiState>,
+    ApiKey(key): ApiKey,
+    ApiQuery(query): ApiQuery<FeedQuery>,
+) -> ApiResult {
+    state.limiters.feed.check(&key).map_err(ApiError::RateLimited)?;
+    
+    let events = state.dns_server.domain_feed(query.since).await.map_err(ApiError::internal)?;
+    let cursor = events.last().map(|e| e.id).unwrap_or(query.since);
+    Ok(Json(json!({"events": events, "cursor": cursor})))
+}
+
+async fn runtime(State(state): State<ApiState>, _key: ApiKey) -> Json<RuntimeInfo> {
+    Json(state.dns_server.runtime_info().await)
+}
+
+async fn metrics(State(state): State<ApiState>, _key: ApiKey) -> Response {
+    let body = state.dns_server.metrics().await;
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+async fn capture_status(State(state): State<ApiState>, _key: ApiKey) -> Json<Value> {
+    Json(json!({"capture": state.dns_server.capture_status()}))
+}
+
+async fn start_capture(
+    State(state): State<ApiState>,
+    _key: ApiKey,
+    ApiJson(request): ApiJson<CaptureRequest>,
+) -> ApiResult {
+    let status = state.dns_server.start_capture(request).map_err(ApiError::forbidden)?;
+    Ok(Json(json!({"status": "capturing", "capture": status})))
+}
+
+async fn stop_capture(State(state): State<ApiState>, _key: ApiKey) -> Json<Value> {
+    Json(json!({"status": "stopped", "capture": state.dns_server.stop_capture()}))
+}
+
+#[derive(Deserialize)]
+struct ResolveQuery {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+}
+
+async fn resolve(State(state): State<ApiState>, ApiQuery(query): ApiQuery<ResolveQuery>) -> ApiResult {
+    let name = query.name.ok_or_else(|| ApiError::bad_request("Missing name"))?;
+    let record_type = query.record_type.unwrap_or_else(|| "A".to_string());
+    let record_type = RecordType::from_str(&record_type.to_uppercase())
+        .map_err(|_| ApiError::BadRequest(format!("Unknown record type: {}", record_type)))?;
+    
+    let message = state.dns_server.resolve(&name, record_type).await.map_err(ApiError::bad_request)?;
+    Ok(Json(to_rfc8427(&message)))
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    by: Option<String>,
+}
+
+/// Domain counts, plus with `stats_push_enabled` the query counters pushed by every node.
+/// The domain counts come from memory, so they are still served while the database is down.
+async fn stats(State(state): State<ApiState>, ApiQuery(query): ApiQuery<StatsQuery>) -> ApiResult<Json<Value>> {
+    let by = query.by.as_deref().map(Breakdown::from_str).transpose().map_err(ApiError::bad_request)?;
+    let push_enabled = state.dns_server.config().stats_push_enabled;
+    if by.is_some() && !push_enabled {
+        return Err(ApiError::bad_request("Per-node stats need stats_push_enabled"));
+    }
+    
+    let mut stats = serde_json::to_value(state.dns_server.get_stats().await).map_err(ApiError::internal)?;
+    if push_enabled && state.dns_server.database_available() {
+        match state.dns_server.fleet_stats(by).await {
+            Ok(fleet) => stats["queries"] = serde_json::to_value(fleet).map_err(ApiError::internal)?,
+            Err(e) => warn!("Failed to read node stats: {}", e),
+        }
+    }
+    Ok(Json(stats))
+}
+
+#[derive(Deserialize)]
+struct TopQuery {
+    window: Option<String>,
+    n: Option<usize>,
+}
+
+async fn top_stats(State(state): State<ApiState>, ApiQuery(query): ApiQuery<TopQuery>) -> ApiResult<Json<TopStats>> {
+    let window = parse_window(query.window.as_deref().unwrap_or("5m"))
+        .ok_or_else(|| ApiError::bad_request("Invalid window, use e.g. 30s, 5m or 1h"))?;
+    Ok(Json(state.dns_server.top_stats(window, query.n.unwrap_or(10))))
+}
+
+async fn transport_stats(State(state): State<ApiState>) -> Json<TransportSnapshot> {
+    Json(state.dns_server.transport_stats())
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// `2026-01-01` (midnight UTC) or a full RFC 3339 timestamp.
+fn parse_export_time(value: &str) -> ApiResult<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| ApiError::bad_request(format!("Invalid time {}, use e.g. 2026-01-01 or 2026-01-01T00:00:00Z", value)))
+}
+
+/// Per-domain usage over `[from, to)`, by default the last 30 days, as CSV or JSON.
+async fn export_stats(State(state): State<ApiState>, ApiQuery(query): ApiQuery<ExportQuery>) -> ApiResult<Response> {
+    let to = match &query.to {
+        Some(to) => parse_export_time(to)?,
+        None => Utc::now(),
+    };
+    let from = match &query.from {
+        Some(from) => parse_export_time(from)?,
+        None => to - chrono::Duration::days(30),
+    };
+    let rows = state.dns_server.usage_report(from, to).await.map_err(ApiError::bad_request)?;
+    
+    match query.format.as_deref().unwrap_or("csv") {
+        "csv" => {
+            let filename = format!("attachment; filename=\"usage-{}-{}.csv\"", from.format("%Y%m%d"), to.format("%Y%m%d"));
+            Ok((
+                [(header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, filename)],
+                to_csv(&rows),
+            ).into_response())
+        }
+        "json" => Ok(Json(json!({"from": from, "to": to, "domains": rows})).into_response()),
+        _ => Err(ApiError::bad_request("Invalid format, use csv or json")),
+    }
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn sync(State(state): State<ApiState>, ApiQuery(query): ApiQuery<SyncQuery>) -> ApiResult {
+    let plan = state.dns_server.sync_supabase(query.dry_run).await
+        .map_err(|e| ApiError::Unavailable(e.to_string()))?;
+    let status = if query.dry_run { "dry_run" } else { "synced" };
+    Ok(Json(json!({"status": status, "changes": plan})))
+}
+
+async fn reload(State(state): State<ApiState>) -> ApiResult {
+    let report = state.dns_server.reload().await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "reloaded", "changes": report})))
+}
+
+async fn jobs(State(state): State<ApiState>) -> Json<Value> {
+    Json(json!({"jobs": state.dns_server.job_stats()}))
+}
+
+async fn effective_config(State(state): State<ApiState>, _key: ApiKey) -> Json<EffectiveConfig> {
+    Json(state.dns_server.effective_config().clone())
+}
+
+/// Either `domains` or `all_pending`, never both.
+#[derive(Deserialize)]
+struct BulkVerifyRequest {
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    all_pending: bool,
+}
+
+async fn verify_domains(State(state): State<ApiState>, ApiJson(request): ApiJson<BulkVerifyRequest>) -> ApiResult {
+    let domains = match (request.all_pending, request.domains.is_empty()) {
+        (true, true) => state.dns_server.unverified_domains().await,
+        (false, false) => request.domains,
+        _ => return Err(ApiError::bad_request("Send either a list of domains or all_pending: true")),
+    };
+    let results = state.dns_server.verify_domains(domains).await.map_err(ApiError::internal)?;
+    let verified = results.iter().filter(|result| result.verified).count();
+    Ok(Json(json!({"checked": results.len(), "verified": verified, "results": results})))
+}
+
+async fn verify_domain(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    if state.dns_server.get_domain_info(&domain).await.is_none() {
+        return Err(ApiError::not_found(format!("Domain not found: {}", domain)));
+    }
+    let verified = state.dns_server.force_verification(&domain).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "verified": verified})))
+}
+
+async fn flush_cache(State(state): State<ApiState>) -> Json<Value> {
+    let flushed = state.dns_server.flush_cache();
+    Json(json!({"status": "flushed", "entries": flushed}))
+}
+
+async fn flush_cache_for(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> Json<Value> {
+    let flushed = state.dns_server.flush_cache_for(&domain);
+    Json(json!({"status": "flushed", "domain": domain, "entries": flushed}))
+}
+
+async fn list_domains(State(state): State<ApiState>) -> Json<Vec<String>> {
+    Json(state.dns_server.list_domains().await)
+}
+
+#[derive(Deserialize)]
+struct AddDomainRequest {
+    domain: String,
+    ip: String,
+    #[serde(default)]
+    discord: bool,
+    /// A delegated subdomain like `mail.customer.com` rather than a registered domain.
+    #[serde(default)]
+    subdomain: bool,
+    /// Name of a configured routing profile the domain's mail goes through.
+    #[serde(default)]
+    routing_profile: Option<String>,
+}
+
+/// A retry carrying the same `Idempotency-Key` header as an earlier add is acknowledged
+/// without being applied again.
+async fn add_domain(
+    State(state): State<ApiState>,
+    Actor(actor): Actor,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<AddDomainRequest>,
+) -> ApiResult {
+    let idempotency_key = match headers.get("idempotency-key") {
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= 255 => Some(key),
+            _ => return Err(ApiError::bad_request("Idempotency-Key must be 1 to 255 visible ASCII characters")),
+        },
+        None => None,
+    };
+    
+    if let Some(profile) = &request.routing_profile {
+        if state.dns_server.config().routing_profile(profile).is_none() {
+            return Err(ApiError::bad_request(format!("Unknown routing profile: {}", profile)));
+        }
+    }
+    
+    // Re-adding a served domain under another spelling is more likely a mistake than an update
+    let domain = canonical_domain(&request.domain).map_err(ApiError::bad_request)?;
+    if domain != request.domain && state.dns_server.get_domain_info(&domain).await.is_some() {
+        return Err(ApiError::bad_request(format!("{} is already served as {}", request.domain, domain)));
+    }
+    
+    let applied = state.dns_server
+        .add_domain(&domain, &request.ip, request.discord, request.subdomain, request.routing_profile.as_deref(), &actor, idempotency_key)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "added", "replayed": !applied})))
+}
+
+#[derive(Deserialize)]
+struct DomainQuery {
+    include: Option<String>,
+}
+
+/// The domain's record, plus its Supabase owner with `?include=owner`. Owner details
+/// include the customer's email, so they need a key from `api_keys`.
+async fn get_domain(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    ApiQuery(query): ApiQuery<DomainQuery>,
+    key: Option<ApiKey>,
+) -> ApiResult {
+    let record = state.dns_server.get_domain_info(&domain).await
+        .ok_or_else(|| ApiError::not_found(format!("Domain not found: {}", domain)))?;
+    let mut view = serde_json::to_value(&record).map_err(ApiError::internal)?;
+    
+    match query.include.as_deref() {
+        None => {}
+        Some("owner") => {
+            key.ok_or(ApiError::Unauthorized)?;
+            let owner = state.dns_server.domain_owner(&domain).await
+                .map_err(|e| ApiError::Unavailable(e.to_string()))?;
+            view["owner"] = json!(owner);
+        }
+        Some(other) => return Err(ApiError::bad_request(format!("Invalid include: {}, use owner", other))),
+    }
+    Ok(Json(view))
+}
+
+async fn remove_domain(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    state.dns_server.remove_domain(&domain).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+/// Editable domain fields; a `null` (or missing) `ttl` drops the override.
+#[derive(Deserialize)]
+struct UpdateDomainRequest {
+    ttl: Option<u32>,
+}
+
+async fn update_domain(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<UpdateDomainRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_domain_ttl(&domain, request.ttl, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "ttl": request.ttl})))
+}
+
+#[derive(Deserialize)]
+struct UpdateRecordRequest {
+    ttl: u32,
+}
+
+async fn update_record(
+    State(state): State<ApiState>,
+    ApiPath((domain, record_type, name)): ApiPath<(String, String, String)>,
+    Actor(actor): Actor,
+    key: Option<ApiKey>,
+    ApiJson(request): ApiJson<UpdateRecordRequest>,
+) -> ApiResult<Response> {
+    if state.dns_server.config().approval_required {
+        let change = RecordChange::SetTtl { record_type, name, ttl: request.ttl };
+        return hold_for_approval(&state, &domain, change, &actor, key).await;
+    }
+    
+    state.dns_server.set_record_ttl(&domain, &record_type, &name, request.ttl, &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "ttl": request.ttl})).into_response())
+}
+
+/// With `approval_required`, queues a records API change in `pending_changes` instead of
+/// applying it. The submitting key is remembered so it cannot approve its own change.
+async fn hold_for_approval(
+    state: &ApiState,
+    domain: &str,
+    change: RecordChange,
+    actor: &str,
+    key: Option<ApiKey>,
+) -> ApiResult<Response> {
+    let ApiKey(key) = key.ok_or(ApiError::Unauthorized)?;
+    let id = state.dns_server.submit_record_change(domain, &change, actor, &key).await
+        .map_err(ApiError::bad_request)?;
+    Ok((StatusCode::ACCEPTED, Json(json!({"status": "pending_approval", "id": id}))).into_response())
+}
+
+/// With `approval_required`, refuses record changes the approval queue cannot hold, rather
+/// than letting one key apply them.
+fn refuse_unapproved(state: &ApiState) -> ApiResult<()> {
+    if state.dns_server.config().approval_required {
+        Err(ApiError::forbidden("This change cannot be queued for approval and is refused while approval_required is set"))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRecordsRequest {
+    values: Vec<String>,
+    ttl: Option<u32>,
+    #[serde(default)]
+    allow_reserved: bool,
+}
+
+/// Record types the records API manages as whole sets; others come from templates and imports.
+/// Returns the type uppercased.
+fn check_settable(record_type: &str) -> ApiResult<String> {
+    let record_type = record_type.to_uppercase();
+    if SERVED_RECORD_TYPES.contains(&record_type.as_str()) {
+        Ok(record_type)
+    } else {
+        Err(ApiError::bad_request(format!(
+            "Only {} records can be set through the records API",
+            SERVED_RECORD_TYPES.join(", "),
+        )))
+    }
+}
+
+async fn set_records(
+    State(state): State<ApiState>,
+    ApiPath((domain, record_type, name)): ApiPath<(String, String, String)>,
+    Actor(actor): Actor,
+    key: Option<ApiKey>,
+    ApiJson(request): ApiJson<SetRecordsRequest>,
+) -> ApiResult<Response> {
+    let record_type = check_settable(&record_type)?;
+    if state.dns_server.config().approval_required {
+        let change = RecordChange::SetRecords {
+            record_type,
+            name,
+            values: request.values,
+            ttl: request.ttl,
+            allow_reserved: request.allow_reserved,
+        };
+        return hold_for_approval(&state, &domain, change, &actor, key).await;
+    }
+    
+    let values = state.dns_server
+        .set_records(&domain, &record_type, &name, &request.values, request.ttl, request.allow_reserved, &actor)
+        .await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "type": record_type, "name": name, "values": values})).into_response())
+}
+
+async fn remove_records(
+    State(state): State<ApiState>,
+    ApiPath((domain, record_type, name)): ApiPath<(String, String, String)>,
+    Actor(actor): Actor,
+    key: Option<ApiKey>,
+) -> ApiResult<Response> {
+    let record_type = check_settable(&record_type)?;
+    if state.dns_server.config().approval_required {
+        let change = RecordChange::ClearRecords { record_type, name };
+        return hold_for_approval(&state, &domain, change, &actor, key).await;
+    }
+    
+    state.dns_server.clear_records(&domain, &record_type, &name, &actor).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "removed"})).into_response())
+}
+
+async fn enable_domain(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    state.dns_server.set_domain_enabled(&domain, true).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "enabled"})))
+}
+
+async fn disable_domain(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    state.dns_server.set_domain_enabled(&domain, false).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "disabled"})))
+}
+
+#[derive(Deserialize)]
+struct SetIpRequest {
+    ip: String,
+}
+
+async fn set_ip(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    ApiJson(request): ApiJson<SetIpRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_domain_ip(&domain, &request.ip).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "ip": request.ip})))
+}
+
+#[derive(Deserialize)]
+struct BimiRequest {
+    logo_url: String,
+    vmc_url: Option<String>,
+}
+
+async fn set_bimi(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<BimiRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_bimi(&domain, &request.logo_url, request.vmc_url.as_deref(), &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated"})))
+}
+
+async fn clear_bimi(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_bimi(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+async fn set_dmarc(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(policy): ApiJson<DmarcPolicy>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_dmarc(&domain, &policy, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "record": policy.to_txt()})))
+}
+
+async fn clear_dmarc(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_dmarc(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+async fn set_mail_routing(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(routing): ApiJson<MailRouting>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_mail_routing(&domain, &routing, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "routing": routing})))
+}
+
+async fn clear_mail_routing(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_mail_routing(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct NameserversRequest {
+    nameservers: Vec<String>,
+}
+
+async fn set_nameservers(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<NameserversRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_nameservers(&domain, &request.nameservers, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "nameservers": request.nameservers})))
+}
+
+async fn clear_nameservers(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_nameservers(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct NameserverSetRequest {
+    set: String,
+}
+
+async fn assign_nameserver_set(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<NameserverSetRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.assign_nameserver_set(&domain, &request.set, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "set": request.set})))
+}
+
+async fn clear_nameserver_set(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_nameserver_set(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct RoutingProfileRequest {
+    profile: String,
+}
+
+async fn assign_routing_profile(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<RoutingProfileRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.assign_routing_profile(&domain, &request.profile, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "profile": request.profile})))
+}
+
+async fn clear_routing_profile(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_routing_profile(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct SubdomainPolicyRequest {
+    policy: SubdomainPolicy,
+}
+
+async fn set_subdomain_policy(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<SubdomainPolicyRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_subdomain_policy(&domain, request.policy, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "policy": request.policy})))
+}
+
+async fn clear_subdomain_policy(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_subdomain_policy(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct SyntheticRulesRequest {
+    rules: Vec<SyntheticRule>,
+}
+
+async fn set_synthetic_rules(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<SyntheticRulesRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_synthetic_rules(&domain, &request.rules, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "rules": request.rules})))
+}
+
+async fn clear_synthetic_rules(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_synthetic_rules(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+#[derive(Deserialize)]
+struct CanariesRequest {
+    canaries: Vec<Canary>,
+}
+
+async fn set_canaries(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<CanariesRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.set_canaries(&domain, &request.canaries, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "canaries": request.canaries})))
+}
+
+async fn clear_canaries(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>, Actor(actor): Actor) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.clear_canaries(&domain, &actor).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+async fn propagation(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult<Json<PropagationReport>> {
+    let report = state.dns_server.propagation(&domain).await.map_err(ApiError::not_found)?;
+    Ok(Json(report))
+}
+
+async fn lint_domain(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult<Json<LintReport>> {
+    let report = state.dns_server.lint_domain(&domain).await.map_err(ApiError::not_found)?;
+    Ok(Json(report))
+}
+
+async fn delegation_instructions(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult<Json<DelegationInstructions>> {
+    let instructions = state.dns_server.delegation_instructions(&domain).await.map_err(ApiError::not_found)?;
+    Ok(Json(instructions))
+}
+
+async fn start_external_verification(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<ExternalVerificationRequest>,
+) -> ApiResult<Json<ExternalVerification>> {
+    refuse_unapproved(&state)?;
+    let verification = state.dns_server.start_external_verification(&domain, &request, &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(verification))
+}
+
+async fn external_verifications(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    let verifications = state.dns_server.external_verifications(&domain);
+    Ok(Json(json!({"domain": domain, "verifications": verifications})))
+}
+
+async fn uptime(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult<Json<DomainUptime>> {
+    let uptime = state.dns_server.domain_uptime(&domain).await.map_err(ApiError::not_found)?;
+    Ok(Json(uptime))
+}
+
+#[derive(Deserialize)]
+struct CertificatesQuery {
+    #[serde(default)]
+    unexpected: bool,
+}
+
+async fn certificates(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    ApiQuery(query): ApiQuery<CertificatesQuery>,
+) -> ApiResult {
+    if state.dns_server.get_domain_info(&domain).await.is_none() {
+        return Err(ApiError::NotFound(format!("Domain not found: {}", domain)));
+    }
+    let certificates = state.dns_server.ct_findings(&domain, query.unexpected).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "certificates": certificates})))
+}
+
+async fn domain_abuse(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    let abuse_state = state.dns_server.abuse_state(&domain).await;
+    let events = state.dns_server.abuse_events(&domain).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "state": abuse_state, "events": events})))
+}
+
+#[derive(Deserialize)]
+struct AbuseStateRequest {
+    state: AbuseState,
+    reason: Option<String>,
+}
+
+async fn set_abuse_state(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<AbuseStateRequest>,
+) -> ApiResult {
+    state.dns_server.set_abuse_state(&domain, Some(request.state), request.reason.as_deref(), &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "updated", "state": request.state})))
+}
+
+#[derive(Deserialize)]
+struct ClearAbuseQuery {
+    reason: Option<String>,
+}
+
+async fn clear_abuse_state(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiQuery(query): ApiQuery<ClearAbuseQuery>,
+) -> ApiResult {
+    state.dns_server.set_abuse_state(&domain, None, query.reason.as_deref(), &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "cleared"})))
+}
+
+#[derive(Deserialize)]
+struct AbuseQuery {
+    state: Option<AbuseState>,
+}
+
+async fn abuse_cases(State(state): State<ApiState>, ApiQuery(query): ApiQuery<AbuseQuery>) -> ApiResult {
+    let cases = state.dns_server.abuse_cases(query.state).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"cases": cases})))
+}
+
+async fn blocked_domains(State(state): State<ApiState>) -> ApiResult {
+    Ok(Json(json!({"blocked": state.dns_server.blocked_domains().await})))
+}
+
+#[derive(Deserialize)]
+struct BlockDomainRequest {
+    pattern: String,
+    #[serde(default)]
+    kind: BlockKind,
+    reason: Option<String>,
+}
+
+async fn block_domain(
+    State(state): State<ApiState>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<BlockDomainRequest>,
+) -> ApiResult {
+    let (entry, existing) = state.dns_server.block_domain(&request.pattern, request.kind, request.reason.as_deref(), &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "blocked", "entry": entry, "existing_domains": existing})))
+}
+
+async fn unblock_domain(State(state): State<ApiState>, ApiPath(id): ApiPath<i64>) -> ApiResult {
+    state.dns_server.unblock_domain(id).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "removed", "id": id})))
+}
+
+async fn answer_overrides(State(state): State<ApiState>) -> ApiResult {
+    Ok(Json(json!({"overrides": state.dns_server.answer_overrides().await})))
+}
+
+async fn add_answer_override(
+    State(state): State<ApiState>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<OverrideRequest>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    let entry = state.dns_server.add_answer_override(&request, &actor).await
+        .map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "active", "override": entry})))
+}
+
+async fn remove_answer_override(State(state): State<ApiState>, ApiPath(id): ApiPath<i64>) -> ApiResult {
+    refuse_unapproved(&state)?;
+    let entry = state.dns_server.remove_answer_override(id).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "lifted", "override": entry})))
+}
+
+async fn nodes(State(state): State<ApiState>) -> ApiResult {
+    let nodes = state.dns_server.nodes().await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"nodes": nodes})))
+}
+
+async fn remove_node(State(state): State<ApiState>, ApiPath(node_id): ApiPath<String>) -> ApiResult {
+    state.dns_server.remove_node(&node_id).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "removed", "node_id": node_id})))
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    limit: Option<i64>,
+}
+
+async fn zone_changes(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    ApiQuery(query): ApiQuery<ChangesQuery>,
+) -> ApiResult {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let changes = state.dns_server.zone_changes(&domain, limit).await.map_err(ApiError::internal)?;
+    let pending = state.dns_server.pending_changes(Some(&domain)).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "changes": changes, "pending": pending})))
+}
+
+#[derive(Deserialize)]
+struct RollbackQuery {
+    to: Option<i64>,
+}
+
+async fn rollback(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiQuery(query): ApiQuery<RollbackQuery>,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    let to = query.to.ok_or_else(|| ApiError::bad_request("Missing or invalid ?to=<change_id>"))?;
+    let reverted = state.dns_server.rollback_records(&domain, to, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "rolled_back", "to": to, "reverted": reverted})))
+}
+
+async fn domain_scheduled(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    let pending = state.dns_server.pending_changes(Some(&domain)).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "pending": pending})))
+}
+
+async fn apply_template(
+    State(state): State<ApiState>,
+    ApiPath((domain, name)): ApiPath<(String, String)>,
+    Actor(actor): Actor,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    let written = state.dns_server.apply_template(&domain, &name, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "applied", "template": name, "records": written})))
+}
+
+async fn remove_template(
+    State(state): State<ApiState>,
+    ApiPath((domain, name)): ApiPath<(String, String)>,
+    Actor(actor): Actor,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    state.dns_server.remove_template(&domain, &name, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "removed", "template": name})))
+}
+
+async fn list_templates(State(state): State<ApiState>) -> ApiResult {
+    let templates = state.dns_server.templates().await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"templates": templates})))
+}
+
+async fn get_template(State(state): State<ApiState>, ApiPath(name): ApiPath<String>) -> ApiResult<Json<RecordTemplate>> {
+    state.dns_server.template(&name).await
+        .map_err(ApiError::internal)?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("Template not found: {}", name)))
+}
+
+async fn save_template(
+    State(state): State<ApiState>,
+    ApiPath(name): ApiPath<String>,
+    ApiJson(mut template): ApiJson<RecordTemplate>,
+) -> ApiResult {
+    template.name = name;
+    state.dns_server.save_template(&template).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "saved", "template": template.name})))
+}
+
+async fn delete_template(State(state): State<ApiState>, ApiPath(name): ApiPath<String>) -> ApiResult {
+    state.dns_server.delete_template(&name).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "removed"})))
+}
+
+async fn reapply_template(
+    State(state): State<ApiState>,
+    ApiPath(name): ApiPath<String>,
+    Actor(actor): Actor,
+) -> ApiResult {
+    refuse_unapproved(&state)?;
+    let report = state.dns_server.reapply_template(&name, &actor).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "reapplied", "template": name, "applied": report.applied, "failed": report.failed})))
+}
+
+async fn scheduled(State(state): State<ApiState>) -> ApiResult {
+    let pending = state.dns_server.pending_changes(None).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"pending": pending})))
+}
+
+async fn cancel_scheduled(State(state): State<ApiState>, ApiPath(id): ApiPath<i64>) -> ApiResult {
+    state.dns_server.cancel_scheduled_change(id).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "cancelled", "id": id})))
+}
+
+#[derive(Deserialize)]
+struct ApprovalsQuery {
+    domain: Option<String>,
+}
+
+async fn approvals(State(state): State<ApiState>, ApiQuery(query): ApiQuery<ApprovalsQuery>) -> ApiResult {
+    let pending = state.dns_server.pending_record_changes(query.domain.as_deref()).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"pending": pending})))
+}
+
+/// Only keys listed in `approver_keys` may approve or reject queued record changes.
+fn check_approver(state: &ApiState, key: &str) -> ApiResult<()> {
+    if key_listed(&state.dns_server.config().approver_keys, key) {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("This key cannot approve changes"))
+    }
+}
+
+async fn approve_change(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<i64>,
+    ApiKey(key): ApiKey,
+    Actor(actor): Actor,
+) -> ApiResult {
+    check_approver(&state, &key)?;
+    let change = state.dns_server.pending_record_change(id).await
+        .map_err(ApiError::internal)?
+        .filter(|change| change.status == ApprovalStatus::Pending)
+        .ok_or_else(|| ApiError::NotFound(format!("No pending record change {}", id)))?;
+    if key_fingerprint(&key) == change.submitter_key {
+        return Err(ApiError::forbidden("A change cannot be approved with the key that submitted it"));
+    }
+    
+    state.dns_server.approve_record_change(&change, &actor, &key).await.map_err(ApiError::bad_request)?;
+    Ok(Json(json!({"status": "approved", "id": id})))
+}
+
+async fn reject_change(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<i64>,
+    ApiKey(key): ApiKey,
+    Actor(actor): Actor,
+) -> ApiResult {
+    check_approver(&state, &key)?;
+    state.dns_server.reject_record_change(id, &actor, &key).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "rejected", "id": id})))
+}
+
+async fn start_ip_migration(
+    State(state): State<ApiState>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<IpMigrationRequest>,
+) -> ApiResult<Response> {
+    refuse_unapproved(&state)?;
+    let migration = state.dns_server.start_ip_migration(&request, &actor).await.map_err(ApiError::bad_request)?;
+    Ok((StatusCode::ACCEPTED, Json(migration)).into_response())
+}
+
+#[derive(Deserialize)]
+struct IpMigrationsQuery {
+    #[serde(default)]
+    active: bool,
+}
+
+async fn ip_migrations(State(state): State<ApiState>, ApiQuery(query): ApiQuery<IpMigrationsQuery>) -> ApiResult {
+    let migrations = state.dns_server.ip_migrations(query.active).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"migrations": migrations})))
+}
+
+async fn ip_migration(State(state): State<ApiState>, ApiPath(id): ApiPath<i64>) -> ApiResult<Json<IpMigration>> {
+    state.dns_server.ip_migration(id).await
+        .map_err(ApiError::internal)?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No IP migration {}", id)))
+}
+
+async fn cancel_ip_migration(
+    State(state): State<ApiState>,
+    ApiPath(id): ApiPath<i64>,
+    Actor(actor): Actor,
+) -> ApiResult<Json<IpMigration>> {
+    let migration = state.dns_server.cancel_ip_migration(id, &actor).await.map_err(ApiError::bad_request)?;
+    Ok(Json(migration))
+}
+
+async fn billing_holds(State(state): State<ApiState>) -> ApiResult {
+    let holds = state.dns_server.billing_holds().await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"holds": holds})))
+}
+
+async fn check_payments(State(state): State<ApiState>) -> ApiResult<Json<PaymentCheckReport>> {
+    let report = state.dns_server.check_payments().await.map_err(|e| ApiError::Unavailable(e.to_string()))?;
+    Ok(Json(report))
+}
+
+/// Transfers need Supabase, so its absence is a 503 rather than a bad request.
+fn transfer_error(e: anyhow::Error) -> ApiError {
+    if e.to_string() == "Supabase is not configured" {
+        return ApiError::Unavailable(e.to_string());
+    }
+    ApiError::bad_request(e)
+}
+
+async fn transfer_domain(
+    State(state): State<ApiState>,
+    ApiPath(domain): ApiPath<String>,
+    Actor(actor): Actor,
+    ApiJson(request): ApiJson<TransferRequest>,
+) -> ApiResult {
+    let (transfer, claim_token) = state.dns_server.transfer_domain(&domain, &request, &actor).await
+        .map_err(transfer_error)?;
+    Ok(Json(json!({"transfer": transfer, "claim_token": claim_token})))
+}
+
+async fn cancel_domain_transfer(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    state.dns_server.cancel_domain_transfer(&domain).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "cancelled"})))
+}
+
+async fn domain_transfers(State(state): State<ApiState>, ApiPath(domain): ApiPath<String>) -> ApiResult {
+    let transfers = state.dns_server.domain_transfers(&domain).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"domain": domain, "transfers": transfers})))
+}
+
+async fn claim_domain_transfer(State(state): State<ApiState>, ApiJson(request): ApiJson<ClaimRequest>) -> ApiResult {
+    let transfer = state.dns_server.claim_domain_transfer(&request).await.map_err(transfer_error)?;
+    Ok(Json(json!({"transfer": transfer})))
+}
+
+#[derive(Deserialize)]
+struct OutboxQuery {
+    status: Option<String>,
+}
+
+async fn outbox(State(state): State<ApiState>, ApiQuery(query): ApiQuery<OutboxQuery>) -> ApiResult {
+    if let Some(status) = query.status.as_deref() {
+        if !matches!(status, "pending" | "delivered" | "dead") {
+            return Err(ApiError::bad_request("status must be pending, delivered or dead"));
+        }
+    }
+    let messages = state.dns_server.outbox(query.status.as_deref()).await.map_err(ApiError::internal)?;
+    Ok(Json(json!({"notifications": messages})))
+}
+
+async fn retry_notification(State(state): State<ApiState>, ApiPath(id): ApiPath<i64>) -> ApiResult {
+    state.dns_server.retry_notification(id).await.map_err(ApiError::not_found)?;
+    Ok(Json(json!({"status": "requeued", "id": id})))
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::validation::{check_ttl, parse_record_value, MAX_RECORD_SET_VALUES};
+
+/// A records API change held in `pending_changes` until a second key approves it, with
+/// `approval_required`.
+///
+/// Stored as JSON in `pending_changes.change`, tagged by `action`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RecordChange {
+    /// `PUT /domains/{domain}/records/{type}/{name}`.
+    SetRecords {
+        /// Absent from changes queued before other types could be set.
+        #[serde(default = "txt")]
+        record_type: String,
+        name: String,
+        values: Vec<String>,
+        ttl: Option<u32>,
+        #[serde(default)]
+        allow_reserved: bool,
+    },
+    /// `DELETE /domains/{domain}/records/{type}/{name}`.
+    ClearRecords {
+        #[serde(default = "txt")]
+        record_type: String,
+        name: String,
+    },
+    /// `PATCH /domains/{domain}/records/{type}/{name}`.
+    SetTtl { record_type: String, name: String, ttl: u32 },
+}
+
+impl RecordChange {
+    /// Catches obviously bad input when the change is submitted rather than when it is approved.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            RecordChange::SetRecords { record_type, values, ttl, .. } => {
+                if values.is_empty() || values.len() > MAX_RECORD_SET_VALUES {
+                    return Err(anyhow::anyhow!("{} record sets need between 1 and {} values", record_type, MAX_RECORD_SET_VALUES));
+                }
+                for value in values {
+                    parse_record_value(record_type, value)?;
+                }
+                if let Some(ttl) = ttl {
+                    check_ttl(*ttl)?;
+                }
+            }
+            RecordChange::SetTtl { ttl, .. } => check_ttl(*ttl)?,
+            RecordChange::ClearRecords { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+fn txt() -> String {
+    "TXT".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalStatus {
+    Pending,
+    /// Approved and applied.
+    Approved,
+    Rejected,
+    /// Approved, but applying it failed; `error` says why.
+    Failed,
+}
+
+impl ApprovalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApprovalStatus::Pending => "pending",
+            ApprovalStatus::Approved => "approved",
+            ApprovalStatus::Rejected => "rejected",
+            ApprovalStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for ApprovalStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(ApprovalStatus::Pending),
+            "approved" => Ok(ApprovalStatus::Approved),
+            "rejected" => Ok(ApprovalStatus::Rejected),
+            "failed" => Ok(ApprovalStatus::Failed),
+            other => Err(anyhow::anyhow!("Unknown approval status: {}", other)),
+        }
+    }
+}
+
+/// A row of `pending_changes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingChange {
+    pub id: i64,
+    pub domain: String,
+    pub change: RecordChange,
+    pub status: ApprovalStatus,
+    /// Actor the change is applied as, from `X-Actor` when it was submitted.
+    pub submitted_by: String,
+    /// Fingerprint of the key that submitted it, which may not approve it.
+    #[serde(skip)]
+    pub submitter_key: String,
+    pub submitted_at: DateTime<Utc>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// What is stored instead of an API key, so the two-person rule can be checked without
+/// keeping the key itself.
+pub fn key_fingerprint(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
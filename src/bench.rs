@@ -0,0 +1,211 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+/// Sockets the load is spread over, each with its own 16-bit query ID space.
+const SOCKETS: usize = 8;
+
+pub struct BenchOptions {
+    pub target: SocketAddr,
+    pub qps: u32,
+    pub duration: Duration,
+    pub timeout: Duration,
+    pub record_type: RecordType,
+    pub qnames: Vec<String>,
+}
+
+impl BenchOptions {
+    /// Reads one query name per line, skipping blanks and `#` comments.
+    pub fn load_qnames(path: &str) -> Result<Vec<String>> {
+        let qnames: Vec<String> = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        
+        if qnames.is_empty() {
+            return Err(anyhow::anyhow!("No query names in {}", path));
+        }
+        Ok(qnames)
+    }
+    
+    /// Names used when no file is given; they exercise the NXDOMAIN path.
+    pub fn generated_qnames() -> Vec<String> {
+        (0..1000).map(|i| format!("q{}.bench.invalid", i)).collect()
+    }
+}
+
+#[derive(Default)]
+struct SocketResults {
+    sent: u64,
+    latencies_us: Vec<u64>,
+    response_codes: HashMap<ResponseCode, u64>,
+    malformed: u64,
+}
+
+/// Floods `target` with queries at a fixed rate and prints latency percentiles and error rates.
+pub async fn run_bench(options: BenchOptions) -> Result<()> {
+    let queries = encode_queries(&options.qnames, options.record_type)?;
+    let send_errors = Arc::new(AtomicU64::new(0));
+    let per_socket_qps = options.qps as f64 / SOCKETS as f64;
+    
+    println!(
+        "bench: {} qps of {} for {}s against {} ({} names)",
+        options.qps, options.record_type, options.duration.as_secs(), options.target, queries.len()
+    );
+    
+    let started = Instant::now();
+    let mut workers = Vec::with_capacity(SOCKETS);
+    for worker in 0..SOCKETS {
+        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+        socket.connect(options.target).await?;
+        
+        let queries = queries.clone();
+        let send_errors = send_errors.clone();
+        let duration = options.duration;
+        let timeout = options.timeout;
+        
+        workers.push(tokio::spawn(async move {
+            run_socket(socket, worker, queries, per_socket_qps, duration, timeout, send_errors).await
+        }));
+    }
+    
+    let mut totals = SocketResults::default();
+    for worker in workers {
+        let results = worker.await?;
+        totals.sent += results.sent;
+        totals.malformed += results.malformed;
+        totals.latencies_us.extend(results.latencies_us);
+        for (code, count) in results.response_codes {
+            *totals.response_codes.entry(code).or_insert(0) += count;
+        }
+    }
+    
+    report(&totals, send_errors.load(Ordering::Relaxed), started.elapsed().min(options.duration));
+    Ok(())
+}
+
+async fn run_socket(
+    socket: Arc<UdpSocket>,
+    worker: usize,
+    queries: Arc<Vec<Vec<u8>>>,
+    qps: f64,
+    duration: Duration,
+    timeout: Duration,
+    send_errors: Arc<AtomicU64>,
+) -> SocketResults {
+    let inflight: Arc<Mutex<HashMap<u16, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+    let results = Arc::new(Mutex::new(SocketResults::default()));
+    
+    let receiver = {
+        let socket = socket.clone();
+        let inflight = inflight.clone();
+        let results = results.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let Ok(len) = socket.recv(&mut buf).await else { continue };
+                let received = Instant::now();
+                let mut results = results.lock().unwrap();
+                
+                if len < 12 {
+                    results.malformed += 1;
+                    continue;
+                }
+                let id = u16::from_be_bytes([buf[0], buf[1]]);
+                let Some(sent) = inflight.lock().unwrap().remove(&id) else { continue };
+                
+                results.latencies_us.push(received.duration_since(sent).as_micros() as u64);
+                *results.response_codes.entry(ResponseCode::from(0, buf[3] & 0x0F)).or_insert(0) += 1;
+            }
+        })
+    };
+    
+    let started = Instant::now();
+    let mut sent: u64 = 0;
+    let mut id: u16 = 0;
+    let mut next_query = worker;
+    
+    while started.elapsed() < duration {
+        let due = (started.elapsed().as_secs_f64() * qps) as u64;
+        while sent < due {
+            let mut packet = queries[next_query % queries.len()].clone();
+            packet[..2].copy_from_slice(&id.to_be_bytes());
+            
+            inflight.lock().unwrap().insert(id, Instant::now());
+            if socket.send(&packet).await.is_err() {
+                inflight.lock().unwrap().remove(&id);
+                send_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            
+            sent += 1;
+            id = id.wrapping_add(1);
+            next_query += SOCKETS;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    
+    // Give in-flight queries until the timeout to come back
+    tokio::time::sleep(timeout).await;
+    receiver.abort();
+    
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sent = sent;
+    results
+}
+
+fn encode_queries(qnames: &[String], record_type: RecordType) -> Result<Arc<Vec<Vec<u8>>>> {
+    let mut queries = Vec::with_capacity(qnames.len());
+    for qname in qnames {
+        let mut message = Message::new();
+        message.set_message_type(MessageType::Query);
+        message.set_op_code(OpCode::Query);
+        message.add_query(Query::query(Name::from_ascii(qname)?, record_type));
+        queries.push(message.to_bytes()?);
+    }
+    Ok(Arc::new(queries))
+}
+
+fn report(results: &SocketResults, send_errors: u64, elapsed: Duration) {
+    let received = results.latencies_us.len() as u64;
+    let lost = results.sent.saturating_sub(received + send_errors);
+    let pct = |n: u64| if results.sent == 0 { 0.0 } else { n as f64 * 100.0 / results.sent as f64 };
+    
+    println!();
+    println!("sent        {} ({:.0} qps achieved)", results.sent, results.sent as f64 / elapsed.as_secs_f64());
+    println!("received    {} ({:.2}%)", received, pct(received));
+    println!("timed out   {} ({:.2}%)", lost, pct(lost));
+    println!("send errors {} ({:.2}%)", send_errors, pct(send_errors));
+    if results.malformed > 0 {
+        println!("malformed   {}", results.malformed);
+    }
+    
+    let mut codes: Vec<_> = results.response_codes.iter().collect();
+    codes.sort_by(|a, b| b.1.cmp(a.1));
+    for (code, count) in codes {
+        println!("rcode {:<10} {} ({:.2}%)", code.to_string(), count, pct(*count));
+    }
+    
+    if received == 0 {
+        return;
+    }
+    
+    let mut latencies = results.latencies_us.clone();
+    latencies.sort_unstable();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize];
+    
+    println!();
+    println!("latency p50   {:>8.3} ms", percentile(0.50) as f64 / 1000.0);
+    println!("latency p90   {:>8.3} ms", percentile(0.90) as f64 / 1000.0);
+    println!("latency p99   {:>8.3} ms", percentile(0.99) as f64 / 1000.0);
+    println!("latency p99.9 {:>8.3} ms", percentile(0.999) as f64 / 1000.0);
+    println!("latency max   {:>8.3} ms", *latencies.last().unwrap() as f64 / 1000.0);
+}
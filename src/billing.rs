@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// How often Supabase payment statuses are checked.
+pub const PAYMENT_CHECK_INTERVAL_SECONDS: u64 = 600;
+/// The Supabase `payment_status` of a paid-up domain.
+const PAID_STATUS: &str = "succeeded";
+
+/// A domain whose Supabase `payment_status` is not `succeeded`, as stored in `billing_holds`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BillingHold {
+    pub domain: String,
+    /// The last unpaid status seen, e.g. `failed` or `pending`.
+    pub payment_status: String,
+    /// When the domain was first seen unpaid; the grace window runs from here.
+    pub unpaid_since: DateTime<Utc>,
+    /// When its MX was switched to the payment-required host; `None` during the grace window.
+    pub paused_at: Option<DateTime<Utc>>,
+}
+
+/// What one payment check changed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PaymentCheckReport {
+    /// Domains newly seen unpaid, whose grace window starts now.
+    pub unpaid: Vec<String>,
+    /// Domains whose grace window ran out, now answered with the payment-required MX.
+    pub paused: Vec<String>,
+    /// Paused domains that were paid for, now answered as before.
+    pub resumed: Vec<String>,
+    /// Holds dropped because payment resumed inside the grace window or the domain is gone.
+    pub cleared: Vec<String>,
+}
+
+pub fn is_paid(payment_status: &str) -> bool {
+    payment_status.eq_ignore_ascii_case(PAID_STATUS)
+}
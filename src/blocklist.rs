@@ -0,0 +1,75 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    /// Blocks exactly the pattern.
+    #[default]
+    Exact,
+    /// Blocks the pattern and every name below it, e.g. `gov` blocks all of `.gov`.
+    Suffix,
+}
+
+impl BlockKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockKind::Exact => "exact",
+            BlockKind::Suffix => "suffix",
+        }
+    }
+}
+
+impl std::str::FromStr for BlockKind {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact" => Ok(BlockKind::Exact),
+            "suffix" => Ok(BlockKind::Suffix),
+            other => Err(anyhow::anyhow!("Unknown block kind: {}", other)),
+        }
+    }
+}
+
+/// An entry of the `blocked_domains` registry.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockedDomain {
+    pub id: i64,
+    pub pattern: String,
+    pub kind: BlockKind,
+    pub reason: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BlockedDomain {
+    /// Whether the entry covers `domain`; suffixes only match on label boundaries.
+    pub fn matches(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        match self.kind {
+            BlockKind::Exact => domain == self.pattern,
+            BlockKind::Suffix => domain == self.pattern || domain.ends_with(&format!(".{}", self.pattern)),
+        }
+    }
+}
+
+/// Lowercases a pattern and checks it looks like a domain name or suffix, e.g. `paypal.com` or `gov`.
+pub fn normalize_pattern(pattern: &str) -> Result<String> {
+    let pattern = pattern.trim().trim_start_matches("*.").trim_matches('.').to_lowercase();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if pattern.is_empty() || pattern.len() > 253 || !pattern.split('.').all(valid_label) {
+        return Err(anyhow::anyhow!("Invalid blocked domain pattern: {}", pattern));
+    }
+    Ok(pattern)
+}
+
+/// The first entry that covers `domain`, if any.
+pub fn find_block<'a>(blocked: &'a [BlockedDomain], domain: &str) -> Option<&'a BlockedDomain> {
+    blocked.iter().find(|entry| entry.matches(domain))
+}
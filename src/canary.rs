@@ -0,0 +1,94 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use crate::txt_record;
+
+/// Most canaries a zone can carry at once.
+const MAX_CANARIES: usize = 20;
+/// Record types a canary can replace.
+const CANARY_TYPES: &[&str] = &["A", "AAAA", "MX", "TXT"];
+
+/// New answers for one name, served to `percent`% of client subnets while the rest keep the
+/// current ones, e.g. a mail host's A record moving to a new IP.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Canary {
+    /// Relative to the zone, `@` for the apex.
+    pub name: String,
+    pub percent: u8,
+    /// Replace the answers of their types; types without one here are left alone.
+    pub records: Vec<CanaryRecord>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CanaryRecord {
+    pub record_type: String,
+    pub value: String,
+    /// MX preference.
+    #[serde(default)]
+    pub priority: u16,
+}
+
+impl Canary {
+    fn validate(&self) -> Result<()> {
+        if self.percent > 100 {
+            return Err(anyhow::anyhow!("Canary percent for {} must be between 0 and 100", self.name));
+        }
+        if self.records.is_empty() {
+            return Err(anyhow::anyhow!("Canary for {} has no records", self.name));
+        }
+        for record in &self.records {
+            let value = record.value.trim();
+            let valid = match record.record_type.as_str() {
+                "A" => value.parse::<std::net::Ipv4Addr>().is_ok(),
+                "AAAA" => value.parse::<std::net::Ipv6Addr>().is_ok(),
+                "MX" => !value.is_empty() && !value.contains(char::is_whitespace),
+                "TXT" => txt_record::validate(&record.value).is_ok(),
+                _ => return Err(anyhow::anyhow!("Canaries can only replace {} records", CANARY_TYPES.join(", "))),
+            };
+            if !valid {
+                return Err(anyhow::anyhow!("Invalid {} value for canary {}: {}", record.record_type, self.name, record.value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `client` is in the canary's share. Clients are bucketed by /24 or /48 subnet,
+    /// so one resolver's pool sees the same answer, and the buckets are hashed with the name so
+    /// two canaries do not pick the same clients.
+    pub fn includes(&self, domain: &str, client: IpAddr) -> bool {
+        if self.percent >= 100 {
+            return true;
+        }
+        let subnet = match client {
+            IpAddr::V4(ip) => ip.octets()[..3].to_vec(),
+            IpAddr::V6(ip) => ip.octets()[..6].to_vec(),
+        };
+        let digest = Sha256::new()
+            .chain_update(domain.as_bytes())
+            .chain_update(b"/")
+            .chain_update(self.name.as_bytes())
+            .chain_update(b"/")
+            .chain_update(&subnet)
+            .finalize();
+        let bucket = u16::from_be_bytes([digest[0], digest[1]]) % 100;
+        bucket < u16::from(self.percent)
+    }
+}
+
+/// Checks a zone's canaries before they are stored.
+pub fn validate_canaries(canaries: &[Canary]) -> Result<()> {
+    if canaries.is_empty() || canaries.len() > MAX_CANARIES {
+        return Err(anyhow::anyhow!("Between 1 and {} canaries are required", MAX_CANARIES));
+    }
+    let mut names = HashSet::new();
+    for canary in canaries {
+        if !names.insert(canary.name.to_lowercase()) {
+            return Err(anyhow::anyhow!("{} has more than one canary", canary.name));
+        }
+        canary.validate()?;
+    }
+    Ok(())
+}
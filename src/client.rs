@@ -0,0 +1,309 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::dns_server::DomainStats;
+use crate::domain_manager::{DomainRecord, VerificationOutcome};
+use crate::node_stats::FleetStats;
+use crate::request_id::REQUEST_ID_HEADER;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A non-2xx answer from the management API, with its `{"error": ...}` message. Returned
+/// inside the `anyhow::Error`, so callers can `downcast_ref` it to branch on the status.
+#[derive(Debug, Clone)]
+pub struct ApiFailure {
+    pub status: StatusCode,
+    pub message: String,
+    /// From `Retry-After` on a `429`.
+    pub retry_after: Option<Duration>,
+    /// The server's `X-Request-Id` for the call, to find it in the server's logs.
+    pub request_id: Option<String>,
+}
+
+impl std::fmt::Display for ApiFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API returned {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiFailure {}
+
+/// `GET /health`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Health {
+    /// `healthy`, or `degraded` while the database is unavailable.
+    pub status: String,
+    pub unavailable_since: Option<DateTime<Utc>>,
+    pub queued_writes: Option<usize>,
+}
+
+/// `GET /stats`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub domains: DomainStats,
+    /// Every node's query counters, with `stats_push_enabled`.
+    pub queries: Option<FleetStats>,
+}
+
+/// Body of `POST /domains`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NewDomain {
+    pub domain: String,
+    pub ip: String,
+    pub discord: bool,
+    pub subdomain: bool,
+    pub routing_profile: Option<String>,
+}
+
+/// What a records API call did. With `approval_required` the change is only queued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordsUpdate {
+    Applied,
+    PendingApproval { id: i64 },
+}
+
+/// Typed client for the management API, for services that drive the DNS server.
+pub struct CybertempDnsClient {
+    http: reqwest::Client,
+    base_url: Url,
+    api_key: Option<String>,
+    actor: Option<String>,
+}
+
+impl CybertempDnsClient {
+    /// `base_url` is where the API listens, e.g. `http://127.0.0.1:8080`.
+    pub fn new(base_url: &str) -> Result<Self> {
+        let base_url = Url::parse(base_url)?;
+        if base_url.cannot_be_a_base() {
+            return Err(anyhow::anyhow!("Invalid API base URL: {}", base_url));
+        }
+        let http = reqwest::Client::builder().timeout(DEFAULT_TIMEOUT).build()?;
+        Ok(Self { http, base_url, api_key: None, actor: None })
+    }
+
+    /// Bearer token sent with every request; needed for endpoints that require a key from
+    /// `api_keys`, and for record changes with `approval_required`.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// `X-Actor` sent with every request, which changes are attributed to.
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Uses `http` instead of a client of its own, e.g. to share a connection pool.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub async fn health(&self) -> Result<Health> {
+        self.send_json(self.request(Method::GET, &["health"])?).await
+    }
+
+    pub async fn stats(&self) -> Result<Stats> {
+        self.send_json(self.request(Method::GET, &["stats"])?).await
+    }
+
+    pub async fn list_domains(&self) -> Result<Vec<String>> {
+        self.send_json(self.request(Method::GET, &["domains"])?).await
+    }
+
+    /// The domain, or `None` if it is not served.
+    pub async fn get_domain(&self, domain: &str) -> Result<Option<DomainRecord>> {
+        match self.send(self.request(Method::GET, &["domains", domain])?).await {
+            Ok(response) => Ok(Some(response.json().await?)),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds a domain. Retries with the same `idempotency_key` are not applied twice; returns
+    /// false for such a replay.
+    pub async fn add_domain(&self, domain: &NewDomain, idempotency_key: Option<&str>) -> Result<bool> {
+        let mut request = self.request(Method::POST, &["domains"])?.json(domain);
+        if let Some(key) = idempotency_key {
+            request = request.header("Idempotency-Key", key);
+        }
+        let body: Value = self.send_json(request).await?;
+        Ok(!body["replayed"].as_bool().unwrap_or(false))
+    }
+
+    pub async fn remove_domain(&self, domain: &str) -> Result<()> {
+        self.send(self.request(Method::DELETE, &["domains", domain])?).await.map(|_| ())
+    }
+
+    pub async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()> {
+        let action = if enabled { "enable" } else { "disable" };
+        self.send(self.request(Method::POST, &["domains", domain, action])?).await.map(|_| ())
+    }
+
+    pub async fn set_domain_ip(&self, domain: &str, ip: &str) -> Result<()> {
+        let request = self.request(Method::PUT, &["domains", domain, "ip"])?.json(&json!({"ip": ip}));
+        self.send(request).await.map(|_| ())
+    }
+
+    /// Replaces the `record_type` records at `name`, relative to the domain (`@` for the apex).
+    /// Values are in zone-file form, e.g. `10 mx1.example.net` for MX.
+    pub async fn set_records(&self, domain: &str, record_type: &str, name: &str, values: &[String], ttl: Option<u32>) -> Result<RecordsUpdate> {
+        let request = self.request(Method::PUT, &["domains", domain, "records", record_type, name])?
+            .json(&json!({"values": values, "ttl": ttl}));
+        self.send_records_update(request).await
+    }
+
+    pub async fn remove_records(&self, domain: &str, record_type: &str, name: &str) -> Result<RecordsUpdate> {
+        self.send_records_update(self.request(Method::DELETE, &["domains", domain, "records", record_type, name])?).await
+    }
+
+    pub async fn set_txt_records(&self, domain: &str, name: &str, values: &[String], ttl: Option<u32>) -> Result<RecordsUpdate> {
+        self.set_records(domain, "TXT", name, values, ttl).await
+    }
+
+    pub async fn remove_txt_records(&self, domain: &str, name: &str) -> Result<RecordsUpdate> {
+        self.remove_records(domain, "TXT", name).await
+    }
+
+    pub async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: u32) -> Result<RecordsUpdate> {
+        let request = self.request(Method::PATCH, &["domains", domain, "records", record_type, name])?
+            .json(&json!({"ttl": ttl}));
+        self.send_records_update(request).await
+    }
+
+    /// Checks the domain's delegation now, returning whether it is verified.
+    pub async fn verify_domain(&self, domain: &str) -> Result<bool> {
+        let body: Value = self.send_json(self.request(Method::POST, &["verify", domain])?).await?;
+        Ok(body["verified"].as_bool().unwrap_or(false))
+    }
+
+    pub async fn verify_domains(&self, domains: &[String]) -> Result<Vec<VerificationOutcome>> {
+        self.verify(json!({"domains": domains})).await
+    }
+
+    /// Checks every domain that is not verified yet.
+    pub async fn verify_pending(&self) -> Result<Vec<VerificationOutcome>> {
+        self.verify(json!({"all_pending": true})).await
+    }
+
+    async fn verify(&self, body: Value) -> Result<Vec<VerificationOutcome>> {
+        let mut body: Value = self.send_json(self.request(Method::POST, &["verify"])?.json(&body)).await?;
+        Ok(serde_json::from_value(body["results"].take())?)
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> Result<RequestBuilder> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid API base URL: {}", self.base_url))?
+            .pop_if_empty()
+            .extend(segments);
+
+        let mut request = self.http.request(method, url);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        if let Some(actor) = &self.actor {
+            request = request.header("X-Actor", actor);
+        }
+        Ok(request)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        let request_id = response.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        let message = body["error"].as_str().unwrap_or_else(|| status.canonical_reason().unwrap_or("")).to_string();
+        Err(ApiFailure { status, message, retry_after, request_id }.into())
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    async fn send_records_update(&self, request: RequestBuilder) -> Result<RecordsUpdate> {
+        let response = self.send(request).await?;
+        if response.status() != StatusCode::ACCEPTED {
+            return Ok(RecordsUpdate::Applied);
+        }
+        let body: Value = response.json().await?;
+        let id = body["id"].as_i64().ok_or_else(|| anyhow::anyhow!("Queued change without an id"))?;
+        Ok(RecordsUpdate::PendingApproval { id })
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ApiFailure>().is_some_and(|failure| failure.status == StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{get, put};
+    use axum::{Json, Router};
+
+    /// Serves `router` on an ephemeral loopback port and returns a client for it.
+    fn client_for(router: Router) -> CybertempDnsClient {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(router.into_make_service());
+        tokio::spawn(server);
+        CybertempDnsClient::new(&format!("http://{}", addr)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failures_carry_retry_after_and_request_id() {
+        let client = client_for(Router::new().route("/health", get(|| async {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("retry-after", "30"), (REQUEST_ID_HEADER, "req-1")],
+                Json(json!({"error": "Rate limit exceeded"})),
+            )
+        })));
+
+        let error = client.health().await.unwrap_err();
+        let failure = error.downcast_ref::<ApiFailure>().unwrap();
+        assert_eq!(failure.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(failure.message, "Rate limit exceeded");
+        assert_eq!(failure.retry_after, Some(Duration::from_secs(30)));
+        assert_eq!(failure.request_id.as_deref(), Some("req-1"));
+    }
+
+    #[tokio::test]
+    async fn accepted_record_changes_are_pending_approval() {
+        let client = client_for(Router::new().route("/domains/:domain/records/:record_type/:name", put(|| async {
+            (StatusCode::ACCEPTED, Json(json!({"status": "pending_approval", "id": 42})))
+        })));
+
+        let update = client.set_txt_records("example.com", "@", &["v=spf1 -all".to_string()], None).await.unwrap();
+        assert_eq!(update, RecordsUpdate::PendingApproval { id: 42 });
+    }
+
+    #[tokio::test]
+    async fn unknown_domains_are_none() {
+        let client = client_for(Router::new().route("/domains/:domain", get(|| async {
+            (StatusCode::NOT_FOUND, Json(json!({"error": "Domain not found"})))
+        })));
+
+        assert!(client.get_domain("example.com").await.unwrap().is_none());
+    }
+}
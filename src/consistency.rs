@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use trust_dns_proto::rr::{RData, RecordType};
+
+use crate::dns_handler::CybertempHandler;
+use crate::domain_manager::{DomainRecord, VerificationStatus};
+
+/// Why an MX or NS target we hand out cannot be resolved from our own answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetProblem {
+    /// Outside every zone we serve, so its address depends on someone else's DNS.
+    OutOfBailiwick,
+    /// Inside one of our zones, but we answer neither A nor AAAA for it.
+    NoAddress,
+}
+
+/// One broken MX/NS target and the domains whose answers point at it.
+#[derive(Debug, Clone, Serialize, Deserialize, async_graphql::SimpleObject)]
+pub struct Misconfiguration {
+    pub record_type: String,
+    pub target: String,
+    pub problem: TargetProblem,
+    pub domains: Vec<String>,
+}
+
+/// Asks `handler` for the MX and NS answers of every served domain, then checks each target
+/// is in our bailiwick and resolves to an address through the same handler.
+pub async fn check_targets(handler: &CybertempHandler, domains: &[DomainRecord]) -> Vec<Misconfiguration> {
+    let mut problems: BTreeMap<(String, String), Option<TargetProblem>> = BTreeMap::new();
+    let mut affected: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+    
+    let served = domains.iter()
+        .filter(|d| d.enabled && d.verification_status == VerificationStatus::Verified);
+    for domain in served {
+        // A coexistence profile's MX hosts belong to the customer's other provider, not us
+        let external_mx: Vec<String> = domain.mail_routing()
+            .map(|routing| routing.mx.iter().map(|mx| mx.host.trim_end_matches('.').to_lowercase()).collect())
+            .unwrap_or_default();
+        
+        for record_type in [RecordType::MX, RecordType::NS] {
+            let Ok(response) = handler.lookup(&domain.domain, record_type).await else { continue };
+            let targets: Vec<String> = response.answers().iter()
+                .filter_map(|record| match record.data() {
+                    Some(RData::MX(mx)) => Some(mx.exchange().to_ascii()),
+                    Some(RData::NS(ns)) => Some(ns.0.to_ascii()),
+                    _ => None,
+                })
+                .map(|target| target.trim_end_matches('.').to_lowercase())
+                .filter(|target| record_type != RecordType::MX || !external_mx.contains(target))
+                .collect();
+            
+            for target in targets {
+                let key = (record_type.to_string(), target);
+                if !problems.contains_key(&key) {
+                    let problem = check_target(handler, &key.1).await;
+                    problems.insert(key.clone(), problem);
+                }
+                if problems[&key].is_some() {
+                    let domains = affected.entry(key).or_default();
+                    if !domains.contains(&domain.domain) {
+                        domains.push(domain.domain.clone());
+                    }
+                }
+            }
+        }
+    }
+    
+    affected.into_iter()
+        .map(|((record_type, target), mut domains)| {
+            domains.sort();
+            Misconfiguration {
+                problem: problems[&(record_type.clone(), target.clone())].unwrap(),
+                record_type,
+                target,
+                domains,
+            }
+        })
+        .collect()
+}
+
+pub(crate) async fn check_target(handler: &CybertempHandler, target: &str) -> Option<TargetProblem> {
+    if !handler.is_in_bailiwick(target).await {
+        return Some(TargetProblem::OutOfBailiwick);
+    }
+    
+    for record_type in [RecordType::A, RecordType::AAAA] {
+        if let Ok(response) = handler.lookup(target, record_type).await {
+            if !response.answers().is_empty() {
+                return None;
+            }
+        }
+    }
+    Some(TargetProblem::NoAddress)
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::database::DnsRecord;
+
+/// crt.sh can take a while for domains with many certificates.
+const CT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// CAA `issue` identifiers of common CAs, and text their certificates' issuer names contain.
+const KNOWN_CAS: &[(&str, &str)] = &[
+    ("letsencrypt.org", "Let's Encrypt"),
+    ("pki.goog", "Google Trust Services"),
+    ("digicert.com", "DigiCert"),
+    ("sectigo.com", "Sectigo"),
+    ("comodoca.com", "Sectigo"),
+    ("zerossl.com", "ZeroSSL"),
+    ("amazon.com", "Amazon"),
+    ("amazontrust.com", "Amazon"),
+    ("globalsign.com", "GlobalSign"),
+    ("buypass.com", "Buypass"),
+    ("ssl.com", "SSL.com"),
+];
+
+/// A certificate as crt.sh lists it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CtEntry {
+    pub id: i64,
+    pub issuer_name: String,
+    pub common_name: Option<String>,
+    /// Every name on the certificate, newline-separated.
+    pub name_value: String,
+    pub serial_number: String,
+    pub not_before: NaiveDateTime,
+    pub not_after: NaiveDateTime,
+}
+
+/// A certificate seen for a hosted domain, as stored in `ct_findings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CtFinding {
+    pub domain: String,
+    /// crt.sh ID of the first log entry seen for the certificate.
+    pub cert_id: i64,
+    pub issuer: String,
+    pub serial_number: String,
+    pub names: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// `None` when neither `ct_expected_issuers` nor the domain's CAA records name a CA.
+    pub expected: Option<bool>,
+    pub seen_at: DateTime<Utc>,
+}
+
+/// Certificates crt.sh has for `domain` and its subdomains that have not expired. Precertificates
+/// and their final certificates share a serial number and come back once.
+pub async fn fetch_certificates(client: &reqwest::Client, base_url: &str, domain: &str) -> Result<Vec<CtEntry>> {
+    let url = format!("{}/", base_url.trim_end_matches('/'));
+    let response = client
+        .get(&url)
+        .query(&[("q", domain), ("output", "json"), ("exclude", "expired")])
+        .timeout(CT_LOOKUP_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("CT lookup for {} returned {}", domain, response.status()));
+    }
+    let mut entries: Vec<CtEntry> = response.json().await?;
+
+    let suffix = format!(".{}", domain);
+    entries.retain(|entry| names(entry).iter().any(|name| name == domain || name.ends_with(&suffix)));
+    entries.sort_by_key(|entry| entry.id);
+    let mut seen = HashSet::new();
+    entries.retain(|entry| seen.insert((entry.issuer_name.clone(), entry.serial_number.clone())));
+    Ok(entries)
+}
+
+/// The names on a certificate, lowercased and deduplicated.
+pub fn names(entry: &CtEntry) -> Vec<String> {
+    let mut names: Vec<String> = entry.name_value.lines()
+        .chain(entry.common_name.as_deref())
+        .map(|name| name.trim().trim_end_matches('.').to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Issuer name fragments the domain's certificates may carry: `ct_expected_issuers`, plus the
+/// known CAs its apex CAA `issue` and `issuewild` records allow.
+pub fn allowed_issuers(records: &[DnsRecord], configured: &[String]) -> Vec<String> {
+    let mut allowed = configured.to_vec();
+    let caa = records.iter()
+        .filter(|r| r.enabled && r.record_type == "CAA" && r.name.trim_end_matches('.') == "@")
+        .filter_map(|r| caa_issuer(&r.value));
+    for issuer in caa {
+        match KNOWN_CAS.iter().find(|(id, _)| *id == issuer) {
+            Some((_, name)) => allowed.push(name.to_string()),
+            None => allowed.push(issuer),
+        }
+    }
+    allowed
+}
+
+/// The CA identifier of a CAA record such as `0 issue "letsencrypt.org; validationmethods=dns-01"`.
+fn caa_issuer(value: &str) -> Option<String> {
+    let mut parts = value.split_whitespace();
+    let _flags = parts.next()?;
+    let tag = parts.next()?;
+    if !tag.eq_ignore_ascii_case("issue") && !tag.eq_ignore_ascii_case("issuewild") {
+        return None;
+    }
+    let issuer = parts.collect::<Vec<_>>().join(" ");
+    let issuer = issuer.trim_matches('"').split(';').next()?.trim().to_ascii_lowercase();
+    (!issuer.is_empty()).then_some(issuer)
+}
+
+/// Whether `issuer` contains one of `allowed`, or `None` with nothing to compare against.
+pub fn is_expected(issuer: &str, allowed: &[String]) -> Option<bool> {
+    if allowed.is_empty() {
+        return None;
+    }
+    let issuer = issuer.to_lowercase();
+    Some(allowed.iter().any(|fragment| issuer.contains(&fragment.to_lowercase())))
+}
+
+impl CtFinding {
+    pub fn new(domain: &str, entry: &CtEntry, allowed: &[String]) -> Self {
+        Self {
+            domain: domain.to_string(),
+            cert_id: entry.id,
+            issuer: entry.issuer_name.clone(),
+            serial_number: entry.serial_number.clone(),
+            names: names(entry),
+            not_before: entry.not_before.and_utc(),
+            not_after: entry.not_after.and_utc(),
+            expected: is_expected(&entry.issuer_name, allowed),
+            seen_at: Utc::now(),
+        }
+    }
+}
@@ -4,6 +4,26 @@ use sqlx::{postgres::PgPoolOptions, Pool, Postgres, Row};
 use chrono::{DateTime, Utc};
 use tracing::info;
 
+use crate::abuse::{AbuseCase, AbuseEvent, AbuseState};
+use crate::answer_override::{AnswerOverride, NewOverride};
+use crate::approval::{ApprovalStatus, PendingChange, RecordChange};
+use crate::billing::BillingHold;
+use crate::blocklist::{BlockKind, BlockedDomain};
+use crate::config::SerialStrategy;
+use crate::ct_monitor::CtFinding;
+use crate::domain_name::domain_key;
+use crate::ip_migration::IpMigration;
+use crate::node_registry::{NodeInfo, NodeRegistration};
+use crate::node_stats::NodeReport;
+use crate::outbox::{Channel, OutboxMessage};
+use crate::record_templates::{RecordTemplate, TemplateRecord};
+use crate::request_id::current_request_id;
+use crate::scheduler::ZoneMutation;
+use crate::stats_history::{QueryCount, QueryTotal};
+use crate::transfer::{DomainTransfer, TransferStatus};
+use crate::transport_stats::TransportBreakdown;
+use crate::zone_import::ImportedRecord;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Domain {
     pub id: String,
@@ -20,10 +40,80 @@ pub struct Domain {
     pub discord: bool,
     pub description: Option<String>,
     pub tags: Option<Vec<String>>,
+    pub soa_serial: i64,
+    pub uptime_30d: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DnsRecord {
+    pub id: String,
+    pub domain: String,
+    pub record_type: String,
+    pub name: String,
+    pub value: String,
+    pub ttl: i32,
+    pub priority: i32,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DomainEvent {
+    pub id: i64,
+    pub domain: String,
+    pub event_type: String,
+    pub enabled: bool,
+    pub verified: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ZoneChange {
+    pub id: i64,
+    pub operation: String,
+    pub record_type: String,
+    pub name: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub actor: String,
+    /// `X-Request-Id` of the API request, or ID of the job run, that made the change.
+    pub request_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduledChange {
+    pub id: i64,
+    pub domain: String,
+    pub mutation: ZoneMutation,
+    pub effective_at: DateTime<Utc>,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VerificationEntry {
+    pub domain: String,
+    pub status: String,
+    pub nameservers: Vec<String>,
+    pub resolver_results: Vec<ResolverCheck>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// One resolver's answer during a verification check.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResolverCheck {
+    pub resolver: String,
+    /// Whether the NS set includes the expected nameservers; `None` when the lookup failed.
+    pub delegated: Option<bool>,
+    pub nameservers: Vec<String>,
+    pub error: Option<String>,
 }
 
 pub struct Database {
     pool: Pool<Postgres>,
+    serial_strategy: SerialStrategy,
 }
 
 impl Database {
@@ -32,9 +122,28 @@ impl Database {
             .max_connections(5)
             .connect(database_url)
             .await?;
-            
+        
         info!("Connected to PostgreSQL database");
-        Ok(Self { pool })
+        Ok(Self { pool, serial_strategy: SerialStrategy::default() })
+    }
+    
+    /// A pool that connects on first use, for starting while PostgreSQL is unreachable.
+    pub fn connect_lazy(database_url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_lazy(database_url)?;
+        Ok(Self { pool, serial_strategy: SerialStrategy::default() })
+    }
+    
+    /// How SOA serials advance on record changes made through this connection pool.
+    pub fn with_serial_strategy(mut self, strategy: SerialStrategy) -> Self {
+        self.serial_strategy = strategy;
+        self
+    }
+    
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
     }
     
     pub async fn get_all_domains(&self) -> Result<Vec<Domain>> {
@@ -54,9 +163,11 @@ impl Database {
                 updated_at,
                 discord,
                 description,
-                tags
+                tags,
+                soa_serial,
+                uptime_30d
             FROM domains 
-            WHERE enabled = true
+            WHERE deleted_at IS NULL
             ORDER BY domain
             "#
         )
@@ -78,6 +189,8 @@ impl Database {
             discord: row.get("discord"),
             description: row.get("description"),
             tags: row.get("tags"),
+            soa_serial: row.get("soa_serial"),
+            uptime_30d: row.get("uptime_30d"),
         }).collect();
         
         Ok(domains)
@@ -100,12 +213,14 @@ impl Database {
                 updated_at,
                 discord,
                 description,
-                tags
+                tags,
+                soa_serial,
+                uptime_30d
             FROM domains 
-            WHERE domain = $1 AND enabled = true
+            WHERE domain = $1 AND deleted_at IS NULL
             "#
         )
-        .bind(domain_name.to_lowercase())
+        .bind(domain_key(domain_name))
         .fetch_optional(&self.pool)
         .await?;
         
@@ -124,21 +239,130 @@ impl Database {
             discord: row.get("discord"),
             description: row.get("description"),
             tags: row.get("tags"),
+            soa_serial: row.get("soa_serial"),
+            uptime_30d: row.get("uptime_30d"),
         });
         
         Ok(domain)
     }
     
+    /// Renames rows not stored in canonical form (see `domain_name`), including internationalized
+    /// names, which the migrations cannot convert, and the name in the tables that refer to
+    /// domains by name. A row whose canonical name another row already has would fail every
+    /// update under the `domains_domain_canonical` check, so it is merged into that row and
+    /// returned: record sets the kept row lacks move over, and the row is removed under the
+    /// name `<id>.duplicate.invalid` with whatever records are left on it.
+    pub async fn canonicalize_domain_names(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT domain FROM domains ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+        let mut taken: std::collections::HashSet<String> = rows.iter().map(|row| row.get("domain")).collect();
+        
+        let mut merged = Vec::new();
+        for row in rows {
+            let domain: String = row.get("domain");
+            let canonical = domain_key(&domain);
+            if canonical == domain {
+                continue;
+            }
+            
+            let mut tx = self.pool.begin().await?;
+            if taken.insert(canonical.clone()) {
+                sqlx::query("UPDATE domains SET domain = $2, updated_at = NOW() WHERE domain = $1")
+                    .bind(&domain)
+                    .bind(&canonical)
+                    .execute(&mut *tx)
+                    .await?;
+                info!("Renamed domain {} to {}", domain, canonical);
+            } else {
+                // Renamed first, so the record moves below can bump its serial
+                let id: String = sqlx::query(
+                    r#"
+                    UPDATE domains
+                    SET domain = id::text || '.duplicate.invalid', enabled = false,
+                        deleted_at = COALESCE(deleted_at, NOW()), updated_at = NOW()
+                    WHERE domain = $1
+                    RETURNING id::text as id
+                    "#
+                )
+                .bind(&domain)
+                .fetch_one(&mut *tx)
+                .await?
+                .get("id");
+                
+                sqlx::query(
+                    r#"
+                    UPDATE dns_records r SET domain_id = keep.id
+                    FROM domains keep
+                    WHERE keep.domain = $2 AND r.domain_id = $1::uuid
+                      AND NOT EXISTS (
+                          SELECT 1 FROM dns_records k
+                          WHERE k.domain_id = keep.id AND k.record_type = r.record_type AND k.name = r.name
+                      )
+                    "#
+                )
+                .bind(&id)
+                .bind(&canonical)
+                .execute(&mut *tx)
+                .await?;
+                merged.push(domain.clone());
+            }
+            
+            Self::rename_domain_references(&mut tx, &domain, &canonical).await?;
+            tx.commit().await?;
+        }
+        
+        Ok(merged)
+    }
+    
+    /// Moves the rows of tables that refer to a domain by name from one spelling to another.
+    /// Where a table keyed by name already has a row for `to`, that row is kept; query counts
+    /// of the same bucket are added up.
+    async fn rename_domain_references(tx: &mut sqlx::Transaction<'_, Postgres>, from: &str, to: &str) -> Result<()> {
+        for table in ["domain_events", "verification_history", "abuse_events", "idempotency_keys", "domain_transfers"] {
+            let sql = format!("UPDATE {} SET domain = $2 WHERE domain = $1", table);
+            sqlx::query(&sql).bind(from).bind(to).execute(&mut **tx).await?;
+        }
+        
+        for table in ["domain_abuse", "billing_holds"] {
+            let sql = format!(
+                "UPDATE {table} SET domain = $2 WHERE domain = $1 AND NOT EXISTS (SELECT 1 FROM {table} WHERE domain = $2)"
+            );
+            sqlx::query(&sql).bind(from).bind(to).execute(&mut **tx).await?;
+            let sql = format!("DELETE FROM {} WHERE domain = $1", table);
+            sqlx::query(&sql).bind(from).execute(&mut **tx).await?;
+        }
+        
+        for table in ["query_stats_5m", "query_stats_hourly", "query_stats_daily"] {
+            let sql = format!(
+                r#"
+                INSERT INTO {table} (domain, bucket_start, queries, negative)
+                SELECT $2, bucket_start, queries, negative FROM {table} WHERE domain = $1
+                ON CONFLICT (domain, bucket_start) DO UPDATE
+                SET queries = {table}.queries + EXCLUDED.queries, negative = {table}.negative + EXCLUDED.negative
+                "#
+            );
+            sqlx::query(&sql).bind(from).bind(to).execute(&mut **tx).await?;
+            let sql = format!("DELETE FROM {} WHERE domain = $1", table);
+            sqlx::query(&sql).bind(from).execute(&mut **tx).await?;
+        }
+        
+        Ok(())
+    }
+    
     pub async fn add_domain(&self, domain: &str, ip_address: &str, discord: bool) -> Result<()> {
         sqlx::query(
             r#"
             INSERT INTO domains (domain, ip_address, discord)
             VALUES ($1, $2::inet, $3)
             ON CONFLICT (domain) DO UPDATE 
-            SET ip_address = $2::inet, discord = $3, updated_at = NOW()
+            SET ip_address = $2::inet, discord = $3, updated_at = NOW(),
+                -- Adding a removed domain again restores it; a disabled one stays disabled
+                enabled = domains.enabled OR domains.deleted_at IS NOT NULL,
+                deleted_at = NULL
             "#
         )
-        .bind(domain.to_lowercase())
+        .bind(domain_key(domain))
         .bind(ip_address)
         .bind(discord)
         .execute(&self.pool)
@@ -148,15 +372,137 @@ impl Database {
         Ok(())
     }
     
+    /// `add_domain`, committed in one transaction with the idempotency key and, with
+    /// `sync_supabase`, an outbox entry pushing the domain to Supabase. Returns false without
+    /// writing anything if `idempotency_key` was already used for this domain.
+    pub async fn add_domain_transactional(
+        &self,
+        domain: &str,
+        ip_address: &str,
+        discord: bool,
+        idempotency_key: Option<&str>,
+        sync_supabase: bool,
+    ) -> Result<bool> {
+        let domain = domain_key(domain);
+        let mut tx = self.pool.begin().await?;
+        
+        if let Some(key) = idempotency_key {
+            let claimed = sqlx::query(
+                "INSERT INTO idempotency_keys (key, operation, domain) VALUES ($1, 'add_domain', $2) ON CONFLICT (key) DO NOTHING"
+            )
+            .bind(key)
+            .bind(&domain)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() == 1;
+            
+            if !claimed {
+                let row = sqlx::query("SELECT operation, domain FROM idempotency_keys WHERE key = $1")
+                    .bind(key)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                tx.rollback().await?;
+                
+                let operation: String = row.get("operation");
+                let used_for: String = row.get("domain");
+                if operation != "add_domain" || used_for != domain {
+                    return Err(anyhow::anyhow!("Idempotency key {} was already used for {} {}", key, operation, used_for));
+                }
+                return Ok(false);
+            }
+        }
+        
+        sqlx::query(
+            r#"
+            INSERT INTO domains (domain, ip_address, discord)
+            VALUES ($1, $2::inet, $3)
+            ON CONFLICT (domain) DO UPDATE 
+            SET ip_address = $2::inet, discord = $3, updated_at = NOW(),
+                -- Adding a removed domain again restores it; a disabled one stays disabled
+                enabled = domains.enabled OR domains.deleted_at IS NOT NULL,
+                deleted_at = NULL
+            "#
+        )
+        .bind(&domain)
+        .bind(ip_address)
+        .bind(discord)
+        .execute(&mut *tx)
+        .await?;
+        
+        if sync_supabase {
+            sqlx::query("INSERT INTO outbox (channel, target, payload) VALUES ($1, $2, $3::jsonb)")
+                .bind(Channel::Supabase.as_str())
+                .bind(&domain)
+                .bind(serde_json::json!({"domain": domain}).to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        
+        tx.commit().await?;
+        info!("Added/updated domain: {} -> {} (discord: {})", domain, ip_address, discord);
+        Ok(true)
+    }
+    
     pub async fn remove_domain(&self, domain: &str) -> Result<()> {
         sqlx::query(
-            "UPDATE domains SET enabled = false, updated_at = NOW() WHERE domain = $1"
+            "UPDATE domains SET enabled = false, deleted_at = NOW(), updated_at = NOW() WHERE domain = $1"
+        )
+        .bind(domain_key(domain))
+        .execute(&self.pool)
+        .await?;
+        
+        info!("Removed domain: {}", domain);
+        Ok(())
+    }
+    
+    /// Toggles serving for a domain while keeping its row and records intact.
+    pub async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()> {
+        let result = sqlx::query(
+            "UPDATE domains SET enabled = $1, updated_at = NOW() WHERE domain = $2 AND deleted_at IS NULL"
+        )
+        .bind(enabled)
+        .bind(domain_key(domain))
+        .execute(&self.pool)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Domain not found: {}", domain));
+        }
+        
+        info!("{} domain: {}", if enabled { "Enabled" } else { "Disabled" }, domain);
+        Ok(())
+    }
+    
+    /// Points the domain's A record at a new address; bumps the SOA serial like a record change.
+    pub async fn set_domain_ip(&self, domain: &str, ip_address: &str) -> Result<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE domains
+            SET ip_address = $1::inet, soa_serial = next_soa_serial(soa_serial, $3), updated_at = NOW()
+            WHERE domain = $2 AND deleted_at IS NULL
+            "#
         )
-        .bind(domain.to_lowercase())
+        .bind(ip_address)
+        .bind(domain_key(domain))
+        .bind(self.serial_strategy.as_str())
         .execute(&self.pool)
         .await?;
         
-        info!("Disabled domain: {}", domain);
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Domain not found: {}", domain));
+        }
+        
+        info!("Set IP for domain {}: {}", domain, ip_address);
+        Ok(())
+    }
+    
+    /// Sends a domain back through NS verification, e.g. after importing it from another provider.
+    pub async fn mark_pending_verification(&self, domain: &str) -> Result<()> {
+        sqlx::query("UPDATE domains SET verified = false, updated_at = NOW() WHERE domain = $1 AND deleted_at IS NULL")
+            .bind(domain_key(domain))
+            .execute(&self.pool)
+            .await?;
+        
         Ok(())
     }
     
@@ -170,10 +516,1749 @@ impl Database {
         )
         .bind(verified)
         .bind(nameservers)
-        .bind(domain.to_lowercase())
+        .bind(domain_key(domain))
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn get_all_records(&self) -> Result<Vec<DnsRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT 
+                r.id::text as id,
+                d.domain,
+                r.record_type,
+                r.name,
+                r.value,
+                r.ttl,
+                r.priority,
+                r.enabled,
+                r.created_at,
+                r.updated_at
+            FROM dns_records r
+            JOIN domains d ON d.id = r.domain_id
+            WHERE r.enabled = true AND d.deleted_at IS NULL
+            ORDER BY d.domain, r.record_type, r.name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let records = rows.into_iter().map(|row| DnsRecord {
+            id: row.get("id"),
+            domain: row.get("domain"),
+            record_type: row.get("record_type"),
+            name: row.get("name"),
+            value: row.get("value"),
+            ttl: row.get("ttl"),
+            priority: row.get("priority"),
+            enabled: row.get("enabled"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }).collect();
+        
+        Ok(records)
+    }
+    
+    /// Replaces every record of `record_type` named `name` on the domain with a single new value.
+    pub async fn set_record(&self, domain: &str, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32, actor: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        sqlx::query(
+            r#"
+            DELETE FROM dns_records 
+            WHERE domain_id = (SELECT id FROM domains WHERE domain = $1)
+              AND record_type = $2 AND name = $3
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(record_type)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+        
+        let result = sqlx::query(
+            r#"
+            INSERT INTO dns_records (domain_id, record_type, name, value, ttl, priority)
+            SELECT id, $2, $3, $4, $5, $6 FROM domains WHERE domain = $1
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(record_type)
+        .bind(name)
+        .bind(value)
+        .bind(ttl)
+        .bind(priority)
+        .execute(&mut *tx)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Domain not found: {}", domain));
+        }
+        
+        tx.commit().await?;
+        
+        info!("Set {} record {} for domain {}", record_type, name, domain);
+        Ok(())
+    }
+    
+    /// Changes the TTL of every record of `record_type` named `name`. Returns the rows updated.
+    pub async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: i32, actor: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        let result = sqlx::query(
+            r#"
+            UPDATE dns_records SET ttl = $4
+            WHERE domain_id = (SELECT id FROM domains WHERE domain = $1)
+              AND record_type = $2 AND name = $3 AND ttl IS DISTINCT FROM $4
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(record_type)
+        .bind(name)
+        .bind(ttl)
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        
+        info!("Set TTL of {} records {} for domain {} to {}", record_type, name, domain, ttl);
+        Ok(result.rows_affected())
+    }
+    
+    pub async fn delete_records(&self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        sqlx::query(
+            r#"
+            DELETE FROM dns_records 
+            WHERE domain_id = (SELECT id FROM domains WHERE domain = $1)
+              AND record_type = $2 AND name = $3
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(record_type)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        
+        info!("Deleted {} records {} for domain {}", record_type, name, domain);
+        Ok(())
+    }
+    
+    /// Replaces each imported (type, name) set with the imported records, in one transaction.
+    pub async fn import_records(&self, domain: &str, records: &[ImportedRecord], actor: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        let domain_id: String = sqlx::query("SELECT id::text as id FROM domains WHERE domain = $1 AND deleted_at IS NULL")
+            .bind(domain_key(domain))
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?
+            .get("id");
+        
+        let mut replaced: Vec<(&str, &str)> = Vec::new();
+        for record in records {
+            let key = (record.record_type.as_str(), record.name.as_str());
+            if !replaced.contains(&key) {
+                sqlx::query("DELETE FROM dns_records WHERE domain_id = $1::uuid AND record_type = $2 AND name = $3")
+                    .bind(&domain_id)
+                    .bind(key.0)
+                    .bind(key.1)
+                    .execute(&mut *tx)
+                    .await?;
+                replaced.push(key);
+            }
+            
+            sqlx::query(
+                r#"
+                INSERT INTO dns_records (domain_id, record_type, name, value, ttl, priority)
+                VALUES ($1::uuid, $2, $3, $4, $5, $6)
+                "#
+            )
+            .bind(&domain_id)
+            .bind(&record.record_type)
+            .bind(&record.name)
+            .bind(&record.value)
+            .bind(record.ttl)
+            .bind(record.priority)
+            .execute(&mut *tx)
+            .await?;
+        }
+        
+        tx.commit().await?;
+        
+        info!("Imported {} records for domain {}", records.len(), domain);
+        Ok(())
+    }
+    
+    /// Change feed events with an id greater than `since`, oldest first.
+    pub async fn get_domain_events(&self, since: i64, limit: i64) -> Result<Vec<DomainEvent>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, domain, event_type, enabled, verified, created_at
+            FROM domain_events
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+            "#
+        )
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let events = rows.into_iter().map(|row| DomainEvent {
+            id: row.get("id"),
+            domain: row.get("domain"),
+            event_type: row.get("event_type"),
+            enabled: row.get("enabled"),
+            verified: row.get("verified"),
+            created_at: row.get("created_at"),
+        }).collect();
+        
+        Ok(events)
+    }
+    
+    /// Last domain event already fanned out to the outbox. With an empty outbox this is the newest
+    /// event, so enabling notifications does not replay history.
+    pub async fn get_outbox_cursor(&self) -> Result<i64> {
+        let row = sqlx::query(
+            r#"
+            SELECT COALESCE(
+                (SELECT MAX(event_id) FROM outbox),
+                (SELECT MAX(id) FROM domain_events),
+                0
+            ) as cursor
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(row.get("cursor"))
+    }
+    
+    /// Queues a notification; a second enqueue of the same event for the same target is ignored.
+    pub async fn enqueue_notification(&self, channel: Channel, target: &str, payload: &serde_json::Value, event_id: Option<i64>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO outbox (channel, target, payload, event_id)
+            VALUES ($1, $2, $3::jsonb, $4)
+            ON CONFLICT (event_id, channel, target) WHERE event_id IS NOT NULL DO NOTHING
+            "#
+        )
+        .bind(channel.as_str())
+        .bind(target)
+        .bind(payload.to_string())
+        .bind(event_id)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn get_due_notifications(&self, limit: i64) -> Result<Vec<OutboxMessage>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, channel, target, payload::text as payload, event_id, status, attempts,
+                   next_attempt_at, last_error, created_at, delivered_at
+            FROM outbox
+            WHERE status = 'pending' AND next_attempt_at <= NOW()
+            ORDER BY next_attempt_at, id
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Self::outbox_from_rows(rows)
+    }
+    
+    /// Newest first, optionally only one status (`pending`, `delivered` or `dead`).
+    pub async fn get_outbox(&self, status: Option<&str>, limit: i64) -> Result<Vec<OutboxMessage>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, channel, target, payload::text as payload, event_id, status, attempts,
+                   next_attempt_at, last_error, created_at, delivered_at
+            FROM outbox
+            WHERE $1::text IS NULL OR status = $1
+            ORDER BY id DESC
+            LIMIT $2
+            "#
+        )
+        .bind(status)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Self::outbox_from_rows(rows)
+    }
+    
+    fn outbox_from_rows(rows: Vec<sqlx::postgres::PgRow>) -> Result<Vec<OutboxMessage>> {
+        rows.into_iter().map(|row| {
+            let channel: String = row.get("channel");
+            let payload: String = row.get("payload");
+            Ok(OutboxMessage {
+                id: row.get("id"),
+                channel: channel.parse()?,
+                target: row.get("target"),
+                payload: serde_json::from_str(&payload)?,
+                event_id: row.get("event_id"),
+                status: row.get("status"),
+                attempts: row.get("attempts"),
+                next_attempt_at: row.get("next_attempt_at"),
+                last_error: row.get("last_error"),
+                created_at: row.get("created_at"),
+                delivered_at: row.get("delivered_at"),
+            })
+        }).collect()
+    }
+    
+    pub async fn mark_notification_delivered(&self, id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE outbox
+            SET status = 'delivered', attempts = attempts + 1, delivered_at = NOW(), last_error = NULL
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Counts a failed attempt and schedules the next one, or dead-letters the message when `retry_in` is `None`.
+    pub async fn mark_notification_failed(&self, id: i64, error: &str, retry_in: Option<std::time::Duration>) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE outbox
+            SET attempts = attempts + 1,
+                last_error = $2,
+                status = CASE WHEN $3::bigint IS NULL THEN 'dead' ELSE 'pending' END,
+                next_attempt_at = NOW() + make_interval(secs => COALESCE($3::bigint, 0))
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_in.map(|d| d.as_secs() as i64))
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Puts a dead-lettered message back in the queue with a fresh attempt budget.
+    pub async fn retry_notification(&self, id: i64) -> Result<()> {
+        let result = sqlx::query(
+            "UPDATE outbox SET status = 'pending', attempts = 0, next_attempt_at = NOW() WHERE id = $1 AND status = 'dead'"
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No dead-lettered notification with id {}", id));
+        }
+        Ok(())
+    }
+    
+    pub async fn record_verification(&self, domain: &str, status: &str, nameservers: &[String], resolver_results: &[ResolverCheck]) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO verification_history (domain, status, nameservers, resolver_results) VALUES ($1, $2, $3, $4::jsonb)"
+        )
+        .bind(domain_key(domain))
+        .bind(status)
+        .bind(nameservers)
+        .bind(serde_json::to_string(resolver_results)?)
         .execute(&self.pool)
         .await?;
         
         Ok(())
     }
-}
\ No newline at end of file
+    
+    pub async fn set_domain_uptime(&self, domain: &str, uptime: Option<f64>) -> Result<()> {
+        sqlx::query("UPDATE domains SET uptime_30d = $2 WHERE domain = $1 AND deleted_at IS NULL")
+            .bind(domain_key(domain))
+            .bind(uptime)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    /// Most recent verification attempts for a domain, newest first.
+    pub async fn get_verification_history(&self, domain: &str, limit: i64) -> Result<Vec<VerificationEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT domain, status, nameservers, resolver_results::text as resolver_results, checked_at
+            FROM verification_history
+            WHERE domain = $1
+            ORDER BY checked_at DESC
+            LIMIT $2
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let entries = rows.into_iter().map(|row| VerificationEntry {
+            domain: row.get("domain"),
+            status: row.get("status"),
+            nameservers: row.get::<Option<Vec<String>>, _>("nameservers").unwrap_or_default(),
+            resolver_results: row.get::<Option<String>, _>("resolver_results")
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            checked_at: row.get("checked_at"),
+        }).collect();
+        
+        Ok(entries)
+    }
+    
+    /// Attributes record changes in this transaction to `actor` and the current request ID in
+    /// `zone_changes`, and has the trigger bump the SOA serial with the configured strategy.
+    async fn set_actor(&self, tx: &mut sqlx::Transaction<'_, Postgres>, actor: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            SELECT set_config('cybertemp.actor', $1, true),
+                   set_config('cybertemp.serial_strategy', $2, true),
+                   set_config('cybertemp.request_id', $3, true)
+            "#
+        )
+        .bind(actor)
+        .bind(self.serial_strategy.as_str())
+        .bind(current_request_id().unwrap_or_default())
+        .execute(&mut **tx)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn get_domain_records(&self, domain: &str) -> Result<Vec<DnsRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT 
+                r.id::text as id,
+                d.domain,
+                r.record_type,
+                r.name,
+                r.value,
+                r.ttl,
+                r.priority,
+                r.enabled,
+                r.created_at,
+                r.updated_at
+            FROM dns_records r
+            JOIN domains d ON d.id = r.domain_id
+            WHERE d.domain = $1 AND r.enabled = true
+            ORDER BY r.record_type, r.name
+            "#
+        )
+        .bind(domain_key(domain))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let records = rows.into_iter().map(|row| DnsRecord {
+            id: row.get("id"),
+            domain: row.get("domain"),
+            record_type: row.get("record_type"),
+            name: row.get("name"),
+            value: row.get("value"),
+            ttl: row.get("ttl"),
+            priority: row.get("priority"),
+            enabled: row.get("enabled"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }).collect();
+        
+        Ok(records)
+    }
+    
+    pub async fn get_soa_serial(&self, domain: &str) -> Result<i64> {
+        let row = sqlx::query("SELECT soa_serial FROM domains WHERE domain = $1")
+            .bind(domain_key(domain))
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        Ok(row.get("soa_serial"))
+    }
+    
+    /// Record-level changes for a domain, newest first.
+    pub async fn get_zone_changes(&self, domain: &str, limit: i64) -> Result<Vec<ZoneChange>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT c.id, c.operation, c.record_type, c.name,
+                   c.before::text as before, c.after::text as after,
+                   c.actor, c.request_id, c.created_at
+            FROM zone_changes c
+            JOIN domains d ON d.id = c.domain_id
+            WHERE d.domain = $1
+            ORDER BY c.id DESC
+            LIMIT $2
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let changes = rows.into_iter().map(|row| ZoneChange {
+            id: row.get("id"),
+            operation: row.get("operation"),
+            record_type: row.get("record_type"),
+            name: row.get("name"),
+            before: row.get::<Option<String>, _>("before").and_then(|v| serde_json::from_str(&v).ok()),
+            after: row.get::<Option<String>, _>("after").and_then(|v| serde_json::from_str(&v).ok()),
+            actor: row.get("actor"),
+            request_id: row.get("request_id"),
+            created_at: row.get("created_at"),
+        }).collect();
+        
+        Ok(changes)
+    }
+    
+    /// Undoes every record change on the domain made after change `to`, newest first.
+    /// The undo itself is logged as new changes. Returns how many changes were reverted.
+    pub async fn rollback_records(&self, domain: &str, to: i64, actor: &str) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        let target = sqlx::query(
+            r#"
+            SELECT c.domain_id::text as domain_id
+            FROM zone_changes c
+            JOIN domains d ON d.id = c.domain_id
+            WHERE d.domain = $1 AND c.id = $2
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(to)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Change {} not found for domain {}", to, domain))?;
+        let domain_id: String = target.get("domain_id");
+        
+        let changes = sqlx::query(
+            r#"
+            SELECT operation, before::text as before, after::text as after
+            FROM zone_changes
+            WHERE domain_id = $1::uuid AND id > $2
+            ORDER BY id DESC
+            "#
+        )
+        .bind(&domain_id)
+        .bind(to)
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        for change in &changes {
+            let after: Option<String> = change.get("after");
+            let before: Option<String> = change.get("before");
+            
+            if let Some(after) = after {
+                sqlx::query("DELETE FROM dns_records WHERE id = ($1::jsonb->>'id')::uuid")
+                    .bind(after)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            if let Some(before) = before {
+                sqlx::query("INSERT INTO dns_records SELECT * FROM jsonb_populate_record(NULL::dns_records, $1::jsonb)")
+                    .bind(before)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        
+        tx.commit().await?;
+        
+        info!("Rolled back {} record changes for domain {} to change {}", changes.len(), domain, to);
+        Ok(changes.len())
+    }
+    
+    pub async fn add_scheduled_change(&self, domain: &str, mutation: &ZoneMutation, effective_at: DateTime<Utc>, actor: &str) -> Result<i64> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO scheduled_changes (domain_id, mutation, effective_at, actor)
+            SELECT id, $2::jsonb, $3, $4 FROM domains WHERE domain = $1 AND deleted_at IS NULL
+            RETURNING id
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(serde_json::to_string(mutation)?)
+        .bind(effective_at)
+        .bind(actor)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        Ok(row.get("id"))
+    }
+    
+    /// Changes not yet applied, soonest first; all domains when `domain` is `None`.
+    pub async fn get_pending_changes(&self, domain: Option<&str>) -> Result<Vec<ScheduledChange>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT s.id, d.domain, s.mutation::text as mutation, s.effective_at, s.actor, s.created_at
+            FROM scheduled_changes s
+            JOIN domains d ON d.id = s.domain_id
+            WHERE s.applied_at IS NULL AND ($1::text IS NULL OR d.domain = $1)
+            ORDER BY s.effective_at, s.id
+            "#
+        )
+        .bind(domain.map(domain_key))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Self::scheduled_changes_from_rows(rows)
+    }
+    
+    /// Pending changes whose `effective_at` has passed, in the order they should be applied.
+    pub async fn get_due_changes(&self) -> Result<Vec<ScheduledChange>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT s.id, d.domain, s.mutation::text as mutation, s.effective_at, s.actor, s.created_at
+            FROM scheduled_changes s
+            JOIN domains d ON d.id = s.domain_id
+            WHERE s.applied_at IS NULL AND s.effective_at <= NOW() AND d.deleted_at IS NULL
+            ORDER BY s.effective_at, s.id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Self::scheduled_changes_from_rows(rows)
+    }
+    
+    fn scheduled_changes_from_rows(rows: Vec<sqlx::postgres::PgRow>) -> Result<Vec<ScheduledChange>> {
+        rows.into_iter().map(|row| {
+            let mutation: String = row.get("mutation");
+            Ok(ScheduledChange {
+                id: row.get("id"),
+                domain: row.get("domain"),
+                mutation: serde_json::from_str(&mutation)?,
+                effective_at: row.get("effective_at"),
+                actor: row.get("actor"),
+                created_at: row.get("created_at"),
+            })
+        }).collect()
+    }
+    
+    /// Marks a scheduled change as done, keeping the error if applying it failed.
+    pub async fn finish_scheduled_change(&self, id: i64, error: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE scheduled_changes SET applied_at = NOW(), error = $2 WHERE id = $1")
+            .bind(id)
+            .bind(error)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn cancel_scheduled_change(&self, id: i64) -> Result<()> {
+        let result = sqlx::query("DELETE FROM scheduled_changes WHERE id = $1 AND applied_at IS NULL")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No pending scheduled change {}", id));
+        }
+        
+        info!("Cancelled scheduled change {}", id);
+        Ok(())
+    }
+    
+    /// Queues a records API change for approval.
+    pub async fn add_pending_change(&self, domain: &str, change: &RecordChange, actor: &str, submitter_key: &str) -> Result<i64> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO pending_changes (domain_id, change, submitted_by, submitter_key)
+            SELECT id, $2::jsonb, $3, $4 FROM domains WHERE domain = $1 AND deleted_at IS NULL
+            RETURNING id
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(serde_json::to_string(change)?)
+        .bind(actor)
+        .bind(submitter_key)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        Ok(row.get("id"))
+    }
+    
+    /// Changes awaiting approval, oldest first; all domains when `domain` is `None`.
+    pub async fn get_pending_record_changes(&self, domain: Option<&str>) -> Result<Vec<PendingChange>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT p.id, d.domain, p.change::text as change, p.status, p.submitted_by, p.submitter_key,
+                   p.submitted_at, p.reviewed_by, p.reviewed_at, p.error
+            FROM pending_changes p
+            JOIN domains d ON d.id = p.domain_id
+            WHERE p.status = 'pending' AND ($1::text IS NULL OR d.domain = $1)
+            ORDER BY p.submitted_at, p.id
+            "#
+        )
+        .bind(domain.map(domain_key))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.iter().map(Self::pending_change_from_row).collect()
+    }
+    
+    pub async fn get_pending_record_change(&self, id: i64) -> Result<Option<PendingChange>> {
+        let row = sqlx::query(
+            r#"
+            SELECT p.id, d.domain, p.change::text as change, p.status, p.submitted_by, p.submitter_key,
+                   p.submitted_at, p.reviewed_by, p.reviewed_at, p.error
+            FROM pending_changes p
+            JOIN domains d ON d.id = p.domain_id
+            WHERE p.id = $1
+            "#
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        row.as_ref().map(Self::pending_change_from_row).transpose()
+    }
+    
+    /// Moves a pending change to `status`. Only one reviewer can win a change: returns false if
+    /// it was no longer pending.
+    pub async fn review_pending_change(&self, id: i64, status: ApprovalStatus, reviewer: &str, reviewer_key: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE pending_changes
+            SET status = $2, reviewed_by = $3, reviewer_key = $4, reviewed_at = NOW()
+            WHERE id = $1 AND status = 'pending'
+            "#
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .bind(reviewer)
+        .bind(reviewer_key)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected() == 1)
+    }
+    
+    /// Marks an approved change whose application failed.
+    pub async fn fail_pending_change(&self, id: i64, error: &str) -> Result<()> {
+        sqlx::query("UPDATE pending_changes SET status = 'failed', error = $2 WHERE id = $1")
+            .bind(id)
+            .bind(error)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    fn pending_change_from_row(row: &sqlx::postgres::PgRow) -> Result<PendingChange> {
+        let change: String = row.get("change");
+        let status: String = row.get("status");
+        Ok(PendingChange {
+            id: row.get("id"),
+            domain: row.get("domain"),
+            change: serde_json::from_str(&change)?,
+            status: status.parse()?,
+            submitted_by: row.get("submitted_by"),
+            submitter_key: row.get("submitter_key"),
+            submitted_at: row.get("submitted_at"),
+            reviewed_by: row.get("reviewed_by"),
+            reviewed_at: row.get("reviewed_at"),
+            error: row.get("error"),
+        })
+    }
+    
+    /// Stores certificates not seen before for the domain, returning the ones that were new.
+    pub async fn add_ct_findings(&self, domain: &str, findings: &[CtFinding]) -> Result<Vec<CtFinding>> {
+        let mut added = Vec::new();
+        for finding in findings {
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO ct_findings (domain_id, cert_id, issuer, serial_number, names, not_before, not_after, expected, seen_at)
+                SELECT id, $2, $3, $4, $5, $6, $7, $8, $9 FROM domains WHERE domain = $1 AND deleted_at IS NULL
+                ON CONFLICT (domain_id, issuer, serial_number) DO NOTHING
+                RETURNING id
+                "#
+            )
+            .bind(domain_key(domain))
+            .bind(finding.cert_id)
+            .bind(&finding.issuer)
+            .bind(&finding.serial_number)
+            .bind(&finding.names)
+            .bind(finding.not_before)
+            .bind(finding.not_after)
+            .bind(finding.expected)
+            .bind(finding.seen_at)
+            .fetch_optional(&self.pool)
+            .await?;
+            
+            if inserted.is_some() {
+                added.push(finding.clone());
+            }
+        }
+        Ok(added)
+    }
+    
+    /// The domain's certificates, newest first; only unexpected ones with `unexpected_only`.
+    pub async fn get_ct_findings(&self, domain: &str, unexpected_only: bool, limit: i64) -> Result<Vec<CtFinding>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT d.domain, f.cert_id, f.issuer, f.serial_number, f.names, f.not_before, f.not_after, f.expected, f.seen_at
+            FROM ct_findings f
+            JOIN domains d ON d.id = f.domain_id
+            WHERE d.domain = $1 AND (NOT $2 OR f.expected = FALSE)
+            ORDER BY f.seen_at DESC, f.id DESC
+            LIMIT $3
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(unexpected_only)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| CtFinding {
+            domain: row.get("domain"),
+            cert_id: row.get("cert_id"),
+            issuer: row.get("issuer"),
+            serial_number: row.get("serial_number"),
+            names: row.get("names"),
+            not_before: row.get("not_before"),
+            not_after: row.get("not_after"),
+            expected: row.get("expected"),
+            seen_at: row.get("seen_at"),
+        }).collect())
+    }
+    
+    /// Records a CT lookup of the domain, returning whether it had been looked up before.
+    pub async fn mark_ct_checked(&self, domain: &str) -> Result<bool> {
+        let row = sqlx::query(
+            r#"
+            UPDATE domains d SET ct_checked_at = NOW()
+            FROM (SELECT id, ct_checked_at FROM domains WHERE domain = $1 AND deleted_at IS NULL FOR UPDATE) previous
+            WHERE d.id = previous.id
+            RETURNING previous.ct_checked_at IS NOT NULL AS checked_before
+            "#
+        )
+        .bind(domain_key(domain))
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(row.map(|row| row.get("checked_before")).unwrap_or(false))
+    }
+    
+    pub async fn insert_ip_migration(&self, migration: &IpMigration) -> Result<i64> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO ip_migrations (phase, state, actor)
+            VALUES ($1, $2::jsonb, $3)
+            RETURNING id
+            "#
+        )
+        .bind(migration.phase.as_str())
+        .bind(serde_json::to_string(migration)?)
+        .bind(&migration.actor)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(row.get("id"))
+    }
+    
+    pub async fn save_ip_migration(&self, migration: &IpMigration) -> Result<()> {
+        sqlx::query("UPDATE ip_migrations SET phase = $2, state = $3::jsonb, updated_at = NOW() WHERE id = $1")
+            .bind(migration.id)
+            .bind(migration.phase.as_str())
+            .bind(serde_json::to_string(migration)?)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    /// Newest first; only those still in progress when `active_only` is set.
+    pub async fn get_ip_migrations(&self, active_only: bool) -> Result<Vec<IpMigration>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, state::text as state FROM ip_migrations
+            WHERE NOT $1 OR phase NOT IN ('completed', 'cancelled')
+            ORDER BY id DESC
+            "#
+        )
+        .bind(active_only)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.into_iter().map(Self::ip_migration_from_row).collect()
+    }
+    
+    pub async fn get_ip_migration(&self, id: i64) -> Result<Option<IpMigration>> {
+        let row = sqlx::query("SELECT id, state::text as state FROM ip_migrations WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        
+        row.map(Self::ip_migration_from_row).transpose()
+    }
+    
+    fn ip_migration_from_row(row: sqlx::postgres::PgRow) -> Result<IpMigration> {
+        let state: String = row.get("state");
+        let mut migration: IpMigration = serde_json::from_str(&state)?;
+        migration.id = row.get("id");
+        Ok(migration)
+    }
+    
+    /// Creates the template or replaces its records; domains keep the old records until re-applied.
+    pub async fn upsert_template(&self, template: &RecordTemplate) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO record_templates (name, description, records)
+            VALUES ($1, $2, $3::jsonb)
+            ON CONFLICT (name) DO UPDATE
+            SET description = $2, records = $3::jsonb
+            "#
+        )
+        .bind(&template.name)
+        .bind(&template.description)
+        .bind(serde_json::to_string(&template.records)?)
+        .execute(&self.pool)
+        .await?;
+        
+        info!("Saved record template {} ({} records)", template.name, template.records.len());
+        Ok(())
+    }
+    
+    pub async fn get_templates(&self) -> Result<Vec<RecordTemplate>> {
+        let rows = sqlx::query(
+            "SELECT name, description, records::text as records, updated_at FROM record_templates ORDER BY name"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.iter().map(Self::template_from_row).collect()
+    }
+    
+    pub async fn get_template(&self, name: &str) -> Result<Option<RecordTemplate>> {
+        let row = sqlx::query(
+            "SELECT name, description, records::text as records, updated_at FROM record_templates WHERE name = $1"
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        row.as_ref().map(Self::template_from_row).transpose()
+    }
+    
+    fn template_from_row(row: &sqlx::postgres::PgRow) -> Result<RecordTemplate> {
+        let records: String = row.get("records");
+        Ok(RecordTemplate {
+            name: row.get("name"),
+            description: row.get("description"),
+            records: serde_json::from_str(&records)?,
+            updated_at: row.get("updated_at"),
+        })
+    }
+    
+    /// Deletes the template. Records it materialized stay on their domains as plain records.
+    pub async fn delete_template(&self, name: &str) -> Result<()> {
+        let result = sqlx::query("DELETE FROM record_templates WHERE name = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Template not found: {}", name));
+        }
+        
+        info!("Deleted record template {}", name);
+        Ok(())
+    }
+    
+    /// Replaces the records a template previously materialized on the domain with `records`.
+    pub async fn apply_template(&self, domain: &str, template: &str, records: &[TemplateRecord], actor: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        let row = sqlx::query(
+            r#"
+            INSERT INTO domain_templates (domain_id, template_id)
+            SELECT d.id, t.id FROM domains d, record_templates t
+            WHERE d.domain = $1 AND d.deleted_at IS NULL AND t.name = $2
+            ON CONFLICT (domain_id, template_id) DO UPDATE SET applied_at = NOW()
+            RETURNING domain_id::text as domain_id, template_id::text as template_id
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(template)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Domain {} or template {} not found", domain, template))?;
+        let domain_id: String = row.get("domain_id");
+        let template_id: String = row.get("template_id");
+        
+        sqlx::query("DELETE FROM dns_records WHERE domain_id = $1::uuid AND template_id = $2::uuid")
+            .bind(&domain_id)
+            .bind(&template_id)
+            .execute(&mut *tx)
+            .await?;
+        
+        for record in records {
+            sqlx::query(
+                r#"
+                INSERT INTO dns_records (domain_id, record_type, name, value, ttl, priority, template_id)
+                VALUES ($1::uuid, $2, $3, $4, $5, $6, $7::uuid)
+                "#
+            )
+            .bind(&domain_id)
+            .bind(&record.record_type)
+            .bind(&record.name)
+            .bind(&record.value)
+            .bind(record.ttl)
+            .bind(record.priority)
+            .bind(&template_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        
+        tx.commit().await?;
+        
+        info!("Applied template {} to domain {} ({} records)", template, domain, records.len());
+        Ok(())
+    }
+    
+    /// Removes a template's records from the domain and forgets the assignment.
+    pub async fn remove_template(&self, domain: &str, template: &str, actor: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.set_actor(&mut tx, actor).await?;
+        
+        let result = sqlx::query(
+            r#"
+            DELETE FROM domain_templates dt
+            USING domains d, record_templates t
+            WHERE dt.domain_id = d.id AND dt.template_id = t.id AND d.domain = $1 AND t.name = $2
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(template)
+        .execute(&mut *tx)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Template {} is not applied to domain {}", template, domain));
+        }
+        
+        sqlx::query(
+            r#"
+            DELETE FROM dns_records
+            WHERE domain_id = (SELECT id FROM domains WHERE domain = $1)
+              AND template_id = (SELECT id FROM record_templates WHERE name = $2)
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(template)
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        
+        info!("Removed template {} from domain {}", template, domain);
+        Ok(())
+    }
+    
+    /// Domains a template has been applied to.
+    pub async fn get_template_domains(&self, template: &str) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT d.domain
+            FROM domain_templates dt
+            JOIN domains d ON d.id = dt.domain_id
+            JOIN record_templates t ON t.id = dt.template_id
+            WHERE t.name = $1 AND d.deleted_at IS NULL
+            ORDER BY d.domain
+            "#
+        )
+        .bind(template)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| row.get("domain")).collect())
+    }
+    
+    /// Current abuse state of every domain name that has one, including banned names without a domain row.
+    pub async fn get_abuse_cases(&self, state: Option<AbuseState>) -> Result<Vec<AbuseCase>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT domain, state, reason, updated_by, updated_at
+            FROM domain_abuse
+            WHERE $1::text IS NULL OR state = $1
+            ORDER BY updated_at DESC
+            "#
+        )
+        .bind(state.map(|s| s.as_str()))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.into_iter()
+            .map(|row| Ok(AbuseCase {
+                domain: row.get("domain"),
+                state: row.get::<String, _>("state").parse()?,
+                reason: row.get("reason"),
+                updated_by: row.get("updated_by"),
+                updated_at: row.get("updated_at"),
+            }))
+            .collect()
+    }
+    
+    /// Moves a domain name from `from` to `to`, clearing its case when `to` is `None`, and audits the transition.
+    pub async fn set_abuse_state(&self, domain: &str, from: Option<AbuseState>, to: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        let mut tx = self.pool.begin().await?;
+        
+        match to {
+            Some(state) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO domain_abuse (domain, state, reason, updated_by)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (domain) DO UPDATE
+                    SET state = $2, reason = $3, updated_by = $4, updated_at = NOW()
+                    "#
+                )
+                .bind(&domain)
+                .bind(state.as_str())
+                .bind(reason)
+                .bind(actor)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM domain_abuse WHERE domain = $1")
+                    .bind(&domain)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        
+        sqlx::query(
+            "INSERT INTO abuse_events (domain, from_state, to_state, reason, actor) VALUES ($1, $2, $3, $4, $5)"
+        )
+        .bind(&domain)
+        .bind(from.map(|s| s.as_str()))
+        .bind(to.map(|s| s.as_str()))
+        .bind(reason)
+        .bind(actor)
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        Ok(())
+    }
+    
+    /// Abuse state transitions for a domain name, newest first.
+    pub async fn get_abuse_events(&self, domain: &str) -> Result<Vec<AbuseEvent>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, domain, from_state, to_state, reason, actor, created_at
+            FROM abuse_events
+            WHERE domain = $1
+            ORDER BY id DESC
+            "#
+        )
+        .bind(domain_key(domain))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.into_iter()
+            .map(|row| Ok(AbuseEvent {
+                id: row.get("id"),
+                domain: row.get("domain"),
+                from_state: row.get::<Option<String>, _>("from_state").map(|s| s.parse()).transpose()?,
+                to_state: row.get::<Option<String>, _>("to_state").map(|s| s.parse()).transpose()?,
+                reason: row.get("reason"),
+                actor: row.get("actor"),
+                created_at: row.get("created_at"),
+            }))
+            .collect()
+    }
+    
+    pub async fn get_billing_holds(&self) -> Result<Vec<BillingHold>> {
+        let rows = sqlx::query("SELECT domain, payment_status, unpaid_since, paused_at FROM billing_holds ORDER BY unpaid_since")
+            .fetch_all(&self.pool)
+            .await?;
+        
+        Ok(rows.into_iter()
+            .map(|row| BillingHold {
+                domain: row.get("domain"),
+                payment_status: row.get("payment_status"),
+                unpaid_since: row.get("unpaid_since"),
+                paused_at: row.get("paused_at"),
+            })
+            .collect())
+    }
+    
+    /// Starts a hold for a domain seen unpaid, or updates the status of its existing one
+    /// without restarting the grace window.
+    pub async fn save_billing_hold(&self, domain: &str, payment_status: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO billing_holds (domain, payment_status)
+            VALUES ($1, $2)
+            ON CONFLICT (domain) DO UPDATE SET payment_status = $2
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(payment_status)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn pause_billing_hold(&self, domain: &str) -> Result<()> {
+        sqlx::query("UPDATE billing_holds SET paused_at = NOW() WHERE domain = $1 AND paused_at IS NULL")
+            .bind(domain_key(domain))
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn clear_billing_hold(&self, domain: &str) -> Result<()> {
+        sqlx::query("DELETE FROM billing_holds WHERE domain = $1")
+            .bind(domain_key(domain))
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    /// Records a transfer. A pending one has a `token_hash` and `expires_at`; a completed one
+    /// is written after the Supabase row has moved.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_domain_transfer(
+        &self,
+        domain: &str,
+        from_user: &str,
+        to_user: Option<&str>,
+        status: TransferStatus,
+        token_hash: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+        actor: &str,
+    ) -> Result<DomainTransfer> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO domain_transfers (domain, from_user, to_user, status, token_hash, expires_at, actor, completed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, CASE WHEN $4 = 'completed' THEN NOW() END)
+            RETURNING id, domain, from_user, to_user, status, actor, created_at, expires_at, completed_at
+            "#
+        )
+        .bind(domain_key(domain))
+        .bind(from_user)
+        .bind(to_user)
+        .bind(status.as_str())
+        .bind(token_hash)
+        .bind(expires_at)
+        .bind(actor)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Self::domain_transfer_from_row(row)
+    }
+    
+    /// Cancels the domain's unredeemed claim tokens and returns how many there were.
+    pub async fn cancel_pending_transfers(&self, domain: &str) -> Result<u64> {
+        let result = sqlx::query("UPDATE domain_transfers SET status = 'cancelled' WHERE domain = $1 AND status = 'pending'")
+            .bind(domain_key(domain))
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    /// The pending transfer a claim token belongs to, if it has not expired.
+    pub async fn get_claimable_transfer(&self, token_hash: &str) -> Result<Option<DomainTransfer>> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, domain, from_user, to_user, status, actor, created_at, expires_at, completed_at
+            FROM domain_transfers
+            WHERE token_hash = $1 AND status = 'pending' AND expires_at > NOW()
+            "#
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        row.map(Self::domain_transfer_from_row).transpose()
+    }
+    
+    /// Marks a claimed transfer done. Returns false if it was cancelled or claimed meanwhile.
+    pub async fn complete_transfer(&self, id: i64, to_user: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE domain_transfers SET status = 'completed', to_user = $2, completed_at = NOW() WHERE id = $1 AND status = 'pending'"
+        )
+        .bind(id)
+        .bind(to_user)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected() == 1)
+    }
+    
+    /// The domain's transfers, newest first.
+    pub async fn get_domain_transfers(&self, domain: &str) -> Result<Vec<DomainTransfer>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, domain, from_user, to_user, status, actor, created_at, expires_at, completed_at
+            FROM domain_transfers
+            WHERE domain = $1
+            ORDER BY id DESC
+            "#
+        )
+        .bind(domain_key(domain))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.into_iter().map(Self::domain_transfer_from_row).collect()
+    }
+    
+    fn domain_transfer_from_row(row: sqlx::postgres::PgRow) -> Result<DomainTransfer> {
+        let status: String = row.get("status");
+        Ok(DomainTransfer {
+            id: row.get("id"),
+            domain: row.get("domain"),
+            from_user: row.get("from_user"),
+            to_user: row.get("to_user"),
+            status: status.parse()?,
+            actor: row.get("actor"),
+            created_at: row.get("created_at"),
+            expires_at: row.get("expires_at"),
+            completed_at: row.get("completed_at"),
+        })
+    }
+    
+    pub async fn get_blocked_domains(&self) -> Result<Vec<BlockedDomain>> {
+        let rows = sqlx::query(
+            "SELECT id, pattern, kind, reason, created_by, created_at FROM blocked_domains ORDER BY pattern"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.iter().map(Self::blocked_domain_from_row).collect()
+    }
+    
+    pub async fn add_blocked_domain(&self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<BlockedDomain> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO blocked_domains (pattern, kind, reason, created_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (pattern, kind) DO UPDATE
+            SET reason = $3, created_by = $4
+            RETURNING id, pattern, kind, reason, created_by, created_at
+            "#
+        )
+        .bind(pattern)
+        .bind(kind.as_str())
+        .bind(reason)
+        .bind(actor)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        info!("Blocked {} domain pattern {}", kind.as_str(), pattern);
+        Self::blocked_domain_from_row(&row)
+    }
+    
+    pub async fn remove_blocked_domain(&self, id: i64) -> Result<()> {
+        let result = sqlx::query("DELETE FROM blocked_domains WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No blocked domain entry {}", id));
+        }
+        Ok(())
+    }
+    
+    fn blocked_domain_from_row(row: &sqlx::postgres::PgRow) -> Result<BlockedDomain> {
+        Ok(BlockedDomain {
+            id: row.get("id"),
+            pattern: row.get("pattern"),
+            kind: row.get::<String, _>("kind").parse()?,
+            reason: row.get("reason"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+        })
+    }
+    
+    /// Overrides that have not expired yet.
+    pub async fn get_answer_overrides(&self) -> Result<Vec<AnswerOverride>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, record_type, action, value, reason, created_by, created_at, expires_at
+            FROM answer_overrides
+            WHERE expires_at > NOW()
+            ORDER BY created_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        rows.iter().map(Self::answer_override_from_row).collect()
+    }
+    
+    pub async fn add_answer_override(&self, entry: &NewOverride, actor: &str) -> Result<AnswerOverride> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO answer_overrides (name, record_type, action, value, reason, created_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, name, record_type, action, value, reason, created_by, created_at, expires_at
+            "#
+        )
+        .bind(&entry.name)
+        .bind(&entry.record_type)
+        .bind(entry.action.as_str())
+        .bind(&entry.value)
+        .bind(&entry.reason)
+        .bind(actor)
+        .bind(entry.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Self::answer_override_from_row(&row)
+    }
+    
+    /// Ends an override now; the row is kept as a record of the incident.
+    pub async fn expire_answer_override(&self, id: i64) -> Result<()> {
+        let result = sqlx::query("UPDATE answer_overrides SET expires_at = NOW() WHERE id = $1 AND expires_at > NOW()")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No active override {}", id));
+        }
+        Ok(())
+    }
+    
+    fn answer_override_from_row(row: &sqlx::postgres::PgRow) -> Result<AnswerOverride> {
+        Ok(AnswerOverride {
+            id: row.get("id"),
+            name: row.get("name"),
+            record_type: row.get("record_type"),
+            action: row.get::<String, _>("action").parse()?,
+            value: row.get("value"),
+            reason: row.get("reason"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+            expires_at: row.get("expires_at"),
+        })
+    }
+    
+    /// Adds flushed 5-minute counts to `query_stats_5m`.
+    pub async fn insert_query_counts(&self, counts: &[QueryCount]) -> Result<()> {
+        if counts.is_empty() {
+            return Ok(());
+        }
+        
+        sqlx::query(
+            r#"
+            INSERT INTO query_stats_5m (domain, bucket_start, queries, negative)
+            SELECT * FROM UNNEST($1::text[], $2::timestamptz[], $3::bigint[], $4::bigint[])
+            ON CONFLICT (domain, bucket_start) DO UPDATE
+            SET queries = query_stats_5m.queries + EXCLUDED.queries,
+                negative = query_stats_5m.negative + EXCLUDED.negative
+            "#
+        )
+        .bind(counts.iter().map(|c| c.domain.clone()).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.bucket_start).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.queries).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.negative).collect::<Vec<_>>())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Rewrites the hourly rows for hours starting in `[from, until)` from the 5-minute buckets.
+    pub async fn rollup_query_stats_hourly(&self, from: DateTime<Utc>, until: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO query_stats_hourly (domain, bucket_start, queries, negative)
+            SELECT domain, date_trunc('hour', bucket_start AT TIME ZONE 'UTC') AT TIME ZONE 'UTC', SUM(queries), SUM(negative)
+            FROM query_stats_5m
+            WHERE bucket_start >= $1 AND bucket_start < $2
+            GROUP BY 1, 2
+            ON CONFLICT (domain, bucket_start) DO UPDATE
+            SET queries = EXCLUDED.queries, negative = EXCLUDED.negative
+            "#
+        )
+        .bind(from)
+        .bind(until)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    /// Rewrites the daily rows for UTC days starting in `[from, until)` from the hourly rows.
+    pub async fn rollup_query_stats_daily(&self, from: DateTime<Utc>, until: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO query_stats_daily (domain, bucket_start, queries, negative)
+            SELECT domain, date_trunc('day', bucket_start AT TIME ZONE 'UTC') AT TIME ZONE 'UTC', SUM(queries), SUM(negative)
+            FROM query_stats_hourly
+            WHERE bucket_start >= $1 AND bucket_start < $2
+            GROUP BY 1, 2
+            ON CONFLICT (domain, bucket_start) DO UPDATE
+            SET queries = EXCLUDED.queries, negative = EXCLUDED.negative
+            "#
+        )
+        .bind(from)
+        .bind(until)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    /// Deletes query stats older than each resolution's cutoff; no daily cutoff keeps them all.
+    /// Returns the rows deleted per resolution.
+    pub async fn prune_query_stats(
+        &self,
+        raw_before: DateTime<Utc>,
+        hourly_before: DateTime<Utc>,
+        daily_before: Option<DateTime<Utc>>,
+    ) -> Result<(u64, u64, u64)> {
+        let raw = sqlx::query("DELETE FROM query_stats_5m WHERE bucket_start < $1")
+            .bind(raw_before)
+            .execute(&self.pool)
+            .await?;
+        let hourly = sqlx::query("DELETE FROM query_stats_hourly WHERE bucket_start < $1")
+            .bind(hourly_before)
+            .execute(&self.pool)
+            .await?;
+        let daily = match daily_before {
+            Some(before) => sqlx::query("DELETE FROM query_stats_daily WHERE bucket_start < $1")
+                .bind(before)
+                .execute(&self.pool)
+                .await?
+                .rows_affected(),
+            None => 0,
+        };
+        
+        Ok((raw.rows_affected(), hourly.rows_affected(), daily))
+    }
+    
+    /// Per-domain query totals in `[from, to)`: daily rows before `hourly_from`, hourly rows
+    /// before `raw_from` and 5-minute buckets after, so no stretch of time is counted twice.
+    pub async fn get_query_totals(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        raw_from: DateTime<Utc>,
+        hourly_from: DateTime<Utc>,
+    ) -> Result<Vec<QueryTotal>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT domain, SUM(queries)::bigint as queries, SUM(negative)::bigint as negative
+            FROM (
+                SELECT domain, queries, negative FROM query_stats_daily
+                WHERE bucket_start >= $1 AND bucket_start < LEAST($2, $4)
+                UNION ALL
+                SELECT domain, queries, negative FROM query_stats_hourly
+                WHERE bucket_start >= GREATEST($1, $4) AND bucket_start < LEAST($2, $3)
+                UNION ALL
+                SELECT domain, queries, negative FROM query_stats_5m
+                WHERE bucket_start >= GREATEST($1, $3) AND bucket_start < $2
+            ) stats
+            GROUP BY domain
+            ORDER BY domain
+            "#
+        )
+        .bind(from)
+        .bind(to)
+        .bind(raw_from)
+        .bind(hourly_from)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| QueryTotal {
+            domain: row.get("domain"),
+            queries: row.get("queries"),
+            negative: row.get("negative"),
+        }).collect())
+    }
+    
+    /// Replaces this node's row per transport in `node_stats` with its current counters.
+    pub async fn push_node_stats(
+        &self,
+        node_id: &str,
+        region: Option<&str>,
+        started_at: DateTime<Utc>,
+        transports: &[TransportBreakdown],
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO node_stats (node_id, region, started_at, transport, queries, edns, truncated, answer_micros, reported_at)
+            SELECT $1, $2, $3, *, NOW()
+            FROM UNNEST($4::text[], $5::bigint[], $6::bigint[], $7::bigint[], $8::bigint[])
+            ON CONFLICT (node_id, transport) DO UPDATE
+            SET region = EXCLUDED.region,
+                started_at = EXCLUDED.started_at,
+                queries = EXCLUDED.queries,
+                edns = EXCLUDED.edns,
+                truncated = EXCLUDED.truncated,
+                answer_micros = EXCLUDED.answer_micros,
+                reported_at = EXCLUDED.reported_at
+            "#
+        )
+        .bind(node_id)
+        .bind(region)
+        .bind(started_at)
+        .bind(transports.iter().map(|t| t.transport.to_string()).collect::<Vec<_>>())
+        .bind(transports.iter().map(|t| t.queries as i64).collect::<Vec<_>>())
+        .bind(transports.iter().map(|t| t.edns as i64).collect::<Vec<_>>())
+        .bind(transports.iter().map(|t| t.truncated as i64).collect::<Vec<_>>())
+        .bind(transports.iter().map(|t| t.answer_micros as i64).collect::<Vec<_>>())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Rows of nodes that pushed their counters at or after `since`.
+    pub async fn get_node_stats(&self, since: DateTime<Utc>) -> Result<Vec<NodeReport>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT node_id, region, transport, started_at, reported_at, queries, edns, truncated, answer_micros
+            FROM node_stats
+            WHERE reported_at >= $1
+            ORDER BY node_id, transport
+            "#
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| NodeReport {
+            node_id: row.get("node_id"),
+            region: row.get("region"),
+            transport: row.get("transport"),
+            started_at: row.get("started_at"),
+            reported_at: row.get("reported_at"),
+            queries: row.get("queries"),
+            edns: row.get("edns"),
+            truncated: row.get("truncated"),
+            answer_micros: row.get("answer_micros"),
+        }).collect())
+    }
+    
+    /// Registers `node` or refreshes its row, stamping the heartbeat.
+    pub async fn register_node(&self, node: &NodeRegistration) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO nodes (node_id, hostname, region, version, config_hash, listen_addresses, started_at, last_heartbeat)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT (node_id) DO UPDATE
+            SET hostname = EXCLUDED.hostname,
+                region = EXCLUDED.region,
+                version = EXCLUDED.version,
+                config_hash = EXCLUDED.config_hash,
+                listen_addresses = EXCLUDED.listen_addresses,
+                started_at = EXCLUDED.started_at,
+                last_heartbeat = EXCLUDED.last_heartbeat
+            "#
+        )
+        .bind(&node.node_id)
+        .bind(&node.hostname)
+        .bind(&node.region)
+        .bind(node.version)
+        .bind(&node.config_hash)
+        .bind(&node.listen_addresses)
+        .bind(node.started_at)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    /// Every registered node, alive or not, by `node_id`.
+    pub async fn get_nodes(&self) -> Result<Vec<NodeInfo>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT node_id, hostname, region, version, config_hash, listen_addresses, started_at, last_heartbeat
+            FROM nodes
+            ORDER BY node_id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| NodeInfo {
+            node_id: row.get("node_id"),
+            hostname: row.get("hostname"),
+            region: row.get("region"),
+            version: row.get("version"),
+            config_hash: row.get("config_hash"),
+            listen_addresses: row.get("listen_addresses"),
+            started_at: row.get("started_at"),
+            last_heartbeat: row.get("last_heartbeat"),
+            alive: false,
+            current: false,
+        }).collect())
+    }
+    
+    pub async fn remove_node(&self, node_id: &str) -> Result<()> {
+        let result = sqlx::query("DELETE FROM nodes WHERE node_id = $1")
+            .bind(node_id)
+            .execute(&self.pool)
+            .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No node {}", node_id));
+        }
+        Ok(())
+    }
+    
+    /// Verification checks in `[from, to)` plus each domain's last check before `from`, which
+    /// holds the status the period started with. Ordered by domain, then time; `domain`
+    /// limits them to one domain.
+    pub async fn get_verification_checks(&self, from: DateTime<Utc>, to: DateTime<Utc>, domain: Option<&str>) -> Result<Vec<(String, String, DateTime<Utc>)>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT domain, status, checked_at FROM (
+                SELECT domain, status, checked_at FROM verification_history
+                WHERE checked_at >= $1 AND checked_at < $2 AND ($3::text IS NULL OR domain = $3)
+                UNION ALL
+                (SELECT DISTINCT ON (domain) domain, status, checked_at FROM verification_history
+                 WHERE checked_at < $1 AND ($3::text IS NULL OR domain = $3)
+                 ORDER BY domain, checked_at DESC)
+            ) checks
+            ORDER BY domain, checked_at
+            "#
+        )
+        .bind(from)
+        .bind(to)
+        .bind(domain.map(domain_key))
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(|row| (row.get("domain"), row.get("status"), row.get("checked_at"))).collect())
+    }
+}
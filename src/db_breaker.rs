@@ -0,0 +1,167 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use tracing::{info, warn};
+
+use crate::database::{Database, ResolverCheck};
+
+/// Consecutive database failures that open the breaker.
+const FAILURE_THRESHOLD: u32 = 3;
+/// Writes kept while the database is down; the oldest are dropped past this, and the next
+/// verification pass rewrites current state anyway.
+const MAX_QUEUED_WRITES: usize = 50_000;
+
+/// A write the verification loop makes, kept for replay while the database is unreachable.
+#[derive(Debug, Clone)]
+pub enum QueuedWrite {
+    Verification {
+        domain: String,
+        verified: bool,
+        nameservers: Vec<String>,
+    },
+    VerificationCheck {
+        domain: String,
+        status: String,
+        nameservers: Vec<String>,
+        checks: Vec<ResolverCheck>,
+    },
+    /// A domain whose grace period ran out.
+    RemoveDomain { domain: String },
+}
+
+impl QueuedWrite {
+    async fn apply(&self, db: &Database) -> Result<()> {
+        match self {
+            QueuedWrite::Verification { domain, verified, nameservers } => {
+                db.update_domain_verification(domain, *verified, nameservers).await
+            }
+            QueuedWrite::VerificationCheck { domain, status, nameservers, checks } => {
+                db.record_verification(domain, status, nameservers, checks).await
+            }
+            QueuedWrite::RemoveDomain { domain } => db.remove_domain(domain).await,
+        }
+    }
+}
+
+/// Database health as shown by `GET /health` and `GET /debug/runtime`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    /// `false` while the breaker is open.
+    pub available: bool,
+    pub unavailable_since: Option<DateTime<Utc>>,
+    pub queued_writes: usize,
+    /// Queued writes dropped because the queue was full.
+    pub dropped_writes: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_since: Option<DateTime<Utc>>,
+    queue: VecDeque<QueuedWrite>,
+    dropped: u64,
+    last_error: Option<String>,
+}
+
+/// Circuit breaker in front of the database. After `FAILURE_THRESHOLD` failures in a row it
+/// opens: verification writes are queued instead of attempted, API writes are refused and
+/// `/health` reports degraded, while DNS keeps answering from memory. `replay` applies the
+/// queue in order once the database answers again and closes the breaker.
+#[derive(Default)]
+pub struct DbBreaker {
+    state: Mutex<BreakerState>,
+}
+
+impl DbBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).open_since.is_some()
+    }
+
+    pub fn record_success(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&self, error: &anyhow::Error) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.consecutive_failures += 1;
+        state.last_error = Some(error.to_string());
+        if state.open_since.is_none() && state.consecutive_failures >= FAILURE_THRESHOLD {
+            state.open_since = Some(Utc::now());
+            warn!("Database failed {} times in a row ({}), queueing writes until it recovers", state.consecutive_failures, error);
+        }
+    }
+
+    /// Applies `write` now, or queues it if the breaker is open, earlier writes are still
+    /// waiting, or the attempt fails.
+    pub async fn write(&self, db: &Database, write: QueuedWrite) {
+        {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.open_since.is_some() || !state.queue.is_empty() {
+                push(&mut state, write);
+                return;
+            }
+        }
+
+        match write.apply(db).await {
+            Ok(()) => self.record_success(),
+            Err(e) => {
+                self.record_failure(&e);
+                push(&mut self.state.lock().unwrap_or_else(PoisonError::into_inner), write);
+            }
+        }
+    }
+
+    /// Applies queued writes oldest first, then closes the breaker. Stops at the first
+    /// failure, leaving that write and the rest queued. Returns how many were applied.
+    pub async fn replay(&self, db: &Database) -> Result<usize> {
+        let mut applied = 0;
+        loop {
+            let next = {
+                let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+                match state.queue.pop_front() {
+                    Some(write) => write,
+                    None => {
+                        if state.open_since.take().is_some() {
+                            info!("Database available again, replayed {} queued writes", applied);
+                        }
+                        state.consecutive_failures = 0;
+                        return Ok(applied);
+                    }
+                }
+            };
+
+            if let Err(e) = next.apply(db).await {
+                self.record_failure(&e);
+                self.state.lock().unwrap_or_else(PoisonError::into_inner).queue.push_front(next);
+                return Err(e);
+            }
+            applied += 1;
+        }
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        BreakerStatus {
+            available: state.open_since.is_none(),
+            unavailable_since: state.open_since,
+            queued_writes: state.queue.len(),
+            dropped_writes: state.dropped,
+            last_error: state.last_error.clone(),
+        }
+    }
+}
+
+fn push(state: &mut BreakerState, write: QueuedWrite) {
+    if state.queue.len() >= MAX_QUEUED_WRITES {
+        state.queue.pop_front();
+        state.dropped += 1;
+    }
+    state.queue.push_back(write);
+}
@@ -4,12 +4,12 @@ use std::time::Duration;
 use tokio::sync::RwLock;
 use tokio::time::interval;
 use tracing::{info, warn, error};
-use trust_dns_resolver::config::*;
 use trust_dns_resolver::TokioAsyncResolver;
 
 use crate::domain_manager::DomainManager;
 use crate::dns_handler::CybertempHandler;
 use crate::config::DnsConfig;
+use crate::resolvers::verification_resolvers;
 
 pub struct DnsChecker {
     resolver: TokioAsyncResolver,
@@ -23,18 +23,16 @@ impl DnsChecker {
         config: DnsConfig,
         domain_manager: Arc<RwLock<DomainManager>>,
         dns_handler: CybertempHandler,
-    ) -> Self {
-        let resolver = TokioAsyncResolver::tokio(
-            ResolverConfig::default(),
-            ResolverOpts::default(),
-        );
+    ) -> Result<Self> {
+        // Domain checks only need one answer, so they use the first verification resolver
+        let resolver = verification_resolvers(&config)?.remove(0).resolver;
         
-        Self {
+        Ok(Self {
             resolver,
             domain_manager,
             dns_handler,
             check_interval: Duration::from_secs(config.dns_check_interval_seconds),
-        }
+        })
     }
     
     pub async fn start_check_loop(self: Arc<Self>) {
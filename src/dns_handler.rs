@@ -1,15 +1,48 @@
-use crate::{DnsConfig, DomainManager, domain_manager::VerificationStatus};
+use crate::{DnsConfig, DomainManager, DomainRecord, domain_manager::VerificationStatus};
+use crate::config::{MailRoutingMode, RecursionPolicy, RoutingProfile, SubdomainPolicy};
+use crate::hostname_template::{self, TemplateContext};
+use crate::database::DnsRecord;
+use crate::query_stats::QueryStats;
+use crate::transport_stats::{Transport, TransportStats};
+use crate::stats_history::DomainQueryCounter;
+use crate::anomaly::QueryAnomalyDetector;
+use crate::abuse::AbuseState;
+use crate::answer_override::{OverrideAction, OVERRIDE_ANSWER_TTL};
+use crate::extended_error::{ExtendedError, InfoCode};
+use crate::response_cache::{CachedAnswer, ResponseCache};
+use crate::packet_guard::PacketGuard;
+use crate::node_health::NodeHealth;
+use crate::synthetic::PatternCache;
+use crate::txt_record;
+use crate::query_middleware::{QueryContext, QueryLog, QueryMiddleware};
+use crate::query_script::{parse_rcode, rcode_name, QueryScript, ScriptAnswer, ScriptQuery, ScriptVerdict};
 use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
-use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
 use trust_dns_proto::rr::{Name, RData, Record, RecordType};
-use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder, EncodeMode};
+
+/// Largest UDP payload we send to clients that don't advertise a bigger EDNS buffer (RFC 1035).
+const MAX_UDP_PAYLOAD: usize = 512;
 
 #[derive(Clone)]
 pub struct CybertempHandler {
     config: DnsConfig,
     domain_manager: Arc<RwLock<DomainManager>>,
+    query_stats: Option<Arc<QueryStats>>,
+    transport_stats: Option<Arc<TransportStats>>,
+    query_anomalies: Option<Arc<QueryAnomalyDetector>>,
+    domain_counter: Option<Arc<DomainQueryCounter>>,
+    response_cache: Option<Arc<ResponseCache>>,
+    packet_guard: Option<Arc<PacketGuard>>,
+    synthetic_patterns: Arc<PatternCache>,
+    query_script: Option<Arc<QueryScript>>,
+    node_health: Option<Arc<NodeHealth>>,
+    middleware: Vec<Arc<dyn QueryMiddleware>>,
 }
 
 impl CybertempHandler {
@@ -17,56 +50,1025 @@ impl CybertempHandler {
         Self {
             config,
             domain_manager,
+            query_stats: None,
+            transport_stats: None,
+            query_anomalies: None,
+            domain_counter: None,
+            response_cache: None,
+            packet_guard: None,
+            synthetic_patterns: Arc::new(PatternCache::new()),
+            query_script: None,
+            node_health: None,
+            middleware: vec![Arc::new(QueryLog)],
         }
     }
     
-    pub async fn handle_request(&self, data: &[u8]) -> Result<Vec<u8>> {
+    pub fn with_query_stats(mut self, query_stats: Arc<QueryStats>) -> Self {
+        self.query_stats = Some(query_stats);
+        self
+    }
+    
+    /// Counts queries per transport, with their EDNS buffer sizes.
+    pub fn with_transport_stats(mut self, transport_stats: Arc<TransportStats>) -> Self {
+        self.transport_stats = Some(transport_stats);
+        self
+    }
+    
+    /// Counts queries per managed domain, which costs a domain lookup per query.
+    pub fn with_query_anomalies(mut self, query_anomalies: Arc<QueryAnomalyDetector>) -> Self {
+        self.query_anomalies = Some(query_anomalies);
+        self
+    }
+    
+    /// Keeps per-domain query counts for the stats history, also at a domain lookup per query.
+    pub fn with_domain_counter(mut self, domain_counter: Arc<DomainQueryCounter>) -> Self {
+        self.domain_counter = Some(domain_counter);
+        self
+    }
+    
+    pub fn with_response_cache(mut self, response_cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(response_cache);
+        self
+    }
+    
+    /// Checks raw packets against the strict parser limits before decoding them.
+    pub fn with_packet_guard(mut self, packet_guard: Arc<PacketGuard>) -> Self {
+        self.packet_guard = Some(packet_guard);
+        self
+    }
+    
+    /// Runs the operator's `on_query` script on every answer before it is sent.
+    pub fn with_query_script(mut self, query_script: Arc<QueryScript>) -> Self {
+        self.query_script = Some(query_script);
+        self
+    }
+    
+    pub fn with_node_health(mut self, node_health: Arc<NodeHealth>) -> Self {
+        self.node_health = Some(node_health);
+        self
+    }
+    
+    /// Adds hooks around answering each question, after those already registered.
+    pub fn with_middleware(mut self, middleware: Arc<dyn QueryMiddleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+    
+    pub async fn handle_request(&self, data: &[u8], client: SocketAddr) -> Result<Vec<u8>> {
+        if let Some(response) = self.screen(data)? {
+            return Ok(response);
+        }
         let request = Message::from_bytes(data)?;
+        if let Some(stats) = &self.transport_stats {
+            stats.record(Transport::Udp, &request);
+        }
+        let max_payload = request.extensions()
+            .as_ref()
+            .map(|edns| edns.max_payload() as usize)
+            .unwrap_or(MAX_UDP_PAYLOAD)
+            .max(MAX_UDP_PAYLOAD);
         
-        let response = self.handle_dns_message(request).await?;
-        
-        let mut response_data = Vec::new();
-        let mut encoder = BinEncoder::new(&mut response_data);
-        response.emit(&mut encoder)?;
+        let started = Instant::now();
+        let (response, response_data) = self.respond(request, client).await?;
+        if let Some(stats) = &self.transport_stats {
+            stats.record_answer_time(Transport::Udp, started.elapsed());
+        }
+        if response_data.len() <= max_payload {
+            return Ok(response_data);
+        }
         
+        // Too large for the client's buffer: drop the records and set TC so it retries over TCP
+        tracing::debug!("Truncating {} byte response (limit {})", response_data.len(), max_payload);
+        if let Some(stats) = &self.transport_stats {
+            stats.record_truncated(Transport::Udp);
+        }
+        Self::encode_message(&Self::truncated(&response))
+    }
+    
+    /// The response's header and question with TC set and no records.
+    fn truncated(response: &Message) -> Message {
+        let mut truncated = Message::new();
+        truncated.set_header(*response.header());
+        truncated.add_queries(response.queries().to_vec());
+        truncated.set_truncated(true);
+        truncated
+    }
+    
+    /// Answers a query that arrived over TCP or TLS, where the response is never truncated.
+    pub async fn handle_stream_request(&self, data: &[u8], client: SocketAddr, transport: Transport) -> Result<Vec<u8>> {
+        if let Some(response) = self.screen(data)? {
+            return Ok(response);
+        }
+        let request = Message::from_bytes(data)?;
+        if let Some(stats) = &self.transport_stats {
+            stats.record(transport, &request);
+        }
+        let started = Instant::now();
+        let (_, response_data) = self.respond(request, client).await?;
+        if let Some(stats) = &self.transport_stats {
+            stats.record_answer_time(transport, started.elapsed());
+        }
         Ok(response_data)
     }
     
-    async fn handle_dns_message(&self, request: Message) -> Result<Message> {
+    /// Runs `data` past the packet guard. `Some` is the FORMERR to send instead of parsing it,
+    /// and an error means the packet is dropped without an answer.
+    fn screen(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(guard) = &self.packet_guard else { return Ok(None) };
+        match guard.check(data) {
+            Ok(()) => Ok(None),
+            Err(rejection) => match rejection.response(data) {
+                Some(response) => {
+                    tracing::debug!("Answering FORMERR to rejected packet ({})", rejection);
+                    Ok(Some(response))
+                }
+                None => Err(anyhow::anyhow!("Dropped rejected packet ({})", rejection)),
+            },
+        }
+    }
+    
+    async fn respond(&self, request: Message, client: SocketAddr) -> Result<(Message, Vec<u8>)> {
+        let mut response = self.handle_dns_message(request, Some(client.ip())).await?;
+        if !self.is_overridden(&response).await {
+            self.apply_canary(&mut response, client.ip()).await;
+            self.apply_query_script(&mut response, client.ip()).await;
+        }
+        self.record_stats(&response, client.ip());
+        self.record_domain_query(&response).await;
+        
+        let mut response_data = Self::encode_message(&response)?;
+        if let Some(reason) = self.exceeded_limit(&response, response_data.len()) {
+            tracing::warn!("Answering {} from {} truncated: {}", Self::question_text(&response), client.ip(), reason);
+            response = Self::truncated(&response);
+            response_data = Self::encode_message(&response)?;
+        }
+        Ok((response, response_data))
+    }
+    
+    /// Why a response breaks `max_rrset_records` or `max_response_bytes`, given its encoded
+    /// size. Such answers are only possible with pathological zone data, so they are replaced
+    /// with an empty TC response rather than sent.
+    fn exceeded_limit(&self, response: &Message, size: usize) -> Option<String> {
+        if self.config.max_response_bytes > 0 && size > self.config.max_response_bytes {
+            return Some(format!("{} bytes is over max_response_bytes ({})", size, self.config.max_response_bytes));
+        }
+        if self.config.max_rrset_records == 0 {
+            return None;
+        }
+        
+        let mut rrsets: HashMap<(&Name, RecordType), usize> = HashMap::new();
+        for record in response.answers().iter().chain(response.name_servers()).chain(response.additionals()) {
+            *rrsets.entry((record.name(), record.record_type())).or_insert(0) += 1;
+        }
+        rrsets.into_iter()
+            .find(|(_, count)| *count > self.config.max_rrset_records)
+            .map(|((name, record_type), count)| {
+                format!("{} {} has {} records, over max_rrset_records ({})", name, record_type, count, self.config.max_rrset_records)
+            })
+    }
+    
+    fn question_text(response: &Message) -> String {
+        response.queries().first()
+            .map(|query| format!("{} {}", query.name(), query.query_type()))
+            .unwrap_or_default()
+    }
+    
+    /// Answers a single question exactly as a client would see it, without touching stats or the cache.
+    /// Canaries and the query script depend on the client's address, so the answer is the one
+    /// clients outside every canary get before the script runs.
+    pub async fn lookup(&self, name: &str, record_type: RecordType) -> Result<Message> {
+        let handler = Self {
+            query_stats: None,
+            transport_stats: None,
+            query_anomalies: None,
+            domain_counter: None,
+            response_cache: None,
+            ..self.clone()
+        };
+        handler.handle_dns_message(Self::question(name, record_type)?, None).await
+    }
+    
+    /// Answers a single question for a client that didn't speak the wire format (DNS JSON),
+    /// going through the cache and counting it like any other query.
+    pub async fn answer(&self, name: &str, record_type: RecordType, client: IpAddr) -> Result<Message> {
+        let mut request = Self::question(name, record_type)?;
+        request.set_recursion_desired(true);
+        if let Some(stats) = &self.transport_stats {
+            stats.record_json();
+        }
+        
+        let started = Instant::now();
+        let mut response = self.handle_dns_message(request, Some(client)).await?;
+        if !self.is_overridden(&response).await {
+            self.apply_canary(&mut response, client).await;
+            self.apply_query_script(&mut response, client).await;
+        }
+        self.record_stats(&response, client);
+        self.record_domain_query(&response).await;
+        
+        if let Some(reason) = self.exceeded_limit(&response, Self::encode_message(&response)?.len()) {
+            tracing::warn!("Answering {} from {} truncated: {}", Self::question_text(&response), client, reason);
+            response = Self::truncated(&response);
+        }
+        if let Some(stats) = &self.transport_stats {
+            stats.record_answer_time(Transport::Doh, started.elapsed());
+        }
+        Ok(response)
+    }
+    
+    /// Swaps the answers of a type the name's canary covers for the canary's, if `client` is in
+    /// its share. Runs after the cache like the query script, and only where there is an answer
+    /// to replace, so a canary never turns NXDOMAIN or an empty answer into records.
+    async fn apply_canary(&self, response: &mut Message, client: IpAddr) {
+        let Some(query) = response.queries().first().cloned() else { return };
+        let qname = query.name().to_ascii();
+        let Some((domain, canary)) = self.domain_manager.read().await.canary_for(qname.trim_end_matches('.')) else { return };
+        if !canary.includes(&domain, client) {
+            return;
+        }
+        
+        let record_type = query.query_type();
+        let type_name = record_type.to_string();
+        let Some(ttl) = response.answers().iter().find(|r| r.record_type() == record_type).map(|r| r.ttl()) else { return };
+        let records: Vec<Record> = canary.records.iter()
+            .filter(|record| record.record_type == type_name)
+            .filter_map(|record| Self::rdata(&record.record_type, &record.value, record.priority))
+            .map(|rdata| Record::from_rdata(query.name().clone(), ttl, rdata))
+            .collect();
+        if records.is_empty() {
+            return;
+        }
+        
+        let mut answers = response.take_answers();
+        answers.retain(|r| r.record_type() != record_type);
+        answers.extend(records);
+        response.add_answers(answers);
+    }
+    
+    /// Lets the query script keep, suppress or replace the answers and response code. Runs
+    /// after the cache, so per-client answers are never cached. A script error, or an answer
+    /// it returns that does not parse, leaves the response as synthesized.
+    async fn apply_query_script(&self, response: &mut Message, client: IpAddr) {
+        let Some(script) = &self.query_script else { return };
+        let Some(query) = response.queries().first().cloned() else { return };
+        let qname = query.name().to_ascii();
+        let qname = qname.trim_end_matches('.');
+        let domain = self.domain_manager.read().await.zone_name(qname).map(str::to_string);
+        
+        let call = ScriptQuery {
+            qname: qname.to_string(),
+            qtype: query.query_type().to_string(),
+            client: client.to_string(),
+            domain,
+            rcode: rcode_name(response.response_code()),
+            answers: response.answers().iter().filter_map(Self::script_answer).collect(),
+        };
+        // Lua runs synchronously, so it gets a blocking thread rather than a runtime worker
+        let script = script.clone();
+        let verdict = tokio::task::spawn_blocking(move || script.run(&call)).await
+            .unwrap_or_else(|e| Err(anyhow::anyhow!("panicked: {}", e)));
+        let (answers, rcode) = match verdict {
+            Ok(ScriptVerdict::Keep) => return,
+            Ok(ScriptVerdict::Suppress) => (Some(Vec::new()), None),
+            Ok(ScriptVerdict::Replace { answers, rcode }) => (answers, rcode),
+            Err(e) => {
+                tracing::warn!("Query script failed for {}: {}", qname, e);
+                return;
+            }
+        };
+        
+        let rcode = match rcode.as_deref().map(|name| (name, parse_rcode(name))) {
+            Some((_, Some(code))) => Some(code),
+            Some((name, None)) => {
+                tracing::warn!("Query script returned unknown rcode {} for {}", name, qname);
+                return;
+            }
+            None => None,
+        };
+        let records = match answers {
+            Some(answers) => {
+                let mut records = Vec::new();
+                for answer in answers {
+                    let owner = match &answer.name {
+                        Some(name) => Name::from_ascii(name).ok(),
+                        None => Some(query.name().clone()),
+                    };
+                    let Some((owner, rdata)) = owner.zip(Self::rdata(&answer.record_type, &answer.value, answer.priority)) else {
+                        tracing::warn!("Query script returned an invalid {} answer for {}: {}", answer.record_type, qname, answer.value);
+                        return;
+                    };
+                    records.push(Record::from_rdata(owner, answer.ttl.unwrap_or(self.config.default_ttl), rdata));
+                }
+                Some(records)
+            }
+            None => None,
+        };
+        
+        if let Some(records) = records {
+            response.take_answers();
+            response.add_answers(records);
+        }
+        if let Some(rcode) = rcode {
+            response.set_response_code(rcode);
+            // Any Extended DNS Error explained the code the script replaced
+            *response.extensions_mut() = None;
+        }
+    }
+    
+    /// An answer record as the query script sees it; `None` for types it cannot write back.
+    fn script_answer(record: &Record) -> Option<ScriptAnswer> {
+        let (value, priority) = match record.data()? {
+            RData::MX(mx) => (mx.exchange().to_string(), mx.preference()),
+            rdata @ (RData::A(_) | RData::AAAA(_) | RData::CNAME(_)) => (rdata.to_string(), 0),
+            RData::TXT(txt) => (txt.txt_data().iter().map(|part| String::from_utf8_lossy(part)).collect(), 0),
+            _ => return None,
+        };
+        Some(ScriptAnswer {
+            name: Some(record.name().to_string()),
+            record_type: record.record_type().to_string(),
+            value,
+            ttl: Some(record.ttl()),
+            priority,
+        })
+    }
+    
+    fn question(name: &str, record_type: RecordType) -> Result<Message> {
+        let mut request = Message::new();
+        request.set_message_type(MessageType::Query);
+        request.set_op_code(OpCode::Query);
+        let mut name = Name::from_ascii(name)?;
+        name.set_fqdn(true);
+        request.add_query(Query::query(name, record_type));
+        // So answers carry Extended DNS Errors for `/resolve` and the DNS JSON API
+        request.set_edns(Edns::new());
+        Ok(request)
+    }
+    
+    fn record_stats(&self, response: &Message, client: IpAddr) {
+        if let (Some(stats), Some(query)) = (&self.query_stats, response.queries().first()) {
+            let negative = response.answers().is_empty() || response.response_code() == ResponseCode::NXDomain;
+            stats.record(client, query.name().to_ascii().trim_end_matches('.'), negative);
+        }
+    }
+    
+    async fn record_domain_query(&self, response: &Message) {
+        if self.query_anomalies.is_none() && self.domain_counter.is_none() {
+            return;
+        }
+        let Some(query) = response.queries().first() else { return };
+        
+        let name = query.name().to_ascii();
+        let manager = self.domain_manager.read().await;
+        let Some(domain) = manager.zone_name(name.trim_end_matches('.')) else { return };
+        if let Some(anomalies) = &self.query_anomalies {
+            anomalies.record(domain);
+        }
+        if let Some(counter) = &self.domain_counter {
+            let negative = response.answers().is_empty() || response.response_code() == ResponseCode::NXDomain;
+            counter.record(domain, negative);
+        }
+    }
+    
+    /// True if `name` is inside a zone we answer for: the infrastructure zone, a white-label
+    /// nameserver identity, or a verified and enabled managed domain.
+    pub async fn is_in_bailiwick(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        if self.config.infrastructure.as_ref().is_some_and(|infra| infra.relative_name(name).is_some()) {
+            return true;
+        }
+        if self.config.nameserver_sets.values().any(|set| set.contains(name)) {
+            return true;
+        }
+        
+        self.domain_manager.read().await.serves(name)
+    }
+    
+    /// Serializes a message with name compression, so repeated owner names and
+    /// MX/NS targets under the same zone collapse to 2-byte pointers.
+    fn encode_message(message: &Message) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut encoder = BinEncoder::with_mode(&mut buffer, EncodeMode::Normal);
+        encoder.set_canonical_names(false);
+        message.emit(&mut encoder)?;
+        
+        Ok(buffer)
+    }
+    
+    async fn handle_dns_message(&self, request: Message, client: Option<IpAddr>) -> Result<Message> {
         let mut response = Message::new();
         response.set_id(request.id());
         response.set_op_code(request.op_code());
         response.set_message_type(MessageType::Response);
         response.set_recursion_desired(request.recursion_desired());
+        // Authoritative only: we never recurse, whatever the client asked for
+        response.set_recursion_available(false);
         
         if request.op_code() != OpCode::Query {
+            response.add_queries(request.queries().to_vec());
             response.set_response_code(ResponseCode::NotImp);
             return Ok(response);
         }
         
-        for query in request.queries() {
-            self.handle_query(query, &mut response).await;
-        }
+        // Exactly one question (RFC 9619): an empty question section leaves nothing to answer, and
+        // several questions cannot share one RCODE and AA bit, so both get FORMERR without a question
+        let [query] = request.queries() else {
+            response.set_response_code(ResponseCode::FormErr);
+            return Ok(response);
+        };
+        // Echo the question; it is also the first target for compression pointers
+        response.add_query(query.clone());
         
+        self.handle_query(query, client, &mut response).await;
+        // Extended DNS Errors travel in an OPT record, which only clients that sent one get back
+        if request.extensions().is_none() {
+            *response.extensions_mut() = None;
+        }
         Ok(response)
     }
     
-    async fn handle_query(&self, query: &Query, response: &mut Message) {
+    /// Answers `query` between the registered middleware's `before` and `after` hooks. An
+    /// answer override skips the `before` hooks too.
+    async fn handle_query(&self, query: &Query, client: Option<IpAddr>, response: &mut Message) {
+        let context = QueryContext { query, client };
+        if !self.apply_override(query, response).await {
+            let refused = self.middleware.iter()
+                .find_map(|middleware| middleware.before(&context).map(|code| (code, middleware.extended_error())));
+            match refused {
+                Some((code, error)) => {
+                    response.set_response_code(code);
+                    if let Some(error) = error {
+                        error.attach(response);
+                    }
+                }
+                None => {
+                    self.answer_query(query, response).await;
+                    if let Some(error) = self.explain_denial(query, response).await {
+                        error.attach(response);
+                    }
+                    self.apply_authority(query, response).await;
+                }
+            }
+        }
+        for middleware in self.middleware.iter().rev() {
+            middleware.after(&context, response);
+        }
+    }
+    
+    /// Answers from the active answer override covering `query`, ahead of the cache and every
+    /// zone. Returns false when there is none.
+    async fn apply_override(&self, query: &Query, response: &mut Message) -> bool {
         let name = query.name().to_ascii();
+        let manager = self.domain_manager.read().await;
+        let Some(entry) = manager.answer_override(name.trim_end_matches('.'), query.query_type()) else { return false };
+        
+        let blocked = ExtendedError::new(
+            InfoCode::Blocked,
+            format!("answer override {}{}", entry.id, entry.reason.as_deref().map(|reason| format!(": {}", reason)).unwrap_or_default()),
+        );
+        match entry.action {
+            OverrideAction::Refuse => {
+                response.set_response_code(ResponseCode::Refused);
+                blocked.attach(response);
+            }
+            OverrideAction::Nxdomain => {
+                response.set_response_code(ResponseCode::NXDomain);
+                blocked.attach(response);
+            }
+            OverrideAction::Answer => {
+                let rdata = entry.record_type.as_deref().zip(entry.value.as_deref())
+                    .and_then(|(record_type, value)| Self::rdata(record_type, value, 0));
+                if let Some(rdata) = rdata {
+                    response.add_answer(Record::from_rdata(query.name().clone(), OVERRIDE_ANSWER_TTL, rdata));
+                }
+                response.set_response_code(ResponseCode::NoError);
+            }
+        }
+        true
+    }
+    
+    /// Whether an answer override covers the response's question, which per-client rewrites
+    /// such as canaries and the query script then leave alone.
+    async fn is_overridden(&self, response: &Message) -> bool {
+        let Some(query) = response.queries().first() else { return false };
+        let name = query.name().to_ascii();
+        self.domain_manager.read().await.answer_override(name.trim_end_matches('.'), query.query_type()).is_some()
+    }
+    
+    /// Sets AA on answers for names in our zones, and refuses recursion for names outside them
+    /// that we have no answer for. Refusals are left alone.
+    async fn apply_authority(&self, query: &Query, response: &mut Message) {
+        if response.response_code() == ResponseCode::Refused {
+            return;
+        }
+        let name = query.name().to_ascii();
+        if self.is_in_bailiwick(&name).await {
+            response.set_authoritative(self.config.authoritative_answers);
+        } else if response.recursion_desired()
+            && self.config.recursion_policy == RecursionPolicy::Refuse
+            && response.answers().is_empty()
+        {
+            response.set_response_code(ResponseCode::Refused);
+            ExtendedError::new(InfoCode::NotAuthoritative, "recursion is not available").attach(response);
+        }
+    }
+    
+    /// Why a zone answered REFUSED or NXDOMAIN, when it was denied rather than simply has no
+    /// such name. Worked out after the cache, which only keeps the response code.
+    async fn explain_denial(&self, query: &Query, response: &Message) -> Option<ExtendedError> {
+        let code = response.response_code();
+        if code != ResponseCode::Refused && code != ResponseCode::NXDomain {
+            return None;
+        }
+        let name = query.name().to_ascii();
+        let zone = self.domain_manager.read().await.find_zone(name.trim_end_matches('.')).await?;
+        
+        if !zone.enabled {
+            return Some(ExtendedError::new(InfoCode::NotAuthoritative, format!("domain {} is disabled", zone.domain)));
+        }
+        if zone.verification_status != VerificationStatus::Verified {
+            return Some(ExtendedError::new(InfoCode::NotAuthoritative, format!("domain {} is not verified", zone.domain)));
+        }
+        if query.query_type() == RecordType::MX && zone.abuse_state == Some(AbuseState::Quarantined) {
+            return Some(ExtendedError::new(InfoCode::Blocked, format!("mail for {} is blocked for abuse", zone.domain)));
+        }
+        None
+    }
+    
+    async fn answer_query(&self, query: &Query, response: &mut Message) {
+        let name = query.name().to_ascii();
+        let name = name.trim_end_matches('.');
         let query_type = query.query_type();
         
-        tracing::debug!("DNS query: {} type: {:?}", name, query_type);
+        // Ahead of the cache, since uptime changes with every query
+        if self.handle_health(name, query_type, response) {
+            return;
+        }
+        
+        if let Some(mut cached) = self.response_cache.as_ref().and_then(|c| c.get(name, query_type)) {
+            Self::echo_case(query, &mut cached.answers);
+            response.add_answers(cached.answers);
+            response.add_additionals(cached.additionals);
+            response.set_response_code(cached.response_code);
+            return;
+        }
+        
+        // Synthesize into a scratch message so the result can be cached on its own
+        let mut answer = Message::new();
+        let handled = self.handle_infrastructure(name, query_type, &mut answer).await
+            || self.handle_subdomain(name, query_type, &mut answer).await;
+        if !handled {
+            match query_type {
+                RecordType::A => self.handle_a_record(name, &mut answer).await,
+                RecordType::MX => self.handle_mx_record(name, &mut answer).await,
+                RecordType::TXT => self.handle_txt_record(name, &mut answer).await,
+                RecordType::NS => self.handle_ns_record(name, &mut answer).await,
+                RecordType::AAAA => self.handle_aaaa_record(name, &mut answer).await,
+                RecordType::SOA => self.handle_soa_record(name, &mut answer).await,
+                _ => {
+                    answer.set_response_code(ResponseCode::NoError);
+                }
+            }
+        }
+        
+        let cached = CachedAnswer {
+            answers: answer.take_answers(),
+            additionals: answer.take_additionals(),
+            response_code: answer.response_code(),
+        };
+        if let Some(cache) = &self.response_cache {
+            cache.insert(name, query_type, cached.clone());
+        }
+        
+        let mut answers = cached.answers;
+        Self::echo_case(query, &mut answers);
+        response.add_answers(answers);
+        response.add_additionals(cached.additionals);
+        response.set_response_code(cached.response_code);
+    }
+    
+    /// Gives answers owned by the queried name the question's exact spelling. Handlers and the
+    /// cache work on lowercased names, but resolvers using 0x20 case randomization drop
+    /// responses whose case differs from what they sent.
+    fn echo_case(query: &Query, answers: &mut [Record]) {
+        let qname = query.name().to_ascii();
+        let qname = qname.trim_end_matches('.');
+        for record in answers {
+            if record.name().to_ascii().trim_end_matches('.').eq_ignore_ascii_case(qname) {
+                record.set_name(query.name().clone());
+            }
+        }
+    }
+    
+    /// Answers `dns_health_name` and `dns_ready_name` for the node itself, with a TTL of 0 so
+    /// every check reaches a node. Returns false for other names.
+    fn handle_health(&self, name: &str, query_type: RecordType, response: &mut Message) -> bool {
+        let Some(health) = &self.node_health else { return false };
+        let matches = |configured: &Option<String>| {
+            configured.as_deref().is_some_and(|n| n.trim_end_matches('.').eq_ignore_ascii_case(name))
+        };
+        
+        let txt = if matches(&self.config.dns_health_name) {
+            health.txt()
+        } else if matches(&self.config.dns_ready_name) {
+            // SERVFAIL fails a plain lookup, so any DNS monitor can alert on it
+            if !health.ready() {
+                response.set_response_code(ResponseCode::ServFail);
+                return true;
+            }
+            health.ready_txt()
+        } else {
+            return false;
+        };
+        let Ok(owner) = Name::from_ascii(name) else { return false };
+        
+        if query_type == RecordType::TXT {
+            response.add_answer(Record::from_rdata(owner, 0, RData::TXT(trust_dns_proto::rr::rdata::TXT::new(txt))));
+        }
+        response.set_response_code(ResponseCode::NoError);
+        true
+    }
+    
+    /// Answers our own hostnames from `[infrastructure]`. Returns false for names it does not
+    /// own, which then fall through to the managed-domain handlers.
+    async fn handle_infrastructure(&self, name: &str, query_type: RecordType, response: &mut Message) -> bool {
+        let Some(infra) = &self.config.infrastructure else { return false };
+        let Some(relative) = infra.relative_name(name) else { return false };
+        let Ok(owner) = Name::from_ascii(name) else { return false };
+        let zone = infra.zone.trim_end_matches('.');
+        
+        let addresses = if relative.is_empty() {
+            &infra.apex_ips
+        } else if relative == "mail" {
+            &infra.mail_ips
+        } else if self.config.nameservers.iter().any(|ns| ns.trim_end_matches('.').eq_ignore_ascii_case(name)) {
+            &self.config.nameserver_ips
+        } else if let Some(profile) = self.profile_target(name, zone).await {
+            &profile.mail_ips
+        } else {
+            return false;
+        };
         
         match query_type {
-            RecordType::A => self.handle_a_record(&name, response).await,
-            RecordType::MX => self.handle_mx_record(&name, response).await,
-            RecordType::TXT => self.handle_txt_record(&name, response).await,
-            RecordType::NS => self.handle_ns_record(&name, response).await,
-            RecordType::AAAA => self.handle_aaaa_record(&name, response).await,
-            _ => {
+            RecordType::A | RecordType::AAAA => {
+                for record in self.address_records(&owner, addresses, query_type) {
+                    response.add_answer(record);
+                }
+            }
+            RecordType::NS if relative.is_empty() => {
+                for ns in &self.config.nameservers {
+                    let Ok(ns_name) = Name::from_ascii(ns) else { continue };
+                    response.add_answer(Record::from_rdata(
+                        owner.clone(),
+                        self.config.default_ttl,
+                        RData::NS(trust_dns_proto::rr::rdata::NS(ns_name.clone())),
+                    ));
+                    if infra.relative_name(ns).is_some() {
+                        for glue in self.address_records(&ns_name, &self.config.nameserver_ips, RecordType::A)
+                            .into_iter()
+                            .chain(self.address_records(&ns_name, &self.config.nameserver_ips, RecordType::AAAA))
+                        {
+                            response.add_additional(glue);
+                        }
+                    }
+                }
+            }
+            RecordType::MX if relative.is_empty() => {
+                let Ok(mail) = Name::from_ascii(&format!("mail.{}", zone)) else { return false };
+                response.add_answer(Record::from_rdata(
+                    owner,
+                    self.config.default_ttl,
+                    RData::MX(trust_dns_proto::rr::rdata::MX::new(self.config.mx_priority, mail.clone())),
+                ));
+                if self.config.mx_additional_records {
+                    for glue in self.address_records(&mail, &infra.mail_ips, RecordType::A)
+                        .into_iter()
+                        .chain(self.address_records(&mail, &infra.mail_ips, RecordType::AAAA))
+                    {
+                        response.add_additional(glue);
+                    }
+                }
+            }
+            RecordType::TXT if relative.is_empty() => {
+                for txt in &infra.txt {
+                    response.add_answer(Record::from_rdata(
+                        owner.clone(),
+                        self.config.default_ttl,
+                        Self::txt_rdata(txt),
+                    ));
+                }
+            }
+            RecordType::SOA if relative.is_empty() => {
+                let primary = self.config.nameservers.first().cloned()
+                    .unwrap_or_else(|| format!("ns1.{}", zone));
+                let (Ok(primary), Ok(hostmaster)) = (Name::from_ascii(&primary), Name::from_ascii(&format!("hostmaster.{}", zone))) else {
+                    return false;
+                };
+                response.add_answer(Record::from_rdata(
+                    owner,
+                    self.config.default_ttl,
+                    RData::SOA(trust_dns_proto::rr::rdata::SOA::new(
+                        primary,
+                        hostmaster,
+                        infra.serial,
+                        3600,
+                        600,
+                        604800,
+                        self.config.default_ttl,
+                    )),
+                ));
+            }
+            _ => {}
+        }
+        
+        response.set_response_code(ResponseCode::NoError);
+        true
+    }
+    
+    /// Applies the zone's subdomain policy to names below a managed domain. Returns false for
+    /// apexes, names outside our zones, and the `_dmarc`/BIMI/nameserver names other handlers own.
+    async fn handle_subdomain(&self, name: &str, query_type: RecordType, response: &mut Message) -> bool {
+        let manager = self.domain_manager.read().await;
+        let Some(zone) = manager.find_zone(name).await else { return false };
+        let name = name.to_lowercase();
+        let Some(relative) = name.strip_suffix(&format!(".{}", zone.domain)) else { return false };
+        if relative == "_dmarc" || relative == "default._bimi" || self.is_nameserver_host(&manager, &name).await {
+            return false;
+        }
+        let Ok(owner) = Name::from_ascii(&name) else { return false };
+        
+        if !zone.enabled || zone.verification_status != VerificationStatus::Verified {
+            response.set_response_code(ResponseCode::Refused);
+            return true;
+        }
+        
+        if query_type == RecordType::MX && zone.abuse_state == Some(AbuseState::Quarantined) {
+            response.set_response_code(ResponseCode::NXDomain);
+            return true;
+        }
+        
+        // The MX target we hand out for the zone exists whatever the policy
+        if name == self.mail_server_for(&zone) {
+            for record in self.address_records(&owner, self.mail_ips_for(&zone), query_type) {
+                response.add_answer(record);
+            }
+            response.set_response_code(ResponseCode::NoError);
+            return true;
+        }
+        
+        // Mail clients look up autodiscover/autoconfig; answered unless the zone has its own records
+        if (relative == "autodiscover" || relative == "autoconfig") && zone.explicit_records(relative).is_empty() {
+            if let Some(target) = self.autodiscover_for(&zone).and_then(|target| Name::from_ascii(target).ok()) {
+                response.add_answer(Record::from_rdata(
+                    owner,
+                    self.ttl_for(&zone),
+                    RData::CNAME(trust_dns_proto::rr::rdata::CNAME(target)),
+                ));
                 response.set_response_code(ResponseCode::NoError);
+                return true;
+            }
+        }
+        
+        // The autodiscover target, when it is not the MX target, is answered like it
+        if Some(&name) == self.autodiscover_for(&zone).as_ref() {
+            for record in self.address_records(&owner, self.mail_ips_for(&zone), query_type) {
+                response.add_answer(record);
+            }
+            response.set_response_code(ResponseCode::NoError);
+            return true;
+        }
+        
+        // Names with records of their own are answered from them, whatever the policy
+        if !zone.explicit_records(relative).is_empty() {
+            response.add_answers(Self::explicit_answers(&zone, relative, &owner, query_type));
+            response.set_response_code(ResponseCode::NoError);
+            return true;
+        }
+        
+        // Pattern rules answer the other names, whatever the policy
+        if self.add_synthetic_records(&zone, &owner, query_type, response) {
+            response.set_response_code(ResponseCode::NoError);
+            return true;
+        }
+        
+        let code = match zone.subdomain_policy().unwrap_or(self.config.default_subdomain_policy) {
+            SubdomainPolicy::Nxdomain | SubdomainPolicy::Explicit => ResponseCode::NXDomain,
+            SubdomainPolicy::Wildcard => {
+                match query_type {
+                    RecordType::A => {
+                        if let Ok(ip) = zone.ip.parse::<std::net::Ipv4Addr>() {
+                            response.add_answer(Record::from_rdata(owner, self.ttl_for(&zone), RData::A(ip.into())));
+                        }
+                    }
+                    RecordType::MX => {
+                        if let Some(bounce) = self.payment_required_mx(&zone) {
+                            response.add_answer(Record::from_rdata(
+                                owner,
+                                self.ttl_for(&zone),
+                                RData::MX(trust_dns_proto::rr::rdata::MX::new(self.config.mx_priority, bounce)),
+                            ));
+                        } else if let Ok(mail_server) = Name::from_ascii(self.mail_server_for(&zone)) {
+                            response.add_answer(Record::from_rdata(
+                                owner,
+                                self.ttl_for(&zone),
+                                RData::MX(trust_dns_proto::rr::rdata::MX::new(self.config.mx_priority, mail_server.clone())),
+                            ));
+                            for glue in self.mail_target_records(&zone, &mail_server) {
+                                response.add_additional(glue);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                ResponseCode::NoError
+            }
+        };
+        
+        response.set_response_code(code);
+        true
+    }
+    
+    /// Adds an answer for each of the zone's pattern rules that matches `owner` and is of
+    /// `query_type` or a CNAME. Returns false if no rule matches the name at all.
+    fn add_synthetic_records(&self, zone: &DomainRecord, owner: &Name, query_type: RecordType, response: &mut Message) -> bool {
+        let name = owner.to_ascii();
+        let name = name.trim_end_matches('.');
+        let rules = zone.synthetic_rules();
+        let mut matched = false;
+        for rule in &rules {
+            let Some(value) = self.synthetic_patterns.expand(rule, name) else { continue };
+            matched = true;
+            if rule.record_type != query_type.to_string() && rule.record_type != "CNAME" {
+                continue;
+            }
+            if let Some(rdata) = Self::rdata(&rule.record_type, &value, rule.priority) {
+                response.add_answer(Record::from_rdata(owner.clone(), rule.ttl.unwrap_or_else(|| self.ttl_for(zone)), rdata));
             }
         }
+        matched
+    }
+    
+    /// The zone's stored records named `relative` that are of `query_type` or a CNAME.
+    fn explicit_answers(zone: &DomainRecord, relative: &str, owner: &Name, query_type: RecordType) -> Vec<Record> {
+        let query_type = query_type.to_string();
+        zone.explicit_records(relative).into_iter()
+            .filter(|r| r.record_type == query_type || r.record_type == "CNAME")
+            .filter_map(|r| Self::explicit_rdata(r).map(|rdata| Record::from_rdata(owner.clone(), r.ttl as u32, rdata)))
+            .collect()
+    }
+    
+    /// Wire form of a stored record, or `None` if its value does not parse.
+    fn explicit_rdata(record: &DnsRecord) -> Option<RData> {
+        Self::rdata(&record.record_type, &record.value, record.priority as u16)
+    }
+    
+    fn rdata(record_type: &str, value: &str, priority: u16) -> Option<RData> {
+        let trimmed = value.trim();
+        match record_type {
+            "A" => trimmed.parse::<std::net::Ipv4Addr>().ok().map(|ip| RData::A(ip.into())),
+            "AAAA" => trimmed.parse::<std::net::Ipv6Addr>().ok().map(|ip| RData::AAAA(ip.into())),
+            "CNAME" => Name::from_ascii(trimmed).ok().map(|target| RData::CNAME(trust_dns_proto::rr::rdata::CNAME(target))),
+            "MX" => Name::from_ascii(trimmed).ok()
+                .map(|target| RData::MX(trust_dns_proto::rr::rdata::MX::new(priority, target))),
+            "SRV" => {
+                let mut parts = trimmed.split_whitespace();
+                let weight = parts.next()?.parse().ok()?;
+                let port = parts.next()?.parse().ok()?;
+                let target = Name::from_ascii(parts.next()?).ok()?;
+                Some(RData::SRV(trust_dns_proto::rr::rdata::SRV::new(priority, weight, port, target)))
+            }
+            "TXT" => Some(Self::txt_rdata(value)),
+            _ => None,
+        }
+    }
+    
+    /// A TXT record for `value`, split into 255-byte character-strings as the wire format needs.
+    fn txt_rdata(value: impl AsRef<str>) -> RData {
+        RData::TXT(trust_dns_proto::rr::rdata::TXT::new(txt_record::chunks(value.as_ref())))
+    }
+    
+    /// TTL for the answers we synthesize for a domain: its override, else `default_ttl`.
+    fn ttl_for(&self, record: &DomainRecord) -> u32 {
+        record.ttl_override().unwrap_or(self.config.default_ttl)
+    }
+    
+    /// The routing profile a domain's mail goes through; `None` for the default mail host.
+    fn routing_profile(&self, record: &DomainRecord) -> Option<&RoutingProfile> {
+        record.routing_profile().and_then(|name| self.config.routing_profile(name))
+    }
+    
+    /// What hostname templates are rendered with for a domain.
+    fn template_context<'a>(&'a self, record: &'a DomainRecord) -> TemplateContext<'a> {
+        match record.routing_profile().and_then(|name| Some((name, self.config.routing_profile(name)?))) {
+            Some((name, profile)) => profile.context(name, self.config.infrastructure_zone()),
+            None => self.config.template_context(),
+        }
+    }
+    
+    /// MX target for a domain: from its routing profile's template, else `mail_server`.
+    fn mail_server_for(&self, record: &DomainRecord) -> String {
+        let template = match self.routing_profile(record) {
+            Some(profile) => &profile.mx_template,
+            None => &self.config.mail_server,
+        };
+        hostname_template::render(template, &self.template_context(record), &record.domain)
+    }
+    
+    /// The bounce host MX answers point at while a domain is paused for non-payment; `None`
+    /// for paid-up domains, or when `payment_required_mx` is unset.
+    fn payment_required_mx(&self, record: &DomainRecord) -> Option<Name> {
+        if !record.payment_paused {
+            return None;
+        }
+        let template = self.config.payment_required_mx.as_ref()?;
+        Name::from_ascii(hostname_template::render(template, &self.config.template_context(), &record.domain)).ok()
+    }
+    
+    /// CNAME target for `autodiscover.<domain>` and `autoconfig.<domain>`: from the routing
+    /// profile's template, else `autodiscover_template`; `None` if neither is set.
+    fn autodiscover_for(&self, record: &DomainRecord) -> Option<String> {
+        let template = self.routing_profile(record)
+            .and_then(|profile| profile.autodiscover_template.as_ref())
+            .or(self.config.autodiscover_template.as_ref())?;
+        Some(hostname_template::render(template, &self.template_context(record), &record.domain))
+    }
+    
+    /// Addresses of a domain's MX target: its routing profile's `mail_ips`, else the first
+    /// `mail_server_ips` entry.
+    fn mail_ips_for(&self, record: &DomainRecord) -> &[String] {
+        match self.routing_profile(record) {
+            Some(profile) => &profile.mail_ips,
+            None => &self.config.mail_server_ips[..self.config.mail_server_ips.len().min(1)],
+        }
+    }
+    
+    /// A and AAAA records for a domain's MX target, for the additional section. Empty with
+    /// `mx_additional_records` off, and for targets outside the domain and our infrastructure
+    /// zone, which we do not answer for.
+    fn mail_target_records(&self, record: &DomainRecord, target: &Name) -> Vec<Record> {
+        if !self.config.mx_additional_records {
+            return Vec::new();
+        }
+        let name = target.to_ascii().trim_end_matches('.').to_ascii_lowercase();
+        let within = |zone: &str| name == zone || name.ends_with(&format!(".{}", zone));
+        let ours = within(&record.domain)
+            || self.config.infrastructure.as_ref().is_some_and(|infra| within(&infra.zone.trim_end_matches('.').to_ascii_lowercase()));
+        if !ours {
+            return Vec::new();
+        }
+        
+        let ips = self.mail_ips_for(record);
+        let mut records = self.address_records(target, ips, RecordType::A);
+        records.extend(self.address_records(target, ips, RecordType::AAAA));
+        records
+    }
+    
+    /// The domain whose MX target `name` is, when `name` is also a managed domain of its own,
+    /// e.g. a separately added `mail.example.com`. Names that are only below a managed domain
+    /// are answered by `handle_subdomain` instead.
+    async fn mail_target_owner(&self, manager: &DomainManager, name: &str) -> Option<DomainRecord> {
+        let (_, parent) = name.split_once('.')?;
+        let owner = manager.find_zone(parent).await?;
+        (self.mail_server_for(&owner) == name.to_ascii_lowercase()).then_some(owner)
+    }
+    
+    /// The profile whose MX or autodiscover target `name` is, for a managed domain actually on
+    /// that profile.
+    async fn profile_target(&self, name: &str, zone: &str) -> Option<&RoutingProfile> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let manager = self.domain_manager.read().await;
+        for (profile_name, profile) in &self.config.routing_profiles {
+            let context = profile.context(profile_name, zone);
+            let Some(domain) = profile.target_domain(&context, &name) else { continue };
+            // Only exists for domains we hand that MX target out to
+            if let Some(record) = manager.get_domain(domain).await {
+                if record.routing_profile() == Some(profile_name.as_str()) {
+                    return Some(profile);
+                }
+            }
+        }
+        None
+    }
+    
+    /// `v=spf1 a mx`, the global `spf_includes`, the routing profile's, then the domain's own
+    /// includes, each once.
+    fn spf_for(&self, record: &DomainRecord) -> String {
+        let routing = record.mail_routing().unwrap_or_default();
+        let context = self.template_context(record);
+        let profile_includes = self.routing_profile(record).map(|profile| &profile.spf_includes[..]).unwrap_or(&[]);
+        let mut includes: Vec<String> = Vec::new();
+        for template in self.config.spf_includes.iter().chain(profile_includes).chain(&routing.spf_includes) {
+            let include = hostname_template::render(template, &context, &record.domain);
+            if !includes.contains(&include) {
+                includes.push(include);
+            }
+        }
+        
+        let mut spf = "v=spf1 a mx".to_string();
+        for include in includes {
+            spf.push_str(&format!(" include:{}", include));
+        }
+        spf.push_str(" -all");
+        spf
     }
     
     async fn handle_a_record(&self, domain: &str, response: &mut Message) {
@@ -78,44 +1080,38 @@ impl CybertempHandler {
                 return;
             }
             
-            // Use the IP from the domain record (which could be Discord IP)
+            // The customer's own apex A records replace ours
+            let name = Name::from_ascii(domain).unwrap();
+            let custom = Self::explicit_answers(&record, "@", &name, RecordType::A);
+            if !custom.is_empty() {
+                response.add_answers(custom);
+                response.set_response_code(ResponseCode::NoError);
+                return;
+            }
+            
+            // Use the IP from the domain record, which its routing profile may have set
             if let Ok(ip) = record.ip.parse::<std::net::Ipv4Addr>() {
-                let name = Name::from_ascii(domain).unwrap();
                 let dns_record = Record::from_rdata(
-                    name,
-                    self.config.default_ttl,
+                    name.clone(),
+                    self.ttl_for(&record),
                     RData::A(ip.into()),
                 );
                 response.add_answer(dns_record);
             }
             
-            // Handle mail subdomain with appropriate IP
-            if domain.starts_with("mail.") || domain == "mail" {
-                let base_domain = if domain == "mail" {
-                    // This is for mail.cybertemp.xyz etc
-                    "cybertemp.xyz"
-                } else {
-                    domain.trim_start_matches("mail.")
-                };
-                
-                if let Some(parent_record) = manager.get_domain(base_domain).await {
-                    let mail_ip = if parent_record.discord {
-                        "37.114.41.81"
-                    } else {
-                        "45.134.39.50"
-                    };
-                    
-                    if let Ok(ip) = mail_ip.parse::<std::net::Ipv4Addr>() {
-                        let name = Name::from_ascii(domain).unwrap();
-                        let dns_record = Record::from_rdata(
-                            name,
-                            self.config.default_ttl,
-                            RData::A(ip.into()),
-                        );
-                        response.add_answer(dns_record);
+            // A domain that is also another domain's MX target answers with the mail addresses
+            // too; a delegated `mail.customer.com` zone is a zone of its own and only answers with its IP
+            if !record.subdomain_hosted() {
+                if let Some(parent_record) = self.mail_target_owner(&manager, domain).await {
+                    for mail_record in self.address_records(&name, self.mail_ips_for(&parent_record), RecordType::A) {
+                        response.add_answer(mail_record);
                     }
                 }
             }
+        } else if self.is_nameserver_host(&manager, domain).await {
+            for record in self.glue_records(domain, RecordType::A) {
+                response.add_answer(record);
+            }
         }
         
         response.set_response_code(ResponseCode::NoError);
@@ -130,39 +1126,79 @@ impl CybertempHandler {
                 return;
             }
             
+            // Quarantined domains keep resolving but stop receiving mail
+            if record.abuse_state == Some(AbuseState::Quarantined) {
+                response.set_response_code(ResponseCode::NXDomain);
+                return;
+            }
+            
             let name = Name::from_ascii(domain).unwrap();
             
-            // Create appropriate mail server name based on Discord flag
-            let mail_server = if record.discord {
-                format!("mail.{}.discord.cybertemp.xyz", domain)
-            } else {
-                self.config.mail_server.replace("{domain}", domain)
-            };
+            // Unpaid domains bounce mail with a payment notice instead of receiving it
+            if let Some(bounce) = self.payment_required_mx(&record) {
+                for owner in [name, Name::from_ascii(&format!("*.{}", domain)).unwrap()] {
+                    response.add_answer(Record::from_rdata(
+                        owner,
+                        self.ttl_for(&record),
+                        RData::MX(trust_dns_proto::rr::rdata::MX::new(self.config.mx_priority, bounce.clone())),
+                    ));
+                }
+                response.set_response_code(ResponseCode::NoError);
+                return;
+            }
             
+            let mail_server = self.mail_server_for(&record);
             let mx_name = Name::from_ascii(&mail_server).unwrap();
+            let routing = record.mail_routing().unwrap_or_default();
+            let custom = Self::explicit_answers(&record, "@", &name, RecordType::MX);
             
-            // Main MX record
-            let mx_record = Record::from_rdata(
-                name.clone(),
-                self.config.default_ttl,
-                RData::MX(trust_dns_proto::rr::rdata::MX::new(
-                    self.config.mx_priority,
-                    mx_name.clone(),
-                )),
-            );
-            response.add_answer(mx_record);
+            if !custom.is_empty() {
+                // The customer's own apex MX records replace ours, and with them our wildcard
+                // MX and the addresses of our mail target, which their mail does not go to
+                response.add_answers(custom);
+                response.set_response_code(ResponseCode::NoError);
+                return;
+            }
+            
+            if routing.mode == MailRoutingMode::Coexistence {
+                // The apex stays with the customer's provider
+                for mx in &routing.mx {
+                    let Ok(host) = Name::from_ascii(&mx.host) else { continue };
+                    response.add_answer(Record::from_rdata(
+                        name.clone(),
+                        self.ttl_for(&record),
+                        RData::MX(trust_dns_proto::rr::rdata::MX::new(mx.priority, host)),
+                    ));
+                }
+            } else {
+                // Main MX record
+                let mx_record = Record::from_rdata(
+                    name.clone(),
+                    self.ttl_for(&record),
+                    RData::MX(trust_dns_proto::rr::rdata::MX::new(
+                        self.config.mx_priority,
+                        mx_name.clone(),
+                    )),
+                );
+                response.add_answer(mx_record);
+            }
             
             // Wildcard MX record
             let wildcard_name = Name::from_ascii(&format!("*.{}", domain)).unwrap();
             let wildcard_mx_record = Record::from_rdata(
                 wildcard_name,
-                self.config.default_ttl,
+                self.ttl_for(&record),
                 RData::MX(trust_dns_proto::rr::rdata::MX::new(
                     self.config.mx_priority,
-                    mx_name,
+                    mx_name.clone(),
                 )),
             );
             response.add_answer(wildcard_mx_record);
+            
+            // Addresses of our MX target, so resolvers need no second lookup
+            for glue in self.mail_target_records(&record, &mx_name) {
+                response.add_additional(glue);
+            }
         }
         
         response.set_response_code(ResponseCode::NoError);
@@ -171,6 +1207,51 @@ impl CybertempHandler {
     async fn handle_txt_record(&self, domain: &str, response: &mut Message) {
         let manager = self.domain_manager.read().await;
         
+        // BIMI assertion record for the default selector
+        if let Some(base_domain) = domain.strip_prefix("default._bimi.") {
+            if let Some(record) = manager.get_domain(base_domain).await {
+                if !record.enabled || record.verification_status != VerificationStatus::Verified {
+                    response.set_response_code(ResponseCode::Refused);
+                    return;
+                }
+                
+                if let Some(bimi) = record.bimi_txt() {
+                    let name = Name::from_ascii(domain).unwrap();
+                    let bimi_record = Record::from_rdata(
+                        name,
+                        self.ttl_for(&record),
+                        Self::txt_rdata(bimi),
+                    );
+                    response.add_answer(bimi_record);
+                }
+            }
+            
+            response.set_response_code(ResponseCode::NoError);
+            return;
+        }
+        
+        // DMARC record, per-domain override or the global policy
+        if let Some(base_domain) = domain.strip_prefix("_dmarc.") {
+            if let Some(record) = manager.get_domain(base_domain).await {
+                if !record.enabled || record.verification_status != VerificationStatus::Verified {
+                    response.set_response_code(ResponseCode::Refused);
+                    return;
+                }
+                
+                let policy = record.dmarc_policy().unwrap_or_else(|| self.config.dmarc.clone());
+                let name = Name::from_ascii(domain).unwrap();
+                let dmarc_record = Record::from_rdata(
+                    name,
+                    self.ttl_for(&record),
+                    Self::txt_rdata(policy.to_txt()),
+                );
+                response.add_answer(dmarc_record);
+            }
+            
+            response.set_response_code(ResponseCode::NoError);
+            return;
+        }
+        
         if let Some(record) = manager.get_domain(domain).await {
             if !record.enabled || record.verification_status != VerificationStatus::Verified {
                 response.set_response_code(ResponseCode::Refused);
@@ -179,26 +1260,31 @@ impl CybertempHandler {
             
             let name = Name::from_ascii(domain).unwrap();
             
+            // Customer TXT records such as site verification; an SPF record of theirs replaces ours,
+            // since a second one would make SPF fail
+            let custom = record.explicit_records("@").into_iter().filter(|r| r.record_type == "TXT").collect::<Vec<_>>();
+            for txt in &custom {
+                response.add_answer(Record::from_rdata(name.clone(), txt.ttl as u32, Self::txt_rdata(&txt.value)));
+            }
+            
             // SPF record
-            let spf_record = Record::from_rdata(
-                name.clone(),
-                self.config.default_ttl,
-                RData::TXT(trust_dns_proto::rr::rdata::TXT::new(vec![
-                    "v=spf1 a mx include:_spf.google.com -all".to_string(),
-                ])),
-            );
-            response.add_answer(spf_record);
-            
-            // DMARC record
-            let dmarc_name = Name::from_ascii(&format!("_dmarc.{}", domain)).unwrap();
-            let dmarc_record = Record::from_rdata(
-                dmarc_name,
-                self.config.default_ttl,
-                RData::TXT(trust_dns_proto::rr::rdata::TXT::new(vec![
-                    "v=DMARC1; p=none;".to_string(),
-                ])),
-            );
-            response.add_answer(dmarc_record);
+            if !custom.iter().any(|txt| txt.value.to_ascii_lowercase().starts_with("v=spf1")) {
+                let spf_record = Record::from_rdata(
+                    name.clone(),
+                    self.ttl_for(&record),
+                    Self::txt_rdata(self.spf_for(&record)),
+                );
+                response.add_answer(spf_record);
+            }
+            
+            // Says why mail bounces while the domain is unpaid
+            if self.payment_required_mx(&record).is_some() {
+                response.add_answer(Record::from_rdata(
+                    name,
+                    self.ttl_for(&record),
+                    Self::txt_rdata(&self.config.payment_required_txt),
+                ));
+            }
         }
         
         response.set_response_code(ResponseCode::NoError);
@@ -214,21 +1300,255 @@ impl CybertempHandler {
             }
             
             let name = Name::from_ascii(domain).unwrap();
+            let nameservers = self.served_nameservers(&record);
             
-            for ns in &self.config.nameservers {
+            for ns in &nameservers {
                 let ns_record = Record::from_rdata(
                     name.clone(),
-                    self.config.default_ttl,
+                    self.ttl_for(&record),
                     RData::NS(trust_dns_proto::rr::rdata::NS(Name::from_ascii(ns).unwrap())),
                 );
                 response.add_answer(ns_record);
+                
+                // Glue for nameserver names we are authoritative for
+                let ns = ns.trim_end_matches('.');
+                if self.is_nameserver_host(&manager, ns).await {
+                    for glue in self.glue_records(ns, RecordType::A).into_iter().chain(self.glue_records(ns, RecordType::AAAA)) {
+                        response.add_additional(glue);
+                    }
+                }
             }
         }
         
         response.set_response_code(ResponseCode::NoError);
     }
     
-    async fn handle_aaaa_record(&self, _domain: &str, response: &mut Message) {
+    async fn handle_soa_record(&self, domain: &str, response: &mut Message) {
+        let manager = self.domain_manager.read().await;
+        
+        if let Some(record) = manager.get_domain(domain).await {
+            if !record.enabled || record.verification_status != VerificationStatus::Verified {
+                response.set_response_code(ResponseCode::Refused);
+                return;
+            }
+            
+            let name = Name::from_ascii(domain).unwrap();
+            let primary = self.served_nameservers(&record).into_iter().next()
+                .unwrap_or_else(|| format!("ns1.{}", domain));
+            
+            let soa_record = Record::from_rdata(
+                name,
+                self.ttl_for(&record),
+                RData::SOA(trust_dns_proto::rr::rdata::SOA::new(
+                    Name::from_ascii(&primary).unwrap(),
+                    Name::from_ascii(&format!("hostmaster.{}", domain)).unwrap(),
+                    record.soa_serial as u32,
+                    3600,
+                    600,
+                    604800,
+                    self.config.default_ttl,
+                )),
+            );
+            response.add_answer(soa_record);
+        }
+        
         response.set_response_code(ResponseCode::NoError);
     }
-}
\ No newline at end of file
+    
+    /// NS names for a domain: its explicit override, its assigned set, or the global list.
+    fn served_nameservers(&self, record: &DomainRecord) -> Vec<String> {
+        if let Some(overrides) = record.nameserver_overrides() {
+            return overrides;
+        }
+        
+        record.nameserver_set()
+            .and_then(|name| self.config.nameserver_sets.get(name))
+            .map(|set| set.nameservers.clone())
+            .unwrap_or_else(|| self.config.nameservers.clone())
+    }
+    
+    /// True for white-label nameserver identities, and for names inside our verified
+    /// zones that we hand out as nameservers, either globally or as some domain's override.
+    async fn is_nameserver_host(&self, manager: &DomainManager, name: &str) -> bool {
+        if self.config.nameserver_sets.values().any(|set| set.contains(name)) {
+            return true;
+        }
+        
+        match manager.find_zone(name).await {
+            Some(zone) if zone.enabled && zone.verification_status == VerificationStatus::Verified => {
+                self.config.nameservers.iter().any(|ns| ns.trim_end_matches('.').eq_ignore_ascii_case(name))
+                    || manager.is_vanity_nameserver(&name.to_lowercase()).await
+            }
+            _ => false,
+        }
+    }
+    
+    /// A or AAAA records pointing `name` at the addresses of the nameserver set it belongs
+    /// to, or at `nameserver_ips` for names outside any set.
+    fn glue_records(&self, name: &str, record_type: RecordType) -> Vec<Record> {
+        let Ok(owner) = Name::from_ascii(name) else { return Vec::new() };
+        let ips = match self.config.nameserver_sets.values().find(|set| set.contains(name)) {
+            Some(set) => &set.ips,
+            None => &self.config.nameserver_ips,
+        };
+        
+        self.address_records(&owner, ips, record_type)
+    }
+    
+    /// The A (or AAAA) records for whichever of `ips` match the family of `record_type`.
+    fn address_records(&self, owner: &Name, ips: &[String], record_type: RecordType) -> Vec<Record> {
+        ips.iter()
+            .filter_map(|ip| ip.parse::<std::net::IpAddr>().ok())
+            .filter_map(|ip| match (ip, record_type) {
+                (std::net::IpAddr::V4(ip), RecordType::A) => Some(RData::A(ip.into())),
+                (std::net::IpAddr::V6(ip), RecordType::AAAA) => Some(RData::AAAA(ip.into())),
+                _ => None,
+            })
+            .map(|rdata| Record::from_rdata(owner.clone(), self.config.default_ttl, rdata))
+            .collect()
+    }
+    
+    async fn handle_aaaa_record(&self, domain: &str, response: &mut Message) {
+        let manager = self.domain_manager.read().await;
+        
+        match manager.get_domain(domain).await {
+            Some(record) => {
+                let serving = record.enabled && record.verification_status == VerificationStatus::Verified;
+                let name = Name::from_ascii(domain).unwrap();
+                let custom = Self::explicit_answers(&record, "@", &name, RecordType::AAAA);
+                if serving && !custom.is_empty() {
+                    // The customer's own apex AAAA records
+                    response.add_answers(custom);
+                } else if serving && !record.subdomain_hosted() {
+                    if let Some(parent_record) = self.mail_target_owner(&manager, domain).await {
+                        for mail_record in self.address_records(&name, self.mail_ips_for(&parent_record), RecordType::AAAA) {
+                            response.add_answer(mail_record);
+                        }
+                    }
+                }
+            }
+            None if self.is_nameserver_host(&manager, domain).await => {
+                for record in self.glue_records(domain, RecordType::AAAA) {
+                    response.add_answer(record);
+                }
+            }
+            None => {}
+        }
+        
+        response.set_response_code(ResponseCode::NoError);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain_store::DomainStore;
+    use crate::test_support::HandlerHarness;
+    use trust_dns_proto::rr::rdata::{MX, TXT};
+    
+    /// An apex MX query's answer: the MX, the wildcard MX and the SPF record, all under one zone.
+    fn mail_response() -> Message {
+        let apex = Name::from_ascii("example.com.").unwrap();
+        let wildcard = Name::from_ascii("*.example.com.").unwrap();
+        let mail = Name::from_ascii("mail.example.com.").unwrap();
+        
+        let mut message = Message::new();
+        message.set_message_type(MessageType::Response);
+        message.add_query(Query::query(apex.clone(), RecordType::MX));
+        message.add_answer(Record::from_rdata(apex.clone(), 300, RData::MX(MX::new(10, mail.clone()))));
+        message.add_answer(Record::from_rdata(wildcard, 300, RData::MX(MX::new(10, mail))));
+        message.add_answer(Record::from_rdata(apex, 300, RData::TXT(TXT::new(vec!["v=spf1 mx -all".to_string()]))));
+        message
+    }
+    
+    #[test]
+    fn compression_shrinks_mail_answers() {
+        let message = mail_response();
+        let compressed = CybertempHandler::encode_message(&message).unwrap();
+        
+        // Canonical names are written out in full, without compression pointers
+        let mut uncompressed = Vec::new();
+        let mut encoder = BinEncoder::new(&mut uncompressed);
+        encoder.set_canonical_names(true);
+        message.emit(&mut encoder).unwrap();
+        
+        assert!(compressed.len() < uncompressed.len(), "{} >= {}", compressed.len(), uncompressed.len());
+        assert!(compressed.len() <= MAX_UDP_PAYLOAD);
+        assert_eq!(Message::from_vec(&compressed).unwrap().answers(), message.answers());
+    }
+    
+    fn request(names: &[&str], op_code: OpCode) -> Message {
+        let mut request = Message::new();
+        request.set_id(7);
+        request.set_message_type(MessageType::Query);
+        request.set_op_code(op_code);
+        for name in names {
+            request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        }
+        request
+    }
+    
+    #[tokio::test]
+    async fn no_question_is_formerr() {
+        let harness = HandlerHarness::new(DnsConfig::default());
+        let response = harness.handle(&request(&[], OpCode::Query)).await.unwrap();
+        
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+        assert!(response.queries().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn several_questions_are_formerr() {
+        let harness = HandlerHarness::new(DnsConfig::default());
+        let response = harness.handle(&request(&["example.com.", "example.net."], OpCode::Query)).await.unwrap();
+        
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+        assert!(response.queries().is_empty());
+        assert!(response.answers().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn other_opcodes_are_notimp() {
+        let harness = HandlerHarness::new(DnsConfig::default());
+        let response = harness.handle(&request(&["example.com."], OpCode::Status)).await.unwrap();
+        
+        assert_eq!(response.op_code(), OpCode::Status);
+        assert_eq!(response.response_code(), ResponseCode::NotImp);
+        assert!(response.answers().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn stored_records_are_answered_after_reconcile() {
+        let harness = HandlerHarness::new(DnsConfig::default());
+        harness.add_domain("example.com", "192.0.2.1", false).await.unwrap();
+        assert_eq!(harness.store().get_all_domains().await.unwrap()[0].domain, "example.com");
+        
+        // Written behind the manager's back, as another node would
+        harness.store().set_record("example.com", "A", "@", "192.0.2.55", 300, 0, "test").await.unwrap();
+        let report = harness.domain_manager().write().await.reconcile_with_database().await.unwrap();
+        assert_eq!(report.drifted, 1);
+        
+        let response = harness.query("example.com.", RecordType::A).await.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let answers: Vec<_> = response.answers().iter().filter_map(|record| record.data().cloned()).collect();
+        assert_eq!(answers, vec![RData::A("192.0.2.55".parse::<std::net::Ipv4Addr>().unwrap().into())]);
+    }
+    
+    #[tokio::test]
+    async fn custom_apex_mx_drops_wildcard_and_glue() {
+        let harness = HandlerHarness::new(DnsConfig::default());
+        harness.add_domain("example.com", "192.0.2.1", false).await.unwrap();
+        harness.domain_manager().write().await
+            .set_records("example.com", "MX", "@", &["5 mx.other.example.".to_string()], None, false, "test")
+            .await
+            .unwrap();
+        
+        let response = harness.query("example.com.", RecordType::MX).await.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let answers: Vec<_> = response.answers().iter().filter_map(|record| record.data().cloned()).collect();
+        assert_eq!(answers, vec![RData::MX(MX::new(5, Name::from_ascii("mx.other.example.").unwrap()))]);
+        assert!(response.answers().iter().all(|record| !record.name().is_wildcard()));
+        assert!(response.additionals().is_empty());
+    }
+}
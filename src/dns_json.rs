@@ -0,0 +1,128 @@
+use serde_json::{json, Value};
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::{RData, Record, RecordType};
+
+use crate::extended_error::extended_error;
+
+/// RFC 8427 JSON for a DNS message, with RDATA in presentation format under `rdata<TYPE>`.
+pub fn to_rfc8427(message: &Message) -> Value {
+    let questions: Vec<Value> = message.queries().iter()
+        .map(|query| json!({
+            "NAME": query.name().to_ascii(),
+            "TYPE": u16::from(query.query_type()),
+            "TYPEname": query.query_type().to_string(),
+            "CLASS": u16::from(query.query_class()),
+        }))
+        .collect();
+    
+    let mut value = json!({
+        "ID": message.id(),
+        "QR": message.message_type() as u8,
+        "Opcode": u8::from(message.op_code()),
+        "AA": message.authoritative() as u8,
+        "TC": message.truncated() as u8,
+        "RD": message.recursion_desired() as u8,
+        "RA": message.recursion_available() as u8,
+        "AD": message.authentic_data() as u8,
+        "CD": message.checking_disabled() as u8,
+        "RCODE": u16::from(message.response_code()),
+        "RCODEname": rcode_name(message.response_code()),
+        "QDCOUNT": message.queries().len(),
+        "ANCOUNT": message.answers().len(),
+        "NSCOUNT": message.name_servers().len(),
+        "ARCOUNT": message.additionals().len(),
+        "questionRRs": questions,
+        "answerRRs": message.answers().iter().map(record_json).collect::<Vec<_>>(),
+        "authorityRRs": message.name_servers().iter().map(record_json).collect::<Vec<_>>(),
+        "additionalRRs": message.additionals().iter().map(record_json).collect::<Vec<_>>(),
+    });
+    // Not part of RFC 8427; the Extended DNS Error explaining a denied answer
+    if let Some((code, text)) = extended_error(message) {
+        value["EDE"] = json!({"INFO-CODE": code, "EXTRA-TEXT": text});
+    }
+    value
+}
+
+fn record_json(record: &Record) -> Value {
+    let record_type: RecordType = record.record_type();
+    let mut value = json!({
+        "NAME": record.name().to_ascii(),
+        "TYPE": u16::from(record_type),
+        "TYPEname": record_type.to_string(),
+        "CLASS": u16::from(record.dns_class()),
+        "TTL": record.ttl(),
+    });
+    if let Some(rdata) = record.data() {
+        value[format!("rdata{}", record_type)] = json!(rdata.to_string());
+    }
+    value
+}
+
+/// The `application/dns-json` schema served by Google and Cloudflare's resolve APIs.
+pub fn to_dns_json(message: &Message) -> Value {
+    let questions: Vec<Value> = message.queries().iter()
+        .map(|query| json!({
+            "name": query.name().to_ascii(),
+            "type": u16::from(query.query_type()),
+        }))
+        .collect();
+    
+    let mut value = json!({
+        "Status": u16::from(message.response_code()),
+        "TC": message.truncated(),
+        "RD": message.recursion_desired(),
+        "RA": message.recursion_available(),
+        "AD": message.authentic_data(),
+        "CD": message.checking_disabled(),
+        "Question": questions,
+    });
+    // Like the public resolvers, sections are omitted rather than sent empty
+    for (key, records) in [
+        ("Answer", message.answers()),
+        ("Authority", message.name_servers()),
+        ("Additional", message.additionals()),
+    ] {
+        if !records.is_empty() {
+            value[key] = json!(records.iter().map(dns_json_record).collect::<Vec<_>>());
+        }
+    }
+    // Cloudflare's spelling of an Extended DNS Error
+    if let Some((code, text)) = extended_error(message) {
+        value["Comment"] = json!([format!("EDE({}): {}", code, text)]);
+    }
+    value
+}
+
+fn dns_json_record(record: &Record) -> Value {
+    let data = match record.data() {
+        // Quoted per character-string, as the public resolvers return TXT data
+        Some(RData::TXT(txt)) => txt.txt_data().iter()
+            .map(|chunk| {
+                let text = String::from_utf8_lossy(chunk).replace('\\', "\\\\").replace('"', "\\\"");
+                format!("\"{}\"", text)
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        Some(rdata) => rdata.to_string(),
+        None => String::new(),
+    };
+    json!({
+        "name": record.name().to_ascii(),
+        "type": u16::from(record.record_type()),
+        "TTL": record.ttl(),
+        "data": data,
+    })
+}
+
+/// The mnemonic from the IANA RCODE registry, e.g. `NXDOMAIN`.
+pub fn rcode_name(code: ResponseCode) -> String {
+    match code {
+        ResponseCode::NoError => "NOERROR".to_string(),
+        ResponseCode::FormErr => "FORMERR".to_string(),
+        ResponseCode::ServFail => "SERVFAIL".to_string(),
+        ResponseCode::NXDomain => "NXDOMAIN".to_string(),
+        ResponseCode::NotImp => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        other => u16::from(other).to_string(),
+    }
+}
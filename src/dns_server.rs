@@ -1,48 +1,175 @@
 use anyhow::Result;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::sync::RwLock;
 use tokio::time::{interval, Duration};
 use tracing::{info, error, warn};
 
-use crate::config::DnsConfig;
-use crate::domain_manager::DomainManager;
+use crate::abuse::{AbuseCase, AbuseEvent, AbuseState};
+use crate::answer_override::{AnswerOverride, OverrideRequest, OVERRIDE_REFRESH_INTERVAL_SECONDS};
+use crate::billing::{is_paid, BillingHold, PaymentCheckReport, PAYMENT_CHECK_INTERVAL_SECONDS};
+use crate::blocklist::{BlockKind, BlockedDomain};
+use crate::canary::Canary;
+use crate::config::{DmarcPolicy, DnsConfig, EffectiveConfig, MailRouting, SubdomainPolicy, DISCORD_PROFILE};
+use crate::consistency::{check_targets, Misconfiguration};
+use crate::lint::{lint_domain, LintReport};
+use crate::smtp_check::{check_mail_servers, MailServerProblem, SmtpExpectations};
+use crate::domain_manager::{DomainManager, DomainRecord, DomainWrite, PartialDelegation, ReconcileReport, ReconcileStats, VerificationChange, VerificationOutcome};
 use crate::dns_handler::CybertempHandler;
-use crate::database::Database;
-use crate::supabase_client::SupabaseClient;
+use crate::database::{Database, DomainEvent, ScheduledChange, VerificationEntry, ZoneChange};
+use crate::db_breaker::{BreakerStatus, DbBreaker};
+use crate::domain_name::domain_key;
+use crate::external_verification::{ExternalVerification, ExternalVerificationRequest, EXTERNAL_VERIFICATION_INTERVAL_SECONDS};
+use crate::ip_migration::{accepts_connections, serves_ip, IpMigration, IpMigrationRequest, MigrationDomain, MigrationPhase, IP_MIGRATION_INTERVAL_SECONDS, VERIFICATION_TIMEOUT_MINUTES};
+use crate::outbox::{backoff, deliver, domain_event_notifications, domain_transfer_notifications, partial_delegation_notifications, Channel, OutboxMessage};
+use crate::query_stats::{QueryStats, TopStats};
+use crate::transport_stats::{TransportSnapshot, TransportStats};
+use crate::anomaly::QueryAnomalyDetector;
+use crate::stats_history::{query_totals, rollup, DomainQueryCounter, QueryTotal, StatsRetention};
+use crate::node_stats::{aggregate, Breakdown, FleetStats, STALE_AFTER_PUSHES};
+use crate::node_registry::{hostname, NodeInfo, NodeRegistration, NODE_HEARTBEAT_INTERVAL_SECONDS};
+use crate::node_health::NodeHealth;
+use crate::usage::{domain_uptime, usage_report, DomainUptime, DomainUsage};
+use crate::outbox::query_spike_notifications;
+use crate::outbox::ct_finding_notifications;
+use crate::ct_monitor::{allowed_issuers, fetch_certificates, CtFinding};
+use crate::response_cache::ResponseCache;
+use crate::packet_capture::{CaptureRequest, CaptureStatus, PacketCapture};
+use crate::packet_guard::PacketGuard;
+use crate::query_middleware::ClientRateLimit;
+use crate::query_script::QueryScript;
+use crate::shadow::ShadowMirror;
+use crate::transfer::{hash_claim_token, new_claim_token, ClaimRequest, DomainTransfer, TransferRequest, TransferStatus, CLAIM_TOKEN_TTL_HOURS};
+use crate::record_templates::{RecordTemplate, ReapplyReport};
+use crate::scheduler::ZoneMutation;
+use crate::approval::{key_fingerprint, ApprovalStatus, PendingChange, RecordChange};
+use crate::supabase_client::{DomainOwner, SupabaseClient, SyncPlan};
+use crate::synthetic::SyntheticRule;
 use crate::http_redirect::start_http_redirect_server;
+use crate::doh::start_doh_server;
+use crate::dns_stream::{serve_tcp, serve_tls, tls_acceptor};
+use crate::api::DnsApiServer;
+use crate::snapshot::{read_snapshot, write_snapshot};
+use crate::state_dump::{write_state_dump, StateDump, DUMP_LOCK_TIMEOUT};
+use crate::resolvers::{propagation_resolvers, verification_quorum, verification_resolvers, NamedResolver};
+use crate::propagation::{check_propagation, PropagationReport};
+use crate::registrar::{instructions, DelegationInstructions, RdapClient};
+use crate::jobs::{supervise, JobScheduler, JobStats};
+use crate::runtime::{rss_bytes, RuntimeInfo, TokioRuntime};
 
-use hyper::{Body, Request, Response, Method, StatusCode};
-use hyper::service::{make_service_fn, service_fn};
-use hyper::Server;
-use std::convert::Infallible;
-use serde_json::json;
+use trust_dns_proto::op::Message;
+use trust_dns_proto::rr::RecordType;
+
+/// Maximum number of events returned per `/feed/domains` poll.
+const FEED_PAGE_SIZE: i64 = 1000;
+/// Receive buffer for UDP queries. Queries are small, but EDNS options such as cookies can
+/// take one past the classic 512 bytes, and a cut-off query fails to parse.
+const MAX_UDP_QUERY: usize = 4096;
+/// How often new domain events are queued and due notifications delivered.
+const OUTBOX_INTERVAL_SECONDS: u64 = 10;
+/// Notifications delivered per outbox pass.
+const OUTBOX_BATCH_SIZE: i64 = 100;
+/// How often staged zone changes are checked for an `effective_at` that has passed.
+const SCHEDULER_INTERVAL_SECONDS: u64 = 30;
+/// How often the database is retried while serving from a snapshot, and pinged otherwise.
+const DATABASE_RETRY_SECONDS: u64 = 10;
+/// Length of the interval per-domain query rates are measured over for spike detection.
+const ANOMALY_INTERVAL_SECONDS: u64 = 60;
+/// How often ended per-client DNS rate limit windows are dropped.
+const DNS_RATE_LIMIT_SWEEP_SECONDS: u64 = 60;
+/// How often closed 5-minute query count buckets are written to the stats history.
+const STATS_FLUSH_INTERVAL_SECONDS: u64 = 60;
+/// How often the stats history is rolled up and pruned.
+const STATS_ROLLUP_INTERVAL_SECONDS: u64 = 3600;
+/// How often internal verification state is pushed to Supabase.
+const SUPABASE_SYNC_INTERVAL_SECONDS: u64 = 300;
+/// How often the in-memory domains are diffed against the database and corrected.
+const RECONCILE_INTERVAL_SECONDS: u64 = 600;
+/// Domains `POST /verify` checks at the same time.
+const BULK_VERIFY_CONCURRENCY: usize = 16;
+/// Exclusion group of the jobs that hold the domain manager's write lock for a whole pass.
+const DOMAIN_MANAGER_JOBS: &str = "domain_manager";
 
 pub struct DnsServer {
     config: DnsConfig,
+    /// `config` with secrets redacted and where each key came from, for `GET /config`.
+    effective_config: EffectiveConfig,
+    /// When this process started, as registered in `nodes`.
+    started_at: chrono::DateTime<chrono::Utc>,
     domain_manager: Arc<RwLock<DomainManager>>,
     supabase_client: Option<Arc<SupabaseClient>>,
     database: Arc<Database>,
+    /// Opens when the database stops answering; see `db_breaker`.
+    db_breaker: Arc<DbBreaker>,
+    query_stats: Arc<QueryStats>,
+    transport_stats: Arc<TransportStats>,
+    query_anomalies: Arc<QueryAnomalyDetector>,
+    domain_counter: Arc<DomainQueryCounter>,
+    stats_retention: StatsRetention,
+    response_cache: Arc<ResponseCache>,
+    maintenance: Arc<AtomicBool>,
+    packet_capture: Arc<PacketCapture>,
+    packet_guard: Arc<PacketGuard>,
+    query_script: Option<Arc<QueryScript>>,
+    /// Staging handler live queries are mirrored to, with `shadow_config_path`.
+    shadow: Option<Arc<ShadowMirror>>,
+    /// Shared by every listener, so a client's UDP, TCP and TLS questions count together.
+    query_rate_limit: Arc<ClientRateLimit>,
+    misconfigurations: Arc<std::sync::RwLock<Vec<Misconfiguration>>>,
+    mail_server_problems: Arc<std::sync::RwLock<Vec<MailServerProblem>>>,
+    /// Set while answering from the on-disk snapshot because the database was down at startup.
+    serving_snapshot: Arc<AtomicBool>,
+    reconcile_stats: std::sync::Mutex<ReconcileStats>,
+    propagation_resolvers: Vec<NamedResolver>,
+    rdap: RdapClient,
+    /// Google/Microsoft ownership records being watched until public resolvers return them.
+    external_verifications: std::sync::Mutex<Vec<ExternalVerification>>,
+    /// Held while an IP migration is read, changed and saved, so the job and the API do not
+    /// overwrite each other.
+    ip_migration_lock: tokio::sync::Mutex<()>,
+    /// Verification status changes to push to Supabase; taken by `run`.
+    verification_events: std::sync::Mutex<Option<UnboundedReceiver<VerificationChange>>>,
+    /// Partial delegations to warn about; taken by `run`.
+    partial_delegation_events: std::sync::Mutex<Option<UnboundedReceiver<PartialDelegation>>>,
+    jobs: JobScheduler,
 }
 
 impl DnsServer {
-    pub async fn new(config_path: &str) -> Result<Self> {
-        let settings = config::Config::builder()
-            .add_source(config::Config::try_from(&DnsConfig::default())?)
-            .add_source(config::File::with_name(config_path).required(false))
-            .build()?;
-            
-        let config: DnsConfig = settings.try_deserialize()?;
+    /// Starts from the config at `config_path`, with `env`'s overlay if one is selected.
+    pub async fn new(config_path: &str, env: Option<&str>) -> Result<Self> {
+        let config = DnsConfig::load(config_path, env)?;
+        let effective_config = config.effective(config_path, env)?;
         
-        info!("Initializing DNS server...");
+        match &effective_config.overlay {
+            Some(overlay) => info!("Initializing cybertemp-dns {} with config {} and overlay {}", env!("CARGO_PKG_VERSION"), config_path, overlay),
+            None => info!("Initializing cybertemp-dns {} with config {}", env!("CARGO_PKG_VERSION"), config_path),
+        }
+        info!("Effective configuration: {}", serde_json::to_string(&effective_config)?);
         
-        // Initialize internal PostgreSQL database
-        let database = Database::new(&config.database_url).await?;
-        let database_arc = Arc::new(database);
+        // Initialize internal PostgreSQL database, falling back to the last snapshot if it is down
+        let (database, snapshot) = match Database::new(&config.database_url).await {
+            Ok(database) => (database, None),
+            Err(e) if config.snapshot_enabled => {
+                let snapshot = read_snapshot(&config.snapshot_path).await.map_err(|snapshot_error| {
+                    anyhow::anyhow!("Database unavailable ({}) and no usable snapshot at {}: {}", e, config.snapshot_path, snapshot_error)
+                })?;
+                warn!("Database unavailable ({}), serving snapshot from {} read-only", e, snapshot.written_at);
+                (Database::connect_lazy(&config.database_url)?, Some(snapshot))
+            }
+            Err(e) => return Err(e),
+        };
+        let database_arc = Arc::new(database.with_serial_strategy(config.soa_serial_strategy));
         
         // Initialize Supabase client if configured
         let supabase_client = if let (Some(url), Some(key)) = (&config.supabase_url, &config.supabase_key) {
-            let client = SupabaseClient::new(url.clone(), key.clone());
+            let discord_ip = config.routing_profile(DISCORD_PROFILE)
+                .and_then(|profile| profile.ip.clone())
+                .unwrap_or_else(|| config.default_ip.clone());
+            let client = SupabaseClient::new(url.clone(), key.clone())
+                .with_domain_ips(config.default_ip.clone(), discord_ip);
             if client.is_configured() {
                 info!("Supabase client configured for URL: {}", url);
                 Some(Arc::new(client))
@@ -55,70 +182,422 @@ impl DnsServer {
             None
         };
         
-        // Sync from Supabase if available
-        if let Some(supabase) = &supabase_client {
-            info!("Syncing domains from Supabase...");
-            match supabase.sync_from_supabase(&database_arc).await {
-                Ok(_) => info!("Successfully synced domains from Supabase"),
-                Err(e) => error!("Failed to sync from Supabase: {}", e),
+        let db_breaker = Arc::new(DbBreaker::new());
+        let mut domain_manager = DomainManager::new()
+            .with_resolvers(verification_resolvers(&config)?, verification_quorum(&config)?)
+            .with_database(database_arc.clone())
+            .with_db_breaker(db_breaker.clone())
+            .with_nameservers(config.nameservers.clone(), config.nameserver_sets.clone())
+            .with_routing_profiles(config.routing_profiles.clone(), config.default_ip.clone())
+            .with_reserved_labels(config.reserved_labels.clone())
+            .with_verification_timeout(Duration::from_secs(config.verification_timeout_seconds))
+            .with_serial_strategy(config.soa_serial_strategy);
+        let verification_events = if supabase_client.is_some() {
+            let (sender, receiver) = unbounded_channel();
+            domain_manager = domain_manager.with_verification_events(sender);
+            Some(receiver)
+        } else {
+            None
+        };
+        let (partial_delegation_sender, partial_delegation_events) = unbounded_channel();
+        domain_manager = domain_manager.with_partial_delegation_events(partial_delegation_sender);
+        
+        // Load domains from internal database
+        let serving_snapshot = snapshot.is_some();
+        match snapshot {
+            Some(snapshot) => domain_manager.load_snapshot(snapshot.domains),
+            None => {
+                if !config.maintenance_mode {
+                    for domain in database_arc.canonicalize_domain_names().await? {
+                        warn!("Merged domain row {} into the row with its canonical name; records it shared with that row stay on the removed row", domain);
+                    }
+                }
+                info!("Loading domains from internal database...");
+                domain_manager.load_from_database().await?;
             }
         }
         
-        let mut domain_manager = DomainManager::new().with_database(database_arc.clone());
+        let domain_manager = Arc::new(RwLock::new(domain_manager));
+        let response_cache = Arc::new(ResponseCache::new(Duration::from_secs(config.response_cache_ttl_seconds)));
+        // Serving a snapshot is read-only, which is what maintenance mode already enforces
+        let maintenance = Arc::new(AtomicBool::new(config.maintenance_mode || serving_snapshot));
+        if config.maintenance_mode {
+            warn!("Starting in maintenance mode");
+        }
         
-        // Load domains from internal database
-        info!("Loading domains from internal database...");
-        domain_manager.load_from_database().await?;
+        let propagation_resolvers = propagation_resolvers(&config)?;
+        let query_anomalies = Arc::new(QueryAnomalyDetector::new(
+            config.anomaly_spike_factor,
+            config.anomaly_min_queries_per_minute,
+            config.anomaly_baseline_minutes,
+        ));
+        let stats_retention = StatsRetention::from_config(&config)?;
+        let packet_guard = Arc::new(PacketGuard::new(config.parser_mode));
+        let query_rate_limit = Arc::new(ClientRateLimit::new(config.dns_rate_limit_per_minute));
+        let query_script = match &config.query_script_path {
+            Some(path) => {
+                info!("Loading query script from {}", path);
+                Some(Arc::new(QueryScript::load(path)?))
+            }
+            None => None,
+        };
         
-        let domain_manager = Arc::new(RwLock::new(domain_manager));
+        let shadow = match &config.shadow_config_path {
+            Some(path) => Some(Arc::new(ShadowMirror::load(path).await?)),
+            None => None,
+        };
         
         Ok(Self {
             config,
+            effective_config,
+            started_at: chrono::Utc::now(),
             domain_manager,
             supabase_client,
             database: database_arc,
+            db_breaker,
+            query_stats: Arc::new(QueryStats::new()),
+            transport_stats: Arc::new(TransportStats::new()),
+            query_anomalies,
+            domain_counter: Arc::new(DomainQueryCounter::new()),
+            stats_retention,
+            response_cache,
+            maintenance,
+            packet_capture: Arc::new(PacketCapture::new()),
+            packet_guard,
+            query_script,
+            shadow,
+            query_rate_limit,
+            misconfigurations: Arc::new(std::sync::RwLock::new(Vec::new())),
+            mail_server_problems: Arc::new(std::sync::RwLock::new(Vec::new())),
+            serving_snapshot: Arc::new(AtomicBool::new(serving_snapshot)),
+            reconcile_stats: std::sync::Mutex::new(ReconcileStats::default()),
+            propagation_resolvers,
+            rdap: RdapClient::new(&config.rdap_url),
+            external_verifications: std::sync::Mutex::new(Vec::new()),
+            ip_migration_lock: tokio::sync::Mutex::new(()),
+            verification_events: std::sync::Mutex::new(verification_events),
+            partial_delegation_events: std::sync::Mutex::new(Some(partial_delegation_events)),
+            jobs: JobScheduler::new(),
         })
     }
     
-    pub async fn run(&mut self) -> Result<()> {
+    pub async fn run(self: Arc<Self>) -> Result<()> {
         info!("Starting DNS server components...");
         
-        // Start domain verification loop
-        let verification_manager = self.domain_manager.clone();
-        let verification_interval = self.config.verification_interval_seconds;
-        tokio::spawn(async move {
-            info!("Starting domain verification loop (interval: {}s)", verification_interval);
-            let mut interval = interval(Duration::from_secs(verification_interval));
+        let verification_interval = Duration::from_secs(self.config.verification_interval_seconds);
+        if self.config.checker_enabled {
+            // Start domain verification loop
+            let verification_manager = self.domain_manager.clone();
+            let maintenance = self.maintenance.clone();
+            self.jobs.spawn("verification", Some(DOMAIN_MANAGER_JOBS), verification_interval, move || {
+                let manager = verification_manager.clone();
+                let maintenance = maintenance.clone();
+                async move {
+                    if maintenance.load(Ordering::Relaxed) {
+                        info!("Maintenance mode active, skipping domain verification");
+                        return Ok(());
+                    }
+                    DomainManager::verify_all(&manager).await
+                }
+            });
+            
+            // Start MX/NS target consistency loop
+            let checker = self.clone();
+            self.jobs.spawn("consistency", None, verification_interval, move || {
+                let checker = checker.clone();
+                async move {
+                    checker.check_consistency().await;
+                    if checker.config.smtp_check_enabled {
+                        checker.check_mail_servers().await;
+                    }
+                    Ok(())
+                }
+            });
+        }
+        
+        // Watch provider verification records until public resolvers return them
+        let watcher = self.clone();
+        self.jobs.spawn("external_verification", None, Duration::from_secs(EXTERNAL_VERIFICATION_INTERVAL_SECONDS), move || {
+            let watcher = watcher.clone();
+            async move {
+                watcher.check_external_verifications().await;
+                Ok(())
+            }
+        });
+        
+        // Watch CT logs for certificates issued on our domains
+        if self.config.ct_monitor_enabled {
+            let monitor = self.clone();
+            let client = reqwest::Client::new();
+            self.jobs.spawn("ct_monitor", None, Duration::from_secs(self.config.ct_monitor_interval_seconds), move || {
+                let monitor = monitor.clone();
+                let client = client.clone();
+                async move {
+                    if monitor.is_maintenance() || !monitor.database_available() {
+                        return Ok(());
+                    }
+                    monitor.check_certificate_transparency(&client).await
+                }
+            });
+        }
+        
+        // Move IP migrations through TTL lowering, cutover, verification and TTL restore
+        let migrator = self.clone();
+        self.jobs.spawn("ip_migrations", None, Duration::from_secs(IP_MIGRATION_INTERVAL_SECONDS), move || {
+            let migrator = migrator.clone();
+            async move {
+                if migrator.is_maintenance() {
+                    return Ok(());
+                }
+                migrator.advance_ip_migrations().await
+            }
+        });
+        
+        // Switch unpaid domains to the payment-required MX once their grace window runs out
+        if self.supabase_client.is_some() && self.config.payment_required_mx.is_some() {
+            let biller = self.clone();
+            self.jobs.spawn("payment_holds", None, Duration::from_secs(PAYMENT_CHECK_INTERVAL_SECONDS), move || {
+                let biller = biller.clone();
+                async move {
+                    if biller.is_maintenance() {
+                        return Ok(());
+                    }
+                    biller.check_payments().await.map(|_| ())
+                }
+            });
+        }
+        
+        // Start scheduled change loop
+        let scheduler = self.clone();
+        self.jobs.spawn("scheduled_changes", None, Duration::from_secs(SCHEDULER_INTERVAL_SECONDS), move || {
+            let scheduler = scheduler.clone();
+            async move {
+                if scheduler.is_maintenance() {
+                    return Ok(());
+                }
+                scheduler.apply_due_changes().await
+            }
+        });
+        
+        // Start notification outbox worker
+        let notifier = self.clone();
+        let client = reqwest::Client::new();
+        let cursor = Arc::new(tokio::sync::Mutex::new(None));
+        self.jobs.spawn("outbox", None, Duration::from_secs(OUTBOX_INTERVAL_SECONDS), move || {
+            let notifier = notifier.clone();
+            let client = client.clone();
+            let cursor = cursor.clone();
+            async move {
+                let mut cursor = cursor.lock().await;
+                match notifier.enqueue_domain_event_notifications(*cursor).await {
+                    Ok(next) => *cursor = Some(next),
+                    Err(e) => error!("Outbox enqueue error: {}", e),
+                }
+                notifier.deliver_notifications(&client).await
+            }
+        });
+        
+        // Forget the per-client query windows that ended, so the limiter stays bounded
+        if self.config.dns_rate_limit_per_minute > 0 {
+            let limiter = self.query_rate_limit.clone();
+            self.jobs.spawn("dns_rate_limit_sweep", None, Duration::from_secs(DNS_RATE_LIMIT_SWEEP_SECONDS), move || {
+                let limiter = limiter.clone();
+                async move {
+                    limiter.sweep();
+                    Ok(())
+                }
+            });
+        }
+        
+        // Start query spike detection. Rates are per elapsed minute, so the jittered first run
+        // closing a short interval does not skew the baselines.
+        if self.config.anomaly_detection_enabled {
+            let detector = self.clone();
+            self.jobs.spawn("query_anomalies", None, Duration::from_secs(ANOMALY_INTERVAL_SECONDS), move || {
+                let detector = detector.clone();
+                async move { detector.check_query_anomalies().await }
+            });
+        }
+        
+        // Persist per-domain query counts, then roll them up and prune them on a slower cycle
+        if self.config.stats_history_enabled {
+            let flusher = self.clone();
+            self.jobs.spawn("stats_flush", None, Duration::from_secs(STATS_FLUSH_INTERVAL_SECONDS), move || {
+                let flusher = flusher.clone();
+                async move {
+                    if flusher.is_maintenance() {
+                        return Ok(());
+                    }
+                    flusher.flush_query_counts().await
+                }
+            });
             
-            loop {
-                interval.tick().await;
-                if let Err(e) = verification_manager.write().await.verify_all_domains().await {
-                    error!("Domain verification error: {}", e);
+            let roller = self.clone();
+            self.jobs.spawn("stats_rollup", None, Duration::from_secs(STATS_ROLLUP_INTERVAL_SECONDS), move || {
+                let roller = roller.clone();
+                async move {
+                    if roller.is_maintenance() {
+                        return Ok(());
+                    }
+                    let report = rollup(&roller.database, &roller.stats_retention, None).await?;
+                    info!(
+                        "Rolled up query stats: {} hourly and {} daily rows, pruned {}/{}/{} 5m/hourly/daily rows",
+                        report.hourly_rows, report.daily_rows, report.pruned_raw, report.pruned_hourly, report.pruned_daily
+                    );
+                    Ok(())
+                }
+            });
+        }
+        
+        // Register this node and keep its heartbeat fresh for `GET /nodes`
+        let registrar = self.clone();
+        self.jobs.spawn("node_heartbeat", None, Duration::from_secs(NODE_HEARTBEAT_INTERVAL_SECONDS), move || {
+            let registrar = registrar.clone();
+            async move {
+                if registrar.is_maintenance() || !registrar.database_available() {
+                    return Ok(());
+                }
+                registrar.database.register_node(&registrar.node_registration()).await
+            }
+        });
+        
+        // Report this node's counters so `/stats` can cover every node behind the anycast address
+        if self.config.stats_push_enabled {
+            let pusher = self.clone();
+            self.jobs.spawn("stats_push", None, Duration::from_secs(self.config.stats_push_interval_seconds), move || {
+                let pusher = pusher.clone();
+                async move {
+                    if pusher.is_maintenance() || !pusher.database_available() {
+                        return Ok(());
+                    }
+                    pusher.push_node_stats().await
+                }
+            });
+        }
+        
+        // Write snapshots of the live data for the next cold start without a database
+        if self.config.snapshot_enabled {
+            let snapshotter = self.clone();
+            let snapshot_interval = Duration::from_secs(self.config.snapshot_interval_seconds);
+            self.jobs.spawn("snapshot", None, snapshot_interval, move || {
+                let snapshotter = snapshotter.clone();
+                async move {
+                    if snapshotter.is_serving_snapshot() {
+                        return Ok(());
+                    }
+                    snapshotter.save_snapshot().await
+                }
+            });
+        }
+        
+        // Dump state to disk on SIGUSR1, for when the API cannot be reached
+        #[cfg(unix)]
+        {
+            let dumper = self.clone();
+            tokio::spawn(async move { dumper.dump_state_on_signal().await });
+        }
+        
+        // Keep retrying the database while answering from a snapshot
+        if self.is_serving_snapshot() {
+            let recovery = self.clone();
+            tokio::spawn(async move {
+                let mut interval = interval(Duration::from_secs(DATABASE_RETRY_SECONDS));
+                
+                loop {
+                    interval.tick().await;
+                    match recovery.recover_database().await {
+                        Ok(_) => break,
+                        Err(e) => warn!("Database still unavailable: {}", e),
+                    }
+                }
+            });
+        }
+        
+        // Ping the database while serving live data, opening the breaker when it stops answering
+        // and replaying the writes queued meanwhile once it is back
+        let prober = self.clone();
+        self.jobs.spawn("database_probe", None, Duration::from_secs(DATABASE_RETRY_SECONDS), move || {
+            let prober = prober.clone();
+            async move {
+                if prober.is_serving_snapshot() {
+                    return Ok(());
                 }
+                prober.probe_database().await
             }
         });
         
-        // Start Supabase sync loop if configured
+        // Start Supabase sync loop if configured. The initial pull runs here rather than before
+        // startup so DNS answers from the database right away instead of waiting on Supabase.
         if let Some(supabase) = self.supabase_client.clone() {
             let database = self.database.clone();
             let domain_manager = self.domain_manager.clone();
+            let response_cache = self.response_cache.clone();
+            let maintenance = self.maintenance.clone();
+            let initial_sync = !self.is_serving_snapshot();
+            let jobs = self.jobs.clone();
             
             tokio::spawn(async move {
-                info!("Starting Supabase sync loop (interval: 300s)");
-                let mut interval = interval(Duration::from_secs(300)); // Sync every 5 minutes
+                if initial_sync && maintenance.load(Ordering::Relaxed) {
+                    info!("Maintenance mode active, skipping initial Supabase sync");
+                } else if initial_sync {
+                    info!("Syncing domains from Supabase...");
+                    match supabase.sync_from_supabase(&database).await {
+                        Ok(_) => {
+                            info!("Successfully synced domains from Supabase");
+                            if let Err(e) = domain_manager.write().await.load_from_database().await {
+                                error!("Failed to reload domains after sync: {}", e);
+                            }
+                            response_cache.flush();
+                        }
+                        Err(e) => error!("Failed to sync from Supabase: {}", e),
+                    }
+                }
                 
-                loop {
-                    interval.tick().await;
-                    info!("Syncing to Supabase...");
-                    if let Err(e) = supabase.sync_to_supabase(&database).await {
-                        error!("Supabase sync error: {}", e);
-                    } else {
-                        info!("Successfully synced to Supabase");
+                jobs.spawn("supabase_sync", Some(DOMAIN_MANAGER_JOBS), Duration::from_secs(SUPABASE_SYNC_INTERVAL_SECONDS), move || {
+                    let supabase = supabase.clone();
+                    let database = database.clone();
+                    let domain_manager = domain_manager.clone();
+                    let response_cache = response_cache.clone();
+                    let maintenance = maintenance.clone();
+                    async move {
+                        if maintenance.load(Ordering::Relaxed) {
+                            info!("Maintenance mode active, skipping Supabase sync");
+                            return Ok(());
+                        }
+                        info!("Syncing to Supabase...");
+                        let synced = supabase.sync_to_supabase(&database).await;
+                        
+                        // Reload domains from database after sync, whether or not the push went through
+                        domain_manager.write().await.load_from_database().await?;
+                        response_cache.flush();
+                        synced
+                    }
+                });
+            });
+        }
+        
+        // Push verification status changes to Supabase right away instead of on the next sync
+        if let (Some(supabase), Some(mut events)) = (self.supabase_client.clone(), self.verification_events.lock().unwrap_or_else(PoisonError::into_inner).take()) {
+            let database = self.database.clone();
+            
+            tokio::spawn(async move {
+                while let Some(change) = events.recv().await {
+                    info!("Domain {} moved from {:?} to {:?}, updating Supabase", change.domain, change.from, change.to);
+                    if let Err(e) = supabase.sync_domain_to_supabase(&database, &change.domain).await {
+                        error!("Failed to push verification change of {} to Supabase: {}", change.domain, e);
                     }
-                    
-                    // Reload domains from database after sync
-                    if let Err(e) = domain_manager.write().await.load_from_database().await {
-                        error!("Failed to reload domains after sync: {}", e);
+                }
+            });
+        }
+        
+        // Warn owners about partial delegations through the notification outbox
+        if let Some(mut events) = self.partial_delegation_events.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            let notifier = self.clone();
+            
+            tokio::spawn(async move {
+                while let Some(partial) = events.recv().await {
+                    if let Err(e) = notifier.notify_partial_delegation(&partial).await {
+                        error!("Failed to queue partial delegation warning for {}: {}", partial.domain, e);
                     }
                 }
             });
@@ -127,7 +606,7 @@ impl DnsServer {
         // Start HTTP redirect server if enabled
         if self.config.http_redirect_enabled {
             let redirect_manager = self.domain_manager.clone();
-            let bind_addr = self.config.bind_address.clone();
+            let bind_addr = self.config.listen_address(&self.config.http_redirect_bind_address).to_string();
             let port = self.config.http_redirect_port;
             let target = self.config.redirect_target.clone();
             
@@ -139,82 +618,203 @@ impl DnsServer {
             });
         }
         
+        // Start DNS JSON API if enabled
+        if self.config.doh_enabled {
+            let handler = self.query_handler();
+            let bind_addr = self.config.listen_address(&self.config.doh_bind_address).to_string();
+            let port = self.config.doh_port;
+            
+            tokio::spawn(async move {
+                if let Err(e) = start_doh_server(&bind_addr, port, handler).await {
+                    error!("DoH server error: {}", e);
+                }
+            });
+        }
+        
+        // Start management API if enabled
+        if self.config.api_enabled {
+            let api_server = DnsApiServer::new(self.clone());
+            let bind_addr = self.config.api_bind_address.clone();
+            let port = self.config.api_port;
+            
+            tokio::spawn(async move {
+                if let Err(e) = api_server.run(&bind_addr, port).await {
+                    error!("DNS API server error: {}", e);
+                }
+            });
+        }
+        
+        // Compare mirrored answers, and follow edits to the staging database
+        if let Some(shadow) = self.shadow.clone() {
+            let comparer = shadow.clone();
+            tokio::spawn(async move { comparer.run().await });
+            self.jobs.spawn("shadow_reconcile", None, Duration::from_secs(RECONCILE_INTERVAL_SECONDS), move || {
+                let shadow = shadow.clone();
+                async move { shadow.reconcile().await.map(|_| ()) }
+            });
+        }
+        
+        // Correct in-memory domains that drifted from the database, e.g. after edits in SQL
+        let reconciler = self.clone();
+        self.jobs.spawn("reconcile", Some(DOMAIN_MANAGER_JOBS), Duration::from_secs(RECONCILE_INTERVAL_SECONDS), move || {
+            let reconciler = reconciler.clone();
+            async move {
+                if reconciler.is_maintenance() || !reconciler.database_available() {
+                    return Ok(());
+                }
+                reconciler.reconcile().await.map(|_| ())
+            }
+        });
+        
+        // Pick up answer overrides added through other nodes' APIs
+        let refresher = self.clone();
+        self.jobs.spawn("answer_overrides", None, Duration::from_secs(OVERRIDE_REFRESH_INTERVAL_SECONDS), move || {
+            let refresher = refresher.clone();
+            async move {
+                if !refresher.database_available() {
+                    return Ok(());
+                }
+                refresher.refresh_answer_overrides().await
+            }
+        });
+        
         // Start auto-discovery loop if enabled
-        if self.config.auto_discovery_enabled {
+        if self.config.checker_enabled && self.config.auto_discovery_enabled {
             let discovery_manager = self.domain_manager.clone();
-            let discovery_interval = self.config.verification_interval_seconds;
+            let maintenance = self.maintenance.clone();
             
-            tokio::spawn(async move {
-                info!("Starting auto-discovery loop (interval: {}s)", discovery_interval);
-                let mut interval = interval(Duration::from_secs(discovery_interval));
-                
-                loop {
-                    interval.tick().await;
-                    if let Err(e) = discovery_manager.write().await.auto_discover_domains().await {
-                        error!("Auto-discovery error: {}", e);
+            self.jobs.spawn("auto_discovery", Some(DOMAIN_MANAGER_JOBS), verification_interval, move || {
+                let manager = discovery_manager.clone();
+                let maintenance = maintenance.clone();
+                async move {
+                    if maintenance.load(Ordering::Relaxed) {
+                        return Ok(());
                     }
+                    manager.write().await.auto_discover_domains().await
+                }
+            });
+        }
+        
+        // Start DNS over TCP and TLS if enabled. Binding and loading the certificate happen
+        // here so a bad address or key fails startup like the UDP socket does.
+        if self.config.tcp_enabled {
+            let addr: SocketAddr = format!(
+                "{}:{}",
+                self.config.listen_address(&self.config.tcp_bind_address),
+                self.config.tcp_port.unwrap_or(self.config.port),
+            ).parse()?;
+            let listener = tokio::net::TcpListener::bind(&addr).await?;
+            info!("DNS TCP server bound to {}", addr);
+            
+            let handler = self.query_handler();
+            let capture = self.packet_capture.clone();
+            let max_connections = self.config.tcp_max_connections;
+            tokio::spawn(async move {
+                if let Err(e) = serve_tcp(listener, handler, Some(capture), max_connections).await {
+                    error!("DNS TCP server error: {}", e);
+                }
+            });
+        }
+        
+        if self.config.dot_enabled {
+            let (Some(cert_path), Some(key_path)) = (&self.config.dot_cert_path, &self.config.dot_key_path) else {
+                return Err(anyhow::anyhow!("dot_enabled needs dot_cert_path and dot_key_path"));
+            };
+            let acceptor = tls_acceptor(cert_path, key_path)?;
+            let addr: SocketAddr = format!(
+                "{}:{}",
+                self.config.listen_address(&self.config.dot_bind_address),
+                self.config.dot_port,
+            ).parse()?;
+            let listener = tokio::net::TcpListener::bind(&addr).await?;
+            info!("DNS over TLS server bound to {}", addr);
+            
+            let handler = self.query_handler();
+            let capture = self.packet_capture.clone();
+            let max_connections = self.config.tcp_max_connections;
+            tokio::spawn(async move {
+                if let Err(e) = serve_tls(listener, acceptor, handler, Some(capture), max_connections).await {
+                    error!("DNS over TLS server error: {}", e);
                 }
             });
         }
         
-        // Start main DNS server
-        self.start_dns_server().await
+        // Start main DNS server; a node without UDP keeps running for its other components
+        if self.config.udp_enabled {
+            let server = self.clone();
+            supervise("UDP DNS server", move || {
+                let server = server.clone();
+                async move { server.start_dns_server().await }
+            }).await
+        } else {
+            info!("UDP DNS disabled, running the other enabled components only");
+            std::future::pending().await
+        }
     }
     
     async fn start_dns_server(&self) -> Result<()> {
         let addr: SocketAddr = format!("{}:{}", self.config.bind_address, self.config.port)
             .parse()?;
-            
-        let handler = CybertempHandler::new(
-            self.config.clone(),
-            self.domain_manager.clone(),
-        );
+        
+        let handler = self.query_handler();
         
         info!("Starting DNS server on {}", addr);
         
         let socket = tokio::net::UdpSocket::bind(&addr).await?;
         info!("DNS server bound to {}", addr);
         
-        let mut buf = [0u8; 512];
-        
-        loop {
-            match socket.recv_from(&mut buf).await {
-                Ok((len, src)) => {
-                    let data = buf[..len].to_vec();
-                    
-                    if let Ok(response_data) = handler.handle_request(&data).await {
-                        if let Err(e) = socket.send_to(&response_data, src).await {
-                            error!("Error sending DNS response: {}", e);
-                        }
-                    } else {
-                        error!("Error handling DNS request");
-                    }
-                }
-                Err(e) => {
-                    error!("Error receiving DNS packet: {}", e);
-                }
-            }
-        }
+        serve_udp(socket, handler, Some(self.packet_capture.clone())).await
     }
     
     // Domain management API methods
-    pub async fn add_domain(&self, domain: &str, ip: &str, discord: bool) -> Result<()> {
-        let mut manager = self.domain_manager.write().await;
-        manager.add_domain(domain, ip, discord).await?;
-        
-        // Sync to Supabase if configured
-        if let Some(supabase) = &self.supabase_client {
-            if let Err(e) = supabase.sync_to_supabase(&self.database).await {
-                error!("Failed to sync new domain to Supabase: {}", e);
+    /// Adds a domain, or with `subdomain` a delegated subdomain hosted as its own zone.
+    /// Adds a domain. The database row and, with Supabase configured, an outbox entry pushing
+    /// the domain there are committed together before memory is updated, so a failure leaves
+    /// nothing half-applied. Returns false if `idempotency_key` was already used for it.
+    /// On a `routing_profile` (or `discord`) with a `template`, the template is applied too.
+    pub async fn add_domain(
+        &self,
+        domain: &str,
+        ip: &str,
+        discord: bool,
+        subdomain: bool,
+        routing_profile: Option<&str>,
+        actor: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<bool> {
+        let write = DomainWrite {
+            idempotency_key,
+            sync_supabase: self.supabase_client.is_some(),
+            routing_profile,
+        };
+        let applied = {
+            let mut manager = self.domain_manager.write().await;
+            let applied = if subdomain {
+                manager.add_subdomain_zone(domain, ip, discord, actor, &write).await?
+            } else {
+                manager.add_domain_with(domain, ip, discord, &write).await?
+            };
+            if let (true, Some(profile)) = (applied, routing_profile) {
+                manager.assign_routing_profile(domain, profile, actor).await?;
             }
+            applied
+        };
+        
+        let template = routing_profile.or(discord.then_some(DISCORD_PROFILE))
+            .and_then(|profile| self.config.routing_profile(profile))
+            .and_then(|profile| profile.template.clone());
+        if let (true, Some(template)) = (applied, template) {
+            self.apply_template(domain, &template, actor).await?;
         }
+        self.response_cache.invalidate_domain(domain);
         
-        Ok(())
+        Ok(applied)
     }
     
     pub async fn remove_domain(&self, domain: &str) -> Result<()> {
         let mut manager = self.domain_manager.write().await;
         manager.remove_domain(domain).await?;
+        self.response_cache.invalidate_domain(domain);
         
         // Sync to Supabase if configured
         if let Some(supabase) = &self.supabase_client {
@@ -229,6 +829,7 @@ impl DnsServer {
     pub async fn discover_domain(&self, domain: &str) -> Result<()> {
         let mut manager = self.domain_manager.write().await;
         manager.discover_domain(domain).await?;
+        self.response_cache.invalidate_domain(domain);
         
         // Sync to Supabase if configured
         if let Some(supabase) = &self.supabase_client {
@@ -240,159 +841,1698 @@ impl DnsServer {
         Ok(())
     }
     
-    pub async fn list_domains(&self) -> Vec<String> {
-        let manager = self.domain_manager.read().await;
-        manager.list_domains().await
+    pub async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_domain_enabled(domain, enabled).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
     }
     
-    pub async fn get_domain_info(&self, domain: &str) -> Option<crate::domain_manager::DomainRecord> {
-        let manager = self.domain_manager.read().await;
-        manager.get_domain(domain).await
+    pub async fn set_domain_ip(&self, domain: &str, ip: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_domain_ip(domain, ip).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
     }
     
-    pub async fn force_verification(&self, domain: &str) -> Result<bool> {
+    pub async fn set_bimi(&self, domain: &str, logo_url: &str, vmc_url: Option<&str>, actor: &str) -> Result<()> {
         let mut manager = self.domain_manager.write().await;
-        let verified = manager.verify_domain(domain).await;
-        
-        // Sync to Supabase if configured
-        if let Some(supabase) = &self.supabase_client {
-            if let Err(e) = supabase.sync_to_supabase(&self.database).await {
-                error!("Failed to sync verification status to Supabase: {}", e);
-            }
-        }
-        
-        Ok(verified)
+        manager.set_bimi(domain, logo_url, vmc_url, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
     }
     
-    pub async fn get_stats(&self) -> DomainStats {
-        let manager = self.domain_manager.read().await;
-        let domains = manager.get_all_domains().await;
-        
-        let total = domains.len();
-        let verified = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::Verified).count();
-        let pending = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::PendingVerification).count();
-        let grace_period = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::GracePeriod).count();
-        let discord = domains.iter().filter(|d| d.discord).count();
-        
-        DomainStats {
-            total_domains: total,
-            verified_domains: verified,
-            pending_verification: pending,
-            grace_period: grace_period,
-            discord_domains: discord,
-            supabase_connected: self.supabase_client.is_some(),
-        }
-    }
-}
-
-#[derive(Debug, Clone, serde::Serialize)]
-pub struct DomainStats {
-    pub total_domains: usize,
-    pub verified_domains: usize,
-    pub pending_verification: usize,
-    pub grace_period: usize,
-    pub discord_domains: usize,
-    pub supabase_connected: bool,
-}
-
-// API server for remote management
-pub struct DnsApiServer {
-    dns_server: Arc<DnsServer>,
-}
-
-impl DnsApiServer {
-    pub fn new(dns_server: Arc<DnsServer>) -> Self {
-        Self { dns_server }
+    pub async fn clear_bimi(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_bimi(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
     }
     
-    pub async fn run(&self, bind_addr: &str, port: u16) -> Result<()> {
-        let addr: SocketAddr = format!("{}:{}", bind_addr, port).parse()?;
-        let dns_server = Arc::clone(&self.dns_server);
-        
-        let make_svc = make_service_fn(move |_conn| {
-            let dns_server = Arc::clone(&dns_server);
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
-                    Self::handle_api_request(req, Arc::clone(&dns_server))
-                }))
-            }
-        });
-
-        let server = Server::bind(&addr).serve(make_svc);
-        info!("DNS API server running on http://{}", addr);
-        
-        server.await?;
-        Ok(())
-    }
-    
-    async fn handle_api_request(
-        req: Request<Body>,
-        dns_server: Arc<DnsServer>,
-    ) -> Result<Response<Body>, Infallible> {
-        let path = req.uri().path();
-        let method = req.method();
-        
-        match (method, path) {
-            (&Method::GET, "/health") => {
-                Ok(Response::new(Body::from(json!({"status": "healthy"}).to_string())))
-            }
-            (&Method::GET, "/stats") => {
-                let stats = dns_server.get_stats().await;
-                Ok(Response::new(Body::from(serde_json::to_string(&stats).unwrap())))
-            }
-            (&Method::GET, "/domains") => {
-                let domains = dns_server.list_domains().await;
-                Ok(Response::new(Body::from(json!(domains).to_string())))
-            }
-            (&Method::POST, "/domains") => {
-                // Parse domain addition request
-                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
-                if let Ok(data) = serde_json::from_slice::<serde_json::Value>(&body) {
-                    if let (Some(domain), Some(ip)) = (data.get("domain"), data.get("ip")) {
-                        let discord = data.get("discord").and_then(|d| d.as_bool()).unwrap_or(false);
-                        if let (Some(domain_str), Some(ip_str)) = (domain.as_str(), ip.as_str()) {
-                            match dns_server.add_domain(domain_str, ip_str, discord).await {
-                                Ok(_) => Ok(Response::new(Body::from(json!({"status": "added"}).to_string()))),
-                                Err(e) => Ok(Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(Body::from(json!({"error": e.to_string()}).to_string()))
-                                    .unwrap()),
-                            }
-                        } else {
-                            Ok(Response::builder()
-                                .status(StatusCode::BAD_REQUEST)
-                                .body(Body::from(json!({"error": "Invalid domain or ip"}).to_string()))
-                                .unwrap())
-                        }
-                    } else {
-                        Ok(Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::from(json!({"error": "Missing domain or ip"}).to_string()))
-                            .unwrap())
-                    }
-                } else {
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body(Body::from(json!({"error": "Invalid JSON"}).to_string()))
-                        .unwrap())
-                }
-            }
-            (&Method::DELETE, path) if path.starts_with("/domains/") => {
-                let domain = path.trim_start_matches("/domains/");
-                match dns_server.remove_domain(domain).await {
-                    Ok(_) => Ok(Response::new(Body::from(json!({"status": "removed"}).to_string()))),
-                    Err(e) => Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(json!({"error": e.to_string()}).to_string()))
-                        .unwrap()),
-                }
-            }
-            _ => {
-                Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Body::from(json!({"error": "Not found"}).to_string()))
-                    .unwrap())
-            }
-        }
+    pub async fn set_dmarc(&self, domain: &str, policy: &DmarcPolicy, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_dmarc(domain, policy, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
     }
-}
\ No newline at end of file
+    
+    pub async fn clear_dmarc(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_dmarc(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn set_mail_routing(&self, domain: &str, routing: &MailRouting, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_mail_routing(domain, routing, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn clear_mail_routing(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_mail_routing(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    /// Sets the domain's TTL override, or with `None` goes back to `default_ttl`.
+    pub async fn set_domain_ttl(&self, domain: &str, ttl: Option<u32>, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        match ttl {
+            Some(ttl) => manager.set_ttl(domain, ttl, actor).await?,
+            None => manager.clear_ttl(domain, actor).await?,
+        }
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: u32, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_record_ttl(domain, record_type, name, ttl, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_records(
+        &self,
+        domain: &str,
+        record_type: &str,
+        name: &str,
+        values: &[String],
+        ttl: Option<u32>,
+        allow_reserved: bool,
+        actor: &str,
+    ) -> Result<Vec<String>> {
+        let mut manager = self.domain_manager.write().await;
+        let stored = manager.set_records(domain, record_type, name, values, ttl, allow_reserved, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(stored)
+    }
+    
+    pub async fn clear_records(&self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_records(domain, record_type, name, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn set_nameservers(&self, domain: &str, nameservers: &[String], actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_nameservers(domain, nameservers, actor).await?;
+        // Glue answers for the vanity names can live in other zones
+        self.response_cache.flush();
+        Ok(())
+    }
+    
+    pub async fn clear_nameservers(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_nameservers(domain, actor).await?;
+        self.response_cache.flush();
+        Ok(())
+    }
+    
+    pub async fn assign_nameserver_set(&self, domain: &str, set: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.assign_nameserver_set(domain, set, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn clear_nameserver_set(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_nameserver_set(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn assign_routing_profile(&self, domain: &str, profile: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.assign_routing_profile(domain, profile, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn clear_routing_profile(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_routing_profile(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn set_subdomain_policy(&self, domain: &str, policy: SubdomainPolicy, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_subdomain_policy(domain, policy, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn clear_subdomain_policy(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_subdomain_policy(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn set_synthetic_rules(&self, domain: &str, rules: &[SyntheticRule], actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_synthetic_rules(domain, rules, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    pub async fn clear_synthetic_rules(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_synthetic_rules(domain, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    /// Canaries are applied to answers after the response cache, so nothing cached is dropped.
+    pub async fn set_canaries(&self, domain: &str, canaries: &[Canary], actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_canaries(domain, canaries, actor).await?;
+        Ok(())
+    }
+    
+    pub async fn clear_canaries(&self, domain: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.clear_canaries(domain, actor).await?;
+        Ok(())
+    }
+    
+    pub async fn apply_mutation(&self, domain: &str, mutation: &ZoneMutation, actor: &str) -> Result<()> {
+        match mutation {
+            ZoneMutation::SetIp { ip } => self.set_domain_ip(domain, ip).await,
+            ZoneMutation::Enable => self.set_domain_enabled(domain, true).await,
+            ZoneMutation::Disable => self.set_domain_enabled(domain, false).await,
+            ZoneMutation::SetBimi { logo_url, vmc_url } => self.set_bimi(domain, logo_url, vmc_url.as_deref(), actor).await,
+            ZoneMutation::ClearBimi => self.clear_bimi(domain, actor).await,
+            ZoneMutation::SetDmarc(policy) => self.set_dmarc(domain, policy, actor).await,
+            ZoneMutation::ClearDmarc => self.clear_dmarc(domain, actor).await,
+            ZoneMutation::SetMailRouting(routing) => self.set_mail_routing(domain, routing, actor).await,
+            ZoneMutation::ClearMailRouting => self.clear_mail_routing(domain, actor).await,
+            ZoneMutation::SetTtl { ttl } => self.set_domain_ttl(domain, *ttl, actor).await,
+            ZoneMutation::SetNameservers { nameservers } => self.set_nameservers(domain, nameservers, actor).await,
+            ZoneMutation::ClearNameservers => self.clear_nameservers(domain, actor).await,
+            ZoneMutation::AssignNameserverSet { set } => self.assign_nameserver_set(domain, set, actor).await,
+            ZoneMutation::ClearNameserverSet => self.clear_nameserver_set(domain, actor).await,
+            ZoneMutation::AssignRoutingProfile { profile } => self.assign_routing_profile(domain, profile, actor).await,
+            ZoneMutation::ClearRoutingProfile => self.clear_routing_profile(domain, actor).await,
+            ZoneMutation::SetSubdomainPolicy { policy } => self.set_subdomain_policy(domain, *policy, actor).await,
+            ZoneMutation::ClearSubdomainPolicy => self.clear_subdomain_policy(domain, actor).await,
+            ZoneMutation::SetSyntheticRules { rules } => self.set_synthetic_rules(domain, rules, actor).await,
+            ZoneMutation::ClearSyntheticRules => self.clear_synthetic_rules(domain, actor).await,
+            ZoneMutation::SetCanaries { canaries } => self.set_canaries(domain, canaries, actor).await,
+            ZoneMutation::ClearCanaries => self.clear_canaries(domain, actor).await,
+            ZoneMutation::SetRecords { record_type, name, values, ttl, allow_reserved } => {
+                self.set_records(domain, record_type, name, values, *ttl, *allow_reserved, actor).await.map(|_| ())
+            }
+            ZoneMutation::SetRecordTtl { record_type, name, ttl } => self.set_record_ttl(domain, record_type, name, *ttl, actor).await,
+            ZoneMutation::ClearRecords { record_type, name } => self.clear_records(domain, record_type, name, actor).await,
+        }
+    }
+    
+    /// Stages `mutation` to be applied by the scheduler at `effective_at`. Returns the change ID.
+    pub async fn schedule_mutation(
+        &self,
+        domain: &str,
+        mutation: &ZoneMutation,
+        effective_at: chrono::DateTime<chrono::Utc>,
+        actor: &str,
+    ) -> Result<i64> {
+        if effective_at <= chrono::Utc::now() {
+            return Err(anyhow::anyhow!("effective_at must be in the future"));
+        }
+        mutation.validate()?;
+        
+        let id = self.database.add_scheduled_change(domain, mutation, effective_at, actor).await?;
+        info!("Scheduled change {} for domain {} at {}", id, domain, effective_at);
+        Ok(id)
+    }
+    
+    pub async fn pending_changes(&self, domain: Option<&str>) -> Result<Vec<ScheduledChange>> {
+        self.database.get_pending_changes(domain).await
+    }
+    
+    pub async fn cancel_scheduled_change(&self, id: i64) -> Result<()> {
+        self.database.cancel_scheduled_change(id).await
+    }
+    
+    /// Queues a records API change until a second key approves it. `key` is the submitter's
+    /// API key, which may not approve it.
+    pub async fn submit_record_change(&self, domain: &str, change: &RecordChange, actor: &str, key: &str) -> Result<i64> {
+        change.validate()?;
+        let id = self.database.add_pending_change(domain, change, actor, &key_fingerprint(key)).await?;
+        info!("Record change {} for domain {} awaits approval", id, domain);
+        Ok(id)
+    }
+    
+    pub async fn pending_record_changes(&self, domain: Option<&str>) -> Result<Vec<PendingChange>> {
+        self.database.get_pending_record_changes(domain).await
+    }
+    
+    pub async fn pending_record_change(&self, id: i64) -> Result<Option<PendingChange>> {
+        self.database.get_pending_record_change(id).await
+    }
+    
+    /// Approves and applies a pending change, attributed to whoever submitted it. The approving
+    /// key must differ from the submitting one. A change that fails to apply is marked with its
+    /// error and not retried.
+    pub async fn approve_record_change(&self, change: &PendingChange, reviewer: &str, key: &str) -> Result<()> {
+        let reviewer_key = key_fingerprint(key);
+        if reviewer_key == change.submitter_key {
+            return Err(anyhow::anyhow!("A change cannot be approved with the key that submitted it"));
+        }
+        if !self.database.review_pending_change(change.id, ApprovalStatus::Approved, reviewer, &reviewer_key).await? {
+            return Err(anyhow::anyhow!("Change {} is no longer pending", change.id));
+        }
+        
+        let actor = change.submitted_by.as_str();
+        let result = match &change.change {
+            RecordChange::SetRecords { record_type, name, values, ttl, allow_reserved } => {
+                self.set_records(&change.domain, record_type, name, values, *ttl, *allow_reserved, actor).await.map(|_| ())
+            }
+            RecordChange::ClearRecords { record_type, name } => {
+                self.clear_records(&change.domain, record_type, name, actor).await
+            }
+            RecordChange::SetTtl { record_type, name, ttl } => {
+                self.set_record_ttl(&change.domain, record_type, name, *ttl, actor).await
+            }
+        };
+        match result {
+            Ok(()) => {
+                info!("Applied record change {} for domain {}, approved by {}", change.id, change.domain, reviewer);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Approved record change {} for domain {} failed: {}", change.id, change.domain, e);
+                self.database.fail_pending_change(change.id, &e.to_string()).await?;
+                Err(e)
+            }
+        }
+    }
+    
+    pub async fn reject_record_change(&self, id: i64, reviewer: &str, key: &str) -> Result<()> {
+        if !self.database.review_pending_change(id, ApprovalStatus::Rejected, reviewer, &key_fingerprint(key)).await? {
+            return Err(anyhow::anyhow!("No pending record change {}", id));
+        }
+        info!("Rejected record change {}", id);
+        Ok(())
+    }
+    
+    /// Applies every staged change whose time has come, attributed to whoever staged it.
+    /// A change that fails is recorded with its error and not retried.
+    async fn apply_due_changes(&self) -> Result<()> {
+        for change in self.database.get_due_changes().await? {
+            let result = self.apply_mutation(&change.domain, &change.mutation, &change.actor).await;
+            match &result {
+                Ok(()) => info!("Applied scheduled change {} for domain {}", change.id, change.domain),
+                Err(e) => error!("Scheduled change {} for domain {} failed: {}", change.id, change.domain, e),
+            }
+            let error = result.err().map(|e| e.to_string());
+            self.database.finish_scheduled_change(change.id, error.as_deref()).await?;
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn templates(&self) -> Result<Vec<RecordTemplate>> {
+        self.database.get_templates().await
+    }
+    
+    pub async fn template(&self, name: &str) -> Result<Option<RecordTemplate>> {
+        self.database.get_template(name).await
+    }
+    
+    pub async fn save_template(&self, template: &RecordTemplate) -> Result<()> {
+        template.validate(&self.config.reserved_labels)?;
+        self.database.upsert_template(template).await
+    }
+    
+    pub async fn delete_template(&self, name: &str) -> Result<()> {
+        self.database.delete_template(name).await
+    }
+    
+    /// Materializes a template on one domain. Returns how many records it wrote.
+    pub async fn apply_template(&self, domain: &str, name: &str, actor: &str) -> Result<usize> {
+        let template = self.database.get_template(name).await?
+            .ok_or_else(|| anyhow::anyhow!("Template not found: {}", name))?;
+        
+        let mut manager = self.domain_manager.write().await;
+        let written = manager.apply_template(domain, &template, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(written)
+    }
+    
+    pub async fn remove_template(&self, domain: &str, name: &str, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.remove_template(domain, name, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        Ok(())
+    }
+    
+    /// Re-applies the current version of a template to every domain it was applied to.
+    pub async fn reapply_template(&self, name: &str, actor: &str) -> Result<ReapplyReport> {
+        let template = self.database.get_template(name).await?
+            .ok_or_else(|| anyhow::anyhow!("Template not found: {}", name))?;
+        
+        let mut report = ReapplyReport::default();
+        for domain in self.database.get_template_domains(name).await? {
+            let mut manager = self.domain_manager.write().await;
+            match manager.apply_template(&domain, &template, actor).await {
+                Ok(_) => {
+                    self.response_cache.invalidate_domain(&domain);
+                    report.applied.push(domain);
+                }
+                Err(e) => {
+                    error!("Failed to re-apply template {} to {}: {}", name, domain, e);
+                    report.failed.insert(domain, e.to_string());
+                }
+            }
+        }
+        
+        info!("Re-applied template {} to {} domains ({} failed)", name, report.applied.len(), report.failed.len());
+        Ok(report)
+    }
+    
+    /// Handler for client-facing listeners: counted in stats and served through the cache.
+    fn query_handler(&self) -> CybertempHandler {
+        let handler = CybertempHandler::new(self.config.clone(), self.domain_manager.clone())
+            .with_query_stats(self.query_stats.clone())
+            .with_transport_stats(self.transport_stats.clone())
+            .with_response_cache(self.response_cache.clone())
+            .with_packet_guard(self.packet_guard.clone());
+        let handler = if self.config.dns_health_name.is_some() || self.config.dns_ready_name.is_some() {
+            let health = NodeHealth::new(self.config.node_id(), self.started_at, self.db_breaker.clone());
+            handler.with_node_health(Arc::new(health))
+        } else {
+            handler
+        };
+        let handler = match &self.query_script {
+            Some(script) => handler.with_query_script(script.clone()),
+            None => handler,
+        };
+        let handler = if self.config.dns_rate_limit_per_minute > 0 {
+            handler.with_middleware(self.query_rate_limit.clone())
+        } else {
+            handler
+        };
+        let handler = if self.config.anomaly_detection_enabled {
+            handler.with_query_anomalies(self.query_anomalies.clone())
+        } else {
+            handler
+        };
+        let handler = if self.config.stats_history_enabled {
+            handler.with_domain_counter(self.domain_counter.clone())
+        } else {
+            handler
+        };
+        // Last, so the mirrored answer is the one the other middleware finished with
+        match &self.shadow {
+            Some(shadow) => handler.with_middleware(shadow.clone()),
+            None => handler,
+        }
+    }
+    
+    /// What a client asking us `name`/`record_type` would get back right now.
+    pub async fn resolve(&self, name: &str, record_type: RecordType) -> Result<Message> {
+        CybertempHandler::new(self.config.clone(), self.domain_manager.clone())
+            .lookup(name, record_type)
+            .await
+    }
+    
+    /// Best-practice findings (SPF, DMARC, DKIM, MX targets, CNAME conflicts, TTLs) for the answers we serve for `domain`.
+    pub async fn lint_domain(&self, domain: &str) -> Result<LintReport> {
+        let record = self.get_domain_info(domain).await
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        let handler = CybertempHandler::new(self.config.clone(), self.domain_manager.clone());
+        Ok(lint_domain(&handler, &record, &self.propagation_resolvers).await)
+    }
+    
+    /// Re-checks every MX/NS target we hand out and keeps the problems for `/stats`.
+    pub async fn check_consistency(&self) -> usize {
+        let handler = CybertempHandler::new(self.config.clone(), self.domain_manager.clone());
+        let domains = self.domain_manager.read().await.get_all_domains().await;
+        let found = check_targets(&handler, &domains).await;
+        
+        for problem in &found {
+            warn!(
+                "{} target {} is misconfigured ({:?}) for {} domains",
+                problem.record_type, problem.target, problem.problem, problem.domains.len()
+            );
+        }
+        let count = found.len();
+        *self.misconfigurations.write().unwrap_or_else(PoisonError::into_inner) = found;
+        count
+    }
+    
+    /// Connects to the mail servers behind every MX target we hand out and keeps the problems for `/stats`.
+    pub async fn check_mail_servers(&self) -> usize {
+        let handler = CybertempHandler::new(self.config.clone(), self.domain_manager.clone());
+        let domains = self.domain_manager.read().await.get_all_domains().await;
+        let expect = SmtpExpectations {
+            helo: self.config.nameservers.first().map(String::as_str).unwrap_or("localhost"),
+            timeout: Duration::from_secs(self.config.smtp_check_timeout_seconds),
+            banner: &self.config.smtp_banner,
+            routing_profiles: &self.config.routing_profiles,
+        };
+        let found = check_mail_servers(&handler, &domains, &expect).await;
+        
+        for problem in &found {
+            warn!(
+                "Mail server {} has a problem ({:?}: {}) for {} domains",
+                problem.ip, problem.problem, problem.detail, problem.domains.len()
+            );
+        }
+        let count = found.len();
+        *self.mail_server_problems.write().unwrap_or_else(PoisonError::into_inner) = found;
+        count
+    }
+    
+    /// Writes the closed 5-minute query count buckets to the stats history. Counts that fail to
+    /// write are dropped rather than held, so a long database outage cannot grow memory.
+    pub async fn flush_query_counts(&self) -> Result<()> {
+        let counts = self.domain_counter.take_closed(chrono::Utc::now());
+        self.database.insert_query_counts(&counts).await
+    }
+    
+    /// What this node writes to `nodes` on every heartbeat.
+    fn node_registration(&self) -> NodeRegistration {
+        NodeRegistration {
+            node_id: self.config.node_id(),
+            hostname: hostname(),
+            region: self.config.node_region.clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            config_hash: self.effective_config.fingerprint(),
+            listen_addresses: self.config.listeners(),
+            started_at: self.started_at,
+        }
+    }
+    
+    /// Every registered node, with whether it is still heartbeating.
+    pub async fn nodes(&self) -> Result<Vec<NodeInfo>> {
+        let now = chrono::Utc::now();
+        let node_id = self.config.node_id();
+        Ok(self.database.get_nodes().await?
+            .into_iter()
+            .map(|node| node.with_status(now, &node_id))
+            .collect())
+    }
+    
+    /// Forgets a node that was shut down for good. One still running registers again on its
+    /// next heartbeat.
+    pub async fn remove_node(&self, node_id: &str) -> Result<()> {
+        self.database.remove_node(node_id).await
+    }
+    
+    /// Writes this node's transport counters to `node_stats` under its `node_id`.
+    pub async fn push_node_stats(&self) -> Result<()> {
+        let snapshot = self.transport_stats.snapshot();
+        self.database.push_node_stats(&self.config.node_id(), self.config.node_region.as_deref(), snapshot.since, &snapshot.transports).await
+    }
+    
+    /// Counters of every node that pushed within the last few push intervals, added up and
+    /// optionally split by node or region.
+    pub async fn fleet_stats(&self, by: Option<Breakdown>) -> Result<FleetStats> {
+        let window = self.config.stats_push_interval_seconds.max(1) * u64::from(STALE_AFTER_PUSHES);
+        let since = chrono::Utc::now() - chrono::Duration::seconds(window as i64);
+        let reports = self.database.get_node_stats(since).await?;
+        Ok(aggregate(&reports, by))
+    }
+    
+    /// Closes the current measurement interval and queues an alert for every domain whose
+    /// query rate spiked.
+    pub async fn check_query_anomalies(&self) -> Result<()> {
+        for spike in self.query_anomalies.evaluate() {
+            warn!(
+                "Query spike on {}: {:.0} queries/min against a baseline of {:.1}",
+                spike.domain, spike.queries_per_minute, spike.baseline_per_minute
+            );
+            for (channel, target, payload) in query_spike_notifications(&self.config, &spike) {
+                self.database.enqueue_notification(channel, &target, &payload, None).await?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Looks up every verified domain in CT logs and stores certificates not seen before,
+    /// alerting on those from an issuer the domain does not expect. A domain's first lookup
+    /// only records the certificates it already has.
+    pub async fn check_certificate_transparency(&self, client: &reqwest::Client) -> Result<()> {
+        let domains = self.domain_manager.read().await.get_all_domains().await;
+        for record in domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::Verified) {
+            let entries = match fetch_certificates(client, &self.config.ct_monitor_url, &record.domain).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("CT lookup for {} failed: {}", record.domain, e);
+                    continue;
+                }
+            };
+            let allowed = allowed_issuers(&record.records, &self.config.ct_expected_issuers);
+            let findings: Vec<CtFinding> = entries.iter().map(|entry| CtFinding::new(&record.domain, entry, &allowed)).collect();
+            let added = self.database.add_ct_findings(&record.domain, &findings).await?;
+            if !self.database.mark_ct_checked(&record.domain).await? {
+                info!("Recorded {} existing certificates for {}", added.len(), record.domain);
+                continue;
+            }
+            
+            for finding in added {
+                if finding.expected != Some(false) {
+                    info!("New certificate for {} from {}", finding.domain, finding.issuer);
+                    continue;
+                }
+                warn!("Unexpected certificate for {} from {} (crt.sh ID {})", finding.domain, finding.issuer, finding.cert_id);
+                self.notify_ct_finding(&finding).await?;
+            }
+        }
+        Ok(())
+    }
+    
+    async fn notify_ct_finding(&self, finding: &CtFinding) -> Result<()> {
+        let owner_email = match &self.supabase_client {
+            Some(supabase) => match supabase.get_domain(&finding.domain).await.ok().flatten() {
+                Some(row) => supabase.get_user_email(&row.user_id).await.ok().flatten(),
+                None => None,
+            },
+            None => None,
+        };
+        for (channel, target, payload) in ct_finding_notifications(&self.config, finding, owner_email.as_deref()) {
+            self.database.enqueue_notification(channel, &target, &payload, None).await?;
+        }
+        Ok(())
+    }
+    
+    /// Certificates found in CT logs for the domain, newest first.
+    pub async fn ct_findings(&self, domain: &str, unexpected_only: bool) -> Result<Vec<CtFinding>> {
+        self.database.get_ct_findings(domain, unexpected_only, FEED_PAGE_SIZE).await
+    }
+    
+    /// Queues a warning that a domain is delegated to only some of our nameservers.
+    pub async fn notify_partial_delegation(&self, partial: &PartialDelegation) -> Result<()> {
+        for (channel, target, payload) in partial_delegation_notifications(&self.config, partial) {
+            self.database.enqueue_notification(channel, &target, &payload, None).await?;
+        }
+        Ok(())
+    }
+    
+    /// Fans domain events after `since` out to every configured notification target and returns
+    /// the new cursor. Without a cursor it resumes from what the outbox already holds.
+    pub async fn enqueue_domain_event_notifications(&self, since: Option<i64>) -> Result<i64> {
+        let mut cursor = match since {
+            Some(cursor) => cursor,
+            None => self.database.get_outbox_cursor().await?,
+        };
+        
+        for event in self.database.get_domain_events(cursor, FEED_PAGE_SIZE).await? {
+            for (channel, target, payload) in domain_event_notifications(&self.config, &event) {
+                self.database.enqueue_notification(channel, &target, &payload, Some(event.id)).await?;
+            }
+            cursor = event.id;
+        }
+        Ok(cursor)
+    }
+    
+    /// Delivers due notifications, rescheduling failures with exponential backoff and
+    /// dead-lettering them after `outbox_max_attempts`.
+    pub async fn deliver_notifications(&self, client: &reqwest::Client) -> Result<()> {
+        for message in self.database.get_due_notifications(OUTBOX_BATCH_SIZE).await? {
+            let delivered = match message.channel {
+                Channel::Supabase => self.push_domain_to_supabase(&message.target).await,
+                _ => deliver(client, &message, self.config.webhook_secret.as_deref()).await,
+            };
+            match delivered {
+                Ok(_) => self.database.mark_notification_delivered(message.id).await?,
+                Err(e) => {
+                    let attempts = message.attempts + 1;
+                    let retry_in = (attempts < self.config.outbox_max_attempts).then(|| backoff(attempts));
+                    if retry_in.is_none() {
+                        warn!("Notification {} to {} dead-lettered after {} attempts: {}", message.id, message.target, attempts, e);
+                    }
+                    self.database.mark_notification_failed(message.id, &e.to_string(), retry_in).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Delivers a queued Supabase push; retried with backoff like any other outbox message.
+    async fn push_domain_to_supabase(&self, domain: &str) -> Result<()> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        supabase.sync_domain_to_supabase(&self.database, domain).await
+    }
+    
+    pub async fn outbox(&self, status: Option<&str>) -> Result<Vec<OutboxMessage>> {
+        self.database.get_outbox(status, FEED_PAGE_SIZE).await
+    }
+    
+    pub async fn retry_notification(&self, id: i64) -> Result<()> {
+        self.database.retry_notification(id).await
+    }
+    
+    /// Domain add/remove/verify events after `since`, for the mail backend to poll.
+    pub async fn domain_feed(&self, since: i64) -> Result<Vec<DomainEvent>> {
+        self.database.get_domain_events(since, FEED_PAGE_SIZE).await
+    }
+    
+    pub async fn list_domains(&self) -> Vec<String> {
+        let manager = self.domain_manager.read().await;
+        manager.list_domains().await
+    }
+    
+    pub async fn get_all_domains(&self) -> Vec<crate::domain_manager::DomainRecord> {
+        let manager = self.domain_manager.read().await;
+        manager.get_all_domains().await
+    }
+    
+    pub async fn verification_history(&self, domain: &str, limit: i64) -> Result<Vec<VerificationEntry>> {
+        self.database.get_verification_history(domain, limit).await
+    }
+    
+    /// Record-level change history for a domain, newest first.
+    pub async fn zone_changes(&self, domain: &str, limit: i64) -> Result<Vec<ZoneChange>> {
+        self.database.get_zone_changes(domain, limit).await
+    }
+    
+    pub async fn abuse_state(&self, domain: &str) -> Option<AbuseState> {
+        let manager = self.domain_manager.read().await;
+        manager.abuse_state(domain)
+    }
+    
+    /// Audited abuse state transitions for a domain, newest first.
+    pub async fn abuse_events(&self, domain: &str) -> Result<Vec<AbuseEvent>> {
+        self.database.get_abuse_events(domain).await
+    }
+    
+    pub async fn abuse_cases(&self, state: Option<AbuseState>) -> Result<Vec<AbuseCase>> {
+        self.database.get_abuse_cases(state).await
+    }
+    
+    pub async fn set_abuse_state(&self, domain: &str, state: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.set_abuse_state(domain, state, reason, actor).await?;
+        self.response_cache.invalidate_domain(domain);
+        
+        // A ban removes the domain, so Supabase has to learn about it like any other removal
+        if state == Some(AbuseState::Banned) {
+            if let Some(supabase) = &self.supabase_client {
+                if let Err(e) = supabase.sync_to_supabase(&self.database).await {
+                    error!("Failed to sync banned domain to Supabase: {}", e);
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn blocked_domains(&self) -> Vec<BlockedDomain> {
+        let manager = self.domain_manager.read().await;
+        manager.blocked_domains().to_vec()
+    }
+    
+    /// Adds a blocked-domain entry, returning it with the already served domains it covers.
+    pub async fn block_domain(&self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<(BlockedDomain, Vec<String>)> {
+        let mut manager = self.domain_manager.write().await;
+        manager.block_domain(pattern, kind, reason, actor).await
+    }
+    
+    pub async fn unblock_domain(&self, id: i64) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.unblock_domain(id).await
+    }
+    
+    pub async fn answer_overrides(&self) -> Vec<AnswerOverride> {
+        self.domain_manager.read().await.answer_overrides()
+    }
+    
+    /// Adds an override, answered from the next query on; cached answers are bypassed, not flushed.
+    pub async fn add_answer_override(&self, request: &OverrideRequest, actor: &str) -> Result<AnswerOverride> {
+        let entry = request.validate(chrono::Utc::now())?;
+        let mut manager = self.domain_manager.write().await;
+        manager.add_answer_override(entry, actor).await
+    }
+    
+    pub async fn remove_answer_override(&self, id: i64) -> Result<AnswerOverride> {
+        let mut manager = self.domain_manager.write().await;
+        manager.remove_answer_override(id).await
+    }
+    
+    async fn refresh_answer_overrides(&self) -> Result<()> {
+        let overrides = self.database.get_answer_overrides().await?;
+        self.domain_manager.write().await.set_answer_overrides(overrides);
+        Ok(())
+    }
+    
+    pub async fn rollback_records(&self, domain: &str, to: i64, actor: &str) -> Result<usize> {
+        let mut manager = self.domain_manager.write().await;
+        let reverted = manager.rollback_records(domain, to, actor).await?;
+        // Rolled-back NS overrides can change glue served from other zones
+        self.response_cache.flush();
+        Ok(reverted)
+    }
+    
+    pub async fn get_domain_info(&self, domain: &str) -> Option<crate::domain_manager::DomainRecord> {
+        let manager = self.domain_manager.read().await;
+        manager.get_domain(domain).await
+    }
+    
+    /// Memory, task and in-memory map sizes, plus every background job's counters.
+    pub async fn runtime_info(&self) -> RuntimeInfo {
+        let (domains, records) = {
+            let manager = self.domain_manager.read().await;
+            (manager.domain_count(), manager.record_count())
+        };
+        
+        RuntimeInfo {
+            rss_bytes: rss_bytes(),
+            domains,
+            records,
+            response_cache_entries: self.response_cache.len(),
+            stats_buckets: self.domain_counter.len(),
+            anomaly_baselines: self.query_anomalies.tracked_domains(),
+            rejected_packets: self.packet_guard.rejected(),
+            reconcile: self.reconcile_stats.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            database: self.db_breaker.status(),
+            tokio: TokioRuntime::current(),
+            shadow: self.shadow.as_ref().map(|shadow| shadow.status()),
+            jobs: self.jobs.stats(),
+        }
+    }
+    
+    /// Domain, verification and transport metrics in the Prometheus text format.
+    pub async fn metrics(&self) -> String {
+        let mut metrics = self.domain_manager.read().await.metrics().render();
+        metrics.push_str(&self.transport_stats.render());
+        metrics
+    }
+    
+    /// Timing and skip counters of the background jobs.
+    pub fn job_stats(&self) -> Vec<JobStats> {
+        self.jobs.stats()
+    }
+    
+    /// The Supabase user and payment details behind a domain; errors if Supabase is not configured.
+    pub async fn domain_owner(&self, domain: &str) -> Result<Option<DomainOwner>> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        supabase.get_domain_owner(domain).await
+    }
+    
+    /// Moves domains in and out of the payment-required state by their Supabase `payment_status`.
+    /// An unpaid domain gets a billing hold, and once `payment_grace_hours` have passed its MX
+    /// points at `payment_required_mx`. A paid one loses its hold and is answered as before.
+    pub async fn check_payments(&self) -> Result<PaymentCheckReport> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        if self.config.payment_required_mx.is_none() {
+            return Err(anyhow::anyhow!("payment_required_mx is not configured"));
+        }
+        
+        let served: HashSet<String> = self.list_domains().await.into_iter().collect();
+        let holds: HashMap<String, BillingHold> = self.database.get_billing_holds().await?
+            .into_iter()
+            .map(|hold| (hold.domain.clone(), hold))
+            .collect();
+        let grace = chrono::Duration::hours(self.config.payment_grace_hours);
+        let now = chrono::Utc::now();
+        let mut report = PaymentCheckReport::default();
+        let mut seen = HashSet::new();
+        
+        for row in supabase.get_all_domains().await? {
+            let domain = domain_key(&row.domain);
+            if !served.contains(&domain) || !seen.insert(domain.clone()) {
+                continue;
+            }
+            let hold = holds.get(&domain);
+            
+            if is_paid(&row.payment_status) {
+                if let Some(hold) = hold {
+                    self.database.clear_billing_hold(&domain).await?;
+                    match hold.paused_at {
+                        Some(_) => report.resumed.push(domain),
+                        None => report.cleared.push(domain),
+                    }
+                }
+                continue;
+            }
+            
+            if hold.map(|hold| hold.payment_status.as_str()) != Some(row.payment_status.as_str()) {
+                self.database.save_billing_hold(&domain, &row.payment_status).await?;
+                if hold.is_none() {
+                    report.unpaid.push(domain.clone());
+                }
+            }
+            let unpaid_since = hold.map_or(now, |hold| hold.unpaid_since);
+            if !hold.is_some_and(|hold| hold.paused_at.is_some()) && now - unpaid_since >= grace {
+                self.database.pause_billing_hold(&domain).await?;
+                report.paused.push(domain);
+            }
+        }
+        
+        // Holds of removed domains would otherwise pause them straight away if they came back
+        for domain in holds.keys().filter(|domain| !served.contains(*domain)) {
+            self.database.clear_billing_hold(domain).await?;
+            report.cleared.push(domain.clone());
+        }
+        
+        {
+            let mut manager = self.domain_manager.write().await;
+            for domain in &report.paused {
+                manager.set_payment_paused(domain, true);
+            }
+            for domain in &report.resumed {
+                manager.set_payment_paused(domain, false);
+            }
+        }
+        for domain in &report.paused {
+            self.response_cache.invalidate_domain(domain);
+            warn!("Domain {} is unpaid past its grace window, mail now bounces with a payment notice", domain);
+        }
+        for domain in &report.resumed {
+            self.response_cache.invalidate_domain(domain);
+            info!("Payment for {} resumed, mail is delivered again", domain);
+        }
+        Ok(report)
+    }
+    
+    /// Domains currently unpaid, in or past their grace window.
+    pub async fn billing_holds(&self) -> Result<Vec<BillingHold>> {
+        self.database.get_billing_holds().await
+    }
+    
+    /// Hands a domain to another Supabase user. With `to_user` the domain's row moves right away;
+    /// without it a claim token is issued and returned, the only time it can be read. Either way
+    /// an earlier unclaimed token for the domain stops working.
+    pub async fn transfer_domain(&self, domain: &str, request: &TransferRequest, actor: &str) -> Result<(DomainTransfer, Option<String>)> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        let domain = domain_key(domain);
+        if self.get_domain_info(&domain).await.is_none() {
+            return Err(anyhow::anyhow!("Domain {} is not served", domain));
+        }
+        let row = supabase.get_domain(&domain).await?
+            .ok_or_else(|| anyhow::anyhow!("Domain {} has no Supabase row", domain))?;
+        
+        let to_user = request.to_user.as_deref().map(str::trim).filter(|user| !user.is_empty());
+        if let Some(to_user) = to_user {
+            if to_user == row.user_id {
+                return Err(anyhow::anyhow!("Domain {} already belongs to {}", domain, to_user));
+            }
+            let Some(to_email) = supabase.get_user_email(to_user).await? else {
+                return Err(anyhow::anyhow!("Unknown user: {}", to_user));
+            };
+            
+            self.database.cancel_pending_transfers(&domain).await?;
+            self.move_supabase_row(&row.id, to_user).await?;
+            let transfer = self.database.add_domain_transfer(
+                &domain, &row.user_id, Some(to_user), TransferStatus::Completed, None, None, actor,
+            ).await?;
+            info!("Transferred {} from {} to {} ({})", domain, row.user_id, to_user, actor);
+            
+            let from_email = supabase.get_user_email(&row.user_id).await.ok().flatten();
+            self.notify_domain_transfer(&transfer, from_email.as_deref(), Some(&to_email)).await?;
+            return Ok((transfer, None));
+        }
+        
+        let (token, token_hash) = new_claim_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(CLAIM_TOKEN_TTL_HOURS);
+        self.database.cancel_pending_transfers(&domain).await?;
+        let transfer = self.database.add_domain_transfer(
+            &domain, &row.user_id, None, TransferStatus::Pending, Some(&token_hash), Some(expires_at), actor,
+        ).await?;
+        info!("Issued a claim token for {} on behalf of {} ({})", domain, row.user_id, actor);
+        
+        let from_email = supabase.get_user_email(&row.user_id).await.ok().flatten();
+        self.notify_domain_transfer(&transfer, from_email.as_deref(), None).await?;
+        Ok((transfer, Some(token)))
+    }
+    
+    /// Redeems a claim token for `request.user_id`. Fails if the token is unknown, expired or
+    /// already used, or if the domain changed hands since the token was issued.
+    pub async fn claim_domain_transfer(&self, request: &ClaimRequest) -> Result<DomainTransfer> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        let transfer = self.database.get_claimable_transfer(&hash_claim_token(&request.token)).await?
+            .ok_or_else(|| anyhow::anyhow!("Invalid or expired claim token"))?;
+        let to_user = request.user_id.trim();
+        
+        let row = supabase.get_domain(&transfer.domain).await?
+            .ok_or_else(|| anyhow::anyhow!("Domain {} has no Supabase row", transfer.domain))?;
+        if row.user_id != transfer.from_user {
+            self.database.cancel_pending_transfers(&transfer.domain).await?;
+            return Err(anyhow::anyhow!("Domain {} changed owner since the claim token was issued", transfer.domain));
+        }
+        if to_user == row.user_id {
+            return Err(anyhow::anyhow!("Domain {} already belongs to {}", transfer.domain, to_user));
+        }
+        let Some(to_email) = supabase.get_user_email(to_user).await? else {
+            return Err(anyhow::anyhow!("Unknown user: {}", to_user));
+        };
+        
+        // Marking the token used first means two racing claims cannot both move the row
+        if !self.database.complete_transfer(transfer.id, to_user).await? {
+            return Err(anyhow::anyhow!("Invalid or expired claim token"));
+        }
+        self.move_supabase_row(&row.id, to_user).await?;
+        info!("{} claimed {} from {}", to_user, transfer.domain, transfer.from_user);
+        
+        let transfer = DomainTransfer {
+            to_user: Some(to_user.to_string()),
+            status: TransferStatus::Completed,
+            completed_at: Some(chrono::Utc::now()),
+            ..transfer
+        };
+        let from_email = supabase.get_user_email(&transfer.from_user).await.ok().flatten();
+        self.notify_domain_transfer(&transfer, from_email.as_deref(), Some(&to_email)).await?;
+        Ok(transfer)
+    }
+    
+    /// Withdraws the domain's unclaimed claim token; errors if there is none.
+    pub async fn cancel_domain_transfer(&self, domain: &str) -> Result<()> {
+        if self.database.cancel_pending_transfers(domain).await? == 0 {
+            return Err(anyhow::anyhow!("Domain {} has no pending transfer", domain));
+        }
+        info!("Cancelled the pending transfer of {}", domain);
+        Ok(())
+    }
+    
+    /// Every transfer of the domain, newest first.
+    pub async fn domain_transfers(&self, domain: &str) -> Result<Vec<DomainTransfer>> {
+        self.database.get_domain_transfers(domain).await
+    }
+    
+    async fn move_supabase_row(&self, row_id: &str, to_user: &str) -> Result<()> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        let mut updates = HashMap::new();
+        updates.insert("user_id", serde_json::Value::String(to_user.to_string()));
+        updates.insert("updated_at", serde_json::Value::String(chrono::Utc::now().to_rfc3339()));
+        supabase.update_domain(row_id, updates).await
+    }
+    
+    async fn notify_domain_transfer(&self, transfer: &DomainTransfer, from_email: Option<&str>, to_email: Option<&str>) -> Result<()> {
+        for (channel, target, payload) in domain_transfer_notifications(&self.config, transfer, from_email, to_email) {
+            self.database.enqueue_notification(channel, &target, &payload, None).await?;
+        }
+        Ok(())
+    }
+    
+    /// Runs a Supabase sync in both directions now, or with `dry_run` only reports what it would change.
+    pub async fn sync_supabase(&self, dry_run: bool) -> Result<SyncPlan> {
+        let supabase = self.supabase_client.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Supabase is not configured"))?;
+        let plan = supabase.plan_sync(&self.database).await?;
+        if dry_run {
+            return Ok(plan);
+        }
+        
+        supabase.sync_from_supabase(&self.database).await?;
+        supabase.sync_to_supabase(&self.database).await?;
+        self.domain_manager.write().await.load_from_database().await?;
+        self.response_cache.flush();
+        Ok(plan)
+    }
+    
+    /// Which public resolvers and parent zone servers already see the domain delegated to us.
+    pub async fn propagation(&self, domain: &str) -> Result<PropagationReport> {
+        let (status, expected) = {
+            let manager = self.domain_manager.read().await;
+            let record = manager.get_domain(domain).await
+                .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+            (record.verification_status.clone(), manager.expected_nameservers(&record))
+        };
+        
+        let timeout = Duration::from_secs(self.config.verification_timeout_seconds);
+        Ok(check_propagation(domain, status, expected, &self.propagation_resolvers, timeout).await)
+    }
+    
+    /// How to delegate the domain to its expected nameservers, for the registrar RDAP names.
+    /// A failed RDAP lookup falls back to generic instructions rather than failing the request.
+    pub async fn delegation_instructions(&self, domain: &str) -> Result<DelegationInstructions> {
+        let (expected, subdomain) = {
+            let manager = self.domain_manager.read().await;
+            let record = manager.get_domain(domain).await
+                .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+            (manager.expected_nameservers(&record), record.subdomain_hosted())
+        };
+        let domain = domain_key(domain);
+        
+        let registrar = if subdomain {
+            None
+        } else {
+            self.rdap.registrar(&domain).await.unwrap_or_else(|e| {
+                warn!("RDAP lookup of {} failed: {}", domain, e);
+                None
+            })
+        };
+        Ok(instructions(&domain, registrar, expected, subdomain))
+    }
+    
+    /// Creates the record a Google or Microsoft ownership check looks for and starts watching
+    /// public resolvers for it. A TXT value is added next to the apex's existing TXT records.
+    pub async fn start_external_verification(&self, domain: &str, request: &ExternalVerificationRequest, actor: &str) -> Result<ExternalVerification> {
+        let record = request.planned_record()?;
+        {
+            let mut manager = self.domain_manager.write().await;
+            let existing = manager.get_domain(domain).await
+                .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+            if record.record_type == "CNAME" {
+                manager.set_cname_record(domain, &record.name, &record.value, actor).await?;
+            } else {
+                let mut values: Vec<String> = existing.records.iter()
+                    .filter(|r| r.record_type == "TXT" && r.name == record.name)
+                    .map(|r| r.value.clone())
+                    .collect();
+                if !values.contains(&record.value) {
+                    values.push(record.value.clone());
+                }
+                manager.set_txt_records(domain, &record.name, &values, None, false, actor).await?;
+            }
+        }
+        self.response_cache.invalidate_domain(domain);
+        
+        let mut verification = ExternalVerification::new(domain, request, record);
+        verification.check(&self.propagation_resolvers).await;
+        
+        let mut verifications = self.external_verifications.lock().unwrap_or_else(PoisonError::into_inner);
+        verifications.retain(|v| !(v.domain == verification.domain && v.provider == verification.provider));
+        verifications.push(verification.clone());
+        info!("Started {:?} verification for {}", verification.provider, verification.domain);
+        Ok(verification)
+    }
+    
+    pub fn external_verifications(&self, domain: &str) -> Vec<ExternalVerification> {
+        let domain = domain_key(domain);
+        self.external_verifications.lock().unwrap_or_else(PoisonError::into_inner).iter()
+            .filter(|v| v.domain == domain)
+            .cloned()
+            .collect()
+    }
+    
+    /// Looks up every unfinished verification again. Finished ones stay listed for the API.
+    async fn check_external_verifications(&self) {
+        let pending: Vec<ExternalVerification> = self.external_verifications.lock().unwrap_or_else(PoisonError::into_inner).iter()
+            .filter(|v| !v.is_finished())
+            .cloned()
+            .collect();
+        
+        for mut verification in pending {
+            verification.check(&self.propagation_resolvers).await;
+            if verification.is_finished() {
+                info!("{:?} verification for {}: {:?}", verification.provider, verification.domain, verification.state);
+            }
+            
+            // Replace the entry unless the verification was restarted meanwhile
+            let mut verifications = self.external_verifications.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(entry) = verifications.iter_mut()
+                .find(|v| v.domain == verification.domain && v.provider == verification.provider && v.started_at == verification.started_at)
+            {
+                *entry = verification;
+            }
+        }
+    }
+    
+    /// Starts moving domains from one IP to another. Their TTLs are lowered right away and the
+    /// A records switched at `cutover_at`, which has to leave the current TTLs time to run out.
+    pub async fn start_ip_migration(&self, request: &IpMigrationRequest, actor: &str) -> Result<IpMigration> {
+        let lowered_ttl = request.validate()?;
+        let _guard = self.ip_migration_lock.lock().await;
+        
+        let all = self.domain_manager.read().await.get_all_domains().await;
+        let records: Vec<DomainRecord> = match &request.domains {
+            Some(domains) => {
+                let mut seen = HashSet::new();
+                let mut selected = Vec::new();
+                for domain in domains {
+                    let domain = domain_key(domain);
+                    let record = all.iter().find(|r| r.domain == domain)
+                        .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+                    if record.ip != request.from_ip {
+                        return Err(anyhow::anyhow!("{} points at {}, not {}", domain, record.ip, request.from_ip));
+                    }
+                    if !seen.insert(domain.clone()) {
+                        return Err(anyhow::anyhow!("{} is listed more than once", domain));
+                    }
+                    selected.push(record.clone());
+                }
+                selected
+            }
+            None => all.into_iter().filter(|r| r.ip == request.from_ip).collect(),
+        };
+        if records.is_empty() {
+            return Err(anyhow::anyhow!("No domains point at {}", request.from_ip));
+        }
+        
+        for active in self.database.get_ip_migrations(true).await? {
+            if let Some(domain) = records.iter().find(|r| active.domains.iter().any(|d| d.domain == r.domain)) {
+                return Err(anyhow::anyhow!("{} is already part of IP migration {}", domain.domain, active.id));
+            }
+        }
+        
+        // Resolvers may hold the current records for up to the longest current TTL
+        let longest_ttl = records.iter()
+            .map(|r| r.ttl_override().unwrap_or(self.config.default_ttl))
+            .max()
+            .unwrap_or(self.config.default_ttl);
+        let now = chrono::Utc::now();
+        let earliest = now + chrono::Duration::seconds(i64::from(longest_ttl));
+        let cutover_at = match request.cutover_at {
+            Some(at) if at < earliest => {
+                return Err(anyhow::anyhow!("cutover_at must be at least {}s away, the longest TTL among the domains", longest_ttl));
+            }
+            Some(at) => at,
+            None => earliest,
+        };
+        
+        let mut migration = IpMigration {
+            id: 0,
+            from_ip: request.from_ip.clone(),
+            to_ip: request.to_ip.clone(),
+            lowered_ttl,
+            cutover_at,
+            check_port: request.check_port,
+            phase: MigrationPhase::WaitingForCutover,
+            domains: records.iter().map(|r| MigrationDomain {
+                domain: r.domain.clone(),
+                previous_ttl: r.ttl_override(),
+                ttl_lowered: false,
+                cut_over: false,
+                verified: false,
+                ttl_restored: false,
+                error: None,
+            }).collect(),
+            actor: actor.to_string(),
+            created_at: now,
+            cut_over_at: None,
+            finished_at: None,
+            error: None,
+        };
+        migration.id = self.database.insert_ip_migration(&migration).await?;
+        
+        for domain in migration.domains.iter_mut() {
+            if domain.previous_ttl.unwrap_or(self.config.default_ttl) <= lowered_ttl {
+                continue;
+            }
+            match self.set_domain_ttl(&domain.domain, Some(lowered_ttl), actor).await {
+                Ok(()) => domain.ttl_lowered = true,
+                Err(e) => domain.error = Some(format!("Lowering the TTL failed: {}", e)),
+            }
+        }
+        self.database.save_ip_migration(&migration).await?;
+        
+        info!("Started IP migration {} of {} domains from {} to {}, cutover at {}",
+            migration.id, migration.domains.len(), migration.from_ip, migration.to_ip, migration.cutover_at);
+        Ok(migration)
+    }
+    
+    pub async fn ip_migrations(&self, active_only: bool) -> Result<Vec<IpMigration>> {
+        self.database.get_ip_migrations(active_only).await
+    }
+    
+    pub async fn ip_migration(&self, id: i64) -> Result<Option<IpMigration>> {
+        self.database.get_ip_migration(id).await
+    }
+    
+    /// Stops a migration before its cutover; the lowered TTLs are restored by the next pass.
+    pub async fn cancel_ip_migration(&self, id: i64, actor: &str) -> Result<IpMigration> {
+        let _guard = self.ip_migration_lock.lock().await;
+        let mut migration = self.database.get_ip_migration(id).await?
+            .ok_or_else(|| anyhow::anyhow!("No IP migration {}", id))?;
+        if migration.phase != MigrationPhase::WaitingForCutover {
+            return Err(anyhow::anyhow!("IP migration {} is {} and can no longer be cancelled", id, migration.phase.as_str()));
+        }
+        
+        migration.error = Some(format!("Cancelled by {}", actor));
+        migration.phase = MigrationPhase::RestoringTtl;
+        self.database.save_ip_migration(&migration).await?;
+        info!("Cancelled IP migration {}", id);
+        Ok(migration)
+    }
+    
+    /// Moves every unfinished migration on by at most one phase.
+    async fn advance_ip_migrations(&self) -> Result<()> {
+        let _guard = self.ip_migration_lock.lock().await;
+        for mut migration in self.database.get_ip_migrations(true).await? {
+            let phase = migration.phase;
+            self.advance_ip_migration(&mut migration).await;
+            self.database.save_ip_migration(&migration).await?;
+            if migration.phase != phase {
+                info!("IP migration {}: {} -> {}", migration.id, phase.as_str(), migration.phase.as_str());
+            }
+        }
+        Ok(())
+    }
+    
+    async fn advance_ip_migration(&self, migration: &mut IpMigration) {
+        let now = chrono::Utc::now();
+        match migration.phase {
+            MigrationPhase::WaitingForCutover => {
+                if now < migration.cutover_at {
+                    return;
+                }
+                if let Some(port) = migration.check_port {
+                    if !accepts_connections(&migration.to_ip, port).await {
+                        warn!("IP migration {} aborted: {} does not accept connections on port {}", migration.id, migration.to_ip, port);
+                        migration.error = Some(format!("{} did not accept connections on port {} at the cutover; nothing was moved", migration.to_ip, port));
+                        migration.phase = MigrationPhase::RestoringTtl;
+                        return;
+                    }
+                }
+                
+                for domain in migration.domains.iter_mut().filter(|d| d.error.is_none()) {
+                    let current = self.domain_manager.read().await.get_domain(&domain.domain).await;
+                    match current {
+                        Some(record) if record.ip == migration.from_ip => {
+                            match self.set_domain_ip(&domain.domain, &migration.to_ip).await {
+                                Ok(()) => domain.cut_over = true,
+                                Err(e) => domain.error = Some(format!("Cutover failed: {}", e)),
+                            }
+                        }
+                        Some(record) if record.ip == migration.to_ip => domain.cut_over = true,
+                        Some(record) => domain.error = Some(format!("IP was changed to {} during the migration", record.ip)),
+                        None => domain.error = Some("Domain was removed during the migration".to_string()),
+                    }
+                }
+                migration.cut_over_at = Some(now);
+                migration.phase = MigrationPhase::Verifying;
+            }
+            MigrationPhase::Verifying => {
+                for domain in migration.domains.iter_mut().filter(|d| d.cut_over && !d.verified && d.error.is_none()) {
+                    domain.verified = serves_ip(&self.propagation_resolvers, &domain.domain, &migration.to_ip).await;
+                }
+                
+                let pending = migration.domains.iter().any(|d| d.cut_over && !d.verified && d.error.is_none());
+                let cut_over_at = migration.cut_over_at.unwrap_or(now);
+                let timed_out = now - cut_over_at > chrono::Duration::minutes(VERIFICATION_TIMEOUT_MINUTES);
+                if pending && timed_out {
+                    for domain in migration.domains.iter_mut().filter(|d| d.cut_over && !d.verified && d.error.is_none()) {
+                        domain.error = Some(format!("Public resolvers did not all return {} within {} minutes", migration.to_ip, VERIFICATION_TIMEOUT_MINUTES));
+                    }
+                }
+                if !pending || timed_out {
+                    migration.phase = MigrationPhase::RestoringTtl;
+                }
+            }
+            MigrationPhase::RestoringTtl => {
+                for domain in migration.domains.iter_mut().filter(|d| d.ttl_lowered && !d.ttl_restored) {
+                    let current = self.domain_manager.read().await.get_domain(&domain.domain).await;
+                    // TTLs changed by hand since the migration lowered them are left alone
+                    if current.and_then(|r| r.ttl_override()) == Some(migration.lowered_ttl) {
+                        if let Err(e) = self.set_domain_ttl(&domain.domain, domain.previous_ttl, &migration.actor).await {
+                            warn!("IP migration {} could not restore the TTL of {}: {}", migration.id, domain.domain, e);
+                            continue;
+                        }
+                    }
+                    domain.ttl_restored = true;
+                }
+                
+                if migration.domains.iter().all(|d| !d.ttl_lowered || d.ttl_restored) {
+                    migration.phase = if migration.cut_over_at.is_some() { MigrationPhase::Completed } else { MigrationPhase::Cancelled };
+                    migration.finished_at = Some(now);
+                }
+            }
+            MigrationPhase::Completed | MigrationPhase::Cancelled => {}
+        }
+    }
+    
+    /// Checks many domains at once, `BULK_VERIFY_CONCURRENCY` at a time, in the order given.
+    /// Status changes reach Supabase through the usual verification events.
+    pub async fn verify_domains(&self, domains: Vec<String>) -> Result<Vec<VerificationOutcome>> {
+        let mut seen = HashSet::new();
+        let domains: Vec<String> = domains.iter()
+            .map(|domain| domain_key(domain))
+            .filter(|domain| seen.insert(domain.clone()))
+            .collect();
+        
+        let permits = Arc::new(tokio::sync::Semaphore::new(BULK_VERIFY_CONCURRENCY));
+        let mut checks = tokio::task::JoinSet::new();
+        for (index, domain) in domains.into_iter().enumerate() {
+            let manager = self.domain_manager.clone();
+            let permits = permits.clone();
+            checks.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                (index, DomainManager::verify_with_outcome(&manager, &domain).await)
+            });
+        }
+        
+        let mut outcomes = Vec::with_capacity(checks.len());
+        while let Some(checked) = checks.join_next().await {
+            outcomes.push(checked?);
+        }
+        outcomes.sort_by_key(|(index, _)| *index);
+        
+        let outcomes: Vec<VerificationOutcome> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+        for outcome in &outcomes {
+            self.response_cache.invalidate_domain(&outcome.domain);
+        }
+        info!(
+            "Bulk verification checked {} domains, {} verified",
+            outcomes.len(),
+            outcomes.iter().filter(|outcome| outcome.verified).count()
+        );
+        Ok(outcomes)
+    }
+    
+    /// Enabled domains that are not verified, for `POST /verify` with `all_pending`.
+    pub async fn unverified_domains(&self) -> Vec<String> {
+        self.domain_manager.read().await.unverified_domains()
+    }
+    
+    pub async fn force_verification(&self, domain: &str) -> Result<bool> {
+        let verified = DomainManager::verify(&self.domain_manager, domain).await;
+        self.response_cache.invalidate_domain(domain);
+        
+        // Sync to Supabase if configured
+        if let Some(supabase) = &self.supabase_client {
+            if let Err(e) = supabase.sync_to_supabase(&self.database).await {
+                error!("Failed to sync verification status to Supabase: {}", e);
+            }
+        }
+        
+        Ok(verified)
+    }
+    
+    pub fn is_serving_snapshot(&self) -> bool {
+        self.serving_snapshot.load(Ordering::Relaxed)
+    }
+    
+    pub async fn save_snapshot(&self) -> Result<()> {
+        let domains = self.domain_manager.read().await.get_all_domains().await;
+        write_snapshot(&self.config.snapshot_path, domains).await
+    }
+    
+    /// Writes the domain map, cache size, database breaker and job heartbeats to a new file in
+    /// `state_dump_dir`, returning its path.
+    pub async fn dump_state(&self) -> Result<String> {
+        let domains = match tokio::time::timeout(DUMP_LOCK_TIMEOUT, self.domain_manager.read()).await {
+            Ok(manager) => Some(manager.get_all_domains().await),
+            Err(_) => {
+                warn!("Domain manager still locked after {}s, dumping state without domains", DUMP_LOCK_TIMEOUT.as_secs());
+                None
+            }
+        };
+        let dump = StateDump {
+            dumped_at: chrono::Utc::now(),
+            version: env!("CARGO_PKG_VERSION"),
+            maintenance: self.is_maintenance(),
+            serving_snapshot: self.is_serving_snapshot(),
+            response_cache_entries: self.response_cache.len(),
+            database: self.db_breaker.status(),
+            jobs: self.jobs.stats(),
+            domains,
+        };
+        write_state_dump(&self.config.state_dump_dir, &dump).await
+    }
+    
+    #[cfg(unix)]
+    async fn dump_state_on_signal(&self) {
+        use tokio::signal::unix::{signal, SignalKind};
+        
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                error!("Failed to listen for SIGUSR1, state dumps are unavailable: {}", e);
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            match self.dump_state().await {
+                Ok(path) => info!("Wrote state dump to {}", path),
+                Err(e) => error!("Failed to write state dump: {}", e),
+            }
+        }
+    }
+    
+    /// Rereads every domain from the database, e.g. after rows were fixed by hand in SQL, then
+    /// flushes the answer cache and rewrites the snapshot. Also ends serving from the startup
+    /// snapshot, like `recover_database`.
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let report = {
+            let mut manager = self.domain_manager.write().await;
+            let before = manager.get_all_domains().await;
+            manager.load_from_database().await?;
+            ReloadReport::between(before, manager.get_all_domains().await)
+        };
+        self.response_cache.flush();
+        
+        if self.serving_snapshot.swap(false, Ordering::Relaxed) {
+            self.maintenance.store(self.config.maintenance_mode, Ordering::Relaxed);
+            info!("Database reloaded while serving the snapshot, serving live data");
+        }
+        if self.config.snapshot_enabled {
+            self.save_snapshot().await?;
+        }
+        
+        info!("Reloaded {} domains: {} added, {} removed, {} changed",
+              report.domains, report.added, report.removed, report.changed);
+        Ok(report)
+    }
+    
+    /// Diffs the in-memory domains against the database and applies the corrections, dropping
+    /// cached answers only for the domains that changed.
+    pub async fn reconcile(&self) -> Result<ReconcileReport> {
+        let report = self.domain_manager.write().await.reconcile_with_database().await?;
+        for domain in &report.changed_domains {
+            self.response_cache.invalidate_domain(domain);
+        }
+        self.reconcile_stats.lock().unwrap_or_else(PoisonError::into_inner).record(&report);
+        
+        if !report.changed_domains.is_empty() || report.duplicates > 0 {
+            warn!("Reconciled domains with the database: {} added, {} removed, {} drifted, {} duplicate rows",
+                  report.added, report.removed, report.drifted, report.duplicates);
+        }
+        Ok(report)
+    }
+    
+    /// Feeds one ping into the breaker. A failure is only counted, since the breaker logs
+    /// when it opens; after a success, queued writes are replayed in order.
+    async fn probe_database(&self) -> Result<()> {
+        if let Err(e) = self.database.ping().await {
+            self.db_breaker.record_failure(&e);
+            return Ok(());
+        }
+        self.db_breaker.record_success();
+        self.db_breaker.replay(&self.database).await?;
+        Ok(())
+    }
+    
+    /// Whether the database breaker is closed; API writes are refused while it is open.
+    pub fn database_available(&self) -> bool {
+        !self.db_breaker.is_open()
+    }
+    
+    pub fn database_status(&self) -> BreakerStatus {
+        self.db_breaker.status()
+    }
+    
+    /// Switches from the startup snapshot to the database once it answers again, lifting the
+    /// read-only mode unless `maintenance_mode` was configured.
+    async fn recover_database(&self) -> Result<()> {
+        self.database.ping().await?;
+        self.domain_manager.write().await.load_from_database().await?;
+        self.response_cache.flush();
+        self.serving_snapshot.store(false, Ordering::Relaxed);
+        self.maintenance.store(self.config.maintenance_mode, Ordering::Relaxed);
+        info!("Database connection restored, serving live data");
+        Ok(())
+    }
+    
+    pub fn config(&self) -> &DnsConfig {
+        &self.config
+    }
+    
+    pub fn effective_config(&self) -> &EffectiveConfig {
+        &self.effective_config
+    }
+    
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+    
+    /// Toggles maintenance mode. DNS keeps answering from memory while it is on.
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::Relaxed);
+        warn!("Maintenance mode {}", if enabled { "enabled" } else { "disabled" });
+    }
+    
+    /// Starts writing raw query/response pairs to `debug_capture_dir`.
+    pub fn start_capture(&self, request: CaptureRequest) -> Result<CaptureStatus> {
+        if !self.config.debug_capture_enabled {
+            return Err(anyhow::anyhow!("Packet capture is disabled (debug_capture_enabled = false)"));
+        }
+        
+        self.packet_capture.start(
+            std::path::Path::new(&self.config.debug_capture_dir),
+            request.format,
+            request.qname,
+            Duration::from_secs(request.duration_seconds),
+        )
+    }
+    
+    pub fn stop_capture(&self) -> Option<CaptureStatus> {
+        self.packet_capture.stop()
+    }
+    
+    pub fn capture_status(&self) -> Option<CaptureStatus> {
+        self.packet_capture.status()
+    }
+    
+    /// Drops every cached answer. Returns how many entries were removed.
+    pub fn flush_cache(&self) -> usize {
+        self.response_cache.flush()
+    }
+    
+    /// Drops cached answers for a domain and its subdomains.
+    pub fn flush_cache_for(&self, domain: &str) -> usize {
+        self.response_cache.invalidate_domain(domain)
+    }
+    
+    pub fn top_stats(&self, window: Duration, n: usize) -> TopStats {
+        self.query_stats.top(window, n)
+    }
+    
+    pub fn transport_stats(&self) -> TransportSnapshot {
+        self.transport_stats.snapshot()
+    }
+    
+    /// Per-domain query counts in `[from, to)`. Needs `stats_history_enabled`; without it
+    /// there are none.
+    pub async fn query_totals(&self, from: chrono::DateTime<chrono::Utc>, to: chrono::DateTime<chrono::Utc>) -> Result<Vec<QueryTotal>> {
+        if from >= to {
+            return Err(anyhow::anyhow!("from must be before to"));
+        }
+        query_totals(&self.database, &self.stats_retention, from, to).await
+    }
+    
+    /// Verification uptime and status periods for a managed domain over the last 30 days.
+    pub async fn domain_uptime(&self, domain: &str) -> Result<DomainUptime> {
+        if self.get_domain_info(domain).await.is_none() {
+            return Err(anyhow::anyhow!("Domain not found: {}", domain));
+        }
+        domain_uptime(&self.database, domain, chrono::Utc::now()).await
+    }
+    
+    /// Per-domain query counts and verification uptime in `[from, to)`, for usage billing.
+    /// Query counts need `stats_history_enabled`; without it they are all zero.
+    pub async fn usage_report(&self, from: chrono::DateTime<chrono::Utc>, to: chrono::DateTime<chrono::Utc>) -> Result<Vec<DomainUsage>> {
+        if from >= to {
+            return Err(anyhow::anyhow!("from must be before to"));
+        }
+        
+        let totals = query_totals(&self.database, &self.stats_retention, from, to).await?;
+        let checks = self.database.get_verification_checks(from, to, None).await?;
+        let domains = self.list_domains().await;
+        Ok(usage_report(totals, checks, domains, from, to))
+    }
+    
+    pub async fn get_stats(&self) -> DomainStats {
+        let manager = self.domain_manager.read().await;
+        let domains = manager.get_all_domains().await;
+        
+        let total = domains.len();
+        let verified = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::Verified).count();
+        let pending = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::PendingVerification).count();
+        let grace_period = domains.iter().filter(|d| d.enabled && d.verification_status == crate::domain_manager::VerificationStatus::GracePeriod).count();
+        let partially_delegated = domains.iter().filter(|d| d.enabled && d.partially_delegated()).count();
+        let discord = domains.iter().filter(|d| d.discord).count();
+        let disabled = domains.iter().filter(|d| !d.enabled).count();
+        
+        DomainStats {
+            total_domains: total,
+            verified_domains: verified,
+            pending_verification: pending,
+            grace_period: grace_period,
+            partially_delegated,
+            discord_domains: discord,
+            disabled_domains: disabled,
+            supabase_connected: self.supabase_client.is_some(),
+            serving_snapshot: self.is_serving_snapshot(),
+            misconfigured_targets: self.misconfigurations.read().unwrap_or_else(PoisonError::into_inner).clone(),
+            mail_server_problems: self.mail_server_problems.read().unwrap_or_else(PoisonError::into_inner).clone(),
+        }
+    }
+}
+
+/// Answers queries arriving on `socket` until the socket errors out for good.
+pub(crate) async fn serve_udp(
+    socket: tokio::net::UdpSocket,
+    handler: CybertempHandler,
+    capture: Option<Arc<PacketCapture>>,
+) -> Result<()> {
+    let local_addr = socket.local_addr()?;
+    let mut buf = [0u8; MAX_UDP_QUERY];
+    
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, src)) => {
+                let data = buf[..len].to_vec();
+                
+                if let Ok(response_data) = handler.handle_request(&data, src).await {
+                    if let Some(capture) = &capture {
+                        capture.record(src, local_addr, &data, &response_data);
+                    }
+                    if let Err(e) = socket.send_to(&response_data, src).await {
+                        error!("Error sending DNS response: {}", e);
+                    }
+                } else {
+                    error!("Error handling DNS request");
+                }
+            }
+            Err(e) => {
+                error!("Error receiving DNS packet: {}", e);
+            }
+        }
+    }
+}
+
+/// How a reload changed the in-memory domains.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReloadReport {
+    pub domains: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// Domains whose settings, records, verification or abuse state differ after the reload.
+    pub changed: usize,
+}
+
+impl ReloadReport {
+    fn between(before: Vec<DomainRecord>, after: Vec<DomainRecord>) -> Self {
+        let before: HashMap<String, serde_json::Value> = before.into_iter()
+            .map(|record| (record.domain.clone(), serde_json::to_value(&record).unwrap_or_default()))
+            .collect();
+        let mut report = ReloadReport { domains: after.len(), added: 0, removed: 0, changed: 0 };
+        
+        for record in &after {
+            match before.get(&record.domain) {
+                None => report.added += 1,
+                Some(old) if *old != serde_json::to_value(record).unwrap_or_default() => report.changed += 1,
+                Some(_) => {}
+            }
+        }
+        let after: HashSet<&str> = after.iter().map(|record| record.domain.as_str()).collect();
+        report.removed = before.keys().filter(|domain| !after.contains(domain.as_str())).count();
+        report
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, async_graphql::SimpleObject)]
+pub struct DomainStats {
+    pub total_domains: usize,
+    pub verified_domains: usize,
+    pub pending_verification: usize,
+    pub grace_period: usize,
+    /// Verified domains whose NS records name only some of our nameservers
+    pub partially_delegated: usize,
+    pub discord_domains: usize,
+    pub disabled_domains: usize,
+    pub supabase_connected: bool,
+    /// Answering from the startup snapshot until the database is reachable again
+    pub serving_snapshot: bool,
+    /// MX/NS targets that do not resolve from our own answers, from the last consistency check
+    pub misconfigured_targets: Vec<Misconfiguration>,
+    /// Mail servers behind our MX targets that failed the last SMTP check, if enabled
+    pub mail_server_problems: Vec<MailServerProblem>,
+}
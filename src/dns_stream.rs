@@ -0,0 +1,168 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error};
+
+use crate::dns_handler::CybertempHandler;
+use crate::packet_capture::PacketCapture;
+use crate::transport_stats::Transport;
+
+/// How long a connection may sit idle, take over the TLS handshake, or stall reading an answer,
+/// before it is closed (RFC 7766 §6.2.3).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers length-prefixed queries on every connection accepted from `listener`, with at most
+/// `max_connections` open at once. Connections over the limit are closed as soon as they are
+/// accepted, so a flood of idle clients cannot exhaust file descriptors.
+pub(crate) async fn serve_tcp(
+    listener: TcpListener,
+    handler: CybertempHandler,
+    capture: Option<Arc<PacketCapture>>,
+    max_connections: usize,
+) -> Result<()> {
+    let local_addr = listener.local_addr()?;
+    let connections = Arc::new(Semaphore::new(max_connections.max(1)));
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Error accepting DNS TCP connection: {}", e);
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            debug!("Closing DNS TCP connection from {}: {} connections open", peer, max_connections);
+            continue;
+        };
+
+        let handler = handler.clone();
+        let capture = capture.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = serve_connection(stream, peer, local_addr, Transport::Tcp, &handler, capture.as_deref()).await {
+                debug!("DNS TCP connection from {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Same as `serve_tcp`, with each connection wrapped in TLS first. Handshakes count against
+/// `max_connections`.
+pub(crate) async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    handler: CybertempHandler,
+    capture: Option<Arc<PacketCapture>>,
+    max_connections: usize,
+) -> Result<()> {
+    let local_addr = listener.local_addr()?;
+    let connections = Arc::new(Semaphore::new(max_connections.max(1)));
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Error accepting DNS TLS connection: {}", e);
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            debug!("Closing DNS TLS connection from {}: {} connections open", peer, max_connections);
+            continue;
+        };
+
+        let acceptor = acceptor.clone();
+        let handler = handler.clone();
+        let capture = capture.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let stream = match timeout(IDLE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            if let Err(e) = serve_connection(stream, peer, local_addr, Transport::Tls, &handler, capture.as_deref()).await {
+                debug!("DNS TLS connection from {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Loads the DoT certificate chain and the first private key (PKCS#8, RSA or EC) from PEM files.
+pub(crate) fn tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor> {
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!("No certificates found in {}", cert_path));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", key_path))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Answers queries on one connection until the client closes it, goes idle or sends garbage.
+async fn serve_connection<S>(
+    mut stream: S,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    transport: Transport,
+    handler: &CybertempHandler,
+    capture: Option<&PacketCapture>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        // A closed or idle connection ends here without an error
+        let Ok(Ok(len)) = timeout(IDLE_TIMEOUT, stream.read_u16()).await else {
+            return Ok(());
+        };
+        let mut data = vec![0u8; len as usize];
+        timeout(IDLE_TIMEOUT, stream.read_exact(&mut data)).await??;
+
+        let response_data = handler.handle_stream_request(&data, peer, transport).await?;
+        if let Some(capture) = capture {
+            capture.record(peer, local_addr, &data, &response_data);
+        }
+
+        let len = u16::try_from(response_data.len())
+            .map_err(|_| anyhow::anyhow!("{} byte response does not fit a TCP message", response_data.len()))?;
+        // A client that stops reading would otherwise hold the connection slot forever
+        timeout(IDLE_TIMEOUT, async {
+            stream.write_u16(len).await?;
+            stream.write_all(&response_data).await?;
+            stream.flush().await
+        }).await??;
+    }
+}
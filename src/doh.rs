@@ -0,0 +1,96 @@
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tracing::{info, error};
+use trust_dns_proto::rr::RecordType;
+use crate::dns_handler::CybertempHandler;
+use crate::dns_json::to_dns_json;
+
+const DNS_JSON: &str = "application/dns-json";
+
+/// Serves `GET /dns-query?name=&type=` in the JSON format of Google and Cloudflare's resolve APIs.
+pub async fn start_doh_server(
+    bind_addr: &str,
+    port: u16,
+    handler: CybertempHandler,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let addr: SocketAddr = format!("{}:{}", bind_addr, port).parse()?;
+    
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let handler = handler.clone();
+        let client = conn.remote_addr().ip();
+        
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle_doh_request(req, handler.clone(), client)
+            }))
+        }
+    });
+    
+    let server = Server::bind(&addr).serve(make_svc);
+    
+    info!("DNS JSON API running on http://{}/dns-query", addr);
+    
+    if let Err(e) = server.await {
+        error!("DoH server error: {}", e);
+    }
+    
+    Ok(())
+}
+
+async fn handle_doh_request(
+    req: Request<Body>,
+    handler: CybertempHandler,
+    client: IpAddr,
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != "/dns-query" {
+        return Ok(error_response(StatusCode::NOT_FOUND, "Not found"));
+    }
+    if req.method() != Method::GET {
+        return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported"));
+    }
+    
+    let query = req.uri().query().unwrap_or("");
+    let param = |key: &str| query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string());
+    
+    let Some(name) = param("name").filter(|n| !n.is_empty()) else {
+        return Ok(error_response(StatusCode::BAD_REQUEST, "Missing name"));
+    };
+    let record_type = param("type").unwrap_or_else(|| "A".to_string());
+    let Some(record_type) = parse_record_type(&record_type) else {
+        return Ok(error_response(StatusCode::BAD_REQUEST, &format!("Unknown record type: {}", record_type)));
+    };
+    
+    match handler.answer(&name, record_type, client).await {
+        Ok(message) => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, DNS_JSON)
+            .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(to_dns_json(&message).to_string()))
+            .unwrap()),
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, &e.to_string())),
+    }
+}
+
+/// Accepts a mnemonic (`MX`, `aaaa`) or a numeric type (`15`), as the public resolvers do.
+fn parse_record_type(value: &str) -> Option<RecordType> {
+    match value.parse::<u16>() {
+        Ok(code) => Some(RecordType::from(code)),
+        Err(_) => RecordType::from_str(&value.to_uppercase()).ok(),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, DNS_JSON)
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(json!({"error": message}).to_string()))
+        .unwrap()
+}
@@ -1,17 +1,97 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::RwLock;
 use tokio::time::interval;
 use tracing::{info, warn, error};
 use trust_dns_resolver::config::*;
-use trust_dns_resolver::TokioAsyncResolver;
 use trust_dns_proto::rr::RecordType;
 use chrono::{DateTime, Utc};
 
-use crate::database::Database;
+use crate::abuse::{check_transition, AbuseState};
+use crate::answer_override::{find_override, AnswerOverride, NewOverride};
+use crate::blocklist::{find_block, normalize_pattern, BlockKind, BlockedDomain};
+use crate::canary::{validate_canaries, Canary};
+use crate::config::{DmarcPolicy, MailRouting, NameserverSet, RoutingProfile, SerialStrategy, SubdomainPolicy, DISCORD_PROFILE};
+use crate::database::{Database, DnsRecord, ResolverCheck};
+use crate::db_breaker::{DbBreaker, QueuedWrite};
+use crate::domain_name::{canonical_domain, domain_key};
+use crate::domain_store::DomainStore;
+use crate::metrics::{DomainMetrics, Transition, VerificationCycle};
+use crate::record_templates::RecordTemplate;
+use crate::propagation::check_parent_delegation;
+use crate::resolvers::{check_delegation, missing_nameservers, NamedResolver};
+use crate::synthetic::{validate_rules, SyntheticRule};
+use crate::validation::{check_record_name, check_ttl, format_record_value, parse_record_value, DEFAULT_RESERVED_LABELS, MAX_RECORD_SET_VALUES};
+use crate::usage::domain_uptime;
+use crate::zone_import::ImportedRecord;
+
+/// Stored record types that are answered, in place of the synthesized answers at their name;
+/// the other rows are per-domain settings.
+pub const SERVED_RECORD_TYPES: &[&str] = &["A", "AAAA", "CNAME", "MX", "TXT", "SRV"];
+
+/// What one reconcile pass found different between memory and the database, and corrected.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconcileReport {
+    /// In the database but missing from memory.
+    pub added: usize,
+    /// In memory but gone from the database, or banned since.
+    pub removed: usize,
+    /// Present in both with a different IP, flags, SOA serial, abuse state or records.
+    pub drifted: usize,
+    /// Database rows for a name already read with different letter case.
+    pub duplicates: usize,
+    /// Every domain added, removed or corrected, for cache invalidation.
+    #[serde(skip)]
+    pub changed_domains: Vec<String>,
+}
+
+/// Reconcile totals since startup, shown under `reconcile` in `GET /debug/runtime`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconcileStats {
+    pub runs: u64,
+    pub added: u64,
+    pub removed: u64,
+    pub drifted: u64,
+    pub duplicates: u64,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_report: Option<ReconcileReport>,
+}
+
+impl ReconcileStats {
+    pub fn record(&mut self, report: &ReconcileReport) {
+        self.runs += 1;
+        self.added += report.added as u64;
+        self.removed += report.removed as u64;
+        self.drifted += report.drifted as u64;
+        self.duplicates += report.duplicates as u64;
+        self.last_run = Some(Utc::now());
+        self.last_report = Some(report.clone());
+    }
+}
+
+/// The domain state as read from the database, before it replaces or corrects memory.
+struct StoredState {
+    domains: HashMap<String, DomainRecord>,
+    banned: HashSet<String>,
+    blocked: Vec<BlockedDomain>,
+    overrides: Vec<AnswerOverride>,
+    duplicates: usize,
+}
+
+/// How `DomainManager::add_domain_with` writes a new domain.
+#[derive(Debug, Default)]
+pub struct DomainWrite<'a> {
+    /// Client-chosen key; repeating a write with a key already used is a no-op.
+    pub idempotency_key: Option<&'a str>,
+    /// Queue a push of the domain to Supabase in the outbox, in the same transaction.
+    pub sync_supabase: bool,
+    /// Routing profile the domain is added on; its `ip` replaces the one given.
+    pub routing_profile: Option<&'a str>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DomainRecord {
@@ -24,6 +104,120 @@ pub struct DomainRecord {
     pub verification_status: VerificationStatus,
     pub grace_period_ends: Option<DateTime<Utc>>,
     pub discord: bool,
+    #[serde(default)]
+    pub records: Vec<DnsRecord>,
+    #[serde(default)]
+    pub soa_serial: i64,
+    #[serde(default)]
+    pub abuse_state: Option<AbuseState>,
+    /// Unpaid past the grace window: MX answers point at `payment_required_mx`.
+    #[serde(default)]
+    pub payment_paused: bool,
+    /// Percentage of the last 30 days spent verified; `None` before the first check.
+    #[serde(default)]
+    pub uptime_30d: Option<f64>,
+    /// Our nameservers the last successful check did not find in the NS set; non-empty means
+    /// the domain is only partially delegated.
+    #[serde(default)]
+    pub missing_nameservers: Vec<String>,
+}
+
+impl DomainRecord {
+    /// TXT payload for `default._bimi.<domain>`, if a BIMI logo has been configured.
+    ///
+    /// The logo URL and optional VMC URL are stored space-separated in a single `BIMI` row.
+    pub fn bimi_txt(&self) -> Option<String> {
+        let record = self.records.iter().find(|r| r.record_type == "BIMI" && r.name == "default")?;
+        let mut parts = record.value.split_whitespace();
+        let logo = parts.next()?;
+        let vmc = parts.next().unwrap_or("");
+        Some(format!("v=BIMI1; l={}; a={};", logo, vmc))
+    }
+    
+    /// Per-domain DMARC policy override, stored as JSON in a `DMARC` row.
+    pub fn dmarc_policy(&self) -> Option<DmarcPolicy> {
+        let record = self.records.iter().find(|r| r.record_type == "DMARC" && r.name == "_dmarc")?;
+        serde_json::from_str(&record.value).ok()
+    }
+    
+    /// Per-domain mail routing profile, stored as JSON in a `MAILROUTE` row.
+    pub fn mail_routing(&self) -> Option<MailRouting> {
+        let record = self.records.iter().find(|r| r.record_type == "MAILROUTE" && r.name == "@")?;
+        serde_json::from_str(&record.value).ok()
+    }
+    
+    /// Per-domain TTL for synthesized answers, from a `TTL` row.
+    pub fn ttl_override(&self) -> Option<u32> {
+        let record = self.records.iter().find(|r| r.record_type == "TTL" && r.name == "@")?;
+        record.value.parse().ok()
+    }
+    
+    /// Verified, but with only some of our nameservers in its NS set.
+    pub fn partially_delegated(&self) -> bool {
+        self.verification_status == VerificationStatus::Verified && !self.missing_nameservers.is_empty()
+    }
+    
+    /// Whether this is a delegated subdomain such as `mail.customer.com` rather than a
+    /// registered domain, from a `HOSTING` row.
+    pub fn subdomain_hosted(&self) -> bool {
+        self.records.iter().any(|r| r.record_type == "HOSTING" && r.name == "@" && r.value == "subdomain")
+    }
+    
+    /// Per-domain nameserver set, stored space-separated in an `NSSET` row.
+    pub fn nameserver_overrides(&self) -> Option<Vec<String>> {
+        let record = self.records.iter().find(|r| r.record_type == "NSSET" && r.name == "@")?;
+        Some(record.value.split_whitespace().map(str::to_string).collect())
+    }
+    
+    /// Name of the configured nameserver set this domain is assigned to, from an `NSGROUP` row.
+    pub fn nameserver_set(&self) -> Option<&str> {
+        let record = self.records.iter().find(|r| r.record_type == "NSGROUP" && r.name == "@")?;
+        Some(record.value.as_str())
+    }
+    
+    /// Name of the routing profile mail for this domain goes through, from a `ROUTING` row,
+    /// else `discord` for domains with the legacy Discord flag.
+    pub fn routing_profile(&self) -> Option<&str> {
+        match self.records.iter().find(|r| r.record_type == "ROUTING" && r.name == "@") {
+            Some(record) => Some(record.value.as_str()),
+            None if self.discord => Some(DISCORD_PROFILE),
+            None => None,
+        }
+    }
+    
+    /// Pattern rules synthesizing records for subdomains, stored as JSON in a `SYNTH` row.
+    pub fn synthetic_rules(&self) -> Vec<SyntheticRule> {
+        self.records.iter()
+            .find(|r| r.record_type == "SYNTH" && r.name == "@")
+            .and_then(|record| serde_json::from_str(&record.value).ok())
+            .unwrap_or_default()
+    }
+    
+    /// Gradual answer changes, stored as JSON in a `CANARY` row.
+    pub fn canaries(&self) -> Vec<Canary> {
+        self.records.iter()
+            .find(|r| r.record_type == "CANARY" && r.name == "@")
+            .and_then(|record| serde_json::from_str(&record.value).ok())
+            .unwrap_or_default()
+    }
+    
+    /// Per-domain subdomain policy override, from a `SUBDOMAIN` row.
+    pub fn subdomain_policy(&self) -> Option<SubdomainPolicy> {
+        let record = self.records.iter().find(|r| r.record_type == "SUBDOMAIN" && r.name == "@")?;
+        record.value.parse().ok()
+    }
+    
+    /// Explicit records owned by `name` (relative like `www`, or fully qualified), of the types served for subdomains.
+    pub fn explicit_records(&self, name: &str) -> Vec<&DnsRecord> {
+        let fqdn = format!("{}.{}", name, self.domain);
+        self.records.iter()
+            .filter(|r| r.enabled && SERVED_RECORD_TYPES.contains(&r.record_type.as_str()))
+            .filter(|r| {
+                let owner = r.name.trim_end_matches('.');
+                owner.eq_ignore_ascii_case(name) || owner.eq_ignore_ascii_case(&fqdn)
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -34,75 +228,479 @@ pub enum VerificationStatus {
     GracePeriod,
 }
 
+impl VerificationStatus {
+    /// The status's metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationStatus::Verified => "verified",
+            VerificationStatus::PendingVerification => "pending_verification",
+            VerificationStatus::FailedVerification => "failed_verification",
+            VerificationStatus::GracePeriod => "grace_period",
+        }
+    }
+}
+
+/// One domain's check in a bulk verification, as returned by `POST /verify`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationOutcome {
+    pub domain: String,
+    pub verified: bool,
+    /// The status before and after the check; `None` if the domain is not managed.
+    pub previous_status: Option<VerificationStatus>,
+    pub status: Option<VerificationStatus>,
+    /// The NS set the resolvers returned.
+    pub nameservers: Vec<String>,
+    /// Our nameservers missing from it, when the domain is only partially delegated.
+    pub missing_nameservers: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Sent when a verification check moves a domain to another status.
+#[derive(Debug, Clone)]
+pub struct VerificationChange {
+    pub domain: String,
+    pub from: VerificationStatus,
+    pub to: VerificationStatus,
+}
+
+/// Sent when a verification check first finds a domain delegated to only some of our nameservers.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartialDelegation {
+    pub domain: String,
+    /// The NS set resolvers returned.
+    pub nameservers: Vec<String>,
+    /// Our nameservers missing from it.
+    pub missing: Vec<String>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Outcome of a verification check once the resolvers' answers are counted against the quorum.
+enum Consensus {
+    /// Enough resolvers see the expected nameservers; holds the NS set one of them returned.
+    Delegated(Vec<String>),
+    /// Enough resolvers see an NS set without them.
+    NotDelegated(Vec<String>),
+    /// Enough resolvers failed the lookup; holds one of the errors.
+    Failed(String),
+    /// No outcome reached the quorum.
+    Inconclusive,
+}
+
+impl Consensus {
+    /// Delegation wins over the other outcomes when a low quorum lets several be reached.
+    fn of(checks: &[ResolverCheck], quorum: usize) -> Self {
+        let agreeing = |delegated: Option<bool>| checks.iter().filter(move |c| c.delegated == delegated);
+        
+        if agreeing(Some(true)).count() >= quorum {
+            let check = agreeing(Some(true)).next();
+            Consensus::Delegated(check.map(|c| c.nameservers.clone()).unwrap_or_default())
+        } else if agreeing(Some(false)).count() >= quorum {
+            let check = agreeing(Some(false)).next();
+            Consensus::NotDelegated(check.map(|c| c.nameservers.clone()).unwrap_or_default())
+        } else if agreeing(None).count() >= quorum {
+            let check = agreeing(None).next();
+            Consensus::Failed(check.and_then(|c| c.error.clone()).unwrap_or_default())
+        } else {
+            Consensus::Inconclusive
+        }
+    }
+}
+
+/// A domain due for a check and the nameservers it should be delegated to.
+struct VerificationTarget {
+    domain: String,
+    expected: Vec<String>,
+    subdomain: bool,
+}
+
+/// What applying a check decided: the outcome, and the database writes it still needs.
+struct VerificationResult {
+    verified: bool,
+    /// The status change the check made, if any.
+    transition: Option<Transition>,
+    writes: Vec<QueuedWrite>,
+}
+
+/// The verification side of the manager: resolvers to ask and where results are written.
+/// Copied out of the manager per check, so lookups and database writes never run under its
+/// lock while the domain map keeps serving DNS.
+struct Verifier {
+    resolvers: Vec<NamedResolver>,
+    quorum: usize,
+    timeout: Duration,
+    database: Option<Arc<Database>>,
+    breaker: Arc<DbBreaker>,
+}
+
+impl Verifier {
+    /// Asks every resolver about the target's delegation; returns their answers and the quorum
+    /// they are counted against.
+    async fn check(&self, target: &VerificationTarget) -> (Vec<ResolverCheck>, usize) {
+        // A delegated subdomain is checked where its delegation lives, at the parent zone's
+        // servers, and needs a majority of them
+        if target.subdomain {
+            let checks = check_parent_delegation(&self.resolvers, &target.domain, &target.expected, self.timeout).await;
+            let quorum = checks.len() / 2 + 1;
+            (checks, quorum)
+        } else {
+            (check_delegation(&self.resolvers, &target.domain, &target.expected).await, self.quorum)
+        }
+    }
+    
+    /// Makes a check's writes, or queues them while the database is down.
+    async fn persist(&self, writes: Vec<QueuedWrite>) {
+        let Some(db) = &self.database else {
+            return;
+        };
+        for write in writes {
+            self.breaker.write(db, write).await;
+        }
+    }
+    
+    /// Recomputes and stores the domain's 30-day uptime from its history. `None` when it could
+    /// not be computed; uptime is read back from the history, so it waits while the database is down.
+    async fn uptime(&self, domain: &str) -> Option<Option<f64>> {
+        let db = self.database.as_ref()?;
+        if self.breaker.is_open() {
+            return None;
+        }
+        
+        match domain_uptime(db, domain, Utc::now()).await {
+            Ok(uptime) => {
+                if let Err(e) = db.set_domain_uptime(domain, uptime.uptime_percent).await {
+                    error!("Failed to store uptime for {}: {}", domain, e);
+                }
+                Some(uptime.uptime_percent)
+            }
+            Err(e) => {
+                error!("Failed to compute uptime for {}: {}", domain, e);
+                None
+            }
+        }
+    }
+}
+
 pub struct DomainManager {
     domains: HashMap<String, DomainRecord>,
-    resolver: TokioAsyncResolver,
+    resolvers: Vec<NamedResolver>,
+    verification_quorum: usize,
+    verification_timeout: Duration,
     verification_interval: Duration,
+    serial_strategy: SerialStrategy,
     grace_period: Duration,
+    /// Where domains and records are persisted; without one they only live in memory.
+    store: Option<Arc<dyn DomainStore>>,
+    /// Where verification history goes; only Postgres keeps it.
     database: Option<Arc<Database>>,
+    /// Queues verification writes while the database is down.
+    breaker: Arc<DbBreaker>,
+    nameservers: Vec<String>,
+    nameserver_sets: HashMap<String, NameserverSet>,
+    routing_profiles: HashMap<String, RoutingProfile>,
+    /// A record for discovered domains without a routing profile.
+    default_ip: String,
+    /// Names banned for abuse; they are no longer in `domains` and may not be added again.
+    banned: HashSet<String>,
+    /// The `blocked_domains` registry; matching names may not be added or discovered.
+    blocked: Vec<BlockedDomain>,
+    /// Active `answer_overrides`, checked before anything else is answered.
+    overrides: Vec<AnswerOverride>,
+    reserved_labels: Vec<String>,
+    verification_events: Option<UnboundedSender<VerificationChange>>,
+    partial_delegation_events: Option<UnboundedSender<PartialDelegation>>,
+    last_verification_cycle: Option<VerificationCycle>,
+    /// Status changes since startup, for `GET /metrics`.
+    verification_transitions: BTreeMap<Transition, u64>,
 }
 
 impl DomainManager {
     pub fn new() -> Self {
-        let resolver = TokioAsyncResolver::tokio(
-            ResolverConfig::default(),
-            ResolverOpts::default(),
-        );
-        
         Self {
             domains: HashMap::new(),
-            resolver,
+            resolvers: vec![NamedResolver::google(ResolverOpts::default())],
+            verification_quorum: 1,
+            verification_timeout: Duration::from_secs(5),
+            serial_strategy: SerialStrategy::default(),
             verification_interval: Duration::from_secs(3600),
             grace_period: Duration::from_secs(48 * 3600),
+            store: None,
             database: None,
+            breaker: Arc::new(DbBreaker::new()),
+            nameservers: vec!["ns1.cybertemp.xyz".to_string(), "ns2.cybertemp.xyz".to_string()],
+            nameserver_sets: HashMap::new(),
+            routing_profiles: HashMap::new(),
+            default_ip: "45.134.39.50".to_string(),
+            banned: HashSet::new(),
+            blocked: Vec::new(),
+            overrides: Vec::new(),
+            reserved_labels: DEFAULT_RESERVED_LABELS.iter().map(|label| label.to_string()).collect(),
+            verification_events: None,
+            partial_delegation_events: None,
+            last_verification_cycle: None,
+            verification_transitions: BTreeMap::new(),
         }
     }
     
+    /// Persists to Postgres, which also keeps verification history.
     pub fn with_database(mut self, database: Arc<Database>) -> Self {
+        self.store = Some(database.clone());
         self.database = Some(database);
         self
     }
     
+    /// Persists domains and records to `store`, without verification history.
+    pub fn with_store(mut self, store: Arc<dyn DomainStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+    
+    /// Breaker shared with the rest of the server, so API writes and `/health` see outages the
+    /// verification loop runs into.
+    pub fn with_db_breaker(mut self, breaker: Arc<DbBreaker>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+    
+    /// Resolvers each verification check asks, and how many of them must agree before a
+    /// domain's status changes. See `resolvers::verification_resolvers`.
+    pub fn with_resolvers(mut self, resolvers: Vec<NamedResolver>, quorum: usize) -> Self {
+        if !resolvers.is_empty() {
+            self.resolvers = resolvers;
+        }
+        self.verification_quorum = quorum;
+        self
+    }
+    
+    /// Nameservers verification expects by default, and the named sets domains can be assigned to.
+    pub fn with_nameservers(mut self, nameservers: Vec<String>, nameserver_sets: HashMap<String, NameserverSet>) -> Self {
+        self.nameservers = nameservers;
+        self.nameserver_sets = nameserver_sets;
+        self
+    }
+    
+    /// Routing profiles domains can be assigned to, and the IP for discovered domains without one.
+    pub fn with_routing_profiles(mut self, routing_profiles: HashMap<String, RoutingProfile>, default_ip: String) -> Self {
+        self.routing_profiles = routing_profiles;
+        self.default_ip = default_ip;
+        self
+    }
+    
+    /// How long to wait for each parent zone server when verifying subdomain-hosted domains.
+    pub fn with_verification_timeout(mut self, timeout: Duration) -> Self {
+        self.verification_timeout = timeout;
+        self
+    }
+    
+    /// How SOA serials advance for zones without a database; the database applies its own.
+    pub fn with_serial_strategy(mut self, strategy: SerialStrategy) -> Self {
+        self.serial_strategy = strategy;
+        self
+    }
+    
+    /// Where to report verification status changes as they happen.
+    pub fn with_verification_events(mut self, events: UnboundedSender<VerificationChange>) -> Self {
+        self.verification_events = Some(events);
+        self
+    }
+    
+    /// Where to report domains found partially delegated, so their owners can be warned.
+    pub fn with_partial_delegation_events(mut self, events: UnboundedSender<PartialDelegation>) -> Self {
+        self.partial_delegation_events = Some(events);
+        self
+    }
+    
+    /// Record names templates may only write to when the record sets `allow_reserved`.
+    pub fn with_reserved_labels(mut self, reserved_labels: Vec<String>) -> Self {
+        self.reserved_labels = reserved_labels;
+        self
+    }
+    
+    /// NS names a domain should delegate to: its explicit override, its assigned set, or the defaults.
+    pub fn expected_nameservers(&self, record: &DomainRecord) -> Vec<String> {
+        if let Some(overrides) = record.nameserver_overrides() {
+            return overrides;
+        }
+        
+        record.nameserver_set()
+            .and_then(|name| self.nameserver_sets.get(name))
+            .map(|set| set.nameservers.clone())
+            .unwrap_or_else(|| self.nameservers.clone())
+    }
+    
+    pub fn domain_count(&self) -> usize {
+        self.domains.len()
+    }
+    
+    /// Stored records across all domains, including per-domain setting rows.
+    pub fn record_count(&self) -> usize {
+        self.domains.values().map(|record| record.records.len()).sum()
+    }
+    
     pub async fn load_from_database(&mut self) -> Result<()> {
-        if let Some(db) = &self.database {
-            let db_domains = db.get_all_domains().await?;
+        if let Some(state) = self.read_database().await? {
+            self.domains = state.domains;
+            self.banned = state.banned;
+            self.blocked = state.blocked;
+            self.overrides = state.overrides;
+            info!("Loaded {} domains from database", self.domains.len());
+        }
+        
+        Ok(())
+    }
+    
+    /// Diffs the in-memory domains against the database and corrects what drifted: domains
+    /// added or removed behind our back, and settings, records or abuse state changed directly
+    /// in SQL. Verification state is left alone, since only this process's checks move it.
+    pub async fn reconcile_with_database(&mut self) -> Result<ReconcileReport> {
+        let Some(state) = self.read_database().await? else {
+            return Ok(ReconcileReport::default());
+        };
+        let mut report = ReconcileReport { duplicates: state.duplicates, ..ReconcileReport::default() };
+        
+        let removed: Vec<String> = self.domains.keys()
+            .filter(|domain| !state.domains.contains_key(*domain))
+            .cloned()
+            .collect();
+        for domain in removed {
+            self.domains.remove(&domain);
+            report.removed += 1;
+            report.changed_domains.push(domain);
+        }
+        
+        for (domain, stored) in state.domains {
+            let Some(record) = self.domains.get_mut(&domain) else {
+                self.domains.insert(domain.clone(), stored);
+                report.added += 1;
+                report.changed_domains.push(domain);
+                continue;
+            };
             
-            for domain in db_domains {
-                let record = DomainRecord {
-                    domain: domain.domain.clone(),
-                    ip: domain.ip_address,
-                    enabled: domain.enabled,
-                    created_at: domain.created_at,
-                    last_verified: domain.last_verified,
-                    nameservers: domain.nameservers.unwrap_or_default(),
-                    verification_status: if domain.verified { 
-                        VerificationStatus::Verified 
-                    } else { 
-                        VerificationStatus::PendingVerification 
-                    },
-                    grace_period_ends: None,
-                    discord: domain.discord,
-                };
-                
-                self.domains.insert(domain.domain, record);
+            let drifted = record.ip != stored.ip
+                || record.enabled != stored.enabled
+                || record.discord != stored.discord
+                || record.soa_serial != stored.soa_serial
+                || record.abuse_state != stored.abuse_state
+                || record.payment_paused != stored.payment_paused
+                || serde_json::to_value(&record.records).ok() != serde_json::to_value(&stored.records).ok();
+            if drifted {
+                record.ip = stored.ip;
+                record.enabled = stored.enabled;
+                record.discord = stored.discord;
+                record.soa_serial = stored.soa_serial;
+                record.abuse_state = stored.abuse_state;
+                record.payment_paused = stored.payment_paused;
+                record.records = stored.records;
+                report.drifted += 1;
+                report.changed_domains.push(domain);
+            }
+        }
+        
+        self.banned = state.banned;
+        self.blocked = state.blocked;
+        self.overrides = state.overrides;
+        Ok(report)
+    }
+    
+    /// Everything the in-memory state is built from, or `None` without a database. Names are
+    /// keyed lowercase, as lookups are; rows differing only in case keep the first one read.
+    async fn read_database(&self) -> Result<Option<StoredState>> {
+        let Some(db) = &self.store else {
+            return Ok(None);
+        };
+        let db_domains = db.get_all_domains().await?;
+        let db_records = db.get_all_records().await?;
+        let abuse_cases = db.get_abuse_cases(None).await?;
+        let billing_holds = db.get_billing_holds().await?;
+        let blocked = db.get_blocked_domains().await?;
+        let overrides = db.get_answer_overrides().await?;
+        
+        // Build the new map first so a failed load leaves the current one in place
+        let mut domains = HashMap::new();
+        let mut duplicates = 0;
+        for domain in db_domains {
+            let key = domain_key(&domain.domain);
+            if domains.contains_key(&key) {
+                warn!("Ignoring duplicate database row for domain {}", domain.domain);
+                duplicates += 1;
+                continue;
             }
             
-            info!("Loaded {} domains from database", self.domains.len());
+            let record = DomainRecord {
+                domain: key.clone(),
+                ip: domain.ip_address,
+                enabled: domain.enabled,
+                created_at: domain.created_at,
+                last_verified: domain.last_verified,
+                nameservers: domain.nameservers.unwrap_or_default(),
+                verification_status: if domain.verified { 
+                    VerificationStatus::Verified 
+                } else { 
+                    VerificationStatus::PendingVerification 
+                },
+                grace_period_ends: None,
+                discord: domain.discord,
+                records: Vec::new(),
+                soa_serial: domain.soa_serial,
+                abuse_state: None,
+                payment_paused: false,
+                uptime_30d: domain.uptime_30d,
+                missing_nameservers: Vec::new(),
+            };
+            
+            domains.insert(key, record);
         }
         
-        Ok(())
+        for dns_record in db_records {
+            if let Some(record) = domains.get_mut(&domain_key(&dns_record.domain)) {
+                record.records.push(dns_record);
+            }
+        }
+        
+        // From the stored NS sets, so a restart does not warn about known partial delegations again
+        for record in domains.values_mut() {
+            if record.verification_status == VerificationStatus::Verified {
+                record.missing_nameservers = missing_nameservers(&record.nameservers, &self.expected_nameservers(record));
+            }
+        }
+        
+        let mut banned = HashSet::new();
+        for case in abuse_cases {
+            if case.state == AbuseState::Banned {
+                domains.remove(&case.domain);
+                banned.insert(case.domain);
+            } else if let Some(record) = domains.get_mut(&case.domain) {
+                record.abuse_state = Some(case.state);
+            }
+        }
+        
+        for hold in billing_holds.iter().filter(|hold| hold.paused_at.is_some()) {
+            if let Some(record) = domains.get_mut(&hold.domain) {
+                record.payment_paused = true;
+            }
+        }
+        
+        Ok(Some(StoredState { domains, banned, blocked, overrides, duplicates }))
+    }
+    
+    /// Replaces the domain map with one read from an on-disk snapshot.
+    pub fn load_snapshot(&mut self, domains: Vec<DomainRecord>) {
+        self.domains = domains.into_iter()
+            .map(|record| (record.domain.clone(), record))
+            .collect();
+        info!("Loaded {} domains from snapshot", self.domains.len());
     }
     
     pub async fn discover_domain(&mut self, domain: &str) -> Result<()> {
-        let domain = domain.to_lowercase();
+        let domain = domain_key(domain);
         
         // Check if domain already exists
         if self.domains.contains_key(&domain) {
             return Ok(());
         }
+        self.ensure_registrable(&domain)?;
         
         // Try to auto-discover the domain by checking if it points to our nameservers
-        match self.resolver.lookup(domain.clone(), RecordType::NS).await {
+        match self.resolvers[0].resolver.lookup(domain.clone(), RecordType::NS).await {
             Ok(ns_lookup) => {
                 let current_ns: Vec<String> = ns_lookup.iter()
                     .filter_map(|r| r.as_ns().map(|ns| ns.to_string()))
@@ -116,15 +714,12 @@ impl DomainManager {
                 
                 if has_our_ns {
                     // Auto-add this domain to our database
-                    let ip = if domain.contains("discord") { 
-                        "37.114.41.81".to_string() 
-                    } else { 
-                        "45.134.39.50".to_string() 
-                    };
-                    
                     let discord = domain.contains("discord");
+                    let ip = discord.then(|| self.profile_ip(DISCORD_PROFILE))
+                        .flatten()
+                        .unwrap_or_else(|| self.default_ip.clone());
                     
-                    if let Some(db) = &self.database {
+                    if let Some(db) = &self.store {
                         db.add_domain(&domain, &ip, discord).await?;
                     }
                     
@@ -138,6 +733,12 @@ impl DomainManager {
                         verification_status: VerificationStatus::Verified,
                         grace_period_ends: None,
                         discord,
+                        records: Vec::new(),
+                        soa_serial: 1,
+                        abuse_state: None,
+                        payment_paused: false,
+                        uptime_30d: None,
+                        missing_nameservers: Vec::new(),
                     };
                     
                     self.domains.insert(domain.clone(), record);
@@ -152,78 +753,68 @@ impl DomainManager {
         Ok(())
     }
     
-    pub async fn verify_domain(&mut self, domain: &str) -> bool {
-        let domain = domain.to_lowercase();
+    /// Checks one domain's delegation and updates its status. The manager is only locked
+    /// briefly before and after the resolver lookups, so DNS answers are never held up by them.
+    pub async fn verify(manager: &RwLock<Self>, domain: &str) -> bool {
+        Self::run_check(manager, domain).await.is_some_and(|result| result.verified)
+    }
+    
+    /// `verify`, reporting the domain's status before and after the check and the NS set found.
+    pub async fn verify_with_outcome(manager: &RwLock<Self>, domain: &str) -> VerificationOutcome {
+        let domain = domain_key(domain);
+        let previous_status = manager.read().await.domains.get(&domain).map(|record| record.verification_status.clone());
+        let mut outcome = VerificationOutcome {
+            domain: domain.clone(),
+            verified: false,
+            previous_status,
+            status: None,
+            nameservers: Vec::new(),
+            missing_nameservers: Vec::new(),
+            error: None,
+        };
         
-        match self.resolver.lookup(domain.clone(), RecordType::NS).await {
-            Ok(ns_lookup) => {
-                let current_ns: Vec<String> = ns_lookup.iter()
-                    .filter_map(|r| r.as_ns().map(|ns| ns.to_string()))
-                    .collect();
-                
-                if let Some(record) = self.domains.get_mut(&domain) {
-                    record.nameservers = current_ns.clone();
-                    record.last_verified = Some(Utc::now());
-                    
-                    // Check if our nameservers are configured
-                    let our_ns = vec!["ns1.cybertemp.xyz".to_string(), "ns2.cybertemp.xyz".to_string()];
-                    let has_our_ns = current_ns.iter().any(|ns| {
-                        our_ns.iter().any(|our_ns| ns.contains(our_ns))
-                    });
-                    
-                    if has_our_ns {
-                        record.verification_status = VerificationStatus::Verified;
-                        record.grace_period_ends = None;
-                        
-                        // Update database
-                        if let Some(db) = &self.database {
-                            if let Err(e) = db.update_domain_verification(&domain, true, &current_ns).await {
-                                error!("Failed to update database for domain {}: {}", domain, e);
-                            }
-                        }
-                        
-                        info!("Domain {} verified with correct nameservers", domain);
-                    } else {
-                        if record.verification_status == VerificationStatus::Verified {
-                            // Domain was verified but now lost nameservers - start grace period
-                            record.verification_status = VerificationStatus::GracePeriod;
-                            record.grace_period_ends = Some(Utc::now() + chrono::Duration::from_std(self.grace_period).unwrap());
-                            warn!("Domain {} lost nameservers, starting 48h grace period", domain);
-                        } else if record.verification_status == VerificationStatus::GracePeriod {
-                            // Check if grace period expired
-                            if let Some(grace_end) = record.grace_period_ends {
-                                if Utc::now() > grace_end {
-                                    record.enabled = false;
-                                    record.verification_status = VerificationStatus::FailedVerification;
-                                    
-                                    // Remove from database
-                                    if let Some(db) = &self.database {
-                                        if let Err(e) = db.remove_domain(&domain).await {
-                                            error!("Failed to remove domain {} from database: {}", domain, e);
-                                        }
-                                    }
-                                    
-                                    warn!("Domain {} grace period expired, disabling", domain);
-                                }
-                            }
-                        } else {
-                            record.verification_status = VerificationStatus::PendingVerification;
-                        }
-                    }
-                    
-                    return has_our_ns;
-                }
-            }
-            Err(e) => {
-                if let Some(record) = self.domains.get_mut(&domain) {
-                    record.verification_status = VerificationStatus::FailedVerification;
-                    record.last_verified = Some(Utc::now());
-                    warn!("Failed to verify domain {}: {}", domain, e);
-                }
+        let Some(result) = Self::run_check(manager, &domain).await else {
+            outcome.error = Some(format!("Domain not found: {}", domain));
+            return outcome;
+        };
+        outcome.verified = result.verified;
+        if let Some(record) = manager.read().await.domains.get(&domain) {
+            outcome.status = Some(record.verification_status.clone());
+            outcome.nameservers = record.nameservers.clone();
+            outcome.missing_nameservers = record.missing_nameservers.clone();
+        }
+        outcome
+    }
+    
+    /// Enabled domains that are not verified: pending, failed or in their grace period.
+    pub fn unverified_domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = self.domains.values()
+            .filter(|record| record.enabled && record.verification_status != VerificationStatus::Verified)
+            .map(|record| record.domain.clone())
+            .collect();
+        domains.sort();
+        domains
+    }
+    
+    /// `verify`, returning the whole result; `None` if the domain is not managed.
+    async fn run_check(manager: &RwLock<Self>, domain: &str) -> Option<VerificationResult> {
+        let (verifier, target) = {
+            let manager = manager.read().await;
+            let target = manager.verification_target(domain)?;
+            (manager.verifier(), target)
+        };
+        
+        let (checks, quorum) = verifier.check(&target).await;
+        let mut result = manager.write().await.apply_verification(&target.domain, &target.expected, checks, quorum)?;
+        verifier.persist(std::mem::take(&mut result.writes)).await;
+        
+        if let Some(uptime) = verifier.uptime(&target.domain).await {
+            if let Some(record) = manager.write().await.domains.get_mut(&target.domain) {
+                record.uptime_30d = uptime;
             }
         }
         
-        false
+        Some(result)
     }
     
     pub async fn start_verification_loop(manager: Arc<RwLock<Self>>) {
@@ -231,24 +822,195 @@ impl DomainManager {
         
         loop {
             interval.tick().await;
-            if let Err(e) = manager.write().await.verify_all_domains().await {
+            if let Err(e) = Self::verify_all(&manager).await {
                 error!("Verification loop error: {}", e);
             }
         }
     }
     
-    pub async fn verify_all_domains(&mut self) -> Result<()> {
-        let domains: Vec<String> = self.domains.keys().cloned().collect();
+    /// Verifies every domain in turn, locking the manager per domain rather than for the pass.
+    pub async fn verify_all(manager: &RwLock<Self>) -> Result<()> {
+        let started = std::time::Instant::now();
+        let domains = manager.read().await.list_domains().await;
+        let mut checked = 0;
+        let mut transitions = BTreeMap::new();
         
         for domain in domains {
-            self.verify_domain(&domain).await;
+            let Some(result) = Self::run_check(manager, &domain).await else {
+                continue;
+            };
+            checked += 1;
+            if let Some(transition) = result.transition {
+                *transitions.entry(transition).or_insert(0) += 1;
+            }
         }
         
+        let cycle = VerificationCycle {
+            finished_at: Utc::now(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            checked,
+            transitions,
+        };
+        info!("Verification pass checked {} domains in {}ms", cycle.checked, cycle.duration_ms);
+        manager.write().await.last_verification_cycle = Some(cycle);
         Ok(())
     }
     
+    /// Domain counts per status and verification timings for `GET /metrics`.
+    pub fn metrics(&self) -> DomainMetrics {
+        let mut metrics = DomainMetrics {
+            records: self.record_count(),
+            last_cycle: self.last_verification_cycle.clone(),
+            transitions: self.verification_transitions.clone(),
+            ..DomainMetrics::default()
+        };
+        for status in [
+            VerificationStatus::Verified,
+            VerificationStatus::PendingVerification,
+            VerificationStatus::FailedVerification,
+            VerificationStatus::GracePeriod,
+        ] {
+            metrics.by_status.insert(status.as_str(), 0);
+        }
+        
+        for record in self.domains.values() {
+            if record.enabled {
+                *metrics.by_status.entry(record.verification_status.as_str()).or_insert(0) += 1;
+                if record.partially_delegated() {
+                    metrics.partially_delegated += 1;
+                }
+            } else {
+                metrics.disabled += 1;
+            }
+        }
+        metrics
+    }
+    
+    /// The resolvers and write path a check uses, copied out so it can run unlocked.
+    fn verifier(&self) -> Verifier {
+        Verifier {
+            resolvers: self.resolvers.clone(),
+            quorum: self.verification_quorum,
+            timeout: self.verification_timeout,
+            database: self.database.clone(),
+            breaker: self.breaker.clone(),
+        }
+    }
+    
+    fn verification_target(&self, domain: &str) -> Option<VerificationTarget> {
+        let domain = domain_key(domain);
+        let record = self.domains.get(&domain)?;
+        Some(VerificationTarget {
+            expected: self.expected_nameservers(record),
+            subdomain: record.subdomain_hosted(),
+            domain,
+        })
+    }
+    
+    /// Moves the domain's status according to a finished check. Returns `None` if the domain
+    /// was removed while the check ran; otherwise the database writes to make, which are left
+    /// to the caller so they happen outside the lock.
+    fn apply_verification(&mut self, domain: &str, expected: &[String], checks: Vec<ResolverCheck>, quorum: usize) -> Option<VerificationResult> {
+        let consensus = Consensus::of(&checks, quorum);
+        let record = self.domains.get_mut(domain)?;
+        record.last_verified = Some(Utc::now());
+        let previous_status = record.verification_status.clone();
+        let mut writes = Vec::new();
+        
+        let (verified, current_ns) = match consensus {
+            Consensus::Delegated(current_ns) => {
+                let missing = missing_nameservers(&current_ns, expected);
+                if missing.is_empty() && !record.missing_nameservers.is_empty() {
+                    info!("Domain {} is now delegated to all of our nameservers", domain);
+                } else if !missing.is_empty() && missing != record.missing_nameservers {
+                    // Resolvers that pick the foreign nameserver get its answers, so lookups fail intermittently
+                    warn!("Domain {} is only partially delegated, missing {}", domain, missing.join(", "));
+                    if let Some(events) = &self.partial_delegation_events {
+                        let _ = events.send(PartialDelegation {
+                            domain: domain.to_string(),
+                            nameservers: current_ns.clone(),
+                            missing: missing.clone(),
+                            detected_at: Utc::now(),
+                        });
+                    }
+                }
+                record.missing_nameservers = missing;
+                record.nameservers = current_ns.clone();
+                record.verification_status = VerificationStatus::Verified;
+                record.grace_period_ends = None;
+                writes.push(QueuedWrite::Verification {
+                    domain: domain.to_string(),
+                    verified: true,
+                    nameservers: current_ns.clone(),
+                });
+                
+                info!("Domain {} verified with correct nameservers", domain);
+                (true, current_ns)
+            }
+            Consensus::NotDelegated(current_ns) => {
+                record.nameservers = current_ns.clone();
+                record.missing_nameservers.clear();
+                
+                if record.verification_status == VerificationStatus::Verified {
+                    // Domain was verified but now lost nameservers - start grace period
+                    record.verification_status = VerificationStatus::GracePeriod;
+                    record.grace_period_ends = Some(Utc::now() + chrono::Duration::from_std(self.grace_period).unwrap());
+                    warn!("Domain {} lost nameservers, starting 48h grace period", domain);
+                } else if record.verification_status == VerificationStatus::GracePeriod {
+                    // Check if grace period expired
+                    if let Some(grace_end) = record.grace_period_ends {
+                        if Utc::now() > grace_end {
+                            record.enabled = false;
+                            record.verification_status = VerificationStatus::FailedVerification;
+                            writes.push(QueuedWrite::RemoveDomain { domain: domain.to_string() });
+                            warn!("Domain {} grace period expired, disabling", domain);
+                        }
+                    }
+                } else {
+                    record.verification_status = VerificationStatus::PendingVerification;
+                }
+                (false, current_ns)
+            }
+            Consensus::Failed(e) => {
+                record.verification_status = VerificationStatus::FailedVerification;
+                warn!("Failed to verify domain {}: {}", domain, e);
+                (false, Vec::new())
+            }
+            Consensus::Inconclusive => {
+                // A minority of resolvers (e.g. one with a stale cache) must not move the domain
+                warn!("Verification resolvers disagree on {} without reaching a quorum of {}, keeping status {:?}",
+                      domain, quorum, record.verification_status);
+                (record.verification_status == VerificationStatus::Verified, record.nameservers.clone())
+            }
+        };
+        
+        writes.push(QueuedWrite::VerificationCheck {
+            domain: domain.to_string(),
+            status: format!("{:?}", record.verification_status),
+            nameservers: current_ns,
+            checks,
+        });
+        
+        let mut transition = None;
+        if record.verification_status != previous_status {
+            let change = (previous_status.as_str(), record.verification_status.as_str());
+            *self.verification_transitions.entry(change).or_insert(0) += 1;
+            transition = Some(change);
+            
+            if let Some(events) = &self.verification_events {
+                let _ = events.send(VerificationChange {
+                    domain: domain.to_string(),
+                    from: previous_status,
+                    to: record.verification_status.clone(),
+                });
+            }
+        }
+        
+        Some(VerificationResult { verified, transition, writes })
+    }
+    
     pub async fn get_domain(&self, domain: &str) -> Option<DomainRecord> {
-        let domain = domain.to_lowercase();
+        let domain = domain_key(domain);
         self.domains.get(&domain).cloned()
     }
     
@@ -260,12 +1022,42 @@ impl DomainManager {
         self.domains.keys().cloned().collect()
     }
     
+    /// Adds a delegated subdomain such as `mail.customer.com` as its own zone, verified against
+    /// the NS delegation in its parent zone.
+    pub async fn add_subdomain_zone(&mut self, domain: &str, ip: &str, discord: bool, actor: &str, write: &DomainWrite<'_>) -> Result<bool> {
+        if domain.trim_end_matches('.').split('.').count() < 3 {
+            return Err(anyhow::anyhow!("{} is not a subdomain, add it as a regular domain", domain));
+        }
+        
+        let applied = self.add_domain_with(domain, ip, discord, write).await?;
+        // A replayed write still sets the marker if the first attempt failed between the two
+        let hosted = self.domains.get(&domain_key(domain)).is_some_and(|record| record.subdomain_hosted());
+        if applied || !hosted {
+            self.set_custom_record(domain, "HOSTING", "@", "subdomain", actor).await?;
+        }
+        Ok(applied)
+    }
+    
     pub async fn add_domain(&mut self, domain: &str, ip: &str, discord: bool) -> Result<()> {
-        let domain = domain.to_lowercase();
+        self.add_domain_with(domain, ip, discord, &DomainWrite::default()).await.map(|_| ())
+    }
+    
+    /// Adds a domain with its database row, idempotency key and queued Supabase push committed
+    /// together, and only then to memory. Returns false if the idempotency key was already
+    /// used, in which case nothing is written.
+    pub async fn add_domain_with(&mut self, domain: &str, ip: &str, discord: bool, write: &DomainWrite<'_>) -> Result<bool> {
+        let domain = canonical_domain(domain)?;
+        self.ensure_registrable(&domain)?;
+        if let Some(profile) = write.routing_profile {
+            if !self.routing_profiles.contains_key(profile) {
+                return Err(anyhow::anyhow!("Unknown routing profile: {}", profile));
+            }
+        }
+        let profile = write.routing_profile.or(discord.then_some(DISCORD_PROFILE));
         
         let record = DomainRecord {
             domain: domain.clone(),
-            ip: if discord { "37.114.41.81".to_string() } else { ip.to_string() },
+            ip: profile.and_then(|profile| self.profile_ip(profile)).unwrap_or_else(|| ip.to_string()),
             enabled: true,
             created_at: Utc::now(),
             last_verified: None,
@@ -273,30 +1065,803 @@ impl DomainManager {
             verification_status: VerificationStatus::PendingVerification,
             grace_period_ends: None,
             discord,
+            records: Vec::new(),
+            soa_serial: 1,
+            abuse_state: None,
+            payment_paused: false,
+            uptime_30d: None,
+            missing_nameservers: Vec::new(),
         };
         
-        // Add to database
-        if let Some(db) = &self.database {
-            db.add_domain(&domain, &record.ip, discord).await?;
+        if let Some(db) = &self.store {
+            let applied = db.add_domain_transactional(&domain, &record.ip, discord, write.idempotency_key, write.sync_supabase).await?;
+            if !applied {
+                info!("Domain {} was already added with this idempotency key", domain);
+                return Ok(false);
+            }
         }
         
         self.domains.insert(domain.clone(), record);
         
         info!("Added domain: {} -> {} (discord: {})", domain, ip, discord);
+        Ok(true)
+    }
+    
+    /// Marks a domain verified in memory only, without an NS lookup or database write.
+    pub fn mark_verified(&mut self, domain: &str) -> Result<()> {
+        let record = self.domains.get_mut(&domain_key(domain))
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        record.verification_status = VerificationStatus::Verified;
+        record.last_verified = Some(Utc::now());
         Ok(())
     }
     
+    pub async fn set_domain_enabled(&mut self, domain: &str, enabled: bool) -> Result<()> {
+        let domain = domain_key(domain);
+        
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        if let Some(db) = &self.store {
+            db.set_domain_enabled(&domain, enabled).await?;
+        }
+        
+        record.enabled = enabled;
+        
+        info!("{} domain: {}", if enabled { "Enabled" } else { "Disabled" }, domain);
+        Ok(())
+    }
+    
+    pub async fn set_domain_ip(&mut self, domain: &str, ip: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        ip.parse::<std::net::Ipv4Addr>()
+            .map_err(|_| anyhow::anyhow!("Invalid IPv4 address: {}", ip))?;
+        
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        if let Some(db) = &self.store {
+            db.set_domain_ip(&domain, ip).await?;
+            record.soa_serial = db.get_soa_serial(&domain).await?;
+        } else {
+            record.soa_serial = self.serial_strategy.next(record.soa_serial);
+        }
+        
+        record.ip = ip.to_string();
+        
+        info!("Set IP for domain {}: {}", domain, ip);
+        Ok(())
+    }
+    
+    pub async fn set_bimi(&mut self, domain: &str, logo_url: &str, vmc_url: Option<&str>, actor: &str) -> Result<()> {
+        if !logo_url.starts_with("https://") || !logo_url.ends_with(".svg") {
+            return Err(anyhow::anyhow!("BIMI logo must be an https:// URL to an SVG file"));
+        }
+        if let Some(vmc) = vmc_url {
+            if !vmc.starts_with("https://") || !vmc.ends_with(".pem") {
+                return Err(anyhow::anyhow!("BIMI VMC must be an https:// URL to a PEM file"));
+            }
+        }
+        
+        let value = match vmc_url {
+            Some(vmc) => format!("{} {}", logo_url, vmc),
+            None => logo_url.to_string(),
+        };
+        
+        self.set_custom_record(domain, "BIMI", "default", &value, actor).await?;
+        
+        info!("Set BIMI logo for domain {}: {}", domain, logo_url);
+        Ok(())
+    }
+    
+    pub async fn clear_bimi(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "BIMI", "default", actor).await?;
+        
+        info!("Cleared BIMI logo for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn set_dmarc(&mut self, domain: &str, policy: &DmarcPolicy, actor: &str) -> Result<()> {
+        policy.validate()?;
+        
+        let value = serde_json::to_string(policy)?;
+        self.set_custom_record(domain, "DMARC", "_dmarc", &value, actor).await?;
+        
+        info!("Set DMARC policy for domain {}: {}", domain, policy.to_txt());
+        Ok(())
+    }
+    
+    pub async fn set_mail_routing(&mut self, domain: &str, routing: &MailRouting, actor: &str) -> Result<()> {
+        routing.validate()?;
+        
+        let value = serde_json::to_string(routing)?;
+        self.set_custom_record(domain, "MAILROUTE", "@", &value, actor).await?;
+        
+        info!("Set mail routing for domain {}: {:?}", domain, routing.mode);
+        Ok(())
+    }
+    
+    pub async fn clear_mail_routing(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "MAILROUTE", "@", actor).await?;
+        
+        info!("Cleared mail routing for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn set_ttl(&mut self, domain: &str, ttl: u32, actor: &str) -> Result<()> {
+        check_ttl(ttl)?;
+        self.set_custom_record(domain, "TTL", "@", &ttl.to_string(), actor).await?;
+        
+        info!("Set TTL for domain {}: {}", domain, ttl);
+        Ok(())
+    }
+    
+    pub async fn clear_ttl(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "TTL", "@", actor).await?;
+        
+        info!("Cleared TTL for domain {}", domain);
+        Ok(())
+    }
+    
+    /// Changes the TTL of a served record set, e.g. the `A` records named `www`.
+    pub async fn set_record_ttl(&mut self, domain: &str, record_type: &str, name: &str, ttl: u32, actor: &str) -> Result<()> {
+        check_ttl(ttl)?;
+        let domain = domain_key(domain);
+        let record_type = record_type.to_uppercase();
+        if !SERVED_RECORD_TYPES.contains(&record_type.as_str()) {
+            return Err(anyhow::anyhow!("TTLs can only be set on {} records", SERVED_RECORD_TYPES.join(", ")));
+        }
+        
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        if !record.records.iter().any(|r| r.record_type == record_type && r.name == name) {
+            return Err(anyhow::anyhow!("No {} records named {} on {}", record_type, name, domain));
+        }
+        
+        if let Some(db) = &self.store {
+            db.set_record_ttl(&domain, &record_type, name, ttl as i32, actor).await?;
+            record.soa_serial = db.get_soa_serial(&domain).await?;
+        } else {
+            record.soa_serial = self.serial_strategy.next(record.soa_serial);
+        }
+        
+        for r in record.records.iter_mut().filter(|r| r.record_type == record_type && r.name == name) {
+            r.ttl = ttl as i32;
+        }
+        
+        info!("Set TTL of {} records {} for domain {}: {}", record_type, name, domain, ttl);
+        Ok(())
+    }
+    
+    /// Replaces the `record_type` records named `name` (`@` for the apex) with `values`, given in
+    /// zone-file presentation form. Returns the values as stored, in the same form.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_records(
+        &mut self,
+        domain: &str,
+        record_type: &str,
+        name: &str,
+        values: &[String],
+        ttl: Option<u32>,
+        allow_reserved: bool,
+        actor: &str,
+    ) -> Result<Vec<String>> {
+        let record_type = record_type.to_uppercase();
+        if !SERVED_RECORD_TYPES.contains(&record_type.as_str()) {
+            return Err(anyhow::anyhow!("Only {} records can be set", SERVED_RECORD_TYPES.join(", ")));
+        }
+        let name = self.check_owner_name(name, allow_reserved)?;
+        if values.is_empty() || values.len() > MAX_RECORD_SET_VALUES {
+            return Err(anyhow::anyhow!("{} record sets need between 1 and {} values", record_type, MAX_RECORD_SET_VALUES));
+        }
+        let values = values.iter()
+            .map(|value| parse_record_value(&record_type, value))
+            .collect::<Result<Vec<(String, i32)>>>()?;
+        if let Some(ttl) = ttl {
+            check_ttl(ttl)?;
+        }
+        
+        // A CNAME owns its name outright
+        let others = self.domains.get(&domain_key(domain))
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?
+            .records.iter()
+            .filter(|r| r.name == name && SERVED_RECORD_TYPES.contains(&r.record_type.as_str()))
+            .any(|r| (r.record_type == "CNAME") != (record_type == "CNAME"));
+        if record_type == "CNAME" && (name == "@" || values.len() > 1) {
+            return Err(anyhow::anyhow!("A CNAME takes a single target and cannot be at the apex"));
+        }
+        if record_type == "SRV" && name == "@" {
+            return Err(anyhow::anyhow!("SRV records are named _service._proto, not the apex"));
+        }
+        if others {
+            return Err(anyhow::anyhow!("{} already has records a {} cannot sit alongside", name, record_type));
+        }
+        
+        self.replace_record_set(domain, &record_type, &name, &values, ttl.unwrap_or(300) as i32, actor).await?;
+        
+        info!("Set {} {} records {} for domain {}", values.len(), record_type, name, domain);
+        Ok(values.iter().map(|(value, priority)| format_record_value(&record_type, value, *priority)).collect())
+    }
+    
+    /// Replaces the TXT records named `name` (`@` for the apex) with `values`, which may be
+    /// given quoted as in a zone file. Returns the values as stored.
+    pub async fn set_txt_records(&mut self, domain: &str, name: &str, values: &[String], ttl: Option<u32>, allow_reserved: bool, actor: &str) -> Result<Vec<String>> {
+        self.set_records(domain, "TXT", name, values, ttl, allow_reserved, actor).await
+    }
+    
+    /// Points `name` at `target` with a CNAME, refusing names that already have other records.
+    pub async fn set_cname_record(&mut self, domain: &str, name: &str, target: &str, actor: &str) -> Result<()> {
+        let name = self.check_owner_name(name, false)?;
+        if name == "@" {
+            return Err(anyhow::anyhow!("The apex cannot be a CNAME"));
+        }
+        let target = target.trim().trim_end_matches('.').to_lowercase();
+        trust_dns_proto::rr::Name::from_ascii(&target)
+            .map_err(|_| anyhow::anyhow!("Invalid CNAME target: {}", target))?;
+        let existing = self.domains.get(&domain_key(domain))
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?
+            .records.iter()
+            .any(|r| r.name == name && r.record_type != "CNAME");
+        if existing {
+            return Err(anyhow::anyhow!("{} already has records, which a CNAME cannot sit alongside", name));
+        }
+        
+        self.replace_record_set(domain, "CNAME", &name, &[(target.clone(), 0)], 300, actor).await?;
+        
+        info!("Set CNAME {} -> {} for domain {}", name, target, domain);
+        Ok(())
+    }
+    
+    /// A record owner relative to the domain, lowercased; `@` is the apex.
+    fn check_owner_name(&self, name: &str, allow_reserved: bool) -> Result<String> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let valid = name == "@" || (name.len() <= 253 && name.split('.').all(|label| {
+            !label.is_empty() && label.len() <= 63 && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }));
+        if !valid {
+            return Err(anyhow::anyhow!("Invalid record name: {}; use a name relative to the domain, or @ for the apex", name));
+        }
+        if !allow_reserved {
+            check_record_name(&name, &self.reserved_labels)?;
+        }
+        Ok(name)
+    }
+    
+    /// Replaces every `record_type` record named `name` with one per value and priority.
+    async fn replace_record_set(&mut self, domain: &str, record_type: &str, name: &str, values: &[(String, i32)], ttl: i32, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        if let Some(db) = &self.store {
+            let rows: Vec<ImportedRecord> = values.iter()
+                .map(|(value, priority)| ImportedRecord {
+                    record_type: record_type.to_string(),
+                    name: name.to_string(),
+                    value: value.clone(),
+                    ttl,
+                    priority: *priority,
+                })
+                .collect();
+            db.import_records(&domain, &rows, actor).await?;
+            record.soa_serial = db.get_soa_serial(&domain).await?;
+        } else {
+            record.soa_serial = self.serial_strategy.next(record.soa_serial);
+        }
+        
+        record.records.retain(|r| !(r.record_type == record_type && r.name == name));
+        for (value, priority) in values {
+            record.records.push(DnsRecord {
+                id: String::new(),
+                domain: domain.clone(),
+                record_type: record_type.to_string(),
+                name: name.to_string(),
+                value: value.clone(),
+                ttl,
+                priority: *priority,
+                enabled: true,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+    
+    /// Removes every `record_type` record named `name`, so the synthesized answers apply again.
+    pub async fn clear_records(&mut self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        let record_type = record_type.to_uppercase();
+        if !SERVED_RECORD_TYPES.contains(&record_type.as_str()) {
+            return Err(anyhow::anyhow!("Only {} records can be removed", SERVED_RECORD_TYPES.join(", ")));
+        }
+        let name = name.trim_end_matches('.').to_lowercase();
+        let exists = self.domains.get(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?
+            .records.iter()
+            .any(|r| r.record_type == record_type && r.name == name);
+        if !exists {
+            return Err(anyhow::anyhow!("No {} records named {} on {}", record_type, name, domain));
+        }
+        
+        self.clear_custom_record(&domain, &record_type, &name, actor).await?;
+        
+        info!("Removed {} records {} for domain {}", record_type, name, domain);
+        Ok(())
+    }
+    
+    pub async fn clear_txt_records(&mut self, domain: &str, name: &str, actor: &str) -> Result<()> {
+        self.clear_records(domain, "TXT", name, actor).await
+    }
+    
+    pub async fn set_nameservers(&mut self, domain: &str, nameservers: &[String], actor: &str) -> Result<()> {
+        if nameservers.is_empty() || nameservers.len() > 8 {
+            return Err(anyhow::anyhow!("Between 1 and 8 nameservers are required"));
+        }
+        
+        let nameservers: Vec<String> = nameservers.iter()
+            .map(|ns| ns.trim().trim_end_matches('.').to_lowercase())
+            .collect();
+        for ns in &nameservers {
+            if !ns.contains('.') || trust_dns_proto::rr::Name::from_ascii(ns).is_err() {
+                return Err(anyhow::anyhow!("Invalid nameserver name: {}", ns));
+            }
+        }
+        
+        self.set_custom_record(domain, "NSSET", "@", &nameservers.join(" "), actor).await?;
+        
+        info!("Set nameservers for domain {}: {}", domain, nameservers.join(", "));
+        Ok(())
+    }
+    
+    pub async fn clear_nameservers(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "NSSET", "@", actor).await?;
+        
+        info!("Cleared nameserver override for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn assign_nameserver_set(&mut self, domain: &str, set: &str, actor: &str) -> Result<()> {
+        if !self.nameserver_sets.contains_key(set) {
+            return Err(anyhow::anyhow!("Unknown nameserver set: {}", set));
+        }
+        
+        self.set_custom_record(domain, "NSGROUP", "@", set, actor).await?;
+        
+        info!("Assigned domain {} to nameserver set {}", domain, set);
+        Ok(())
+    }
+    
+    pub async fn clear_nameserver_set(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "NSGROUP", "@", actor).await?;
+        
+        info!("Cleared nameserver set for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn assign_routing_profile(&mut self, domain: &str, profile: &str, actor: &str) -> Result<()> {
+        if !self.routing_profiles.contains_key(profile) {
+            return Err(anyhow::anyhow!("Unknown routing profile: {}", profile));
+        }
+        
+        self.set_custom_record(domain, "ROUTING", "@", profile, actor).await?;
+        
+        info!("Assigned domain {} to routing profile {}", domain, profile);
+        Ok(())
+    }
+    
+    pub async fn clear_routing_profile(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "ROUTING", "@", actor).await?;
+        
+        info!("Cleared routing profile for domain {}", domain);
+        Ok(())
+    }
+    
+    /// The A record a routing profile gives its domains, if it sets one.
+    fn profile_ip(&self, profile: &str) -> Option<String> {
+        self.routing_profiles.get(profile).and_then(|profile| profile.ip.clone())
+    }
+    
+    pub async fn set_subdomain_policy(&mut self, domain: &str, policy: SubdomainPolicy, actor: &str) -> Result<()> {
+        self.set_custom_record(domain, "SUBDOMAIN", "@", policy.as_str(), actor).await?;
+        
+        info!("Set subdomain policy for domain {}: {}", domain, policy.as_str());
+        Ok(())
+    }
+    
+    pub async fn clear_subdomain_policy(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "SUBDOMAIN", "@", actor).await?;
+        
+        info!("Cleared subdomain policy for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn set_synthetic_rules(&mut self, domain: &str, rules: &[SyntheticRule], actor: &str) -> Result<()> {
+        validate_rules(rules)?;
+        
+        let value = serde_json::to_string(rules)?;
+        self.set_custom_record(domain, "SYNTH", "@", &value, actor).await?;
+        
+        info!("Set {} synthetic record rules for domain {}", rules.len(), domain);
+        Ok(())
+    }
+    
+    pub async fn clear_synthetic_rules(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "SYNTH", "@", actor).await?;
+        
+        info!("Cleared synthetic record rules for domain {}", domain);
+        Ok(())
+    }
+    
+    pub async fn set_canaries(&mut self, domain: &str, canaries: &[Canary], actor: &str) -> Result<()> {
+        validate_canaries(canaries)?;
+        
+        let value = serde_json::to_string(canaries)?;
+        self.set_custom_record(domain, "CANARY", "@", &value, actor).await?;
+        
+        info!("Set {} canaries for domain {}", canaries.len(), domain);
+        Ok(())
+    }
+    
+    pub async fn clear_canaries(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "CANARY", "@", actor).await?;
+        
+        info!("Cleared canaries for domain {}", domain);
+        Ok(())
+    }
+    
+    /// The canary for `name`, with the domain it belongs to. Checked on every answered query,
+    /// so zones without a `CANARY` row cost a lookup and nothing more.
+    pub fn canary_for(&self, name: &str) -> Option<(String, Canary)> {
+        let name = name.to_lowercase();
+        let mut candidate = name.as_str();
+        let record = loop {
+            if let Some(record) = self.domains.get(candidate) {
+                break record;
+            }
+            candidate = candidate.split_once('.')?.1;
+        };
+        if !record.records.iter().any(|r| r.record_type == "CANARY") {
+            return None;
+        }
+        
+        let relative = match name.strip_suffix(&format!(".{}", record.domain)) {
+            Some(relative) => relative,
+            None => "@",
+        };
+        let canary = record.canaries().into_iter().find(|canary| canary.name.eq_ignore_ascii_case(relative))?;
+        Some((record.domain.clone(), canary))
+    }
+    
+    /// The served zone containing `name`, found by stripping leading labels.
+    pub async fn find_zone(&self, name: &str) -> Option<DomainRecord> {
+        let name = name.to_lowercase();
+        let mut candidate = name.as_str();
+        loop {
+            if let Some(record) = self.domains.get(candidate) {
+                return Some(record.clone());
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+    
+    /// Whether `name` falls under an enabled, verified domain, without cloning its record.
+    pub fn serves(&self, name: &str) -> bool {
+        self.zone_name(name)
+            .and_then(|zone| self.domains.get(zone))
+            .is_some_and(|zone| zone.enabled && zone.verification_status == VerificationStatus::Verified)
+    }
+    
+    /// Managed domain `name` falls under, without cloning its record.
+    pub fn zone_name(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        let mut candidate = name.as_str();
+        loop {
+            if let Some((domain, _)) = self.domains.get_key_value(candidate) {
+                return Some(domain.as_str());
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+    
+    /// True if `name` is listed in any domain's nameserver override.
+    pub async fn is_vanity_nameserver(&self, name: &str) -> bool {
+        self.domains.values()
+            .filter_map(|d| d.nameserver_overrides())
+            .any(|set| set.iter().any(|ns| ns == name))
+    }
+    
+    pub async fn clear_dmarc(&mut self, domain: &str, actor: &str) -> Result<()> {
+        self.clear_custom_record(domain, "DMARC", "_dmarc", actor).await?;
+        
+        info!("Cleared DMARC policy override for domain {}", domain);
+        Ok(())
+    }
+    
+    async fn set_custom_record(&mut self, domain: &str, record_type: &str, name: &str, value: &str, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        if let Some(db) = &self.store {
+            db.set_record(&domain, record_type, name, value, 300, 0, actor).await?;
+            record.soa_serial = db.get_soa_serial(&domain).await?;
+        } else {
+            record.soa_serial = self.serial_strategy.next(record.soa_serial);
+        }
+        
+        record.records.retain(|r| !(r.record_type == record_type && r.name == name));
+        record.records.push(DnsRecord {
+            id: String::new(),
+            domain: domain.clone(),
+            record_type: record_type.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        });
+        
+        Ok(())
+    }
+    
+    async fn clear_custom_record(&mut self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        if let Some(db) = &self.store {
+            db.delete_records(&domain, record_type, name, actor).await?;
+            record.soa_serial = db.get_soa_serial(&domain).await?;
+        } else {
+            record.soa_serial = self.serial_strategy.next(record.soa_serial);
+        }
+        
+        record.records.retain(|r| !(r.record_type == record_type && r.name == name));
+        
+        Ok(())
+    }
+    
+    /// Restores the domain's records to their state right after zone change `to`.
+    pub async fn rollback_records(&mut self, domain: &str, to: i64, actor: &str) -> Result<usize> {
+        let domain = domain_key(domain);
+        let db = self.store.clone()
+            .ok_or_else(|| anyhow::anyhow!("Rollback requires a database"))?;
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        let reverted = db.rollback_records(&domain, to, actor).await?;
+        record.records = db.get_domain_records(&domain).await?;
+        record.soa_serial = db.get_soa_serial(&domain).await?;
+        
+        info!("Rolled back domain {} to change {} ({} changes reverted)", domain, to, reverted);
+        Ok(reverted)
+    }
+    
+    /// Materializes the template's records on the domain, replacing any from an earlier application.
+    pub async fn apply_template(&mut self, domain: &str, template: &RecordTemplate, actor: &str) -> Result<usize> {
+        let domain = domain_key(domain);
+        let db = self.store.clone()
+            .ok_or_else(|| anyhow::anyhow!("Record templates require a database"))?;
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        let records = template.render(&domain, &record.ip);
+        for rendered in records.iter().filter(|r| !r.allow_reserved) {
+            check_record_name(&rendered.name, &self.reserved_labels)?;
+        }
+        db.apply_template(&domain, &template.name, &records, actor).await?;
+        record.records = db.get_domain_records(&domain).await?;
+        record.soa_serial = db.get_soa_serial(&domain).await?;
+        
+        Ok(records.len())
+    }
+    
+    pub async fn remove_template(&mut self, domain: &str, template: &str, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        let db = self.store.clone()
+            .ok_or_else(|| anyhow::anyhow!("Record templates require a database"))?;
+        let record = self.domains.get_mut(&domain)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))?;
+        
+        db.remove_template(&domain, template, actor).await?;
+        record.records = db.get_domain_records(&domain).await?;
+        record.soa_serial = db.get_soa_serial(&domain).await?;
+        
+        Ok(())
+    }
+    
+    /// Refuses names banned for abuse or covered by the blocked-domain registry.
+    fn ensure_registrable(&self, domain: &str) -> Result<()> {
+        if self.banned.contains(domain) {
+            return Err(anyhow::anyhow!("Domain {} is banned for abuse", domain));
+        }
+        if let Some(entry) = find_block(&self.blocked, domain) {
+            return Err(anyhow::anyhow!("Domain {} is blocked by {} pattern {}", domain, entry.kind.as_str(), entry.pattern));
+        }
+        Ok(())
+    }
+    
+    pub fn blocked_domains(&self) -> &[BlockedDomain] {
+        &self.blocked
+    }
+    
+    /// Adds a registry entry. Domains already served are left alone; they are returned so
+    /// they can be reviewed.
+    pub async fn block_domain(&mut self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<(BlockedDomain, Vec<String>)> {
+        let pattern = normalize_pattern(pattern)?;
+        
+        let entry = match &self.store {
+            Some(db) => db.add_blocked_domain(&pattern, kind, reason, actor).await?,
+            None => BlockedDomain {
+                id: self.blocked.iter().map(|entry| entry.id).max().unwrap_or(0) + 1,
+                pattern,
+                kind,
+                reason: reason.map(str::to_string),
+                created_by: actor.to_string(),
+                created_at: Utc::now(),
+            },
+        };
+        self.blocked.retain(|existing| existing.pattern != entry.pattern || existing.kind != entry.kind);
+        self.blocked.push(entry.clone());
+        
+        let mut existing: Vec<String> = self.domains.keys()
+            .filter(|domain| entry.matches(domain))
+            .cloned()
+            .collect();
+        existing.sort();
+        
+        info!("Blocked {} pattern {} (by {})", entry.kind.as_str(), entry.pattern, actor);
+        Ok((entry, existing))
+    }
+    
+    pub async fn unblock_domain(&mut self, id: i64) -> Result<()> {
+        if let Some(db) = &self.store {
+            db.remove_blocked_domain(id).await?;
+        } else if !self.blocked.iter().any(|entry| entry.id == id) {
+            return Err(anyhow::anyhow!("No blocked domain entry {}", id));
+        }
+        self.blocked.retain(|entry| entry.id != id);
+        
+        info!("Removed blocked domain entry {}", id);
+        Ok(())
+    }
+    
+    /// Overrides that have not expired yet.
+    pub fn answer_overrides(&self) -> Vec<AnswerOverride> {
+        let now = Utc::now();
+        self.overrides.iter().filter(|entry| entry.is_active(now)).cloned().collect()
+    }
+    
+    /// The override answering `qname`/`qtype` right now, if any.
+    pub fn answer_override(&self, qname: &str, qtype: RecordType) -> Option<&AnswerOverride> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        find_override(&self.overrides, qname, qtype, Utc::now())
+    }
+    
+    /// Replaces the overrides with ones freshly read from the database.
+    pub fn set_answer_overrides(&mut self, overrides: Vec<AnswerOverride>) {
+        self.overrides = overrides;
+    }
+    
+    pub async fn add_answer_override(&mut self, entry: NewOverride, actor: &str) -> Result<AnswerOverride> {
+        let entry = match &self.store {
+            Some(db) => db.add_answer_override(&entry, actor).await?,
+            None => AnswerOverride {
+                id: self.overrides.iter().map(|existing| existing.id).max().unwrap_or(0) + 1,
+                name: entry.name,
+                record_type: entry.record_type,
+                action: entry.action,
+                value: entry.value,
+                reason: entry.reason,
+                created_by: actor.to_string(),
+                created_at: Utc::now(),
+                expires_at: entry.expires_at,
+            },
+        };
+        let now = Utc::now();
+        self.overrides.retain(|existing| existing.is_active(now));
+        self.overrides.push(entry.clone());
+        
+        warn!(
+            "Override {} {} {} until {} (by {})",
+            entry.name, entry.record_type.as_deref().unwrap_or("ANY"), entry.action.as_str(), entry.expires_at, actor
+        );
+        Ok(entry)
+    }
+    
+    /// Ends an override before it expires.
+    pub async fn remove_answer_override(&mut self, id: i64) -> Result<AnswerOverride> {
+        let now = Utc::now();
+        let entry = self.overrides.iter()
+            .find(|entry| entry.id == id && entry.is_active(now))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No active override {}", id))?;
+        if let Some(db) = &self.store {
+            db.expire_answer_override(id).await?;
+        }
+        self.overrides.retain(|existing| existing.id != id && existing.is_active(now));
+        
+        info!("Lifted override {} for {}", id, entry.name);
+        Ok(entry)
+    }
+    
+    /// Abuse state of a served domain, or `Banned` for a name that was removed for abuse.
+    pub fn abuse_state(&self, domain: &str) -> Option<AbuseState> {
+        let domain = domain_key(domain);
+        if self.banned.contains(&domain) {
+            return Some(AbuseState::Banned);
+        }
+        self.domains.get(&domain).and_then(|record| record.abuse_state)
+    }
+    
+    /// Escalates a domain's abuse case, or clears it when `state` is `None`. Banning removes
+    /// the domain; clearing a ban only allows the name to be added again.
+    pub async fn set_abuse_state(&mut self, domain: &str, state: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()> {
+        let domain = domain_key(domain);
+        if !self.domains.contains_key(&domain) && !self.banned.contains(&domain) {
+            return Err(anyhow::anyhow!("Domain not found: {}", domain));
+        }
+        
+        let from = self.abuse_state(&domain);
+        check_transition(from, state)?;
+        
+        if let Some(db) = &self.store {
+            db.set_abuse_state(&domain, from, state, reason, actor).await?;
+        }
+        
+        match state {
+            Some(AbuseState::Banned) => {
+                self.remove_domain(&domain).await?;
+                self.banned.insert(domain.clone());
+            }
+            None if from == Some(AbuseState::Banned) => {
+                self.banned.remove(&domain);
+            }
+            _ => {
+                if let Some(record) = self.domains.get_mut(&domain) {
+                    record.abuse_state = state;
+                }
+            }
+        }
+        
+        info!(
+            "Abuse state of {}: {} -> {} (by {})",
+            domain,
+            from.map_or("none", |s| s.as_str()),
+            state.map_or("none", |s| s.as_str()),
+            actor
+        );
+        Ok(())
+    }
+    
+    /// Switches a served domain's MX to or from the payment-required host; the billing hold
+    /// itself is stored by the caller. Returns false if the domain is not served.
+    pub fn set_payment_paused(&mut self, domain: &str, paused: bool) -> bool {
+        let Some(record) = self.domains.get_mut(&domain_key(domain)) else {
+            return false;
+        };
+        record.payment_paused = paused;
+        true
+    }
+    
     pub async fn auto_discover_domains(&mut self) -> Result<()> {
         // TODO: Implement auto-discovery logic
         Ok(())
     }
     
     pub async fn remove_domain(&mut self, domain: &str) -> Result<()> {
-        let domain = domain.to_lowercase();
+        let domain = domain_key(domain);
         
         if self.domains.remove(&domain).is_some() {
             // Remove from database
-            if let Some(db) = &self.database {
+            if let Some(db) = &self.store {
                 db.remove_domain(&domain).await?;
             }
             
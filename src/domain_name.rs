@@ -0,0 +1,62 @@
+use anyhow::Result;
+
+/// The one spelling a domain is stored, keyed and matched under everywhere: ASCII with
+/// internationalized labels as `xn--` A-labels, lowercase, and without the trailing dot.
+/// `Bücher.DE.` and `xn--bcher-kva.de` are the same domain and both become the latter.
+pub fn canonical_domain(domain: &str) -> Result<String> {
+    let trimmed = domain.trim().trim_end_matches('.');
+    if trimmed.is_empty() {
+        return Err(anyhow::anyhow!("Domain name is empty"));
+    }
+
+    let ascii = idna::domain_to_ascii(trimmed)
+        .map_err(|_| anyhow::anyhow!("Invalid domain name: {}", domain))?;
+    if ascii.len() > 253 || ascii.split('.').any(|label| label.is_empty() || label.len() > 63) {
+        return Err(anyhow::anyhow!("Invalid domain name: {}", domain));
+    }
+    Ok(ascii)
+}
+
+/// `canonical_domain` for lookups, where a name it rejects should simply not match anything
+/// rather than fail; such names are only lowercased.
+pub fn domain_key(domain: &str) -> String {
+    canonical_domain(domain).unwrap_or_else(|_| domain.trim().trim_end_matches('.').to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_domain_converts_unicode_case_and_trailing_dot() {
+        assert_eq!(canonical_domain("Bücher.DE.").unwrap(), "xn--bcher-kva.de");
+        assert_eq!(canonical_domain("xn--bcher-kva.de").unwrap(), "xn--bcher-kva.de");
+        assert_eq!(canonical_domain("Example.COM").unwrap(), "example.com");
+        assert_eq!(canonical_domain(" example.com. ").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn canonical_domain_rejects_empty_and_overlong_names() {
+        assert!(canonical_domain("").is_err());
+        assert!(canonical_domain(".").is_err());
+        assert!(canonical_domain("a..example.com").is_err());
+
+        assert!(canonical_domain(&format!("{}.com", "a".repeat(63))).is_ok());
+        assert!(canonical_domain(&format!("{}.com", "a".repeat(64))).is_err());
+
+        let label = "a".repeat(63);
+        let longest = format!("{label}.{label}.{label}.{}", "a".repeat(61));
+        assert_eq!(longest.len(), 253);
+        assert!(canonical_domain(&longest).is_ok());
+        assert!(canonical_domain(&format!("a.{longest}")).is_err());
+    }
+
+    #[test]
+    fn domain_key_matches_canonical_domain_and_lowercases_the_rest() {
+        assert_eq!(domain_key("Bücher.DE."), "xn--bcher-kva.de");
+        assert_eq!(domain_key("EXAMPLE.com."), "example.com");
+
+        let overlong = format!("{}.Example.COM.", "A".repeat(64));
+        assert_eq!(domain_key(&overlong), format!("{}.example.com", "a".repeat(64)));
+    }
+}
@@ -0,0 +1,577 @@
+use anyhow::Result;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::sync::{Mutex, PoisonError};
+
+use crate::abuse::{AbuseCase, AbuseState};
+use crate::answer_override::{AnswerOverride, NewOverride};
+use crate::billing::BillingHold;
+use crate::blocklist::{BlockKind, BlockedDomain};
+use crate::database::{Database, DnsRecord, Domain};
+use crate::domain_name::domain_key;
+use crate::record_templates::TemplateRecord;
+use crate::zone_import::ImportedRecord;
+
+/// Where `DomainManager` persists the state it serves from: domains, their records, and the
+/// abuse, billing, blocklist and override state read with them. `Database` is the production
+/// store; `MemoryStore` keeps the same state in memory for tests and the self-test.
+///
+/// Verification history and uptime are not part of it and are only kept in Postgres.
+#[async_trait]
+pub trait DomainStore: Send + Sync {
+    /// Domains that have not been removed.
+    async fn get_all_domains(&self) -> Result<Vec<Domain>>;
+    /// Enabled records of every domain that has not been removed.
+    async fn get_all_records(&self) -> Result<Vec<DnsRecord>>;
+    async fn get_domain_records(&self, domain: &str) -> Result<Vec<DnsRecord>>;
+    async fn get_soa_serial(&self, domain: &str) -> Result<i64>;
+    async fn get_abuse_cases(&self, state: Option<AbuseState>) -> Result<Vec<AbuseCase>>;
+    async fn get_billing_holds(&self) -> Result<Vec<BillingHold>>;
+    async fn get_blocked_domains(&self) -> Result<Vec<BlockedDomain>>;
+    /// Overrides that have not expired yet.
+    async fn get_answer_overrides(&self) -> Result<Vec<AnswerOverride>>;
+
+    /// Adds the domain, or restores it if it was removed.
+    async fn add_domain(&self, domain: &str, ip_address: &str, discord: bool) -> Result<()>;
+    /// `add_domain` with an idempotency key and, with `sync_supabase`, a queued Supabase push,
+    /// all or nothing. Returns false without writing if the key was already used for the domain.
+    async fn add_domain_transactional(
+        &self,
+        domain: &str,
+        ip_address: &str,
+        discord: bool,
+        idempotency_key: Option<&str>,
+        sync_supabase: bool,
+    ) -> Result<bool>;
+    async fn remove_domain(&self, domain: &str) -> Result<()>;
+    async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()>;
+    /// Bumps the SOA serial like a record change.
+    async fn set_domain_ip(&self, domain: &str, ip_address: &str) -> Result<()>;
+
+    /// Replaces every record of `record_type` named `name` with a single new value.
+    #[allow(clippy::too_many_arguments)]
+    async fn set_record(&self, domain: &str, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32, actor: &str) -> Result<()>;
+    /// Returns the records updated.
+    async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: i32, actor: &str) -> Result<u64>;
+    async fn delete_records(&self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()>;
+    /// Replaces each imported (type, name) set with the imported records.
+    async fn import_records(&self, domain: &str, records: &[ImportedRecord], actor: &str) -> Result<()>;
+    /// Replaces the records `template` previously materialized on the domain with `records`.
+    async fn apply_template(&self, domain: &str, template: &str, records: &[TemplateRecord], actor: &str) -> Result<()>;
+    async fn remove_template(&self, domain: &str, template: &str, actor: &str) -> Result<()>;
+    /// Undoes every record change made after change `to`. Returns how many were reverted.
+    async fn rollback_records(&self, domain: &str, to: i64, actor: &str) -> Result<usize>;
+
+    async fn set_abuse_state(&self, domain: &str, from: Option<AbuseState>, to: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()>;
+    async fn add_blocked_domain(&self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<BlockedDomain>;
+    async fn remove_blocked_domain(&self, id: i64) -> Result<()>;
+    async fn add_answer_override(&self, entry: &NewOverride, actor: &str) -> Result<AnswerOverride>;
+    async fn expire_answer_override(&self, id: i64) -> Result<()>;
+}
+
+#[async_trait]
+impl DomainStore for Database {
+    async fn get_all_domains(&self) -> Result<Vec<Domain>> {
+        Database::get_all_domains(self).await
+    }
+
+    async fn get_all_records(&self) -> Result<Vec<DnsRecord>> {
+        Database::get_all_records(self).await
+    }
+
+    async fn get_domain_records(&self, domain: &str) -> Result<Vec<DnsRecord>> {
+        Database::get_domain_records(self, domain).await
+    }
+
+    async fn get_soa_serial(&self, domain: &str) -> Result<i64> {
+        Database::get_soa_serial(self, domain).await
+    }
+
+    async fn get_abuse_cases(&self, state: Option<AbuseState>) -> Result<Vec<AbuseCase>> {
+        Database::get_abuse_cases(self, state).await
+    }
+
+    async fn get_billing_holds(&self) -> Result<Vec<BillingHold>> {
+        Database::get_billing_holds(self).await
+    }
+
+    async fn get_blocked_domains(&self) -> Result<Vec<BlockedDomain>> {
+        Database::get_blocked_domains(self).await
+    }
+
+    async fn get_answer_overrides(&self) -> Result<Vec<AnswerOverride>> {
+        Database::get_answer_overrides(self).await
+    }
+
+    async fn add_domain(&self, domain: &str, ip_address: &str, discord: bool) -> Result<()> {
+        Database::add_domain(self, domain, ip_address, discord).await
+    }
+
+    async fn add_domain_transactional(
+        &self,
+        domain: &str,
+        ip_address: &str,
+        discord: bool,
+        idempotency_key: Option<&str>,
+        sync_supabase: bool,
+    ) -> Result<bool> {
+        Database::add_domain_transactional(self, domain, ip_address, discord, idempotency_key, sync_supabase).await
+    }
+
+    async fn remove_domain(&self, domain: &str) -> Result<()> {
+        Database::remove_domain(self, domain).await
+    }
+
+    async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()> {
+        Database::set_domain_enabled(self, domain, enabled).await
+    }
+
+    async fn set_domain_ip(&self, domain: &str, ip_address: &str) -> Result<()> {
+        Database::set_domain_ip(self, domain, ip_address).await
+    }
+
+    async fn set_record(&self, domain: &str, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32, actor: &str) -> Result<()> {
+        Database::set_record(self, domain, record_type, name, value, ttl, priority, actor).await
+    }
+
+    async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: i32, actor: &str) -> Result<u64> {
+        Database::set_record_ttl(self, domain, record_type, name, ttl, actor).await
+    }
+
+    async fn delete_records(&self, domain: &str, record_type: &str, name: &str, actor: &str) -> Result<()> {
+        Database::delete_records(self, domain, record_type, name, actor).await
+    }
+
+    async fn import_records(&self, domain: &str, records: &[ImportedRecord], actor: &str) -> Result<()> {
+        Database::import_records(self, domain, records, actor).await
+    }
+
+    async fn apply_template(&self, domain: &str, template: &str, records: &[TemplateRecord], actor: &str) -> Result<()> {
+        Database::apply_template(self, domain, template, records, actor).await
+    }
+
+    async fn remove_template(&self, domain: &str, template: &str, actor: &str) -> Result<()> {
+        Database::remove_template(self, domain, template, actor).await
+    }
+
+    async fn rollback_records(&self, domain: &str, to: i64, actor: &str) -> Result<usize> {
+        Database::rollback_records(self, domain, to, actor).await
+    }
+
+    async fn set_abuse_state(&self, domain: &str, from: Option<AbuseState>, to: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()> {
+        Database::set_abuse_state(self, domain, from, to, reason, actor).await
+    }
+
+    async fn add_blocked_domain(&self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<BlockedDomain> {
+        Database::add_blocked_domain(self, pattern, kind, reason, actor).await
+    }
+
+    async fn remove_blocked_domain(&self, id: i64) -> Result<()> {
+        Database::remove_blocked_domain(self, id).await
+    }
+
+    async fn add_answer_override(&self, entry: &NewOverride, actor: &str) -> Result<AnswerOverride> {
+        Database::add_answer_override(self, entry, actor).await
+    }
+
+    async fn expire_answer_override(&self, id: i64) -> Result<()> {
+        Database::expire_answer_override(self, id).await
+    }
+}
+
+/// A `DomainStore` kept in memory, with the same semantics as the Postgres one: removed
+/// domains keep their row until added again, and record changes bump the SOA serial.
+/// Rollbacks need zone history and are refused.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    next_id: i64,
+    /// Domains with the time they were removed, if they were.
+    domains: Vec<(Domain, Option<DateTime<Utc>>)>,
+    /// Records with the template that materialized them, if any.
+    records: Vec<(DnsRecord, Option<String>)>,
+    templates: HashSet<(String, String)>,
+    idempotency_keys: HashSet<(String, String)>,
+    abuse_cases: Vec<AbuseCase>,
+    billing_holds: Vec<BillingHold>,
+    blocked: Vec<BlockedDomain>,
+    overrides: Vec<AnswerOverride>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    
+    /// Starts a billing hold, already paused if `paused`.
+    pub fn add_billing_hold(&self, domain: &str, payment_status: &str, paused: bool) {
+        let now = Utc::now();
+        self.state().billing_holds.push(BillingHold {
+            domain: domain_key(domain),
+            payment_status: payment_status.to_string(),
+            unpaid_since: now,
+            paused_at: paused.then_some(now),
+        });
+    }
+}
+
+impl MemoryState {
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+    
+    fn live_domain(&mut self, domain: &str) -> Result<&mut Domain> {
+        let key = domain_key(domain);
+        self.domains.iter_mut()
+            .find(|(stored, deleted_at)| stored.domain == key && deleted_at.is_none())
+            .map(|(stored, _)| stored)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))
+    }
+    
+    fn is_live(&self, domain: &str) -> bool {
+        self.domains.iter().any(|(stored, deleted_at)| stored.domain == domain && deleted_at.is_none())
+    }
+    
+    fn bump_serial(&mut self, domain: &str) -> Result<()> {
+        let stored = self.live_domain(domain)?;
+        stored.soa_serial += 1;
+        stored.updated_at = Utc::now();
+        Ok(())
+    }
+    
+    fn upsert_domain(&mut self, domain: &str, ip_address: &str, discord: bool) {
+        let now = Utc::now();
+        if let Some((stored, deleted_at)) = self.domains.iter_mut().find(|(stored, _)| stored.domain == domain) {
+            // Adding a removed domain again restores it; a disabled one stays disabled
+            stored.enabled = stored.enabled || deleted_at.is_some();
+            *deleted_at = None;
+            stored.ip_address = ip_address.to_string();
+            stored.discord = discord;
+            stored.updated_at = now;
+            return;
+        }
+        
+        let id = self.next_id();
+        self.domains.push((Domain {
+            id: id.to_string(),
+            domain: domain.to_string(),
+            ip_address: ip_address.to_string(),
+            mail_server: format!("mail.{}", domain),
+            mx_priority: 10,
+            enabled: true,
+            verified: false,
+            last_verified: None,
+            nameservers: None,
+            created_at: now,
+            updated_at: now,
+            discord,
+            description: None,
+            tags: None,
+            soa_serial: 1,
+            uptime_30d: None,
+        }, None));
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    fn insert_record(&mut self, domain: &str, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32, template: Option<&str>) {
+        let id = self.next_id();
+        let now = Utc::now();
+        self.records.push((DnsRecord {
+            id: id.to_string(),
+            domain: domain.to_string(),
+            record_type: record_type.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            ttl,
+            priority,
+            enabled: true,
+            created_at: now,
+            updated_at: now,
+        }, template.map(str::to_string)));
+    }
+    
+    fn remove_records(&mut self, domain: &str, record_type: &str, name: &str) {
+        self.records.retain(|(record, _)| !(record.domain == domain && record.record_type == record_type && record.name == name));
+    }
+}
+
+#[async_trait]
+impl DomainStore for MemoryStore {
+    async fn get_all_domains(&self) -> Result<Vec<Domain>> {
+        let mut domains: Vec<Domain> = self.state().domains.iter()
+            .filter(|(_, deleted_at)| deleted_at.is_none())
+            .map(|(stored, _)| stored.clone())
+            .collect();
+        domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+        Ok(domains)
+    }
+    
+    async fn get_all_records(&self) -> Result<Vec<DnsRecord>> {
+        let state = self.state();
+        Ok(state.records.iter()
+            .filter(|(record, _)| record.enabled && state.is_live(&record.domain))
+            .map(|(record, _)| record.clone())
+            .collect())
+    }
+    
+    async fn get_domain_records(&self, domain: &str) -> Result<Vec<DnsRecord>> {
+        let domain = domain_key(domain);
+        let mut records: Vec<DnsRecord> = self.state().records.iter()
+            .filter(|(record, _)| record.domain == domain && record.enabled)
+            .map(|(record, _)| record.clone())
+            .collect();
+        records.sort_by(|a, b| (&a.record_type, &a.name).cmp(&(&b.record_type, &b.name)));
+        Ok(records)
+    }
+    
+    async fn get_soa_serial(&self, domain: &str) -> Result<i64> {
+        let key = domain_key(domain);
+        self.state().domains.iter()
+            .find(|(stored, _)| stored.domain == key)
+            .map(|(stored, _)| stored.soa_serial)
+            .ok_or_else(|| anyhow::anyhow!("Domain not found: {}", domain))
+    }
+    
+    async fn get_abuse_cases(&self, state: Option<AbuseState>) -> Result<Vec<AbuseCase>> {
+        Ok(self.state().abuse_cases.iter()
+            .filter(|case| state.is_none() || state == Some(case.state))
+            .cloned()
+            .collect())
+    }
+    
+    async fn get_billing_holds(&self) -> Result<Vec<BillingHold>> {
+        Ok(self.state().billing_holds.clone())
+    }
+    
+    async fn get_blocked_domains(&self) -> Result<Vec<BlockedDomain>> {
+        Ok(self.state().blocked.clone())
+    }
+    
+    async fn get_answer_overrides(&self) -> Result<Vec<AnswerOverride>> {
+        let now = Utc::now();
+        Ok(self.state().overrides.iter().filter(|entry| entry.is_active(now)).cloned().collect())
+    }
+    
+    async fn add_domain(&self, domain: &str, ip_address: &str, discord: bool) -> Result<()> {
+        self.state().upsert_domain(&domain_key(domain), ip_address, discord);
+        Ok(())
+    }
+    
+    async fn add_domain_transactional(
+        &self,
+        domain: &str,
+        ip_address: &str,
+        discord: bool,
+        idempotency_key: Option<&str>,
+        _sync_supabase: bool,
+    ) -> Result<bool> {
+        let domain = domain_key(domain);
+        let mut state = self.state();
+        
+        if let Some(key) = idempotency_key {
+            if let Some((_, used_for)) = state.idempotency_keys.iter().find(|(used, _)| used == key) {
+                if *used_for != domain {
+                    return Err(anyhow::anyhow!("Idempotency key {} was already used for add_domain {}", key, used_for));
+                }
+                return Ok(false);
+            }
+            state.idempotency_keys.insert((key.to_string(), domain.clone()));
+        }
+        
+        state.upsert_domain(&domain, ip_address, discord);
+        Ok(true)
+    }
+    
+    async fn remove_domain(&self, domain: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let now = Utc::now();
+        for (stored, deleted_at) in self.state().domains.iter_mut().filter(|(stored, _)| stored.domain == key) {
+            stored.enabled = false;
+            stored.updated_at = now;
+            *deleted_at = Some(now);
+        }
+        Ok(())
+    }
+    
+    async fn set_domain_enabled(&self, domain: &str, enabled: bool) -> Result<()> {
+        let mut state = self.state();
+        let stored = state.live_domain(domain)?;
+        stored.enabled = enabled;
+        stored.updated_at = Utc::now();
+        Ok(())
+    }
+    
+    async fn set_domain_ip(&self, domain: &str, ip_address: &str) -> Result<()> {
+        let mut state = self.state();
+        state.live_domain(domain)?.ip_address = ip_address.to_string();
+        state.bump_serial(domain)
+    }
+    
+    async fn set_record(&self, domain: &str, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32, _actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        state.bump_serial(&key)?;
+        state.remove_records(&key, record_type, name);
+        state.insert_record(&key, record_type, name, value, ttl, priority, None);
+        Ok(())
+    }
+    
+    async fn set_record_ttl(&self, domain: &str, record_type: &str, name: &str, ttl: i32, _actor: &str) -> Result<u64> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        let mut updated = 0;
+        for (record, _) in state.records.iter_mut() {
+            if record.domain == key && record.record_type == record_type && record.name == name && record.ttl != ttl {
+                record.ttl = ttl;
+                record.updated_at = Utc::now();
+                updated += 1;
+            }
+        }
+        if updated > 0 {
+            state.bump_serial(&key)?;
+        }
+        Ok(updated)
+    }
+    
+    async fn delete_records(&self, domain: &str, record_type: &str, name: &str, _actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        state.remove_records(&key, record_type, name);
+        if state.is_live(&key) {
+            state.bump_serial(&key)?;
+        }
+        Ok(())
+    }
+    
+    async fn import_records(&self, domain: &str, records: &[ImportedRecord], _actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        state.bump_serial(&key)?;
+        
+        let mut replaced: Vec<(&str, &str)> = Vec::new();
+        for record in records {
+            let set = (record.record_type.as_str(), record.name.as_str());
+            if !replaced.contains(&set) {
+                state.remove_records(&key, set.0, set.1);
+                replaced.push(set);
+            }
+            state.insert_record(&key, &record.record_type, &record.name, &record.value, record.ttl, record.priority, None);
+        }
+        Ok(())
+    }
+    
+    async fn apply_template(&self, domain: &str, template: &str, records: &[TemplateRecord], _actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        state.bump_serial(&key)?;
+        state.templates.insert((key.clone(), template.to_string()));
+        
+        state.records.retain(|(record, applied)| !(record.domain == key && applied.as_deref() == Some(template)));
+        for record in records {
+            state.insert_record(&key, &record.record_type, &record.name, &record.value, record.ttl, record.priority, Some(template));
+        }
+        Ok(())
+    }
+    
+    async fn remove_template(&self, domain: &str, template: &str, _actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        if !state.templates.remove(&(key.clone(), template.to_string())) {
+            return Err(anyhow::anyhow!("Template {} is not applied to domain {}", template, domain));
+        }
+        
+        state.records.retain(|(record, applied)| !(record.domain == key && applied.as_deref() == Some(template)));
+        if state.is_live(&key) {
+            state.bump_serial(&key)?;
+        }
+        Ok(())
+    }
+    
+    async fn rollback_records(&self, _domain: &str, _to: i64, _actor: &str) -> Result<usize> {
+        Err(anyhow::anyhow!("Rollback needs zone history, which only the Postgres store keeps"))
+    }
+    
+    async fn set_abuse_state(&self, domain: &str, _from: Option<AbuseState>, to: Option<AbuseState>, reason: Option<&str>, actor: &str) -> Result<()> {
+        let key = domain_key(domain);
+        let mut state = self.state();
+        state.abuse_cases.retain(|case| case.domain != key);
+        if let Some(to) = to {
+            state.abuse_cases.push(AbuseCase {
+                domain: key,
+                state: to,
+                reason: reason.map(str::to_string),
+                updated_by: actor.to_string(),
+                updated_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+    
+    async fn add_blocked_domain(&self, pattern: &str, kind: BlockKind, reason: Option<&str>, actor: &str) -> Result<BlockedDomain> {
+        let mut state = self.state();
+        if let Some(entry) = state.blocked.iter_mut().find(|entry| entry.pattern == pattern && entry.kind == kind) {
+            entry.reason = reason.map(str::to_string);
+            entry.created_by = actor.to_string();
+            return Ok(entry.clone());
+        }
+        
+        let entry = BlockedDomain {
+            id: state.next_id(),
+            pattern: pattern.to_string(),
+            kind,
+            reason: reason.map(str::to_string),
+            created_by: actor.to_string(),
+            created_at: Utc::now(),
+        };
+        state.blocked.push(entry.clone());
+        state.blocked.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+        Ok(entry)
+    }
+    
+    async fn remove_blocked_domain(&self, id: i64) -> Result<()> {
+        let mut state = self.state();
+        let before = state.blocked.len();
+        state.blocked.retain(|entry| entry.id != id);
+        if state.blocked.len() == before {
+            return Err(anyhow::anyhow!("No blocked domain entry {}", id));
+        }
+        Ok(())
+    }
+    
+    async fn add_answer_override(&self, entry: &NewOverride, actor: &str) -> Result<AnswerOverride> {
+        let mut state = self.state();
+        let stored = AnswerOverride {
+            id: state.next_id(),
+            name: entry.name.clone(),
+            record_type: entry.record_type.clone(),
+            action: entry.action,
+            value: entry.value.clone(),
+            reason: entry.reason.clone(),
+            created_by: actor.to_string(),
+            created_at: Utc::now(),
+            expires_at: entry.expires_at,
+        };
+        state.overrides.push(stored.clone());
+        Ok(stored)
+    }
+    
+    async fn expire_answer_override(&self, id: i64) -> Result<()> {
+        let now = Utc::now();
+        let mut state = self.state();
+        let entry = state.overrides.iter_mut()
+            .find(|entry| entry.id == id && entry.is_active(now))
+            .ok_or_else(|| anyhow::anyhow!("No active override {}", id))?;
+        entry.expires_at = now;
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use trust_dns_proto::op::{Edns, Message};
+use trust_dns_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+/// EDNS option code of an Extended DNS Error (RFC 8914).
+const EDE_OPTION_CODE: u16 = 15;
+/// UDP payload size advertised in the OPT record carrying an error, the DNS Flag Day 2020 default.
+const ADVERTISED_PAYLOAD: u16 = 1232;
+
+/// The INFO-CODEs we send, from the IANA Extended DNS Error Codes registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoCode {
+    /// Nothing more specific fits; the text says what happened.
+    Other = 0,
+    /// Blocked by the operator of the server.
+    Blocked = 15,
+    /// We are not serving the zone the name is in.
+    NotAuthoritative = 20,
+}
+
+/// Why a response was denied, attached to REFUSED and NXDOMAIN answers for clients that
+/// sent an OPT record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub code: InfoCode,
+    pub text: String,
+}
+
+impl ExtendedError {
+    pub fn new(code: InfoCode, text: impl Into<String>) -> Self {
+        Self { code, text: text.into() }
+    }
+
+    /// Adds the error to `response` in an OPT record of its own.
+    pub fn attach(&self, response: &mut Message) {
+        let mut data = (self.code as u16).to_be_bytes().to_vec();
+        data.extend_from_slice(self.text.as_bytes());
+
+        let mut edns = Edns::new();
+        edns.set_max_payload(ADVERTISED_PAYLOAD);
+        edns.options_mut().insert(EdnsOption::Unknown(EDE_OPTION_CODE, data));
+        response.set_edns(edns);
+    }
+}
+
+/// The INFO-CODE and EXTRA-TEXT of the error in `message`'s OPT record, if it has one.
+pub fn extended_error(message: &Message) -> Option<(u16, String)> {
+    let edns = message.extensions().as_ref()?;
+    match edns.option(EdnsCode::from(EDE_OPTION_CODE))? {
+        EdnsOption::Unknown(_, data) if data.len() >= 2 => {
+            Some((u16::from_be_bytes([data[0], data[1]]), String::from_utf8_lossy(&data[2..]).into_owned()))
+        }
+        _ => None,
+    }
+}
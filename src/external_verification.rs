@@ -0,0 +1,220 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use trust_dns_proto::rr::RecordType;
+use trust_dns_resolver::error::ResolveErrorKind;
+
+use crate::domain_name::domain_key;
+use crate::resolvers::NamedResolver;
+
+/// How often pending verifications are looked up again.
+pub const EXTERNAL_VERIFICATION_INTERVAL_SECONDS: u64 = 30;
+/// How long a verification is polled before it is given up on.
+const EXTERNAL_VERIFICATION_TIMEOUT_MINUTES: i64 = 60;
+
+/// A mail or workspace provider that proves domain ownership with a DNS record.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Google,
+    Microsoft,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMethod {
+    /// `google-site-verification=...` or `MS=ms...` at the apex.
+    #[default]
+    Txt,
+    /// Google's alternative: `<token>.<domain>` CNAME to a `googlehosted.com` target.
+    Cname,
+}
+
+/// Body of `POST /domains/{domain}/external-verification`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExternalVerificationRequest {
+    pub provider: Provider,
+    /// The value the provider shows, with or without its `google-site-verification=`/`MS=`
+    /// prefix; for the CNAME method, the record's host label.
+    pub token: String,
+    #[serde(default)]
+    pub method: VerificationMethod,
+    /// CNAME target the provider shows, for the CNAME method.
+    pub target: Option<String>,
+}
+
+/// The record a request asks for, with `name` relative to the domain.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct PlannedRecord {
+    pub record_type: String,
+    pub name: String,
+    pub value: String,
+}
+
+impl ExternalVerificationRequest {
+    /// The record the provider will look for, or why the token does not look like one of theirs.
+    pub fn planned_record(&self) -> Result<PlannedRecord> {
+        let token = self.token.trim();
+        let is_token = |s: &str| !s.is_empty() && s.len() <= 128 && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        match (self.provider, self.method) {
+            (Provider::Google, VerificationMethod::Txt) => {
+                let code = token.strip_prefix("google-site-verification=").unwrap_or(token);
+                if !is_token(code) {
+                    return Err(anyhow::anyhow!("Invalid Google verification token: {}", token));
+                }
+                Ok(PlannedRecord {
+                    record_type: "TXT".to_string(),
+                    name: "@".to_string(),
+                    value: format!("google-site-verification={}", code),
+                })
+            }
+            (Provider::Google, VerificationMethod::Cname) => {
+                let label = token.to_lowercase();
+                if !is_token(&label) {
+                    return Err(anyhow::anyhow!("Invalid Google CNAME host: {}", token));
+                }
+                let target = self.target.as_deref()
+                    .map(|target| target.trim().trim_end_matches('.').to_lowercase())
+                    .ok_or_else(|| anyhow::anyhow!("The CNAME method needs the target Google shows"))?;
+                if !target.ends_with(".googlehosted.com") {
+                    return Err(anyhow::anyhow!("Google CNAME targets end in googlehosted.com, got {}", target));
+                }
+                Ok(PlannedRecord { record_type: "CNAME".to_string(), name: label, value: target })
+            }
+            (Provider::Microsoft, VerificationMethod::Txt) => {
+                let code = token.strip_prefix("MS=").unwrap_or(token);
+                let digits = code.strip_prefix("ms").unwrap_or("");
+                if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(anyhow::anyhow!("Microsoft verification tokens look like MS=ms12345678, got {}", token));
+                }
+                Ok(PlannedRecord {
+                    record_type: "TXT".to_string(),
+                    name: "@".to_string(),
+                    value: format!("MS={}", code),
+                })
+            }
+            (Provider::Microsoft, VerificationMethod::Cname) => {
+                Err(anyhow::anyhow!("Microsoft verification only supports the TXT method"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternalVerificationState {
+    /// The record is served by us but no resolver has been asked yet.
+    RecordCreated,
+    /// Some resolvers already return the record.
+    Propagating,
+    /// Every resolver that answered returns the record, so the provider's lookup will too.
+    Confirmed,
+    /// Not confirmed within the timeout, usually because the domain is not delegated to us.
+    TimedOut,
+}
+
+/// One provider verification and how far it has got, as returned by the API.
+#[derive(Debug, Serialize, Clone)]
+pub struct ExternalVerification {
+    pub domain: String,
+    pub provider: Provider,
+    pub method: VerificationMethod,
+    pub record: PlannedRecord,
+    pub state: ExternalVerificationState,
+    /// Resolvers returning the record on the last check, out of `total`.
+    pub visible: usize,
+    pub total: usize,
+    /// Resolvers that returned something else, or failed, on the last check.
+    pub pending: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl ExternalVerification {
+    pub fn new(domain: &str, request: &ExternalVerificationRequest, record: PlannedRecord) -> Self {
+        Self {
+            domain: domain_key(domain),
+            provider: request.provider,
+            method: request.method,
+            record,
+            state: ExternalVerificationState::RecordCreated,
+            visible: 0,
+            total: 0,
+            pending: Vec::new(),
+            started_at: Utc::now(),
+            checked_at: None,
+            confirmed_at: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, ExternalVerificationState::Confirmed | ExternalVerificationState::TimedOut)
+    }
+
+    /// The record's owner name, fully qualified.
+    fn fqdn(&self) -> String {
+        match self.record.name.as_str() {
+            "@" => self.domain.clone(),
+            name => format!("{}.{}", name, self.domain),
+        }
+    }
+
+    /// Asks every resolver for the record and moves the state on.
+    pub async fn check(&mut self, resolvers: &[NamedResolver]) {
+        let fqdn = self.fqdn();
+        let record_type = if self.record.record_type == "CNAME" { RecordType::CNAME } else { RecordType::TXT };
+        let lookups: Vec<_> = resolvers.iter()
+            .map(|upstream| {
+                let resolver = upstream.resolver.clone();
+                let fqdn = fqdn.clone();
+                tokio::spawn(async move { resolver.lookup(fqdn, record_type).await })
+            })
+            .collect();
+
+        let mut visible = 0;
+        let mut answered = 0;
+        let mut pending = Vec::new();
+        for (upstream, lookup) in resolvers.iter().zip(lookups) {
+            let found = match lookup.await {
+                Ok(Ok(answer)) => {
+                    answered += 1;
+                    answer.iter().any(|rdata| match (rdata.as_txt(), rdata.as_cname()) {
+                        (Some(txt), _) => {
+                            txt.txt_data().iter().map(|part| String::from_utf8_lossy(part)).collect::<String>() == self.record.value
+                        }
+                        (_, Some(cname)) => cname.0.to_ascii().trim_end_matches('.').eq_ignore_ascii_case(&self.record.value),
+                        _ => false,
+                    })
+                }
+                // NXDOMAIN and empty answers count as answered, just not yet visible
+                Ok(Err(e)) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                    answered += 1;
+                    false
+                }
+                _ => false,
+            };
+            if found {
+                visible += 1;
+            } else {
+                pending.push(upstream.name.clone());
+            }
+        }
+
+        let now = Utc::now();
+        self.visible = visible;
+        self.total = resolvers.len();
+        self.pending = pending;
+        self.checked_at = Some(now);
+        self.state = if answered > 0 && visible == answered {
+            self.confirmed_at = Some(now);
+            ExternalVerificationState::Confirmed
+        } else if now - self.started_at > chrono::Duration::minutes(EXTERNAL_VERIFICATION_TIMEOUT_MINUTES) {
+            ExternalVerificationState::TimedOut
+        } else if visible > 0 {
+            ExternalVerificationState::Propagating
+        } else {
+            self.state
+        };
+    }
+}
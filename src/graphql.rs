@@ -0,0 +1,357 @@
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Enum, InputObject, Object, Schema, SimpleObject};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::abuse::AbuseState;
+use crate::dns_server::{DnsServer, DomainStats};
+use crate::domain_manager::{DomainRecord, VerificationStatus};
+use crate::query_stats::{parse_window, TopEntry};
+
+/// Deep enough for the standard introspection query, whose type references nest 7 levels.
+const MAX_QUERY_DEPTH: usize = 15;
+/// Fields selected per query, with fields under `domains` and `verificationHistory` counted
+/// once per item their `limit` allows.
+const MAX_QUERY_COMPLEXITY: usize = 2_000;
+/// Most domains one `domains` query returns; page with `offset` for more.
+const MAX_DOMAINS_PAGE: usize = 500;
+/// Most checks one `verificationHistory` returns.
+const MAX_HISTORY_PAGE: i64 = 500;
+
+pub type CybertempSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn build_schema(dns_server: Arc<DnsServer>) -> CybertempSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(dns_server)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum DomainStatus {
+    Verified,
+    PendingVerification,
+    FailedVerification,
+    GracePeriod,
+}
+
+impl From<&VerificationStatus> for DomainStatus {
+    fn from(status: &VerificationStatus) -> Self {
+        match status {
+            VerificationStatus::Verified => DomainStatus::Verified,
+            VerificationStatus::PendingVerification => DomainStatus::PendingVerification,
+            VerificationStatus::FailedVerification => DomainStatus::FailedVerification,
+            VerificationStatus::GracePeriod => DomainStatus::GracePeriod,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct DomainFilter {
+    pub status: Option<DomainStatus>,
+    pub enabled: Option<bool>,
+    pub discord: Option<bool>,
+    /// Substring match on the domain name
+    pub search: Option<String>,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum DomainSort {
+    Name,
+    CreatedAt,
+    LastVerified,
+}
+
+#[derive(SimpleObject)]
+pub struct Record {
+    pub record_type: String,
+    pub name: String,
+    pub value: String,
+    pub ttl: i32,
+    pub priority: i32,
+}
+
+#[derive(SimpleObject)]
+pub struct VerificationCheck {
+    pub status: String,
+    pub nameservers: Vec<String>,
+    pub resolvers: Vec<ResolverResult>,
+    pub checked_at: DateTime<Utc>,
+}
+
+#[derive(SimpleObject)]
+pub struct ResolverResult {
+    pub resolver: String,
+    /// Null when the lookup failed.
+    pub delegated: Option<bool>,
+    pub nameservers: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// `DomainStats` plus the query statistics of `/stats/top`, `/stats/transport` and the
+/// per-domain counts of `/stats/export`.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Stats {
+    #[graphql(flatten)]
+    domains: DomainStats,
+}
+
+#[derive(SimpleObject)]
+pub struct TopName {
+    pub key: String,
+    pub count: u64,
+}
+
+impl From<TopEntry> for TopName {
+    fn from(entry: TopEntry) -> Self {
+        TopName { key: entry.key, count: entry.count }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct TopNames {
+    pub window_seconds: u64,
+    pub total_queries: u64,
+    pub unique_clients: u64,
+    pub qnames: Vec<TopName>,
+    /// Names answered with NXDOMAIN or an empty answer section
+    pub nxdomain: Vec<TopName>,
+    pub client_subnets: Vec<TopName>,
+}
+
+#[derive(SimpleObject)]
+pub struct TransportCount {
+    pub transport: String,
+    pub queries: u64,
+    pub edns: u64,
+    pub no_edns: u64,
+    pub dnssec_ok: u64,
+    pub truncated: u64,
+    pub mean_answer_micros: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct TransportCounts {
+    pub since: DateTime<Utc>,
+    pub total_queries: u64,
+    pub transports: Vec<TransportCount>,
+}
+
+#[derive(SimpleObject)]
+pub struct DomainQueries {
+    pub domain: String,
+    pub queries: i64,
+    /// Answered with NXDOMAIN or an empty answer section.
+    pub negative: i64,
+}
+
+#[ComplexObject]
+impl Stats {
+    /// Most queried names and client subnets over the last `window`, e.g. `30s`, `5m` or `1h`.
+    async fn top_names(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "String::from(\"5m\")")] window: String,
+        #[graphql(default = 10)] n: usize,
+    ) -> async_graphql::Result<TopNames> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        let window = parse_window(&window).ok_or("Invalid window, use e.g. 30s, 5m or 1h")?;
+        let top = dns_server.top_stats(window, n);
+        
+        Ok(TopNames {
+            window_seconds: top.window_seconds,
+            total_queries: top.total_queries,
+            unique_clients: top.unique_clients,
+            qnames: top.top_qnames.into_iter().map(TopName::from).collect(),
+            nxdomain: top.top_nxdomain.into_iter().map(TopName::from).collect(),
+            client_subnets: top.top_client_subnets.into_iter().map(TopName::from).collect(),
+        })
+    }
+    
+    /// Query counts per transport since startup.
+    async fn transport(&self, ctx: &Context<'_>) -> async_graphql::Result<TransportCounts> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        let snapshot = dns_server.transport_stats();
+        
+        Ok(TransportCounts {
+            since: snapshot.since,
+            total_queries: snapshot.total_queries,
+            transports: snapshot.transports.into_iter().map(|t| TransportCount {
+                transport: t.transport.to_string(),
+                queries: t.queries,
+                edns: t.edns,
+                no_edns: t.no_edns,
+                dnssec_ok: t.dnssec_ok,
+                truncated: t.truncated,
+                mean_answer_micros: t.mean_answer_micros,
+            }).collect(),
+        })
+    }
+    
+    /// Per-domain query counts in `[from, to)`, by default the last 30 days. Needs
+    /// `stats_history_enabled`; without it every count is zero.
+    async fn domain_queries(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Vec<DomainQueries>> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        let to = to.unwrap_or_else(Utc::now);
+        let from = from.unwrap_or(to - chrono::Duration::days(30));
+        let totals = dns_server.query_totals(from, to).await?;
+        
+        Ok(totals.into_iter().map(|total| DomainQueries {
+            domain: total.domain,
+            queries: total.queries,
+            negative: total.negative,
+        }).collect())
+    }
+}
+
+pub struct Domain(DomainRecord);
+
+#[Object]
+impl Domain {
+    async fn name(&self) -> &str {
+        &self.0.domain
+    }
+    
+    async fn ip(&self) -> &str {
+        &self.0.ip
+    }
+    
+    async fn enabled(&self) -> bool {
+        self.0.enabled
+    }
+    
+    async fn status(&self) -> DomainStatus {
+        (&self.0.verification_status).into()
+    }
+    
+    async fn discord(&self) -> bool {
+        self.0.discord
+    }
+    
+    async fn abuse_state(&self) -> Option<AbuseState> {
+        self.0.abuse_state
+    }
+    
+    /// TTL override for synthesized answers; `null` means the server's `default_ttl`.
+    async fn ttl(&self) -> Option<u32> {
+        self.0.ttl_override()
+    }
+    
+    /// Percentage of the last 30 days the domain spent verified; `null` before its first check.
+    async fn uptime_30d(&self) -> Option<f64> {
+        self.0.uptime_30d
+    }
+    
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+    
+    async fn last_verified(&self) -> Option<DateTime<Utc>> {
+        self.0.last_verified
+    }
+    
+    async fn grace_period_ends(&self) -> Option<DateTime<Utc>> {
+        self.0.grace_period_ends
+    }
+    
+    async fn nameservers(&self) -> &[String] {
+        &self.0.nameservers
+    }
+    
+    /// Our nameservers missing from the NS records; non-empty means partially delegated.
+    async fn missing_nameservers(&self) -> &[String] {
+        &self.0.missing_nameservers
+    }
+    
+    async fn records(&self) -> Vec<Record> {
+        self.0.records.iter().map(|r| Record {
+            record_type: r.record_type.clone(),
+            name: r.name.clone(),
+            value: r.value.clone(),
+            ttl: r.ttl,
+            priority: r.priority,
+        }).collect()
+    }
+    
+    /// One database query per domain, so each check counts towards the query's complexity.
+    #[graphql(complexity = "limit.clamp(1, MAX_HISTORY_PAGE) as usize * child_complexity")]
+    async fn verification_history(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20)] limit: i64,
+    ) -> async_graphql::Result<Vec<VerificationCheck>> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        let history = dns_server.verification_history(&self.0.domain, limit.clamp(1, MAX_HISTORY_PAGE)).await?;
+        
+        Ok(history.into_iter().map(|entry| VerificationCheck {
+            status: entry.status,
+            nameservers: entry.nameservers,
+            resolvers: entry.resolver_results.into_iter().map(|check| ResolverResult {
+                resolver: check.resolver,
+                delegated: check.delegated,
+                nameservers: check.nameservers,
+                error: check.error,
+            }).collect(),
+            checked_at: entry.checked_at,
+        }).collect())
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn domain(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Option<Domain>> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        Ok(dns_server.get_domain_info(&name).await.map(Domain))
+    }
+    
+    /// Up to `limit` domains (100 by default, at most 500).
+    #[graphql(complexity = "limit.clamp(1, MAX_DOMAINS_PAGE) * child_complexity")]
+    async fn domains(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<DomainFilter>,
+        sort: Option<DomainSort>,
+        #[graphql(default = false)] descending: bool,
+        #[graphql(default = 100)] limit: usize,
+        #[graphql(default = 0)] offset: usize,
+    ) -> async_graphql::Result<Vec<Domain>> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        let filter = filter.unwrap_or_default();
+        
+        let mut domains: Vec<DomainRecord> = dns_server.get_all_domains().await
+            .into_iter()
+            .filter(|d| filter.status.map_or(true, |s| DomainStatus::from(&d.verification_status) == s))
+            .filter(|d| filter.enabled.map_or(true, |e| d.enabled == e))
+            .filter(|d| filter.discord.map_or(true, |e| d.discord == e))
+            .filter(|d| filter.search.as_ref().map_or(true, |q| d.domain.contains(&q.to_lowercase())))
+            .collect();
+        
+        match sort.unwrap_or(DomainSort::Name) {
+            DomainSort::Name => domains.sort_by(|a, b| a.domain.cmp(&b.domain)),
+            DomainSort::CreatedAt => domains.sort_by_key(|d| d.created_at),
+            DomainSort::LastVerified => domains.sort_by_key(|d| d.last_verified),
+        }
+        if descending {
+            domains.reverse();
+        }
+        
+        Ok(domains.into_iter()
+            .skip(offset)
+            .take(limit.clamp(1, MAX_DOMAINS_PAGE))
+            .map(Domain)
+            .collect())
+    }
+    
+    async fn stats(&self, ctx: &Context<'_>) -> async_graphql::Result<Stats> {
+        let dns_server = ctx.data::<Arc<DnsServer>>()?;
+        Ok(Stats { domains: dns_server.get_stats().await })
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+
+/// Placeholders mail hostname templates may use.
+const PLACEHOLDERS: &[&str] = &["{domain}", "{profile}", "{region}", "{zone}"];
+/// Domain templates are rendered for when they are checked at startup.
+const SAMPLE_DOMAIN: &str = "example.com";
+/// Labels autodiscover targets may not render to, since we answer those with the CNAME itself.
+const AUTODISCOVER_LABELS: &[&str] = &["autodiscover", "autoconfig"];
+
+/// What a template's placeholders other than `{domain}` stand for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateContext<'a> {
+    /// The routing profile's name; empty for domains without one.
+    pub profile: &'a str,
+    /// The routing profile's region; empty if it has none.
+    pub region: &'a str,
+    /// The infrastructure zone.
+    pub zone: &'a str,
+}
+
+/// `template` with its placeholders filled in for `domain`, lowercased.
+pub fn render(template: &str, context: &TemplateContext, domain: &str) -> String {
+    fill(template, context).replace("{domain}", domain).to_lowercase()
+}
+
+/// The domain `name` was rendered for, if it has the template's shape.
+pub fn match_domain<'a>(template: &str, context: &TemplateContext, name: &'a str) -> Option<&'a str> {
+    let template = fill(template, context).to_lowercase();
+    let (prefix, suffix) = template.split_once("{domain}")?;
+    name.strip_prefix(prefix)?.strip_suffix(suffix).filter(|domain| !domain.is_empty())
+}
+
+fn fill(template: &str, context: &TemplateContext) -> String {
+    template
+        .replace("{profile}", context.profile)
+        .replace("{region}", context.region)
+        .replace("{zone}", context.zone.trim_end_matches('.'))
+}
+
+/// What a rendered hostname is used for, which decides where it may point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateUse {
+    /// An MX target, answered with the mail server addresses.
+    Mail,
+    /// An `autodiscover`/`autoconfig` CNAME target, answered like the MX target.
+    Autodiscover,
+    /// An SPF include, looked up elsewhere.
+    SpfInclude,
+    /// An MX target run elsewhere, such as the payment-required bounce host.
+    Bounce,
+}
+
+/// Checks that `template` (the config key `field`) only uses placeholders `context` can fill
+/// and renders to a legal hostname. Mail and autodiscover targets must also be names we
+/// answer for ourselves: below the domain, or for routing profiles below the infrastructure
+/// zone with `{domain}` in them.
+pub fn validate(field: &str, template: &str, context: &TemplateContext, usage: TemplateUse) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}')
+            .ok_or_else(|| anyhow::anyhow!("{} has an unclosed placeholder: {}", field, template))?;
+        let placeholder = &rest[start..start + end + 1];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(anyhow::anyhow!("{} uses unknown placeholder {}; known ones are {}", field, placeholder, PLACEHOLDERS.join(", ")));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if template.contains("{profile}") && context.profile.is_empty() {
+        return Err(anyhow::anyhow!("{} uses {{profile}}, which only routing profiles have", field));
+    }
+    if template.contains("{region}") && context.region.is_empty() {
+        return Err(anyhow::anyhow!("{} uses {{region}}, but no region is configured for it", field));
+    }
+
+    let rendered = render(template, context, SAMPLE_DOMAIN);
+    check_hostname(&rendered).map_err(|e| anyhow::anyhow!("{} renders to an invalid hostname {}: {}", field, rendered, e))?;
+    if matches!(usage, TemplateUse::SpfInclude | TemplateUse::Bounce) {
+        return Ok(());
+    }
+
+    let zone = context.zone.trim_end_matches('.').to_lowercase();
+    let in_domain = is_within(&rendered, SAMPLE_DOMAIN);
+    let in_zone = !context.profile.is_empty() && !zone.is_empty() && is_within(&rendered, &zone) && template.contains("{domain}");
+    if !in_domain && !in_zone {
+        return Err(anyhow::anyhow!(
+            "{} renders to {}, which we do not answer for; it must be below {{domain}}{}",
+            field,
+            rendered,
+            if context.profile.is_empty() { "" } else { ", or below {zone} and contain {domain}" }
+        ));
+    }
+    if usage == TemplateUse::Autodiscover {
+        if let Some(relative) = rendered.strip_suffix(&format!(".{}", SAMPLE_DOMAIN)) {
+            if AUTODISCOVER_LABELS.contains(&relative) {
+                return Err(anyhow::anyhow!("{} renders to {}, which would point the record at itself", field, rendered));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_within(name: &str, zone: &str) -> bool {
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+/// Refuses names resolvers would reject: empty or overlong labels, or characters outside
+/// letters, digits, `-` and `_`, or labels starting or ending with `-`.
+fn check_hostname(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 253 {
+        return Err(anyhow::anyhow!("must be 1 to 253 characters"));
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow::anyhow!("label {:?} must be 1 to 63 characters", label));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow::anyhow!("label {} may only contain letters, digits, - and _", label));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(anyhow::anyhow!("label {} may not start or end with -", label));
+        }
+    }
+    Ok(())
+}
@@ -48,7 +48,7 @@ async fn handle_http_request(
     
     // Check if this is one of our domains
     let manager = domain_manager.read().await;
-    let is_our_domain = manager.get_domain(&host).await.is_some();
+    let is_our_domain = manager.get_domain(&host).await.map(|d| d.enabled).unwrap_or(false);
     
     if is_our_domain {
         // Redirect to cybertemp.xyz
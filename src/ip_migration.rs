@@ -0,0 +1,149 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use trust_dns_proto::rr::RecordType;
+
+use crate::resolvers::NamedResolver;
+use crate::validation::check_ttl;
+
+/// How often migrations are moved on.
+pub const IP_MIGRATION_INTERVAL_SECONDS: u64 = 30;
+/// TTL domains are lowered to unless the request says otherwise.
+const DEFAULT_LOWERED_TTL: u32 = 60;
+/// How long resolvers get to return the new IP before the TTLs are restored anyway.
+pub const VERIFICATION_TIMEOUT_MINUTES: i64 = 60;
+const PORT_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body of `POST /migrations`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IpMigrationRequest {
+    pub from_ip: String,
+    pub to_ip: String,
+    /// Domains to move; every domain answering with `from_ip` when absent.
+    #[serde(default)]
+    pub domains: Option<Vec<String>>,
+    /// TTL held from now until the cutover has been verified.
+    #[serde(default)]
+    pub lowered_ttl: Option<u32>,
+    /// When to switch the A records; defaults to as soon as the old TTLs have run out.
+    #[serde(default)]
+    pub cutover_at: Option<DateTime<Utc>>,
+    /// TCP port the new IP must accept connections on before anything is switched.
+    #[serde(default)]
+    pub check_port: Option<u16>,
+}
+
+impl IpMigrationRequest {
+    /// Checks the addresses and TTL; the domains are checked against the live map by the caller.
+    pub fn validate(&self) -> Result<u32> {
+        let from: Ipv4Addr = self.from_ip.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid IPv4 address: {}", self.from_ip))?;
+        let to: Ipv4Addr = self.to_ip.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid IPv4 address: {}", self.to_ip))?;
+        if from == to {
+            return Err(anyhow::anyhow!("from_ip and to_ip are the same"));
+        }
+        let lowered_ttl = self.lowered_ttl.unwrap_or(DEFAULT_LOWERED_TTL);
+        check_ttl(lowered_ttl)?;
+        Ok(lowered_ttl)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationPhase {
+    /// TTLs are lowered; the A records still point at `from_ip`.
+    WaitingForCutover,
+    /// A records point at `to_ip`; waiting for public resolvers to return it.
+    Verifying,
+    /// Putting the TTLs back as they were.
+    RestoringTtl,
+    Completed,
+    /// Cancelled or aborted before the cutover; nothing was moved.
+    Cancelled,
+}
+
+impl MigrationPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationPhase::WaitingForCutover => "waiting_for_cutover",
+            MigrationPhase::Verifying => "verifying",
+            MigrationPhase::RestoringTtl => "restoring_ttl",
+            MigrationPhase::Completed => "completed",
+            MigrationPhase::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, MigrationPhase::Completed | MigrationPhase::Cancelled)
+    }
+}
+
+/// One domain's progress through a migration.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationDomain {
+    pub domain: String,
+    /// The domain's TTL override before the migration; `None` if it had none.
+    pub previous_ttl: Option<u32>,
+    /// False when its TTL was already at or below the lowered one.
+    pub ttl_lowered: bool,
+    pub cut_over: bool,
+    /// Every public resolver that answered returned the new IP.
+    pub verified: bool,
+    pub ttl_restored: bool,
+    /// Why the domain dropped out, or was not verified in time.
+    pub error: Option<String>,
+}
+
+/// A migration and its progress, as stored in `ip_migrations.state` and returned by the API.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IpMigration {
+    #[serde(default)]
+    pub id: i64,
+    pub from_ip: String,
+    pub to_ip: String,
+    pub lowered_ttl: u32,
+    pub cutover_at: DateTime<Utc>,
+    pub check_port: Option<u16>,
+    pub phase: MigrationPhase,
+    pub domains: Vec<MigrationDomain>,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+    pub cut_over_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Set when the migration was cancelled or aborted.
+    pub error: Option<String>,
+}
+
+/// Whether every resolver that answers returns `ip` as the domain's only A record.
+pub async fn serves_ip(resolvers: &[NamedResolver], domain: &str, ip: &str) -> bool {
+    let lookups: Vec<_> = resolvers.iter()
+        .map(|upstream| {
+            let resolver = upstream.resolver.clone();
+            let domain = domain.to_string();
+            tokio::spawn(async move { resolver.lookup(domain, RecordType::A).await })
+        })
+        .collect();
+
+    let mut answered = 0;
+    for lookup in lookups {
+        let Ok(Ok(answer)) = lookup.await else { continue };
+        answered += 1;
+        let addresses: Vec<String> = answer.iter()
+            .filter_map(|rdata| rdata.as_a().map(|a| a.to_string()))
+            .collect();
+        if addresses.is_empty() || addresses.iter().any(|address| address != ip) {
+            return false;
+        }
+    }
+    answered > 0
+}
+
+/// Whether `ip` accepts TCP connections on `port`.
+pub async fn accepts_connections(ip: &str, port: u16) -> bool {
+    let Ok(ip) = ip.parse::<Ipv4Addr>() else { return false };
+    let connect = tokio::net::TcpStream::connect(SocketAddr::from((ip, port)));
+    matches!(tokio::time::timeout(PORT_CHECK_TIMEOUT, connect).await, Ok(Ok(_)))
+}
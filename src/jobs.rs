@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::time::{interval_at, MissedTickBehavior};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::request_id::{new_request_id, with_request_id};
+
+/// Longest random delay before a job's first run, so loops sharing an interval do not all
+/// start (and take the same locks) at once.
+const MAX_START_JITTER: Duration = Duration::from_secs(30);
+/// Pause before a supervised task that panicked is started again, so a panic on every start
+/// does not spin.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Timing and outcome counters for one background job, as shown by `GET /jobs`.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct JobStats {
+    pub name: String,
+    /// Jobs in the same group never run at the same time.
+    pub group: Option<String>,
+    pub interval_seconds: u64,
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    /// Ticks dropped because the job overran its interval or another job in its group was running.
+    pub skipped: u64,
+    pub last_started: Option<DateTime<Utc>>,
+    /// ID of the latest run, found in its log lines and the zone changes it made.
+    pub last_request_id: Option<String>,
+    pub last_duration_ms: Option<u64>,
+    pub max_duration_ms: u64,
+    pub total_duration_ms: u64,
+    pub last_error: Option<String>,
+}
+
+/// Runs the periodic background loops with skip-if-running semantics: a tick that comes
+/// while the job, or another job in its exclusion group, is still running is skipped
+/// instead of queueing up behind it.
+#[derive(Clone, Default)]
+pub struct JobScheduler {
+    jobs: Arc<Mutex<HashMap<String, JobStats>>>,
+    /// Exclusion groups with a job running right now, and which job it is.
+    busy_groups: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl JobScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns `job` every `every`, after a random delay of up to `MAX_START_JITTER` (capped at
+    /// the interval). `group` names an exclusion group, e.g. jobs taking the domain manager's
+    /// write lock.
+    pub fn spawn<F, Fut>(&self, name: &str, group: Option<&str>, every: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner).insert(name.to_string(), JobStats {
+            name: name.to_string(),
+            group: group.map(str::to_string),
+            interval_seconds: every.as_secs(),
+            ..JobStats::default()
+        });
+
+        let scheduler = self.clone();
+        let name = name.to_string();
+        let group = group.map(str::to_string);
+        let delay = start_jitter(every);
+
+        tokio::spawn(async move {
+            info!("Starting {} job (interval: {}s, first run in {}s)", name, every.as_secs(), delay.as_secs());
+            let mut interval = interval_at(tokio::time::Instant::now() + delay, every);
+            // A run that overruns its interval drops the missed ticks rather than firing them back to back
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                interval.tick().await;
+                let request_id = new_request_id();
+                if !scheduler.try_start(&name, group.as_deref(), &request_id) {
+                    continue;
+                }
+
+                // Each run is its own task, so a panic ends that run instead of the whole loop
+                let span = info_span!("job", name = %name, request_id = %request_id);
+                let started = Instant::now();
+                let run = with_request_id(request_id, job()).instrument(span.clone());
+                let result = match tokio::spawn(run).await {
+                    Ok(result) => result,
+                    Err(e) => Err(anyhow::anyhow!("panicked: {}", e)),
+                };
+                span.in_scope(|| scheduler.finish(&name, group.as_deref(), every, started.elapsed(), result));
+            }
+        });
+    }
+
+    /// Per-job counters, sorted by name.
+    pub fn stats(&self) -> Vec<JobStats> {
+        let mut stats: Vec<JobStats> = self.jobs.lock().unwrap_or_else(PoisonError::into_inner).values().cloned().collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Marks the job running unless its group is busy, in which case the tick is counted as
+    /// skipped.
+    fn try_start(&self, name: &str, group: Option<&str>, request_id: &str) -> bool {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(stats) = jobs.get_mut(name) else {
+            return false;
+        };
+
+        if let Some(group) = group {
+            let mut busy = self.busy_groups.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(holder) = busy.get(group) {
+                stats.skipped += 1;
+                warn!("Skipping {} job, {} is still running", name, holder);
+                return false;
+            }
+            busy.insert(group.to_string(), name.to_string());
+        }
+
+        stats.running = true;
+        stats.last_started = Some(Utc::now());
+        stats.last_request_id = Some(request_id.to_string());
+        true
+    }
+
+    fn finish(&self, name: &str, group: Option<&str>, every: Duration, elapsed: Duration, result: Result<()>) {
+        if let Some(group) = group {
+            self.busy_groups.lock().unwrap_or_else(PoisonError::into_inner).remove(group);
+        }
+
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(stats) = jobs.get_mut(name) else {
+            return;
+        };
+        let elapsed_ms = elapsed.as_millis() as u64;
+        stats.running = false;
+        stats.runs += 1;
+        stats.last_duration_ms = Some(elapsed_ms);
+        stats.max_duration_ms = stats.max_duration_ms.max(elapsed_ms);
+        stats.total_duration_ms += elapsed_ms;
+        // Ticks that passed during the run are dropped by `MissedTickBehavior::Skip`
+        let overrun = (elapsed.as_millis() / every.as_millis().max(1)) as u64;
+        if overrun > 0 {
+            stats.skipped += overrun;
+            warn!("{} job took {}ms, longer than its {}s interval; skipped {} run(s)", name, elapsed_ms, every.as_secs(), overrun);
+        }
+        match result {
+            Ok(()) => stats.last_error = None,
+            Err(e) => {
+                stats.failures += 1;
+                error!("{} job failed: {}", name, e);
+                stats.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Runs `task` until it returns, starting it again after `RESTART_DELAY` whenever it panics.
+/// Errors are returned as-is, so a listener that cannot bind still fails startup.
+pub async fn supervise<F, Fut>(name: &str, task: F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    loop {
+        match tokio::spawn(task()).await {
+            Ok(result) => return result,
+            Err(e) if e.is_panic() => {
+                error!("{} panicked, restarting in {}s", name, RESTART_DELAY.as_secs());
+                tokio::time::sleep(RESTART_DELAY).await;
+            }
+            Err(e) => return Err(anyhow::anyhow!("{} was cancelled: {}", name, e)),
+        }
+    }
+}
+
+/// A random delay in `[0, min(every, MAX_START_JITTER))`.
+fn start_jitter(every: Duration) -> Duration {
+    let max_ms = every.min(MAX_START_JITTER).as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    // RandomState is seeded randomly per instance, which is all the randomness this needs
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % max_ms)
+}
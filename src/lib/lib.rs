@@ -1,10 +1,71 @@
 pub mod dns_server;
 pub mod domain_manager;
+pub mod domain_name;
+pub mod domain_store;
 pub mod dns_handler;
+pub mod dns_stream;
 pub mod database;  // <-- ADD THIS LINE
+pub mod db_breaker;
 pub mod supabase_client;
 pub mod config;
+pub mod hostname_template;
 pub mod http_redirect;
+pub mod graphql;
+pub mod rate_limit;
+pub mod query_middleware;
+pub mod query_stats;
+pub mod transport_stats;
+pub mod response_cache;
+pub mod selftest;
+pub mod shadow;
+pub mod bench;
+pub mod packet_capture;
+pub mod packet_guard;
+pub mod query_script;
+pub mod scheduler;
+pub mod approval;
+pub mod transfer;
+pub mod record_templates;
+pub mod consistency;
+pub mod lint;
+pub mod dns_json;
+pub mod extended_error;
+pub mod doh;
+pub mod external_verification;
+pub mod ip_migration;
+pub mod zone_import;
+pub mod outbox;
+pub mod api;
+pub mod answer_override;
+pub mod webhook_signature;
+pub mod snapshot;
+pub mod state_dump;
+pub mod resolvers;
+pub mod propagation;
+pub mod registrar;
+pub mod smtp_check;
+pub mod ct_monitor;
+pub mod anomaly;
+pub mod abuse;
+pub mod billing;
+pub mod blocklist;
+pub mod canary;
+pub mod validation;
+pub mod stats_history;
+pub mod node_stats;
+pub mod node_registry;
+pub mod node_health;
+pub mod synthetic;
+pub mod txt_record;
+pub mod usage;
+pub mod jobs;
+pub mod request_id;
+pub mod metrics;
+pub mod runtime;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
+#[cfg(any(test, feature = "client"))]
+pub mod client;
 
 pub use dns_server::DnsServer;
 pub use domain_manager::{DomainManager, DomainRecord, VerificationStatus};
@@ -0,0 +1,328 @@
+use serde::Serialize;
+use trust_dns_proto::rr::{RData, RecordType};
+
+use crate::consistency::{check_target, TargetProblem};
+use crate::dns_handler::CybertempHandler;
+use crate::domain_manager::DomainRecord;
+use crate::resolvers::NamedResolver;
+
+/// TTLs below this make resolvers re-ask on nearly every query.
+const MIN_SANE_TTL: u32 = 60;
+/// TTLs above this make mistakes take more than a day to wash out of caches.
+const MAX_SANE_TTL: u32 = 86400;
+/// RFC 7208 section 4.6.4: more DNS-querying terms than this is a permerror.
+const MAX_SPF_LOOKUPS: usize = 10;
+const SPF_MECHANISMS: &[&str] = &["all", "include", "a", "mx", "ptr", "ip4", "ip6", "exists"];
+const SPF_MODIFIERS: &[&str] = &["redirect", "exp"];
+/// Record types whose own TTL is served; the rest are settings stored as records.
+const SERVED_TYPES: &[&str] = &["A", "AAAA", "CNAME", "MX", "TXT"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Mail or resolution is broken, or the record is rejected by receivers.
+    Error,
+    /// Works, but against best practice or likely to hurt deliverability.
+    Warning,
+    Info,
+}
+
+/// One problem found in a domain's served answers.
+#[derive(Debug, Clone, Serialize)]
+pub struct LintFinding {
+    /// Which check raised it: `spf`, `dmarc`, `dkim`, `mx`, `cname` or `ttl`.
+    pub check: &'static str,
+    pub severity: Severity,
+    /// Owner name the finding is about.
+    pub name: String,
+    pub message: String,
+}
+
+/// What `GET /domains/{domain}/lint` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct LintReport {
+    pub domain: String,
+    pub errors: usize,
+    pub warnings: usize,
+    /// Most severe first.
+    pub findings: Vec<LintFinding>,
+}
+
+impl LintReport {
+    fn new(domain: &str, mut findings: Vec<LintFinding>) -> Self {
+        findings.sort_by(|a, b| a.severity.cmp(&b.severity).then_with(|| a.name.cmp(&b.name)));
+        Self {
+            domain: domain.to_string(),
+            errors: findings.iter().filter(|f| f.severity == Severity::Error).count(),
+            warnings: findings.iter().filter(|f| f.severity == Severity::Warning).count(),
+            findings,
+        }
+    }
+}
+
+struct Findings {
+    domain: String,
+    found: Vec<LintFinding>,
+}
+
+impl Findings {
+    fn add(&mut self, check: &'static str, severity: Severity, name: &str, message: impl Into<String>) {
+        let name = if name == "@" { self.domain.clone() } else { format!("{}.{}", name, self.domain) };
+        self.found.push(LintFinding { check, severity, name, message: message.into() });
+    }
+}
+
+/// Checks the answers `handler` gives for `domain` against mail and zone best practices.
+/// MX targets outside our zones are resolved through `resolvers`.
+pub async fn lint_domain(handler: &CybertempHandler, domain: &DomainRecord, resolvers: &[NamedResolver]) -> LintReport {
+    let mut findings = Findings { domain: domain.domain.clone(), found: Vec::new() };
+
+    let apex_txt = txt_answers(handler, &domain.domain).await;
+    let dmarc_txt = txt_answers(handler, &format!("_dmarc.{}", domain.domain)).await;
+    let has_dkim = has_dkim(domain);
+
+    lint_spf(&mut findings, &apex_txt);
+    lint_dmarc(&mut findings, &dmarc_txt, has_dkim);
+    if !has_dkim {
+        findings.add("dkim", Severity::Warning, "@", "No DKIM key published under _domainkey; DMARC can only align through SPF");
+    }
+    lint_mx(&mut findings, handler, domain, resolvers).await;
+    lint_cnames(&mut findings, handler, domain).await;
+    lint_ttls(&mut findings, domain);
+
+    LintReport::new(&domain.domain, findings.found)
+}
+
+async fn txt_answers(handler: &CybertempHandler, name: &str) -> Vec<String> {
+    let Ok(response) = handler.lookup(name, RecordType::TXT).await else { return Vec::new() };
+    response.answers().iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => Some(txt.txt_data().iter().map(|part| String::from_utf8_lossy(part)).collect::<String>()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn lint_spf(findings: &mut Findings, apex_txt: &[String]) {
+    let spf: Vec<&String> = apex_txt.iter()
+        .filter(|txt| txt.to_ascii_lowercase().starts_with("v=spf1"))
+        .collect();
+    let record = match spf.as_slice() {
+        [] => {
+            findings.add("spf", Severity::Warning, "@", "No SPF record; receivers cannot tell which hosts may send for the domain");
+            return;
+        }
+        [record] => record.to_ascii_lowercase(),
+        _ => {
+            findings.add("spf", Severity::Error, "@", format!("{} SPF records; receivers treat more than one as a permerror", spf.len()));
+            return;
+        }
+    };
+
+    let mut terms = record.split_whitespace();
+    if terms.next() != Some("v=spf1") {
+        findings.add("spf", Severity::Error, "@", "SPF record must start with \"v=spf1 \"");
+        return;
+    }
+    let mut lookups = 0;
+    let mut ends_with_policy = false;
+    for term in terms {
+        if ends_with_policy {
+            findings.add("spf", Severity::Warning, "@", format!("\"{}\" comes after \"all\" and is never evaluated", term));
+            continue;
+        }
+        if let Some((modifier, _)) = term.split_once('=') {
+            if !SPF_MODIFIERS.contains(&modifier) {
+                findings.add("spf", Severity::Error, "@", format!("Unknown SPF modifier \"{}\"", modifier));
+            }
+            if modifier == "redirect" {
+                lookups += 1;
+                ends_with_policy = true;
+            }
+            continue;
+        }
+        let mechanism = term.trim_start_matches(['+', '-', '~', '?']);
+        let name = mechanism.split([':', '/']).next().unwrap_or(mechanism);
+        if !SPF_MECHANISMS.contains(&name) {
+            findings.add("spf", Severity::Error, "@", format!("Unknown SPF mechanism \"{}\"", term));
+            continue;
+        }
+        match name {
+            "include" | "a" | "mx" | "ptr" | "exists" => lookups += 1,
+            "all" => {
+                ends_with_policy = true;
+                if !term.starts_with(['-', '~', '?']) {
+                    findings.add("spf", Severity::Error, "@", "\"+all\" lets any host on the internet send as the domain");
+                }
+            }
+            _ => {}
+        }
+        if name == "ptr" {
+            findings.add("spf", Severity::Warning, "@", "\"ptr\" is slow and discouraged by RFC 7208");
+        }
+    }
+    if lookups > MAX_SPF_LOOKUPS {
+        findings.add("spf", Severity::Error, "@", format!("{} DNS lookups; receivers give up after {}", lookups, MAX_SPF_LOOKUPS));
+    }
+    if !ends_with_policy {
+        findings.add("spf", Severity::Warning, "@", "SPF record ends without \"all\" or \"redirect=\", so unlisted hosts are neutral");
+    }
+}
+
+fn lint_dmarc(findings: &mut Findings, dmarc_txt: &[String], has_dkim: bool) {
+    let dmarc: Vec<&String> = dmarc_txt.iter()
+        .filter(|txt| txt.to_ascii_lowercase().starts_with("v=dmarc1"))
+        .collect();
+    let record = match dmarc.as_slice() {
+        [] => {
+            findings.add("dmarc", Severity::Warning, "_dmarc", "No DMARC record; receivers apply their own policy to failing mail");
+            return;
+        }
+        [record] => record.to_ascii_lowercase(),
+        _ => {
+            findings.add("dmarc", Severity::Error, "_dmarc", format!("{} DMARC records; receivers ignore all of them", dmarc.len()));
+            return;
+        }
+    };
+
+    let tags: Vec<(&str, &str)> = record.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let tag = |name: &str| tags.iter().find(|(key, _)| *key == name).map(|(_, value)| *value);
+
+    match tag("p") {
+        None => findings.add("dmarc", Severity::Error, "_dmarc", "DMARC record has no \"p=\" policy tag"),
+        Some("none") => findings.add("dmarc", Severity::Warning, "_dmarc", "Policy is \"none\", so spoofed mail is only reported, not rejected"),
+        Some("quarantine") | Some("reject") => {}
+        Some(other) => findings.add("dmarc", Severity::Error, "_dmarc", format!("Unknown DMARC policy \"{}\"", other)),
+    }
+    if let Some(pct) = tag("pct") {
+        match pct.parse::<u8>() {
+            Ok(pct) if pct > 100 => findings.add("dmarc", Severity::Error, "_dmarc", "\"pct=\" must be between 0 and 100"),
+            Ok(100) => {}
+            Ok(pct) => findings.add("dmarc", Severity::Warning, "_dmarc", format!("Policy only applies to {}% of failing mail", pct)),
+            Err(_) => findings.add("dmarc", Severity::Error, "_dmarc", format!("Invalid \"pct={}\"", pct)),
+        }
+    }
+    if tag("rua").is_none() {
+        findings.add("dmarc", Severity::Info, "_dmarc", "No \"rua=\" address, so no aggregate reports are sent");
+    }
+    for (key, label) in [("adkim", "DKIM"), ("aspf", "SPF")] {
+        if let Some(mode) = tag(key) {
+            if mode != "r" && mode != "s" {
+                findings.add("dmarc", Severity::Error, "_dmarc", format!("Invalid {} alignment mode \"{}\"", label, mode));
+            }
+        }
+    }
+    if !has_dkim && tag("aspf") == Some("s") {
+        findings.add("dmarc", Severity::Warning, "_dmarc", "Strict SPF alignment without DKIM fails for mail relayed through another domain");
+    }
+}
+
+/// Whether the domain has a DKIM key: a TXT record under `_domainkey` with a `p=` tag.
+fn has_dkim(domain: &DomainRecord) -> bool {
+    let suffix = format!("._domainkey.{}", domain.domain);
+    domain.records.iter()
+        .filter(|r| r.enabled && r.record_type == "TXT")
+        .filter(|r| {
+            let owner = r.name.trim_end_matches('.').to_ascii_lowercase();
+            owner.ends_with("._domainkey") || owner.ends_with(&suffix)
+        })
+        .any(|r| r.value.split(';').any(|tag| tag.trim().starts_with("p=")))
+}
+
+async fn lint_mx(findings: &mut Findings, handler: &CybertempHandler, domain: &DomainRecord, resolvers: &[NamedResolver]) {
+    let Ok(response) = handler.lookup(&domain.domain, RecordType::MX).await else { return };
+    let targets: Vec<String> = response.answers().iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::MX(mx)) => Some(mx.exchange().to_ascii().trim_end_matches('.').to_lowercase()),
+            _ => None,
+        })
+        .collect();
+    if targets.is_empty() {
+        findings.add("mx", Severity::Warning, "@", "No MX records, so the domain cannot receive mail");
+        return;
+    }
+
+    for target in targets {
+        match check_target(handler, &target).await {
+            None => {}
+            Some(TargetProblem::NoAddress) => {
+                findings.add("mx", Severity::Error, "@", format!("MX target {} has no A or AAAA record", target));
+            }
+            Some(TargetProblem::OutOfBailiwick) => {
+                if !resolves_externally(resolvers, &target).await {
+                    findings.add("mx", Severity::Error, "@", format!("MX target {} does not resolve through public resolvers", target));
+                }
+            }
+        }
+    }
+}
+
+async fn resolves_externally(resolvers: &[NamedResolver], target: &str) -> bool {
+    for upstream in resolvers {
+        if let Ok(answer) = upstream.resolver.lookup_ip(target).await {
+            if answer.iter().next().is_some() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+async fn lint_cnames(findings: &mut Findings, handler: &CybertempHandler, domain: &DomainRecord) {
+    if let Ok(response) = handler.lookup(&domain.domain, RecordType::CNAME).await {
+        if response.answers().iter().any(|record| record.record_type() == RecordType::CNAME) {
+            findings.add("cname", Severity::Error, "@", "CNAME at the zone apex conflicts with the SOA, NS and MX records there");
+        }
+    }
+
+    let mut names: Vec<String> = domain.records.iter()
+        .filter(|r| r.enabled && r.record_type == "CNAME")
+        .map(|r| relative_name(&r.name, &domain.domain))
+        .filter(|name| name != "@")
+        .collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let others: Vec<&str> = domain.explicit_records(&name).iter()
+            .filter(|r| r.record_type != "CNAME")
+            .map(|r| r.record_type.as_str())
+            .collect();
+        let cnames = domain.explicit_records(&name).iter().filter(|r| r.record_type == "CNAME").count();
+        if !others.is_empty() {
+            findings.add("cname", Severity::Error, &name, format!("CNAME next to {} records at the same name; resolvers only follow the CNAME", others.join(", ")));
+        } else if cnames > 1 {
+            findings.add("cname", Severity::Error, &name, format!("{} CNAME records at the same name; only one is allowed", cnames));
+        }
+    }
+}
+
+fn relative_name(name: &str, domain: &str) -> String {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.eq_ignore_ascii_case(domain) {
+        return "@".to_string();
+    }
+    name.strip_suffix(&format!(".{}", domain)).unwrap_or(name).to_string()
+}
+
+fn lint_ttls(findings: &mut Findings, domain: &DomainRecord) {
+    if let Some(ttl) = domain.ttl_override() {
+        check_ttl(findings, "@", "Domain TTL", ttl);
+    }
+    let served = domain.records.iter()
+        .filter(|r| r.enabled && r.ttl > 0 && SERVED_TYPES.contains(&r.record_type.as_str()));
+    for record in served {
+        let name = relative_name(&record.name, &domain.domain);
+        check_ttl(findings, &name, &format!("{} record TTL", record.record_type), record.ttl as u32);
+    }
+}
+
+fn check_ttl(findings: &mut Findings, name: &str, what: &str, ttl: u32) {
+    if ttl < MIN_SANE_TTL {
+        findings.add("ttl", Severity::Warning, name, format!("{} of {}s is below {}s; every lookup goes back to us", what, ttl, MIN_SANE_TTL));
+    } else if ttl > MAX_SANE_TTL {
+        findings.add("ttl", Severity::Warning, name, format!("{} of {}s is above {}s; changes take over a day to propagate", what, ttl, MAX_SANE_TTL));
+    }
+}
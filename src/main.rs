@@ -1,5 +1,14 @@
 use clap::{Arg, Command};
-use cybertemp_dns::DnsServer;
+use cybertemp_dns::bench::{run_bench, BenchOptions};
+use cybertemp_dns::zone_import::{apply_import, fetch_cloudflare_zone, fetch_route53_zone, ImportOptions};
+use cybertemp_dns::stats_history::{rollup, StatsRetention};
+use cybertemp_dns::config::PanicBehavior;
+use cybertemp_dns::{Database, DnsConfig, DnsServer, SupabaseClient};
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_proto::rr::RecordType;
 use tracing::{info, error};
 use tracing_subscriber;
 
@@ -24,6 +33,13 @@ async fn main() -> anyhow::Result<()> {
                 .help("Sets a custom config file")
                 .default_value("config/dns.toml"),
         )
+        .arg(
+            Arg::new("env")
+                .short('e')
+                .long("env")
+                .value_name("ENV")
+                .help("Layers config/dns.<ENV>.toml over the config file; defaults to CYBERTEMP_ENV"),
+        )
         .arg(
             Arg::new("daemon")
                 .short('d')
@@ -31,9 +47,293 @@ async fn main() -> anyhow::Result<()> {
                 .help("Run as daemon")
                 .action(clap::ArgAction::SetTrue),
         )
+        .subcommand(
+            Command::new("selftest")
+                .about("Serve a synthetic domain on an ephemeral port and validate real query answers"),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Flood a DNS server with queries and report latency percentiles and error rates")
+                .arg(Arg::new("target").long("target").default_value("127.0.0.1:53"))
+                .arg(
+                    Arg::new("qps")
+                        .long("qps")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .help("Seconds to send for")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("timeout-ms")
+                        .long("timeout-ms")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("2000"),
+                )
+                .arg(Arg::new("type").long("type").default_value("A"))
+                .arg(
+                    Arg::new("qname-file")
+                        .long("qname-file")
+                        .value_name("FILE")
+                        .help("One query name per line (defaults to generated NXDOMAIN names)"),
+                ),
+        )
+        .subcommand(
+            Command::new("stats-rollup")
+                .about("Roll up and prune the query stats history once, e.g. to backfill rollups")
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("RFC3339")
+                        .help("Recompute rollups from this time instead of the retention window"),
+                ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Sync domains with Supabase in both directions once")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the changes as JSON without writing them")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Have the running server re-check domains' delegation now, through its API")
+                .arg(Arg::new("domains").value_name("DOMAIN").num_args(0..))
+                .arg(
+                    Arg::new("all-pending")
+                        .long("all-pending")
+                        .help("Check every enabled domain that is not verified")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("api-url")
+                        .long("api-url")
+                        .help("Defaults to api_bind_address and api_port from the config"),
+                )
+                .arg(
+                    Arg::new("api-key")
+                        .long("api-key")
+                        .help("Defaults to the first api_keys entry from the config"),
+                ),
+        )
+        .subcommand(
+            Command::new("lint")
+                .about("Check a domain's served zone against best practices, through the running server's API")
+                .arg(Arg::new("domain").value_name("DOMAIN").required(true))
+                .arg(
+                    Arg::new("api-url")
+                        .long("api-url")
+                        .help("Defaults to api_bind_address and api_port from the config"),
+                )
+                .arg(
+                    Arg::new("api-key")
+                        .long("api-key")
+                        .help("Defaults to the first api_keys entry from the config"),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Import a zone's records from another DNS provider")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("cloudflare")
+                        .about("Pull all records for a zone through the Cloudflare API")
+                        .arg(
+                            Arg::new("token")
+                                .long("token")
+                                .required(true)
+                                .help("API token with Zone.DNS:Read"),
+                        )
+                        .arg(Arg::new("zone").long("zone").required(true))
+                        .arg(
+                            Arg::new("ip")
+                                .long("ip")
+                                .help("Domain IP when creating a zone without an apex A record"),
+                        )
+                        .arg(
+                            Arg::new("pending")
+                                .long("pending")
+                                .help("Add the domain if needed and mark it pending NS verification")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("allow-reserved")
+                                .long("allow-reserved")
+                                .help("Import records on reserved labels such as mail or _dmarc instead of skipping them")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the mapped records without writing them")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("route53")
+                        .about("List a hosted zone's record sets through the AWS SDK (needs --features route53)")
+                        .arg(Arg::new("hosted-zone-id").long("hosted-zone-id").required(true))
+                        .arg(
+                            Arg::new("ip")
+                                .long("ip")
+                                .help("Domain IP when creating a zone without an apex A record"),
+                        )
+                        .arg(
+                            Arg::new("pending")
+                                .long("pending")
+                                .help("Add the domain if needed and mark it pending NS verification")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("allow-reserved")
+                                .long("allow-reserved")
+                                .help("Import records on reserved labels such as mail or _dmarc instead of skipping them")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the mapped records without writing them")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                ),
+        )
         .get_matches();
-
+    
     let config_path = matches.get_one::<String>("config").unwrap();
+    let env = matches.get_one::<String>("env").cloned()
+        .or_else(|| std::env::var("CYBERTEMP_ENV").ok())
+        .filter(|env| !env.is_empty());
+    let env = env.as_deref();
+    
+    if matches.subcommand_matches("selftest").is_some() {
+        if let Err(e) = cybertemp_dns::selftest::run_selftest(config_path, env).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    if let Some(bench) = matches.subcommand_matches("bench") {
+        let qnames = match bench.get_one::<String>("qname-file") {
+            Some(path) => BenchOptions::load_qnames(path)?,
+            None => BenchOptions::generated_qnames(),
+        };
+        let options = BenchOptions {
+            target: bench.get_one::<String>("target").unwrap().parse()?,
+            qps: *bench.get_one::<u32>("qps").unwrap(),
+            duration: Duration::from_secs(*bench.get_one::<u64>("duration").unwrap()),
+            timeout: Duration::from_millis(*bench.get_one::<u64>("timeout-ms").unwrap()),
+            record_type: RecordType::from_str(bench.get_one::<String>("type").unwrap())?,
+            qnames,
+        };
+        return run_bench(options).await;
+    }
+    
+    if let Some(stats) = matches.subcommand_matches("stats-rollup") {
+        let config = DnsConfig::load(config_path, env)?;
+        let since = match stats.get_one::<String>("since") {
+            Some(since) => Some(DateTime::parse_from_rfc3339(since)?.with_timezone(&Utc)),
+            None => None,
+        };
+        let database = Database::new(&config.database_url).await?;
+        let report = rollup(&database, &StatsRetention::from_config(&config)?, since).await?;
+        println!(
+            "{} hourly and {} daily rows written, {} 5m, {} hourly and {} daily rows pruned",
+            report.hourly_rows, report.daily_rows, report.pruned_raw, report.pruned_hourly, report.pruned_daily
+        );
+        return Ok(());
+    }
+    
+    if let Some(sync) = matches.subcommand_matches("sync") {
+        let config = DnsConfig::load(config_path, env)?;
+        let (Some(url), Some(key)) = (config.supabase_url.clone(), config.supabase_key.clone()) else {
+            return Err(anyhow::anyhow!("supabase_url and supabase_key must be set to sync"));
+        };
+        let supabase = SupabaseClient::new(url, key);
+        let database = Database::new(&config.database_url).await?;
+        let plan = supabase.plan_sync(&database).await?;
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        if !sync.get_flag("dry-run") {
+            supabase.sync_from_supabase(&database).await?;
+            supabase.sync_to_supabase(&database).await?;
+        }
+        return Ok(());
+    }
+    
+    // Checks run in the server, whose in-memory verification state a separate process could not update
+    if let Some(verify) = matches.subcommand_matches("verify") {
+        let config = DnsConfig::load(config_path, env)?;
+        let domains: Vec<String> = verify.get_many::<String>("domains")
+            .map(|domains| domains.cloned().collect())
+            .unwrap_or_default();
+        let api_url = verify.get_one::<String>("api-url").cloned()
+            .unwrap_or_else(|| format!("http://{}:{}", config.api_bind_address, config.api_port));
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/verify", api_url.trim_end_matches('/')))
+            .json(&serde_json::json!({"domains": domains, "all_pending": verify.get_flag("all-pending")}));
+        if let Some(key) = verify.get_one::<String>("api-key").or(config.api_keys.first()) {
+            request = request.bearer_auth(key);
+        }
+        
+        let response = request.send().await?;
+        let success = response.status().is_success();
+        let body: serde_json::Value = response.json().await?;
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        if !success {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    if let Some(lint) = matches.subcommand_matches("lint") {
+        let config = DnsConfig::load(config_path, env)?;
+        let domain = lint.get_one::<String>("domain").unwrap();
+        let api_url = lint.get_one::<String>("api-url").cloned()
+            .unwrap_or_else(|| format!("http://{}:{}", config.api_bind_address, config.api_port));
+        let mut request = reqwest::Client::new()
+            .get(format!("{}/domains/{}/lint", api_url.trim_end_matches('/'), domain));
+        if let Some(key) = lint.get_one::<String>("api-key").or(config.api_keys.first()) {
+            request = request.bearer_auth(key);
+        }
+        
+        let response = request.send().await?;
+        let success = response.status().is_success();
+        let body: serde_json::Value = response.json().await?;
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        // Error-severity findings fail the command, so it can gate a deploy
+        if !success || body["errors"].as_u64().unwrap_or(0) > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    if let Some((provider, migrate)) = matches.subcommand_matches("migrate").and_then(|m| m.subcommand()) {
+        let config = DnsConfig::load(config_path, env)?;
+        let import = match provider {
+            "cloudflare" => fetch_cloudflare_zone(
+                migrate.get_one::<String>("token").unwrap(),
+                migrate.get_one::<String>("zone").unwrap(),
+                config.default_ttl as i32,
+            ).await?,
+            _ => fetch_route53_zone(migrate.get_one::<String>("hosted-zone-id").unwrap()).await?,
+        };
+        let options = ImportOptions {
+            ip: migrate.get_one::<String>("ip").cloned(),
+            pending: migrate.get_flag("pending"),
+            allow_reserved: migrate.get_flag("allow-reserved"),
+            dry_run: migrate.get_flag("dry-run"),
+        };
+        return apply_import(&config, &import, &options, &format!("migrate:{}", provider)).await;
+    }
+    
     let daemon_mode = matches.get_flag("daemon");
     
     if daemon_mode {
@@ -42,16 +342,21 @@ async fn main() -> anyhow::Result<()> {
         info!("Starting Cybertemp DNS server...");
     }
     
-    // Handle panics gracefully
-    std::panic::set_hook(Box::new(|panic_info| {
+    // With `panic_behavior = "isolate"` only the panicking task ends; jobs and listeners restart it
+    let abort_on_panic = DnsConfig::load(config_path, env)
+        .map(|config| config.panic_behavior == PanicBehavior::Abort)
+        .unwrap_or(true);
+    std::panic::set_hook(Box::new(move |panic_info| {
         error!("Panic occurred: {}", panic_info);
-        std::process::exit(1);
+        if abort_on_panic {
+            std::process::exit(1);
+        }
     }));
     
-    match DnsServer::new(config_path).await {
-        Ok(mut server) => {
+    match DnsServer::new(config_path, env).await {
+        Ok(server) => {
             info!("DNS server initialized successfully");
-            if let Err(e) = server.run().await {
+            if let Err(e) = Arc::new(server).run().await {
                 error!("DNS server error: {}", e);
                 std::process::exit(1);
             }
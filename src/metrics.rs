@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A status change, as `(from, to)` status names.
+pub type Transition = (&'static str, &'static str);
+
+/// One full verification pass over every domain.
+#[derive(Debug, Clone)]
+pub struct VerificationCycle {
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub checked: usize,
+    pub transitions: BTreeMap<Transition, u64>,
+}
+
+/// `DomainManager` gauges and counters for `GET /metrics`.
+#[derive(Debug, Clone, Default)]
+pub struct DomainMetrics {
+    /// Enabled domains per verification status.
+    pub by_status: BTreeMap<&'static str, usize>,
+    pub disabled: usize,
+    /// Verified domains whose NS set lacks some of our nameservers.
+    pub partially_delegated: usize,
+    pub records: usize,
+    pub last_cycle: Option<VerificationCycle>,
+    /// Status changes since startup, from passes and forced checks alike.
+    pub transitions: BTreeMap<Transition, u64>,
+}
+
+impl DomainMetrics {
+    /// The Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        metric(&mut out, "cybertemp_domains", "gauge", "Enabled domains by verification status.");
+        for (status, count) in &self.by_status {
+            let _ = writeln!(out, "cybertemp_domains{{status=\"{}\"}} {}", status, count);
+        }
+        metric(&mut out, "cybertemp_domains_disabled", "gauge", "Disabled domains.");
+        let _ = writeln!(out, "cybertemp_domains_disabled {}", self.disabled);
+        metric(&mut out, "cybertemp_domains_partially_delegated", "gauge", "Verified domains delegated to only some of our nameservers.");
+        let _ = writeln!(out, "cybertemp_domains_partially_delegated {}", self.partially_delegated);
+        metric(&mut out, "cybertemp_records", "gauge", "Stored records across all domains, including per-domain setting rows.");
+        let _ = writeln!(out, "cybertemp_records {}", self.records);
+
+        metric(&mut out, "cybertemp_verification_transitions_total", "counter", "Verification status changes since startup.");
+        for ((from, to), count) in &self.transitions {
+            let _ = writeln!(out, "cybertemp_verification_transitions_total{{from=\"{}\",to=\"{}\"}} {}", from, to, count);
+        }
+
+        // Absent until the first pass finishes, so a fresh start does not report a zero-length cycle
+        if let Some(cycle) = &self.last_cycle {
+            metric(&mut out, "cybertemp_verification_cycle_duration_seconds", "gauge", "Duration of the last verification pass.");
+            let _ = writeln!(out, "cybertemp_verification_cycle_duration_seconds {:.3}", cycle.duration_ms as f64 / 1000.0);
+            metric(&mut out, "cybertemp_verification_cycle_timestamp_seconds", "gauge", "When the last verification pass finished.");
+            let _ = writeln!(out, "cybertemp_verification_cycle_timestamp_seconds {}", cycle.finished_at.timestamp());
+            metric(&mut out, "cybertemp_verification_cycle_domains", "gauge", "Domains checked in the last verification pass.");
+            let _ = writeln!(out, "cybertemp_verification_cycle_domains {}", cycle.checked);
+            metric(&mut out, "cybertemp_verification_cycle_transitions", "gauge", "Status changes in the last verification pass.");
+            for ((from, to), count) in &cycle.transitions {
+                let _ = writeln!(out, "cybertemp_verification_cycle_transitions{{from=\"{}\",to=\"{}\"}} {}", from, to, count);
+            }
+        }
+
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::db_breaker::DbBreaker;
+
+/// What `dns_health_name` and `dns_ready_name` report about the node that answered, so
+/// monitors can check each anycast node's DNS path and not only its HTTP API.
+pub struct NodeHealth {
+    node_id: String,
+    started_at: DateTime<Utc>,
+    db_breaker: Arc<DbBreaker>,
+}
+
+impl NodeHealth {
+    pub fn new(node_id: String, started_at: DateTime<Utc>, db_breaker: Arc<DbBreaker>) -> Self {
+        Self { node_id, started_at, db_breaker }
+    }
+
+    /// Whether the node has its database, as `/health` reports it. Without it the node keeps
+    /// answering from memory, but changes made elsewhere do not reach it.
+    pub fn ready(&self) -> bool {
+        !self.db_breaker.is_open()
+    }
+
+    /// The strings of the TXT record answered for `dns_health_name`.
+    pub fn txt(&self) -> Vec<String> {
+        let uptime = (Utc::now() - self.started_at).num_seconds().max(0);
+        vec![
+            format!("node={}", self.node_id),
+            format!("version={}", env!("CARGO_PKG_VERSION")),
+            format!("uptime={}", uptime),
+            format!("status={}", if self.ready() { "healthy" } else { "degraded" }),
+        ]
+    }
+
+    /// The strings of the TXT record answered for `dns_ready_name` while the node is ready.
+    pub fn ready_txt(&self) -> Vec<String> {
+        vec!["ready".to_string(), format!("node={}", self.node_id)]
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+/// How often each node refreshes its row in `nodes`.
+pub const NODE_HEARTBEAT_INTERVAL_SECONDS: u64 = 30;
+/// Heartbeats a node may miss before `GET /nodes` reports it dead.
+const DEAD_AFTER_HEARTBEATS: i64 = 3;
+
+/// The machine's hostname, from `HOSTNAME` or `/etc/hostname`.
+pub fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME").ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// What a node writes to `nodes` on every heartbeat.
+#[derive(Debug, Clone)]
+pub struct NodeRegistration {
+    pub node_id: String,
+    pub hostname: Option<String>,
+    pub region: Option<String>,
+    pub version: &'static str,
+    /// Fingerprint of the node's effective configuration, to spot nodes running a different one.
+    pub config_hash: String,
+    /// Enabled listeners, e.g. `udp 0.0.0.0:53`.
+    pub listen_addresses: Vec<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// A row of `nodes`, as returned by `GET /nodes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeInfo {
+    pub node_id: String,
+    pub hostname: Option<String>,
+    pub region: Option<String>,
+    pub version: String,
+    pub config_hash: String,
+    pub listen_addresses: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat: DateTime<Utc>,
+    /// Heartbeated within the last few intervals.
+    pub alive: bool,
+    /// The node that answered the request.
+    pub current: bool,
+}
+
+impl NodeInfo {
+    /// Sets `alive` from `last_heartbeat` and `current` from `node_id`.
+    pub fn with_status(mut self, now: DateTime<Utc>, current_node: &str) -> Self {
+        let dead_after = Duration::seconds(NODE_HEARTBEAT_INTERVAL_SECONDS as i64 * DEAD_AFTER_HEARTBEATS);
+        self.alive = now - self.last_heartbeat < dead_after;
+        self.current = self.node_id == current_node;
+        self
+    }
+}
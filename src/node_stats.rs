@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A node's row is left out of `/stats` once it has missed this many pushes, so nodes that were
+/// shut down or renamed stop counting.
+pub const STALE_AFTER_PUSHES: u32 = 3;
+/// Group key for nodes without a `node_region`.
+const NO_REGION: &str = "unknown";
+
+/// A row of `node_stats`: one node's counters for one transport since it started.
+#[derive(Debug, Clone)]
+pub struct NodeReport {
+    pub node_id: String,
+    pub region: Option<String>,
+    pub transport: String,
+    pub started_at: DateTime<Utc>,
+    pub reported_at: DateTime<Utc>,
+    pub queries: i64,
+    pub edns: i64,
+    pub truncated: i64,
+    pub answer_micros: i64,
+}
+
+/// How `GET /stats?by=` splits the fleet's counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakdown {
+    Node,
+    Region,
+}
+
+impl std::str::FromStr for Breakdown {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "node" => Ok(Breakdown::Node),
+            "region" => Ok(Breakdown::Region),
+            other => Err(anyhow::anyhow!("Unknown breakdown: {}, use node or region", other)),
+        }
+    }
+}
+
+/// Counters added up over some set of nodes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueryTotals {
+    pub queries: i64,
+    pub edns: i64,
+    pub truncated: i64,
+    /// Mean time from parsing a query to having its encoded answer.
+    pub mean_answer_micros: i64,
+    #[serde(skip)]
+    answer_micros: i64,
+}
+
+impl QueryTotals {
+    fn add(&mut self, report: &NodeReport) {
+        self.queries += report.queries;
+        self.edns += report.edns;
+        self.truncated += report.truncated;
+        self.answer_micros += report.answer_micros;
+        self.mean_answer_micros = self.answer_micros / self.queries.max(1);
+    }
+}
+
+/// One node's or region's share of the fleet's counters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsGroup {
+    /// The node ID or region.
+    pub key: String,
+    pub nodes: Vec<String>,
+    /// Latest node restart in the group; the counters start there.
+    pub since: DateTime<Utc>,
+    /// Oldest push in the group, to spot a node that is falling behind.
+    pub reported_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub totals: QueryTotals,
+    pub transports: BTreeMap<String, QueryTotals>,
+}
+
+/// Query counters of every node that pushed recently, as `queries` in `GET /stats`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FleetStats {
+    pub nodes: usize,
+    #[serde(flatten)]
+    pub totals: QueryTotals,
+    pub transports: BTreeMap<String, QueryTotals>,
+    /// Present with `?by=node` or `?by=region`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<StatsGroup>>,
+}
+
+/// Adds up `reports`, and splits them by node or region if asked to.
+pub fn aggregate(reports: &[NodeReport], by: Option<Breakdown>) -> FleetStats {
+    let mut totals = QueryTotals::default();
+    let mut transports: BTreeMap<String, QueryTotals> = BTreeMap::new();
+    for report in reports {
+        totals.add(report);
+        transports.entry(report.transport.clone()).or_default().add(report);
+    }
+    let mut nodes: Vec<&str> = reports.iter().map(|report| report.node_id.as_str()).collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+
+    FleetStats {
+        nodes: nodes.len(),
+        totals,
+        transports,
+        groups: by.map(|by| group(reports, by)),
+    }
+}
+
+fn group(reports: &[NodeReport], by: Breakdown) -> Vec<StatsGroup> {
+    let mut groups: BTreeMap<String, StatsGroup> = BTreeMap::new();
+    for report in reports {
+        let key = match by {
+            Breakdown::Node => report.node_id.clone(),
+            Breakdown::Region => report.region.clone().unwrap_or_else(|| NO_REGION.to_string()),
+        };
+        let group = groups.entry(key.clone()).or_insert_with(|| StatsGroup {
+            key,
+            nodes: Vec::new(),
+            since: report.started_at,
+            reported_at: report.reported_at,
+            totals: QueryTotals::default(),
+            transports: BTreeMap::new(),
+        });
+        if !group.nodes.contains(&report.node_id) {
+            group.nodes.push(report.node_id.clone());
+        }
+        group.since = group.since.max(report.started_at);
+        group.reported_at = group.reported_at.min(report.reported_at);
+        group.totals.add(report);
+        group.transports.entry(report.transport.clone()).or_default().add(report);
+    }
+    groups.into_values().collect()
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::config::DnsConfig;
+use crate::anomaly::QuerySpike;
+use crate::ct_monitor::CtFinding;
+use crate::database::DomainEvent;
+use crate::domain_manager::PartialDelegation;
+use crate::transfer::{DomainTransfer, TransferStatus};
+use crate::webhook_signature::signature_headers;
+
+/// First retry delay; each further failure doubles it up to `MAX_BACKOFF`.
+const BASE_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Generic JSON webhook, POSTed the event as-is.
+    Webhook,
+    /// Discord webhook, POSTed a `{"content": ...}` message.
+    Discord,
+    /// Supabase row of the domain in `target`, brought in line with the internal database.
+    Supabase,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Webhook => "webhook",
+            Channel::Discord => "discord",
+            Channel::Supabase => "supabase",
+        }
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "webhook" => Ok(Channel::Webhook),
+            "discord" => Ok(Channel::Discord),
+            "supabase" => Ok(Channel::Supabase),
+            other => Err(anyhow::anyhow!("Unknown notification channel: {}", other)),
+        }
+    }
+}
+
+/// A queued notification as stored in the `outbox` table.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub channel: Channel,
+    pub target: String,
+    pub payload: Value,
+    pub event_id: Option<i64>,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// One notification for every configured target, each already in its channel's format.
+pub fn domain_event_notifications(config: &DnsConfig, event: &DomainEvent) -> Vec<(Channel, String, Value)> {
+    let mut notifications: Vec<(Channel, String, Value)> = config.webhook_urls.iter()
+        .map(|url| (Channel::Webhook, url.clone(), json!({"type": "domain_event", "event": event})))
+        .collect();
+    
+    if let Some(url) = &config.discord_webhook_url {
+        let content = format!(
+            "Domain `{}` {} (enabled: {}, verified: {})",
+            event.domain, event.event_type, event.enabled, event.verified
+        );
+        notifications.push((Channel::Discord, url.clone(), json!({"content": content})));
+    }
+    notifications
+}
+
+/// Like `domain_event_notifications`, for a query volume spike.
+pub fn query_spike_notifications(config: &DnsConfig, spike: &QuerySpike) -> Vec<(Channel, String, Value)> {
+    let mut notifications: Vec<(Channel, String, Value)> = config.webhook_urls.iter()
+        .map(|url| (Channel::Webhook, url.clone(), json!({"type": "query_spike", "spike": spike})))
+        .collect();
+    
+    if let Some(url) = &config.discord_webhook_url {
+        let content = format!(
+            "Query spike on `{}`: {:.0} queries/min against a baseline of {:.1}",
+            spike.domain, spike.queries_per_minute, spike.baseline_per_minute
+        );
+        notifications.push((Channel::Discord, url.clone(), json!({"content": content})));
+    }
+    notifications
+}
+
+/// Like `domain_event_notifications`, for a domain delegated to only some of our nameservers.
+pub fn partial_delegation_notifications(config: &DnsConfig, partial: &PartialDelegation) -> Vec<(Channel, String, Value)> {
+    let mut notifications: Vec<(Channel, String, Value)> = config.webhook_urls.iter()
+        .map(|url| (Channel::Webhook, url.clone(), json!({"type": "partial_delegation", "delegation": partial})))
+        .collect();
+    
+    if let Some(url) = &config.discord_webhook_url {
+        let content = format!(
+            "Domain `{}` is only partially delegated: {} missing from its NS records, so lookups will fail intermittently",
+            partial.domain, partial.missing.join(", ")
+        );
+        notifications.push((Channel::Discord, url.clone(), json!({"content": content})));
+    }
+    notifications
+}
+
+/// Like `domain_event_notifications`, for a domain changing owner or a claim token being
+/// issued. Webhooks get both users' emails so the receiver can write to each of them; the
+/// claim token is never included.
+pub fn domain_transfer_notifications(
+    config: &DnsConfig,
+    transfer: &DomainTransfer,
+    from_email: Option<&str>,
+    to_email: Option<&str>,
+) -> Vec<(Channel, String, Value)> {
+    let mut notifications: Vec<(Channel, String, Value)> = config.webhook_urls.iter()
+        .map(|url| (Channel::Webhook, url.clone(), json!({
+            "type": "domain_transfer",
+            "transfer": transfer,
+            "from_email": from_email,
+            "to_email": to_email,
+        })))
+        .collect();
+    
+    if let Some(url) = &config.discord_webhook_url {
+        let from = from_email.unwrap_or(&transfer.from_user);
+        let content = match (transfer.status, transfer.to_user.as_deref()) {
+            (TransferStatus::Pending, _) => format!("Domain `{}` is up for transfer by {}, waiting to be claimed", transfer.domain, from),
+            (TransferStatus::Cancelled, _) => format!("Transfer of domain `{}` by {} was cancelled", transfer.domain, from),
+            (TransferStatus::Completed, to_user) => format!(
+                "Domain `{}` transferred from {} to {}",
+                transfer.domain, from, to_email.or(to_user).unwrap_or("unknown user")
+            ),
+        };
+        notifications.push((Channel::Discord, url.clone(), json!({"content": content})));
+    }
+    notifications
+}
+
+/// Like `domain_event_notifications`, for a certificate from an issuer the domain does not
+/// expect. Webhooks get the owner's email when Supabase knows it.
+pub fn ct_finding_notifications(config: &DnsConfig, finding: &CtFinding, owner_email: Option<&str>) -> Vec<(Channel, String, Value)> {
+    let mut notifications: Vec<(Channel, String, Value)> = config.webhook_urls.iter()
+        .map(|url| (Channel::Webhook, url.clone(), json!({
+            "type": "unexpected_certificate",
+            "certificate": finding,
+            "owner_email": owner_email,
+        })))
+        .collect();
+    
+    if let Some(url) = &config.discord_webhook_url {
+        let content = format!(
+            "Unexpected certificate for `{}` in CT logs: issued by {} for {} (crt.sh ID {})",
+            finding.domain, finding.issuer, finding.names.join(", "), finding.cert_id
+        );
+        notifications.push((Channel::Discord, url.clone(), json!({"content": content})));
+    }
+    notifications
+}
+
+/// Delay before the next attempt after `attempts` failed deliveries.
+pub fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    BASE_BACKOFF.saturating_mul(2u32.saturating_pow(exponent)).min(MAX_BACKOFF)
+}
+
+/// POSTs the stored payload; anything but a 2xx counts as a failed attempt. Webhooks are
+/// signed with `secret` when one is configured, Discord messages never are.
+pub async fn deliver(client: &reqwest::Client, message: &OutboxMessage, secret: Option<&str>) -> Result<()> {
+    let body = serde_json::to_vec(&message.payload)?;
+    let mut request = client
+        .post(&message.target)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let (Channel::Webhook, Some(secret)) = (message.channel, secret) {
+        for (name, value) in signature_headers(secret, &body) {
+            request = request.header(name, value);
+        }
+    }
+    let response = request.body(body).send().await?;
+    
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("{} returned {}", message.channel.as_str(), response.status()));
+    }
+    Ok(())
+}
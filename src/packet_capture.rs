@@ -0,0 +1,322 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+use trust_dns_proto::op::Message;
+use trust_dns_proto::serialize::binary::BinDecodable;
+
+/// Longest capture the API will start.
+pub const MAX_CAPTURE_DURATION: Duration = Duration::from_secs(3600);
+/// Captures stop on their own after this many packets.
+const MAX_CAPTURE_PACKETS: u64 = 1_000_000;
+/// LINKTYPE_RAW: each packet starts with a bare IPv4/IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureFormat {
+    Pcap,
+    Hex,
+}
+
+/// Body of `POST /debug/capture`.
+#[derive(Debug, Deserialize)]
+pub struct CaptureRequest {
+    #[serde(default = "default_capture_seconds")]
+    pub duration_seconds: u64,
+    pub qname: Option<String>,
+    #[serde(default = "default_capture_format")]
+    pub format: CaptureFormat,
+}
+
+fn default_capture_seconds() -> u64 {
+    60
+}
+
+fn default_capture_format() -> CaptureFormat {
+    CaptureFormat::Pcap
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureStatus {
+    pub path: String,
+    pub format: CaptureFormat,
+    pub qname: Option<String>,
+    pub packets: u64,
+    pub remaining_seconds: u64,
+}
+
+struct Session {
+    id: u64,
+    writer: BufWriter<File>,
+    path: PathBuf,
+    format: CaptureFormat,
+    qname: Option<String>,
+    ends: Instant,
+    packets: u64,
+}
+
+/// Writes raw query/response pairs to a pcap file or hex log for a bounded time.
+pub struct PacketCapture {
+    active: AtomicBool,
+    next_id: AtomicU64,
+    session: Mutex<Option<Session>>,
+}
+
+impl PacketCapture {
+    pub fn new() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            next_id: AtomicU64::new(1),
+            session: Mutex::new(None),
+        }
+    }
+    
+    /// Cheap check for the query path; only takes the lock while a capture runs.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+    
+    /// Starts a capture in `dir`, replacing any running one, and schedules its end.
+    pub fn start(
+        self: &Arc<Self>,
+        dir: &Path,
+        format: CaptureFormat,
+        qname: Option<String>,
+        duration: Duration,
+    ) -> Result<CaptureStatus> {
+        let duration = duration.min(MAX_CAPTURE_DURATION);
+        let extension = match format {
+            CaptureFormat::Pcap => "pcap",
+            CaptureFormat::Hex => "log",
+        };
+        
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("capture-{}.{}", Utc::now().format("%Y%m%dT%H%M%S"), extension));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        if format == CaptureFormat::Pcap {
+            write_pcap_header(&mut writer)?;
+        }
+        
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let session = Session {
+            id,
+            writer,
+            path,
+            format,
+            qname: qname.map(|q| q.trim_end_matches('.').to_lowercase()),
+            ends: Instant::now() + duration,
+            packets: 0,
+        };
+        let status = session.status();
+        
+        if let Some(previous) = self.session.lock().unwrap_or_else(PoisonError::into_inner).replace(session) {
+            finish(previous);
+        }
+        self.active.store(true, Ordering::Relaxed);
+        info!("Started packet capture to {} for {}s", status.path, duration.as_secs());
+        
+        let capture = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            capture.stop_session(Some(id));
+        });
+        
+        Ok(status)
+    }
+    
+    pub fn stop(&self) -> Option<CaptureStatus> {
+        self.stop_session(None)
+    }
+    
+    pub fn status(&self) -> Option<CaptureStatus> {
+        self.session.lock().unwrap_or_else(PoisonError::into_inner).as_ref().map(Session::status)
+    }
+    
+    /// Ends the running capture, or only the one with `id` so a stale timer cannot stop a newer capture.
+    fn stop_session(&self, id: Option<u64>) -> Option<CaptureStatus> {
+        let mut slot = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+        if id.is_some() && slot.as_ref().map(|s| s.id) != id {
+            return None;
+        }
+        
+        let session = slot.take()?;
+        self.active.store(false, Ordering::Relaxed);
+        let status = session.status();
+        finish(session);
+        Some(status)
+    }
+    
+    /// Records one query/response exchange seen on `server`.
+    pub fn record(&self, client: SocketAddr, server: SocketAddr, query: &[u8], response: &[u8]) {
+        if !self.is_active() {
+            return;
+        }
+        
+        let mut slot = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(session) = slot.as_mut() else { return };
+        
+        if Instant::now() >= session.ends || session.packets >= MAX_CAPTURE_PACKETS {
+            let session = slot.take().unwrap();
+            self.active.store(false, Ordering::Relaxed);
+            finish(session);
+            return;
+        }
+        
+        if let Some(filter) = &session.qname {
+            if !matches_qname(query, filter) {
+                return;
+            }
+        }
+        
+        let result = match session.format {
+            CaptureFormat::Pcap => write_pcap_packet(&mut session.writer, client, server, query)
+                .and_then(|_| write_pcap_packet(&mut session.writer, server, client, response)),
+            CaptureFormat::Hex => write_hex_line(&mut session.writer, "query", client, server, query)
+                .and_then(|_| write_hex_line(&mut session.writer, "response", server, client, response)),
+        };
+        
+        match result {
+            Ok(()) => session.packets += 2,
+            Err(e) => {
+                error!("Packet capture write failed, stopping: {}", e);
+                let session = slot.take().unwrap();
+                self.active.store(false, Ordering::Relaxed);
+                finish(session);
+            }
+        }
+    }
+}
+
+impl Default for PacketCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    fn status(&self) -> CaptureStatus {
+        CaptureStatus {
+            path: self.path.display().to_string(),
+            format: self.format,
+            qname: self.qname.clone(),
+            packets: self.packets,
+            remaining_seconds: self.ends.saturating_duration_since(Instant::now()).as_secs(),
+        }
+    }
+}
+
+fn finish(mut session: Session) {
+    if let Err(e) = session.writer.flush() {
+        error!("Failed to flush packet capture {}: {}", session.path.display(), e);
+    }
+    info!("Packet capture {} finished ({} packets)", session.path.display(), session.packets);
+}
+
+/// True when the first question is `filter` or a name below it.
+fn matches_qname(query: &[u8], filter: &str) -> bool {
+    let Ok(message) = Message::from_bytes(query) else { return false };
+    let Some(question) = message.queries().first() else { return false };
+    
+    let qname = question.name().to_ascii().trim_end_matches('.').to_lowercase();
+    qname == filter || qname.ends_with(&format!(".{}", filter))
+}
+
+fn write_hex_line(writer: &mut impl Write, direction: &str, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> std::io::Result<()> {
+    let hex: String = payload.iter().map(|b| format!("{:02x}", b)).collect();
+    writeln!(writer, "{} {} {} -> {} len={} {}", Utc::now().to_rfc3339(), direction, src, dst, payload.len(), hex)
+}
+
+fn write_pcap_header(writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&4u16.to_le_bytes())?;
+    writer.write_all(&0i32.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&65535u32.to_le_bytes())?;
+    writer.write_all(&LINKTYPE_RAW.to_le_bytes())
+}
+
+/// Wraps a DNS payload in synthesized IP/UDP headers so Wireshark decodes it as DNS.
+fn write_pcap_packet(writer: &mut impl Write, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> std::io::Result<()> {
+    let packet = ip_udp_packet(src, dst, payload);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    
+    writer.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+    writer.write_all(&now.subsec_micros().to_le_bytes())?;
+    writer.write_all(&(packet.len() as u32).to_le_bytes())?;
+    writer.write_all(&(packet.len() as u32).to_le_bytes())?;
+    writer.write_all(&packet)
+}
+
+fn ip_udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    
+    let mut packet = Vec::with_capacity(40 + udp.len());
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let total_len = 20 + udp_len;
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            packet.extend_from_slice(&s.octets());
+            packet.extend_from_slice(&d.octets());
+            let checksum = internet_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            // A zero UDP checksum means "not computed" over IPv4
+        }
+        (s, d) => {
+            let s = to_v6(s);
+            let d = to_v6(d);
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            packet.extend_from_slice(&[17, 64]);
+            packet.extend_from_slice(&s);
+            packet.extend_from_slice(&d);
+            
+            let mut pseudo = Vec::with_capacity(40 + udp.len());
+            pseudo.extend_from_slice(&s);
+            pseudo.extend_from_slice(&d);
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 17]);
+            pseudo.extend_from_slice(&udp);
+            let checksum = match internet_checksum(&pseudo) {
+                0 => 0xffff,
+                c => c,
+            };
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+        }
+    }
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+fn to_v6(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+use crate::config::ParserMode;
+
+/// Size of the fixed DNS header (RFC 1035 §4.1.1).
+const HEADER_LEN: usize = 12;
+/// Queries carry a single question (RFC 9619); the handler answers FORMERR to any other count.
+const MAX_QUESTIONS: u16 = 1;
+/// Longest label, and longest name in wire format, allowed by RFC 1035 §2.3.4.
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+
+/// Why a packet was rejected, also the key of its counter in `GET /debug/runtime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rejection {
+    /// Shorter than the header, so there is no ID to answer to.
+    TooShort,
+    /// QR set: a response sent to us, never answered so two servers cannot bounce it back and forth.
+    Response,
+    TooManyQuestions,
+    UnexpectedAnswers,
+    LabelTooLong,
+    NameTooLong,
+    /// A compression pointer in a question name.
+    CompressedName,
+    /// The question section runs past the end of the packet.
+    Truncated,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::TooShort => "too_short",
+            Rejection::Response => "response",
+            Rejection::TooManyQuestions => "too_many_questions",
+            Rejection::UnexpectedAnswers => "unexpected_answers",
+            Rejection::LabelTooLong => "label_too_long",
+            Rejection::NameTooLong => "name_too_long",
+            Rejection::CompressedName => "compressed_name",
+            Rejection::Truncated => "truncated",
+        }
+    }
+
+    /// A header-only FORMERR reply echoing the packet's ID and opcode, or `None` when the
+    /// packet should be dropped without an answer.
+    pub fn response(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if matches!(self, Rejection::TooShort | Rejection::Response) {
+            return None;
+        }
+
+        let mut response = vec![0u8; HEADER_LEN];
+        response[..2].copy_from_slice(&data[..2]);
+        // QR plus the request's opcode and RD bit; RCODE 1 is FORMERR
+        response[2] = 0x80 | (data[2] & 0x79);
+        response[3] = 0x01;
+        Some(response)
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Checks the raw header and question section of incoming queries and counts what it rejects.
+pub struct PacketGuard {
+    mode: ParserMode,
+    rejected: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl PacketGuard {
+    pub fn new(mode: ParserMode) -> Self {
+        Self {
+            mode,
+            rejected: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Checks `data` in strict mode and counts the rejection if it fails. Lenient mode accepts everything.
+    pub fn check(&self, data: &[u8]) -> Result<(), Rejection> {
+        if self.mode == ParserMode::Lenient {
+            return Ok(());
+        }
+
+        let result = self.check_strict(data);
+        if let Err(rejection) = result {
+            *self.rejected.lock().unwrap_or_else(PoisonError::into_inner).entry(rejection.as_str()).or_insert(0) += 1;
+        }
+        result
+    }
+
+    /// Rejected packets per reason since startup.
+    pub fn rejected(&self) -> BTreeMap<&'static str, u64> {
+        self.rejected.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn check_strict(&self, data: &[u8]) -> Result<(), Rejection> {
+        if data.len() < HEADER_LEN {
+            return Err(Rejection::TooShort);
+        }
+        if data[2] & 0x80 != 0 {
+            return Err(Rejection::Response);
+        }
+
+        let count = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let questions = count(4);
+        if questions > MAX_QUESTIONS {
+            return Err(Rejection::TooManyQuestions);
+        }
+        if count(6) != 0 {
+            return Err(Rejection::UnexpectedAnswers);
+        }
+
+        let mut offset = HEADER_LEN;
+        for _ in 0..questions {
+            offset = check_name(data, offset)?;
+            // QTYPE and QCLASS
+            offset += 4;
+            if offset > data.len() {
+                return Err(Rejection::Truncated);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Walks the name starting at `offset` and returns the offset just past it. Clients send
+/// question names uncompressed, so compression pointers are rejected outright.
+fn check_name(data: &[u8], mut offset: usize) -> Result<usize, Rejection> {
+    let mut name_len = 0;
+    loop {
+        let &len = data.get(offset).ok_or(Rejection::Truncated)?;
+        let len = len as usize;
+        if len & 0xC0 == 0xC0 {
+            return Err(Rejection::CompressedName);
+        }
+        // Also catches the obsolete extended label types (0x40 and 0x80)
+        if len > MAX_LABEL_LEN {
+            return Err(Rejection::LabelTooLong);
+        }
+
+        // Wire length counts each label's length byte, including the root's
+        name_len += len + 1;
+        if name_len > MAX_NAME_LEN {
+            return Err(Rejection::NameTooLong);
+        }
+        offset += len + 1;
+        if len == 0 {
+            return Ok(offset);
+        }
+        if offset > data.len() {
+            return Err(Rejection::Truncated);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RData, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::database::ResolverCheck;
+use crate::domain_manager::VerificationStatus;
+use crate::resolvers::{check_delegation, delegates_to, NamedResolver};
+
+/// Parent zone servers asked directly; the rest of a large set (13 for `com.`) adds little.
+const MAX_PARENT_SERVERS: usize = 4;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VantageKind {
+    /// A public recursive resolver, which may still be serving a cached NS set.
+    Resolver,
+    /// An authoritative server of the parent zone, which sees a registrar change first.
+    Parent,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VantagePoint {
+    pub name: String,
+    pub kind: VantageKind,
+    /// Whether the NS set includes the expected nameservers; `None` when the lookup failed.
+    pub delegated: Option<bool>,
+    pub nameservers: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Which vantage points already see a domain delegated to us.
+#[derive(Debug, Serialize)]
+pub struct PropagationReport {
+    pub domain: String,
+    pub verification_status: VerificationStatus,
+    pub expected_nameservers: Vec<String>,
+    pub parent_zone: Option<String>,
+    /// Vantage points that see the expected nameservers, out of `total`.
+    pub propagated: usize,
+    pub total: usize,
+    pub vantage_points: Vec<VantagePoint>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Asks every resolver and a few of the parent zone's servers, all in parallel. The first
+/// resolver is also used to find the parent zone and its servers.
+pub async fn check_propagation(
+    domain: &str,
+    verification_status: VerificationStatus,
+    expected: Vec<String>,
+    resolvers: &[NamedResolver],
+    timeout: Duration,
+) -> PropagationReport {
+    let domain = domain.to_lowercase();
+    
+    let ((parent_zone, parent_points), checks) = tokio::join!(
+        check_parent(resolvers.first(), &domain, &expected, timeout),
+        check_delegation(resolvers, &domain, &expected),
+    );
+    
+    let vantage_points: Vec<VantagePoint> = checks.into_iter()
+        .map(|check| VantagePoint {
+            name: check.resolver,
+            kind: VantageKind::Resolver,
+            delegated: check.delegated,
+            nameservers: check.nameservers,
+            error: check.error,
+        })
+        .chain(parent_points)
+        .collect();
+    
+    PropagationReport {
+        domain,
+        verification_status,
+        expected_nameservers: expected,
+        parent_zone,
+        propagated: vantage_points.iter().filter(|v| v.delegated == Some(true)).count(),
+        total: vantage_points.len(),
+        vantage_points,
+        checked_at: Utc::now(),
+    }
+}
+
+/// Asks the parent zone's servers for the domain's delegation, one check per server. Used to
+/// verify subdomain-hosted domains, whose delegation lives in the customer's own zone. The
+/// first resolver is used to find the parent zone and its servers.
+pub async fn check_parent_delegation(
+    resolvers: &[NamedResolver],
+    domain: &str,
+    expected: &[String],
+    timeout: Duration,
+) -> Vec<ResolverCheck> {
+    let (_, points) = check_parent(resolvers.first(), domain, expected, timeout).await;
+    points.into_iter()
+        .map(|point| ResolverCheck {
+            resolver: point.name,
+            delegated: point.delegated,
+            nameservers: point.nameservers,
+            error: point.error,
+        })
+        .collect()
+}
+
+/// Finds the parent zone and asks its servers directly. A failure to find them is reported
+/// as a single `parent` vantage point carrying the error.
+async fn check_parent(
+    upstream: Option<&NamedResolver>,
+    domain: &str,
+    expected: &[String],
+    timeout: Duration,
+) -> (Option<String>, Vec<VantagePoint>) {
+    let parent = match upstream {
+        Some(upstream) => parent_servers(&upstream.resolver, domain).await,
+        None => Err(anyhow::anyhow!("No resolvers to find the parent zone with")),
+    };
+    let (zone, servers) = match parent {
+        Ok(parent) => parent,
+        Err(e) => {
+            let point = VantagePoint {
+                name: "parent".to_string(),
+                kind: VantageKind::Parent,
+                delegated: None,
+                nameservers: Vec::new(),
+                error: Some(e.to_string()),
+            };
+            return (None, vec![point]);
+        }
+    };
+    
+    let lookups: Vec<_> = servers.into_iter()
+        .map(|(name, ip)| {
+            let server = SocketAddr::new(ip, 53);
+            let domain = domain.to_string();
+            (name, tokio::spawn(async move { query_parent(server, &domain, timeout).await }))
+        })
+        .collect();
+    
+    let mut points = Vec::with_capacity(lookups.len());
+    for (name, lookup) in lookups {
+        let result = match lookup.await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        points.push(match result {
+            Ok(nameservers) => VantagePoint {
+                name,
+                kind: VantageKind::Parent,
+                delegated: Some(delegates_to(&nameservers, expected)),
+                nameservers,
+                error: None,
+            },
+            Err(e) => VantagePoint { name, kind: VantageKind::Parent, delegated: None, nameservers: Vec::new(), error: Some(e) },
+        });
+    }
+    
+    (Some(zone), points)
+}
+
+/// The closest enclosing zone with an NS set, and the IPv4 addresses of up to
+/// `MAX_PARENT_SERVERS` of its servers.
+async fn parent_servers(resolver: &TokioAsyncResolver, domain: &str) -> Result<(String, Vec<(String, IpAddr)>)> {
+    let mut zone = Name::from_ascii(domain)?.base_name();
+    zone.set_fqdn(true);
+    
+    let mut names: Vec<String> = loop {
+        match resolver.lookup(zone.clone(), RecordType::NS).await {
+            Ok(lookup) => break lookup.iter()
+                .filter_map(|r| r.as_ns().map(|ns| ns.to_string()))
+                .collect(),
+            // No NS set here, so this is not a zone cut; try the next label up
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) && !zone.is_root() => {
+                zone = zone.base_name();
+            }
+            Err(e) => return Err(anyhow::anyhow!("Failed to find the parent zone of {}: {}", domain, e)),
+        }
+    };
+    names.sort();
+    
+    let mut servers = Vec::new();
+    for name in names {
+        if servers.len() == MAX_PARENT_SERVERS {
+            break;
+        }
+        if let Ok(lookup) = resolver.lookup_ip(name.as_str()).await {
+            if let Some(ip) = lookup.iter().find(IpAddr::is_ipv4) {
+                servers.push((name, ip));
+            }
+        }
+    }
+    if servers.is_empty() {
+        return Err(anyhow::anyhow!("No reachable servers for parent zone {}", zone));
+    }
+    
+    Ok((zone.to_string(), servers))
+}
+
+/// Sends a non-recursive NS query to a parent server and returns the NS set from its
+/// referral (or answer). `NXDOMAIN` means the parent has no delegation yet.
+async fn query_parent(server: SocketAddr, domain: &str, timeout: Duration) -> Result<Vec<String>> {
+    let mut name = Name::from_ascii(domain)?;
+    name.set_fqdn(true);
+    
+    let mut request = Message::new();
+    request.set_id(Utc::now().timestamp_subsec_nanos() as u16);
+    request.set_message_type(MessageType::Query);
+    request.set_op_code(OpCode::Query);
+    request.add_query(Query::query(name.clone(), RecordType::NS));
+    
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.send_to(&request.to_bytes()?, server).await?;
+    
+    let mut buf = [0u8; 4096];
+    let (len, _) = tokio::time::timeout(timeout, socket.recv_from(&mut buf)).await
+        .map_err(|_| anyhow::anyhow!("request timed out"))??;
+    let response = Message::from_bytes(&buf[..len])?;
+    
+    if response.id() != request.id() {
+        return Err(anyhow::anyhow!("response id {} does not match query id {}", response.id(), request.id()));
+    }
+    match response.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => return Ok(Vec::new()),
+        code => return Err(anyhow::anyhow!("parent server answered {}", code)),
+    }
+    
+    Ok(response.answers().iter()
+        .chain(response.name_servers())
+        .filter(|r| r.name() == &name)
+        .filter_map(|r| match r.data() {
+            Some(RData::NS(ns)) => Some(ns.to_string()),
+            _ => None,
+        })
+        .collect())
+}
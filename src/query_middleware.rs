@@ -0,0 +1,134 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Message, Query, ResponseCode};
+
+use crate::extended_error::{ExtendedError, InfoCode};
+
+/// The question being answered and who asked it; `client` is `None` for internal lookups
+/// such as `/resolve` and the consistency check.
+pub struct QueryContext<'a> {
+    pub query: &'a Query,
+    pub client: Option<IpAddr>,
+}
+
+/// Compile-time hooks around answering each question, registered with
+/// `CybertempHandler::with_middleware`. `before` hooks run in registration order and `after`
+/// hooks in reverse, so the first middleware registered wraps all the others.
+pub trait QueryMiddleware: Send + Sync {
+    /// Runs before the cache and the zone lookup. Returning a response code answers with it
+    /// and no records, skipping the lookup and the remaining `before` hooks.
+    fn before(&self, _context: &QueryContext) -> Option<ResponseCode> {
+        None
+    }
+
+    /// Why `before` answered with a response code, sent to clients that support Extended DNS Errors.
+    fn extended_error(&self) -> Option<ExtendedError> {
+        None
+    }
+
+    /// Runs on the finished response, including one cut short by a `before` hook.
+    fn after(&self, _context: &QueryContext, _response: &mut Message) {}
+}
+
+/// Logs each question and its outcome at debug level; every handler starts with it.
+pub struct QueryLog;
+
+impl QueryMiddleware for QueryLog {
+    fn before(&self, context: &QueryContext) -> Option<ResponseCode> {
+        tracing::debug!("DNS query: {} type: {:?}", context.query.name(), context.query.query_type());
+        None
+    }
+
+    fn after(&self, context: &QueryContext, response: &mut Message) {
+        tracing::debug!(
+            "DNS answer: {} type: {:?}: {:?}, {} records",
+            context.query.name(),
+            context.query.query_type(),
+            response.response_code(),
+            response.answers().len()
+        );
+    }
+}
+
+/// Independently locked parts of the per-client windows, so queries from different clients
+/// rarely wait on each other.
+const RATE_LIMIT_SHARDS: usize = 64;
+/// Clients tracked per shard. A client arriving at a full shard is not limited until the
+/// next sweep frees room, which keeps memory bounded when source addresses are spoofed.
+const MAX_CLIENTS_PER_SHARD: usize = 4_096;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Refuses clients asking more than a fixed number of questions per minute. Internal lookups
+/// are never limited. Counting is O(1) per question; windows that ended are dropped by
+/// `sweep`, which the server runs from a background job.
+pub struct ClientRateLimit {
+    limit_per_minute: u32,
+    hasher: RandomState,
+    shards: Vec<Mutex<HashMap<IpAddr, (Instant, u32)>>>,
+}
+
+impl ClientRateLimit {
+    pub fn new(limit_per_minute: u32) -> Self {
+        Self {
+            limit_per_minute,
+            hasher: RandomState::new(),
+            shards: (0..RATE_LIMIT_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, client: &IpAddr) -> &Mutex<HashMap<IpAddr, (Instant, u32)>> {
+        let index = self.hasher.hash_one(client) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    /// Counts a question from `client`; false once it is over its limit for this minute.
+    fn allow(&self, client: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut windows = self.shard(&client).lock().unwrap_or_else(PoisonError::into_inner);
+        if windows.len() >= MAX_CLIENTS_PER_SHARD && !windows.contains_key(&client) {
+            return true;
+        }
+
+        let (start, count) = windows.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_LIMIT_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit_per_minute {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Drops the windows that ended, one shard at a time. Returns how many clients are still tracked.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        self.shards.iter()
+            .map(|shard| {
+                let mut windows = shard.lock().unwrap_or_else(PoisonError::into_inner);
+                windows.retain(|_, (start, _)| now.duration_since(*start) < RATE_LIMIT_WINDOW);
+                windows.len()
+            })
+            .sum()
+    }
+}
+
+impl QueryMiddleware for ClientRateLimit {
+    fn before(&self, context: &QueryContext) -> Option<ResponseCode> {
+        let client = context.client?;
+        if self.limit_per_minute == 0 || self.allow(client) {
+            None
+        } else {
+            Some(ResponseCode::Refused)
+        }
+    }
+
+    fn extended_error(&self) -> Option<ExtendedError> {
+        Some(ExtendedError::new(InfoCode::Other, "rate limited"))
+    }
+}
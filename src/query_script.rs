@@ -0,0 +1,179 @@
+use anyhow::Result;
+use trust_dns_proto::op::ResponseCode;
+
+/// What the handler passes to the script for each answered query.
+pub struct ScriptQuery {
+    pub qname: String,
+    pub qtype: String,
+    pub client: String,
+    /// The managed domain the name falls under, if any.
+    pub domain: Option<String>,
+    pub rcode: String,
+    pub answers: Vec<ScriptAnswer>,
+}
+
+/// One answer record in the plain form scripts read and write.
+#[derive(Debug, Clone)]
+pub struct ScriptAnswer {
+    /// Defaults to the query name.
+    pub name: Option<String>,
+    pub record_type: String,
+    /// Presentation form, e.g. `192.0.2.1`; for MX only the host, with the preference in `priority`.
+    pub value: String,
+    pub ttl: Option<u32>,
+    pub priority: u16,
+}
+
+/// The script's decision for one query.
+pub enum ScriptVerdict {
+    /// Returned `nil`: answer as synthesized.
+    Keep,
+    /// Returned `false`: drop every answer.
+    Suppress,
+    /// Returned a table: replace the answers and/or the response code.
+    Replace {
+        answers: Option<Vec<ScriptAnswer>>,
+        rcode: Option<String>,
+    },
+}
+
+/// Response codes as scripts see and set them.
+const RCODES: &[(&str, ResponseCode)] = &[
+    ("NOERROR", ResponseCode::NoError),
+    ("FORMERR", ResponseCode::FormErr),
+    ("SERVFAIL", ResponseCode::ServFail),
+    ("NXDOMAIN", ResponseCode::NXDomain),
+    ("NOTIMP", ResponseCode::NotImp),
+    ("REFUSED", ResponseCode::Refused),
+];
+
+/// `code`'s mnemonic, e.g. `NXDOMAIN`, or its number for codes scripts cannot set.
+pub fn rcode_name(code: ResponseCode) -> String {
+    RCODES.iter()
+        .find(|(_, known)| *known == code)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| u16::from(code).to_string())
+}
+
+pub fn parse_rcode(name: &str) -> Option<ResponseCode> {
+    RCODES.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+/// A Lua script whose global `on_query(query)` function sees every answered query and can
+/// add, change or suppress its answers. Calls are serialized, so it should stay cheap, and one
+/// that runs past `SCRIPT_INSTRUCTION_BUDGET` fails.
+#[cfg(feature = "scripting")]
+pub struct QueryScript {
+    lua: std::sync::Mutex<mlua::Lua>,
+}
+
+/// Memory a script may allocate, so a runaway table cannot take the server down.
+#[cfg(feature = "scripting")]
+const SCRIPT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+/// Lua instructions one `on_query` call may run before it is stopped with an error, so a
+/// slow or looping script cannot hold the interpreter.
+#[cfg(feature = "scripting")]
+const SCRIPT_INSTRUCTION_BUDGET: u32 = 1_000_000;
+/// Instructions between budget checks.
+#[cfg(feature = "scripting")]
+const SCRIPT_HOOK_INTERVAL: u32 = 1_000;
+
+#[cfg(feature = "scripting")]
+impl QueryScript {
+    /// Runs the file once and checks that it defines `on_query`.
+    pub fn load(path: &str) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read query script {}: {}", path, e))?;
+        let lua = mlua::Lua::new();
+        lua.set_memory_limit(SCRIPT_MEMORY_LIMIT)?;
+        lua.set_app_data(ScriptBudget(SCRIPT_INSTRUCTION_BUDGET));
+        lua.set_hook(mlua::HookTriggers::new().every_nth_instruction(SCRIPT_HOOK_INTERVAL), |lua, _debug| {
+            let mut budget = lua.app_data_mut::<ScriptBudget>()
+                .ok_or_else(|| mlua::Error::runtime("query script budget missing"))?;
+            if budget.0 < SCRIPT_HOOK_INTERVAL {
+                return Err(mlua::Error::runtime(format!("on_query ran past {} instructions", SCRIPT_INSTRUCTION_BUDGET)));
+            }
+            budget.0 -= SCRIPT_HOOK_INTERVAL;
+            Ok(())
+        });
+        lua.load(source.as_str()).set_name(path).exec()?;
+        lua.globals()
+            .get::<_, mlua::Function>("on_query")
+            .map_err(|_| anyhow::anyhow!("Query script {} does not define on_query", path))?;
+
+        Ok(Self { lua: std::sync::Mutex::new(lua) })
+    }
+
+    pub fn run(&self, query: &ScriptQuery) -> Result<ScriptVerdict> {
+        let lua = self.lua.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        lua.set_app_data(ScriptBudget(SCRIPT_INSTRUCTION_BUDGET));
+        let on_query: mlua::Function = lua.globals().get("on_query")?;
+
+        let table = lua.create_table()?;
+        let answers = lua.create_table()?;
+        for (i, answer) in query.answers.iter().enumerate() {
+            let entry = lua.create_table()?;
+            entry.set("name", answer.name.as_deref())?;
+            entry.set("type", answer.record_type.as_str())?;
+            entry.set("value", answer.value.as_str())?;
+            entry.set("ttl", answer.ttl)?;
+            entry.set("priority", answer.priority)?;
+            answers.set(i + 1, entry)?;
+        }
+        table.set("qname", query.qname.as_str())?;
+        table.set("qtype", query.qtype.as_str())?;
+        table.set("client", query.client.as_str())?;
+        table.set("domain", query.domain.as_deref())?;
+        table.set("rcode", query.rcode.as_str())?;
+        table.set("answers", answers)?;
+
+        match on_query.call::<_, mlua::Value>(table)? {
+            mlua::Value::Nil => Ok(ScriptVerdict::Keep),
+            mlua::Value::Boolean(false) => Ok(ScriptVerdict::Suppress),
+            mlua::Value::Table(result) => {
+                let answers = match result.get::<_, Option<mlua::Table>>("answers")? {
+                    Some(list) => Some(
+                        list.sequence_values::<mlua::Table>()
+                            .map(|entry| read_answer(&entry?))
+                            .collect::<Result<Vec<_>>>()?,
+                    ),
+                    None => None,
+                };
+                let rcode = result.get::<_, Option<String>>("rcode")?;
+                Ok(ScriptVerdict::Replace { answers, rcode })
+            }
+            other => Err(anyhow::anyhow!("on_query returned a {}, expected nil, false or a table", other.type_name())),
+        }
+    }
+}
+
+/// Instructions the current `on_query` call has left, kept in the interpreter's app data.
+#[cfg(feature = "scripting")]
+struct ScriptBudget(u32);
+
+#[cfg(feature = "scripting")]
+fn read_answer(entry: &mlua::Table) -> Result<ScriptAnswer> {
+    Ok(ScriptAnswer {
+        name: entry.get("name")?,
+        record_type: entry.get::<_, String>("type")?.to_uppercase(),
+        value: entry.get("value")?,
+        ttl: entry.get("ttl")?,
+        priority: entry.get::<_, Option<u16>>("priority")?.unwrap_or(0),
+    })
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct QueryScript;
+
+#[cfg(not(feature = "scripting"))]
+impl QueryScript {
+    pub fn load(_path: &str) -> Result<Self> {
+        Err(anyhow::anyhow!("Query scripts need a build with --features scripting"))
+    }
+
+    pub fn run(&self, _query: &ScriptQuery) -> Result<ScriptVerdict> {
+        Ok(ScriptVerdict::Keep)
+    }
+}
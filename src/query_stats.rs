@@ -0,0 +1,236 @@
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of one-minute buckets kept, i.e. the longest window `/stats/top` can report on.
+const BUCKETS: usize = 60;
+/// Keys tracked per sketch per bucket; heavier hitters than this are never evicted.
+const SKETCH_CAPACITY: usize = 200;
+/// HyperLogLog precision (2^12 registers, ~1.6% standard error).
+const HLL_PRECISION: u32 = 12;
+
+/// Space-Saving heavy-hitter sketch: bounded memory, never under-counts a tracked key.
+#[derive(Clone, Default)]
+struct SpaceSaving {
+    counts: HashMap<String, u64>,
+}
+
+impl SpaceSaving {
+    fn insert(&mut self, key: &str) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        
+        if self.counts.len() < SKETCH_CAPACITY {
+            self.counts.insert(key.to_string(), 1);
+            return;
+        }
+        
+        // Replace the smallest counter, inheriting its count as the error bound
+        let (min_key, min_count) = self.counts.iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(k, c)| (k.clone(), *c))
+            .unwrap();
+        self.counts.remove(&min_key);
+        self.counts.insert(key.to_string(), min_count + 1);
+    }
+}
+
+#[derive(Clone)]
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self { registers: vec![0; 1 << HLL_PRECISION] }
+    }
+    
+    fn insert<T: Hash>(&mut self, value: &T) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+    
+    fn merge(&mut self, other: &HyperLogLog) {
+        for (a, b) in self.registers.iter_mut().zip(&other.registers) {
+            *a = (*a).max(*b);
+        }
+    }
+    
+    fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        
+        // Small-range correction (linear counting)
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()) as u64
+        } else {
+            estimate as u64
+        }
+    }
+}
+
+struct Bucket {
+    minute: u64,
+    queries: u64,
+    clients: SpaceSaving,
+    qnames: SpaceSaving,
+    nxdomain: SpaceSaving,
+    unique_clients: HyperLogLog,
+}
+
+impl Bucket {
+    fn new(minute: u64) -> Self {
+        Self {
+            minute,
+            queries: 0,
+            clients: SpaceSaving::default(),
+            qnames: SpaceSaving::default(),
+            nxdomain: SpaceSaving::default(),
+            unique_clients: HyperLogLog::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopEntry {
+    pub key: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopStats {
+    pub window_seconds: u64,
+    pub total_queries: u64,
+    pub unique_clients: u64,
+    pub top_client_subnets: Vec<TopEntry>,
+    pub top_qnames: Vec<TopEntry>,
+    /// Names answered with NXDOMAIN or an empty answer section
+    pub top_nxdomain: Vec<TopEntry>,
+}
+
+/// Rolling per-minute query sketches for live "who is hammering us" views.
+pub struct QueryStats {
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+impl QueryStats {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new(Vec::with_capacity(BUCKETS)),
+        }
+    }
+    
+    pub fn record(&self, client: IpAddr, qname: &str, negative: bool) {
+        let minute = current_minute();
+        let subnet = client_subnet(client);
+        let qname = qname.to_lowercase();
+        
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.last().map(|b| b.minute) != Some(minute) {
+            if buckets.len() >= BUCKETS {
+                buckets.remove(0);
+            }
+            buckets.push(Bucket::new(minute));
+        }
+        
+        let bucket = buckets.last_mut().unwrap();
+        bucket.queries += 1;
+        bucket.clients.insert(&subnet);
+        bucket.qnames.insert(&qname);
+        bucket.unique_clients.insert(&client);
+        if negative {
+            bucket.nxdomain.insert(&qname);
+        }
+    }
+    
+    /// Merges the buckets inside `window` and returns the `n` heaviest keys of each sketch.
+    pub fn top(&self, window: Duration, n: usize) -> TopStats {
+        let minutes = (window.as_secs() / 60).clamp(1, BUCKETS as u64);
+        let oldest = current_minute().saturating_sub(minutes - 1);
+        
+        let buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let recent: Vec<&Bucket> = buckets.iter().filter(|b| b.minute >= oldest).collect();
+        
+        let mut unique_clients = HyperLogLog::new();
+        for bucket in &recent {
+            unique_clients.merge(&bucket.unique_clients);
+        }
+        
+        TopStats {
+            window_seconds: minutes * 60,
+            total_queries: recent.iter().map(|b| b.queries).sum(),
+            unique_clients: unique_clients.estimate(),
+            top_client_subnets: merge_top(recent.iter().map(|b| &b.clients), n),
+            top_qnames: merge_top(recent.iter().map(|b| &b.qnames), n),
+            top_nxdomain: merge_top(recent.iter().map(|b| &b.nxdomain), n),
+        }
+    }
+}
+
+impl Default for QueryStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn merge_top<'a>(sketches: impl Iterator<Item = &'a SpaceSaving>, n: usize) -> Vec<TopEntry> {
+    let mut merged: HashMap<&str, u64> = HashMap::new();
+    for sketch in sketches {
+        for (key, count) in &sketch.counts {
+            *merged.entry(key.as_str()).or_insert(0) += count;
+        }
+    }
+    
+    let mut entries: Vec<TopEntry> = merged.into_iter()
+        .map(|(key, count)| TopEntry { key: key.to_string(), count })
+        .collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    entries.truncate(n);
+    entries
+}
+
+/// Groups clients by /24 (IPv4) or /48 (IPv6), the usual granularity for abuse blocking.
+fn client_subnet(client: IpAddr) -> String {
+    match client {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.0/24", o[0], o[1], o[2])
+        }
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+    }
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 60)
+        .unwrap_or(0)
+}
+
+/// Parses windows like `30s`, `5m` or `1h`.
+pub fn parse_window(window: &str) -> Option<Duration> {
+    let (value, unit) = window.split_at(window.find(|c: char| !c.is_ascii_digit())?);
+    let value: u64 = value.parse().ok()?;
+    match unit {
+        "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value * 60)),
+        "h" => Some(Duration::from_secs(value * 3600)),
+        _ => None,
+    }
+}
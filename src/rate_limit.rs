@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Fixed one-minute window request counter keyed by caller (API key, IP, ...).
+pub struct RateLimiter {
+    limit_per_minute: u32,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit_per_minute: u32) -> Self {
+        Self {
+            limit_per_minute,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    /// Counts a request for `key`. Returns how long to wait if the key is over its limit.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.limit_per_minute == 0 {
+            return Ok(());
+        }
+        
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        
+        // Keep the map from growing without bound
+        if windows.len() > 10_000 {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+        
+        let (start, count) = windows.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        
+        if *count >= self.limit_per_minute {
+            return Err(WINDOW - now.duration_since(*start));
+        }
+        
+        *count += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::validation::check_record_name;
+
+/// Record types a template may create. The pseudo-types behind BIMI/DMARC/NS overrides have their own endpoints.
+const TEMPLATE_RECORD_TYPES: &[&str] = &["A", "AAAA", "CNAME", "MX", "TXT", "SRV", "CAA"];
+
+/// A named set of records, e.g. "standard-mail", stamped onto domains with `{domain}`/`{ip}` filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordTemplate {
+    #[serde(default)]
+    pub name: String,
+    pub description: Option<String>,
+    pub records: Vec<TemplateRecord>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateRecord {
+    pub record_type: String,
+    pub name: String,
+    pub value: String,
+    #[serde(default = "default_template_ttl")]
+    pub ttl: i32,
+    #[serde(default)]
+    pub priority: i32,
+    /// Lets the record use a reserved label such as `mail` or `_mta-sts`.
+    #[serde(default)]
+    pub allow_reserved: bool,
+}
+
+fn default_template_ttl() -> i32 {
+    300
+}
+
+impl RecordTemplate {
+    /// Checks the template's shape, and that no record lands on a reserved label without opting in.
+    pub fn validate(&self, reserved_labels: &[String]) -> Result<()> {
+        if self.name.is_empty()
+            || self.name.len() > 64
+            || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::anyhow!("Template name must be 1-64 letters, digits, '-' or '_'"));
+        }
+        if self.records.is_empty() {
+            return Err(anyhow::anyhow!("Template must contain at least one record"));
+        }
+        
+        for record in &self.records {
+            if !TEMPLATE_RECORD_TYPES.contains(&record.record_type.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Unsupported template record type {}, expected one of {}",
+                    record.record_type,
+                    TEMPLATE_RECORD_TYPES.join(", ")
+                ));
+            }
+            if record.name.is_empty() || record.value.is_empty() {
+                return Err(anyhow::anyhow!("Template records need a name and a value"));
+            }
+            if record.ttl <= 0 {
+                return Err(anyhow::anyhow!("Template record TTL must be positive"));
+            }
+            if !record.allow_reserved {
+                check_record_name(&record.name, reserved_labels)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// The template's records for one domain, with placeholders substituted.
+    pub fn render(&self, domain: &str, ip: &str) -> Vec<TemplateRecord> {
+        let fill = |s: &str| s.replace("{domain}", domain).replace("{ip}", ip);
+        
+        self.records.iter().map(|record| TemplateRecord {
+            record_type: record.record_type.clone(),
+            name: fill(&record.name),
+            value: fill(&record.value),
+            ttl: record.ttl,
+            priority: record.priority,
+            allow_reserved: record.allow_reserved,
+        }).collect()
+    }
+}
+
+/// Outcome of re-applying a template to every domain that uses it.
+#[derive(Debug, Default, Serialize)]
+pub struct ReapplyReport {
+    pub applied: Vec<String>,
+    pub failed: BTreeMap<String, String>,
+}
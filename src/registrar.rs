@@ -0,0 +1,308 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long an RDAP answer is reused; registrar transfers are rare and the bootstrap servers rate limit.
+const RDAP_CACHE_TTL: Duration = Duration::from_secs(6 * 3600);
+const RDAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The registrar RDAP names for a domain.
+#[derive(Debug, Clone, Serialize)]
+pub struct Registrar {
+    pub name: String,
+    pub iana_id: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Step-by-step nameserver change instructions, as returned by `GET /domains/{domain}/instructions`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DelegationInstructions {
+    pub domain: String,
+    /// `None` when RDAP has no answer, e.g. for ccTLDs without an RDAP server.
+    pub registrar: Option<Registrar>,
+    /// Key of the matched registrar guide, or `generic`/`subdomain`.
+    pub guide: &'static str,
+    pub nameservers: Vec<String>,
+    pub steps: Vec<String>,
+    pub help_url: Option<&'static str>,
+    pub notes: Vec<String>,
+}
+
+/// A registrar whose dashboard we have written steps for.
+struct Guide {
+    key: &'static str,
+    /// Lowercase fragments of the RDAP registrar name.
+    matches: &'static [&'static str],
+    steps: &'static [&'static str],
+    /// The registrar's help centre.
+    help_url: &'static str,
+    notes: &'static [&'static str],
+}
+
+/// `{nameservers}` in a step is replaced with the expected nameservers.
+const GUIDES: &[Guide] = &[
+    Guide {
+        key: "godaddy",
+        matches: &["godaddy"],
+        steps: &[
+            "Sign in to GoDaddy and open My Products > Domains.",
+            "Select the domain, then open the DNS tab and choose Nameservers.",
+            "Click Change Nameservers and pick \"I'll use my own nameservers\".",
+            "Enter {nameservers}, one per field, and save.",
+        ],
+        help_url: "https://www.godaddy.com/help",
+        notes: &[],
+    },
+    Guide {
+        key: "namecheap",
+        matches: &["namecheap"],
+        steps: &[
+            "Sign in to Namecheap and open Domain List.",
+            "Click Manage next to the domain.",
+            "Under Nameservers, choose Custom DNS.",
+            "Enter {nameservers} and click the green checkmark to save.",
+        ],
+        help_url: "https://www.namecheap.com/support/knowledgebase/",
+        notes: &[],
+    },
+    Guide {
+        key: "cloudflare",
+        matches: &["cloudflare"],
+        steps: &[
+            "Cloudflare Registrar only allows Cloudflare's own nameservers.",
+            "Transfer the domain to another registrar, then set its nameservers to {nameservers} there.",
+        ],
+        help_url: "https://developers.cloudflare.com/registrar/",
+        notes: &["Domains registered with Cloudflare cannot be delegated elsewhere; a transfer out is required."],
+    },
+    Guide {
+        key: "porkbun",
+        matches: &["porkbun"],
+        steps: &[
+            "Sign in to Porkbun and open Domain Management.",
+            "Click Details next to the domain, then the edit icon next to Authoritative Nameservers.",
+            "Replace the listed nameservers with {nameservers}, one per line, and submit.",
+        ],
+        help_url: "https://kb.porkbun.com",
+        notes: &[],
+    },
+    Guide {
+        key: "squarespace",
+        matches: &["squarespace", "google llc"],
+        steps: &[
+            "Sign in to Squarespace Domains and select the domain.",
+            "Open DNS > Domain Nameservers.",
+            "Choose Use Custom Nameservers.",
+            "Enter {nameservers} and save.",
+        ],
+        help_url: "https://support.squarespace.com",
+        notes: &["Domains once registered with Google Domains are now managed by Squarespace."],
+    },
+    Guide {
+        key: "namecom",
+        matches: &["name.com"],
+        steps: &[
+            "Sign in to Name.com and open My Domains.",
+            "Select the domain and open Manage Nameservers.",
+            "Delete the default nameservers and add {nameservers}.",
+        ],
+        help_url: "https://www.name.com/support",
+        notes: &[],
+    },
+    Guide {
+        key: "gandi",
+        matches: &["gandi"],
+        steps: &[
+            "Sign in to Gandi and open Domain names.",
+            "Select the domain and open the Nameservers tab.",
+            "Click Change, choose External and enter {nameservers}.",
+        ],
+        help_url: "https://docs.gandi.net",
+        notes: &[],
+    },
+    Guide {
+        key: "ovh",
+        matches: &["ovh"],
+        steps: &[
+            "Sign in to the OVHcloud Control Panel and open Web Cloud > Domain names.",
+            "Select the domain and open the DNS servers tab.",
+            "Click Modify DNS servers, replace the entries with {nameservers} and apply.",
+        ],
+        help_url: "https://help.ovhcloud.com",
+        notes: &[],
+    },
+    Guide {
+        key: "ionos",
+        matches: &["ionos", "1&1"],
+        steps: &[
+            "Sign in to IONOS and open Domains & SSL.",
+            "Click the gear icon next to the domain and choose Adjust Nameservers.",
+            "Select \"Use custom name servers\" and enter {nameservers}.",
+        ],
+        help_url: "https://www.ionos.com/help",
+        notes: &[],
+    },
+    Guide {
+        key: "hostinger",
+        matches: &["hostinger"],
+        steps: &[
+            "Sign in to hPanel and open Domains.",
+            "Click Manage next to the domain, then DNS / Nameservers.",
+            "Click Change Nameservers, enter {nameservers} and save.",
+        ],
+        help_url: "https://support.hostinger.com",
+        notes: &[],
+    },
+    Guide {
+        key: "dynadot",
+        matches: &["dynadot"],
+        steps: &[
+            "Sign in to Dynadot and open My Domains > Manage Domains.",
+            "Select the domain and choose DNS Settings.",
+            "Pick Name Servers from the dropdown, enter {nameservers} and save.",
+        ],
+        help_url: "https://www.dynadot.com/community/help",
+        notes: &[],
+    },
+    Guide {
+        key: "networksolutions",
+        matches: &["network solutions"],
+        steps: &[
+            "Sign in to Network Solutions and open My Domain Names.",
+            "Select the domain and click Manage next to Nameservers.",
+            "Choose \"Point my domain to other name servers\", enter {nameservers} and save.",
+        ],
+        help_url: "https://www.networksolutions.com/support",
+        notes: &[],
+    },
+];
+
+const GENERIC_STEPS: &[&str] = &[
+    "Sign in to the website of the company where the domain is registered.",
+    "Find the domain's nameserver (sometimes \"DNS servers\") settings; this is not the same page as DNS records.",
+    "Choose custom nameservers and replace the existing ones with {nameservers}.",
+    "Save; the change usually shows up within an hour, but can take up to 48 hours.",
+];
+
+/// Looks registrars up over RDAP and keeps the answers for a while.
+pub struct RdapClient {
+    http: reqwest::Client,
+    /// RDAP bootstrap service, e.g. `https://rdap.org`, which redirects to the TLD's server.
+    base_url: String,
+    cache: Mutex<HashMap<String, (Instant, Option<Registrar>)>>,
+}
+
+impl RdapClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The domain's registrar, or `None` if RDAP does not name one. Fails only on network or
+    /// server errors, which are not cached.
+    pub async fn registrar(&self, domain: &str) -> Result<Option<Registrar>> {
+        let domain = domain.to_lowercase();
+        if let Some((fetched, registrar)) = self.cache.lock().unwrap_or_else(PoisonError::into_inner).get(&domain) {
+            if fetched.elapsed() < RDAP_CACHE_TTL {
+                return Ok(registrar.clone());
+            }
+        }
+
+        let response = self.http
+            .get(format!("{}/domain/{}", self.base_url, domain))
+            .header(reqwest::header::ACCEPT, "application/rdap+json")
+            .timeout(RDAP_TIMEOUT)
+            .send()
+            .await?;
+        let registrar = if response.status() == reqwest::StatusCode::NOT_FOUND {
+            None
+        } else if !response.status().is_success() {
+            return Err(anyhow::anyhow!("RDAP lookup of {} returned {}", domain, response.status()));
+        } else {
+            parse_registrar(&response.json::<Value>().await?)
+        };
+
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner).insert(domain, (Instant::now(), registrar.clone()));
+        Ok(registrar)
+    }
+}
+
+/// The entity with the `registrar` role in an RDAP domain response.
+fn parse_registrar(response: &Value) -> Option<Registrar> {
+    let entity = response["entities"].as_array()?.iter().find(|entity| {
+        entity["roles"].as_array().is_some_and(|roles| roles.iter().any(|role| role == "registrar"))
+    })?;
+
+    // vcardArray is ["vcard", [[name, params, type, value], ...]]
+    let vcard_field = |field: &str| -> Option<String> {
+        entity["vcardArray"][1].as_array()?.iter()
+            .find(|property| property[0] == field)
+            .and_then(|property| property[3].as_str())
+            .map(str::to_string)
+    };
+    let name = vcard_field("fn").or_else(|| entity["handle"].as_str().map(str::to_string))?;
+    let iana_id = entity["publicIds"].as_array()
+        .and_then(|ids| ids.iter().find(|id| id["type"] == "IANA Registrar ID"))
+        .and_then(|id| id["identifier"].as_str())
+        .map(str::to_string);
+
+    Some(Registrar { name, iana_id, url: vcard_field("url") })
+}
+
+/// Instructions for delegating `domain` to `nameservers`, tailored to the registrar when we
+/// have a guide for it. Subdomain-hosted domains are delegated in the parent zone instead.
+pub fn instructions(domain: &str, registrar: Option<Registrar>, nameservers: Vec<String>, subdomain: bool) -> DelegationInstructions {
+    let list = nameservers.join(", ");
+    let fill = |steps: &[&str]| -> Vec<String> { steps.iter().map(|step| step.replace("{nameservers}", &list)).collect() };
+
+    if subdomain {
+        let (label, parent) = domain.split_once('.').unwrap_or((domain, ""));
+        return DelegationInstructions {
+            domain: domain.to_string(),
+            registrar,
+            guide: "subdomain",
+            steps: vec![
+                format!("Sign in to the DNS provider that hosts {}.", parent),
+                format!("Add one NS record named {} for each of {}.", label, list),
+                format!("Remove any other records named {}, since they would shadow the delegation.", label),
+            ],
+            nameservers,
+            help_url: None,
+            notes: Vec::new(),
+        };
+    }
+
+    let guide = registrar.as_ref().and_then(|registrar| {
+        let name = registrar.name.to_lowercase();
+        GUIDES.iter().find(|guide| guide.matches.iter().any(|fragment| name.contains(fragment)))
+    });
+    match guide {
+        Some(guide) => DelegationInstructions {
+            domain: domain.to_string(),
+            registrar,
+            guide: guide.key,
+            steps: fill(guide.steps),
+            nameservers,
+            help_url: Some(guide.help_url),
+            notes: guide.notes.iter().map(|note| note.to_string()).collect(),
+        },
+        None => DelegationInstructions {
+            domain: domain.to_string(),
+            guide: "generic",
+            steps: fill(GENERIC_STEPS),
+            nameservers,
+            help_url: None,
+            notes: match &registrar {
+                Some(registrar) => vec![format!("The domain is registered with {}.", registrar.name)],
+                None => vec!["The registrar could not be looked up; check the domain's WHOIS record.".to_string()],
+            },
+            registrar,
+        },
+    }
+}
@@ -0,0 +1,32 @@
+use std::future::Future;
+use uuid::Uuid;
+
+/// Header an API request's ID is accepted in and returned in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longest inbound ID kept; longer ones are replaced with a fresh ID.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub fn new_request_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// An inbound `X-Request-Id`, if it is 1 to 128 visible ASCII characters, so IDs minted by the
+/// web backend carry through to our logs.
+pub fn accept_request_id(value: &str) -> Option<String> {
+    let valid = !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+/// The ID of the API request or job run the current task is working for.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` with `id` as its `current_request_id`. Tasks it spawns do not inherit it.
+pub async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+use trust_dns_proto::rr::RecordType;
+
+use crate::config::{DnsConfig, ResolverProtocol, UpstreamResolver};
+use crate::database::ResolverCheck;
+
+/// Well-known public resolvers used for propagation checks when `propagation_resolvers` is empty.
+const PUBLIC_RESOLVERS: &[(&str, &str)] = &[
+    ("google", "8.8.8.8"),
+    ("cloudflare", "1.1.1.1"),
+    ("quad9", "9.9.9.9"),
+    ("opendns", "208.67.222.222"),
+];
+
+/// An upstream resolver with the name its answers are reported under.
+#[derive(Clone)]
+pub struct NamedResolver {
+    pub name: String,
+    pub resolver: TokioAsyncResolver,
+}
+
+impl NamedResolver {
+    /// Google's public resolvers, used when `verification_resolvers` is empty.
+    pub fn google(opts: ResolverOpts) -> Self {
+        Self {
+            name: "google".to_string(),
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), opts),
+        }
+    }
+    
+    fn from_upstream(name: String, upstream: &UpstreamResolver, opts: ResolverOpts) -> Result<Self> {
+        let mut resolver_config = ResolverConfig::new();
+        for name_server in name_server_configs(upstream)? {
+            resolver_config.add_name_server(name_server);
+        }
+        Ok(Self { name, resolver: TokioAsyncResolver::tokio(resolver_config, opts) })
+    }
+}
+
+fn resolver_opts(config: &DnsConfig) -> ResolverOpts {
+    let mut opts = ResolverOpts::default();
+    opts.timeout = Duration::from_secs(config.verification_timeout_seconds);
+    opts.attempts = config.verification_attempts;
+    opts
+}
+
+fn upstream_name(upstream: &UpstreamResolver) -> String {
+    format!("{}://{}", upstream.protocol.as_str(), upstream.address)
+}
+
+/// One resolver per `verification_resolvers` entry, so each gives an independent answer
+/// instead of the lookups being spread across them.
+pub fn verification_resolvers(config: &DnsConfig) -> Result<Vec<NamedResolver>> {
+    let opts = resolver_opts(config);
+    if config.verification_resolvers.is_empty() {
+        return Ok(vec![NamedResolver::google(opts)]);
+    }
+    
+    config.verification_resolvers.iter()
+        .map(|upstream| NamedResolver::from_upstream(upstream_name(upstream), upstream, opts))
+        .collect()
+}
+
+/// One resolver per `propagation_resolvers` entry, or per well-known public resolver when empty.
+pub fn propagation_resolvers(config: &DnsConfig) -> Result<Vec<NamedResolver>> {
+    let opts = resolver_opts(config);
+    if config.propagation_resolvers.is_empty() {
+        return PUBLIC_RESOLVERS.iter()
+            .map(|(name, address)| {
+                let upstream = UpstreamResolver { address: address.to_string(), protocol: ResolverProtocol::Udp, tls_name: None };
+                NamedResolver::from_upstream(name.to_string(), &upstream, opts)
+            })
+            .collect();
+    }
+    
+    config.propagation_resolvers.iter()
+        .map(|upstream| NamedResolver::from_upstream(upstream_name(upstream), upstream, opts))
+        .collect()
+}
+
+/// Whether an observed NS set includes any of the expected nameservers.
+pub fn delegates_to(nameservers: &[String], expected: &[String]) -> bool {
+    nameservers.iter().any(|ns| {
+        expected.iter().any(|our_ns| ns.contains(our_ns))
+    })
+}
+
+/// The expected nameservers an observed NS set leaves out. Resolvers that are handed a set
+/// missing one of ours still fail over to the other, so `delegates_to` passes, but queries
+/// that land on the foreign one get its answers instead of ours.
+pub fn missing_nameservers(nameservers: &[String], expected: &[String]) -> Vec<String> {
+    expected.iter()
+        .filter(|our_ns| !nameservers.iter().any(|ns| ns.contains(our_ns.as_str())))
+        .cloned()
+        .collect()
+}
+
+/// Asks every resolver for the domain's NS set in parallel.
+pub async fn check_delegation(resolvers: &[NamedResolver], domain: &str, expected: &[String]) -> Vec<ResolverCheck> {
+    let lookups: Vec<_> = resolvers.iter()
+        .map(|upstream| {
+            let resolver = upstream.resolver.clone();
+            let domain = domain.to_string();
+            tokio::spawn(async move { resolver.lookup(domain, RecordType::NS).await })
+        })
+        .collect();
+    
+    let mut checks = Vec::with_capacity(lookups.len());
+    for (upstream, lookup) in resolvers.iter().zip(lookups) {
+        let result = match lookup.await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        
+        checks.push(match result {
+            Ok(ns_lookup) => {
+                let nameservers: Vec<String> = ns_lookup.iter()
+                    .filter_map(|r| r.as_ns().map(|ns| ns.to_string()))
+                    .collect();
+                let delegated = delegates_to(&nameservers, expected);
+                ResolverCheck { resolver: upstream.name.clone(), delegated: Some(delegated), nameservers, error: None }
+            }
+            Err(e) => ResolverCheck { resolver: upstream.name.clone(), delegated: None, nameservers: Vec::new(), error: Some(e) },
+        });
+    }
+    
+    checks
+}
+
+/// How many resolvers must agree before a verification check changes a domain's status:
+/// `verification_quorum`, or a majority of the resolvers when it is unset.
+pub fn verification_quorum(config: &DnsConfig) -> Result<usize> {
+    let resolvers = config.verification_resolvers.len().max(1);
+    match config.verification_quorum {
+        None => Ok(resolvers / 2 + 1),
+        Some(quorum) if quorum == 0 || quorum > resolvers => Err(anyhow::anyhow!(
+            "verification_quorum must be between 1 and the number of verification resolvers ({})", resolvers
+        )),
+        Some(quorum) => Ok(quorum),
+    }
+}
+
+fn name_server_configs(upstream: &UpstreamResolver) -> Result<Vec<NameServerConfig>> {
+    let default_port = match upstream.protocol {
+        ResolverProtocol::Udp | ResolverProtocol::Tcp => 53,
+        ResolverProtocol::Tls => 853,
+        ResolverProtocol::Https => 443,
+    };
+    let socket_addr = match upstream.address.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => {
+            let ip: IpAddr = upstream.address.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid verification resolver address: {}", upstream.address))?;
+            SocketAddr::new(ip, default_port)
+        }
+    };
+    
+    let protocols: &[Protocol] = match upstream.protocol {
+        ResolverProtocol::Udp => &[Protocol::Udp, Protocol::Tcp],
+        ResolverProtocol::Tcp => &[Protocol::Tcp],
+        ResolverProtocol::Tls => &[Protocol::Tls],
+        ResolverProtocol::Https => &[Protocol::Https],
+    };
+    if matches!(upstream.protocol, ResolverProtocol::Tls | ResolverProtocol::Https) && upstream.tls_name.is_none() {
+        return Err(anyhow::anyhow!("Verification resolver {} needs a tls_name", upstream.address));
+    }
+    
+    Ok(protocols.iter()
+        .map(|&protocol| NameServerConfig {
+            socket_addr,
+            protocol,
+            tls_dns_name: upstream.tls_name.clone(),
+            trust_negative_responses: true,
+            tls_config: None,
+            bind_addr: None,
+        })
+        .collect())
+}
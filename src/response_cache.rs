@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::ResponseCode;
+use trust_dns_proto::rr::{Record, RecordType};
+
+/// Inserting into a full cache drops its expired entries, and every entry if none had expired.
+const MAX_ENTRIES: usize = 100_000;
+
+#[derive(Clone)]
+pub struct CachedAnswer {
+    pub answers: Vec<Record>,
+    pub additionals: Vec<Record>,
+    pub response_code: ResponseCode,
+}
+
+struct Entry {
+    answer: CachedAnswer,
+    inserted: Instant,
+}
+
+/// Synthesized answers keyed by (lowercased qname, qtype), bounded by a short TTL so
+/// verification state changes that bypass invalidation still surface quickly.
+pub struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<(String, RecordType), Entry>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+    
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+    
+    pub fn get(&self, qname: &str, qtype: RecordType) -> Option<CachedAnswer> {
+        if !self.is_enabled() {
+            return None;
+        }
+        
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let key = (qname.to_lowercase(), qtype);
+        match entries.get(&key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.answer.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+    
+    pub fn insert(&self, qname: &str, qtype: RecordType, answer: CachedAnswer) {
+        if !self.is_enabled() {
+            return;
+        }
+        
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= MAX_ENTRIES {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        
+        entries.insert((qname.to_lowercase(), qtype), Entry {
+            answer,
+            inserted: Instant::now(),
+        });
+    }
+    
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Drops every entry. Returns how many were removed.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+    
+    /// Drops entries for `domain` and every name below it. Returns how many were removed.
+    pub fn invalidate_domain(&self, domain: &str) -> usize {
+        let domain = domain.to_lowercase();
+        let suffix = format!(".{}", domain);
+        
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let before = entries.len();
+        entries.retain(|(qname, _), _| qname != &domain && !qname.ends_with(&suffix));
+        before - entries.len()
+    }
+}
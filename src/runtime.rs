@@ -0,0 +1,65 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::db_breaker::BreakerStatus;
+use crate::domain_manager::ReconcileStats;
+use crate::jobs::JobStats;
+use crate::shadow::ShadowStatus;
+
+/// Memory and task counts for `GET /debug/runtime`, to tell memory growth and stuck loops apart.
+#[derive(Debug, Serialize)]
+pub struct RuntimeInfo {
+    /// Resident set size from `/proc/self/status`; `None` where that file does not exist.
+    pub rss_bytes: Option<u64>,
+    pub domains: usize,
+    pub records: usize,
+    pub response_cache_entries: usize,
+    /// Open 5-minute per-domain query count buckets not yet flushed to the stats history.
+    pub stats_buckets: usize,
+    /// Domains the query spike detector keeps a baseline for.
+    pub anomaly_baselines: usize,
+    /// Queries turned away by the strict parser since startup, per reason.
+    pub rejected_packets: BTreeMap<&'static str, u64>,
+    /// Drift the periodic reconcile found and corrected since startup.
+    pub reconcile: ReconcileStats,
+    /// The database breaker, including writes queued while it is open.
+    pub database: BreakerStatus,
+    /// Shadow mode comparisons, when `shadow_config_path` is set.
+    pub shadow: Option<ShadowStatus>,
+    pub tokio: TokioRuntime,
+    /// Each background job's counters, including when it last started and whether it is running.
+    pub jobs: Vec<JobStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokioRuntime {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks waiting in the shared run queue; a growing value means the workers cannot keep up.
+    pub global_queue_depth: usize,
+}
+
+impl TokioRuntime {
+    /// Counts for the runtime the caller runs on.
+    pub fn current() -> Self {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        Self {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+        }
+    }
+}
+
+/// The process's resident set size, read from the `VmRSS` line (in kB) of `/proc/self/status`.
+pub fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes: u64 = status.lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
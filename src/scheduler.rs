@@ -0,0 +1,180 @@
+use anyhow::Result;
+use hyper::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::canary::{validate_canaries, Canary};
+use crate::config::{DmarcPolicy, MailRouting, SubdomainPolicy};
+use crate::domain_manager::SERVED_RECORD_TYPES;
+use crate::synthetic::{validate_rules, SyntheticRule};
+use crate::validation::{check_ttl, parse_record_value, MAX_RECORD_SET_VALUES};
+
+/// A zone change that can be staged with `?effective_at=` and applied later by the scheduler.
+///
+/// Stored as JSON in `scheduled_changes.mutation`, tagged by `action`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ZoneMutation {
+    SetIp { ip: String },
+    Enable,
+    Disable,
+    SetBimi { logo_url: String, vmc_url: Option<String> },
+    ClearBimi,
+    SetDmarc(DmarcPolicy),
+    ClearDmarc,
+    SetMailRouting(MailRouting),
+    ClearMailRouting,
+    /// `None` clears the override.
+    SetTtl { ttl: Option<u32> },
+    SetNameservers { nameservers: Vec<String> },
+    ClearNameservers,
+    AssignNameserverSet { set: String },
+    ClearNameserverSet,
+    AssignRoutingProfile { profile: String },
+    ClearRoutingProfile,
+    SetSubdomainPolicy { policy: SubdomainPolicy },
+    ClearSubdomainPolicy,
+    SetSyntheticRules { rules: Vec<SyntheticRule> },
+    ClearSyntheticRules,
+    SetCanaries { canaries: Vec<Canary> },
+    ClearCanaries,
+    /// `PUT /domains/{domain}/records/{type}/{name}`.
+    SetRecords {
+        record_type: String,
+        name: String,
+        values: Vec<String>,
+        ttl: Option<u32>,
+        #[serde(default)]
+        allow_reserved: bool,
+    },
+    /// `PATCH /domains/{domain}/records/{type}/{name}`.
+    SetRecordTtl { record_type: String, name: String, ttl: u32 },
+    /// `DELETE /domains/{domain}/records/{type}/{name}`.
+    ClearRecords { record_type: String, name: String },
+}
+
+impl ZoneMutation {
+    /// Maps a mutating `/domains/{domain}/{resource}` request onto the mutation it would apply;
+    /// `resource` is empty for `/domains/{domain}` itself. The body is the same one the endpoint
+    /// takes when applied immediately.
+    pub fn from_request(method: &Method, resource: &str, body: &[u8]) -> Result<Self> {
+        // Record changes take the type and name from the path
+        let mut path_fields = serde_json::Map::new();
+        let action = if let Some(set) = resource.strip_prefix("records/") {
+            let (record_type, name) = set.split_once('/')
+                .filter(|(record_type, name)| !record_type.is_empty() && !name.is_empty() && !name.contains('/'))
+                .ok_or_else(|| anyhow::anyhow!("Record changes are scheduled at /records/{{type}}/{{name}}"))?;
+            path_fields.insert("record_type".to_string(), json!(record_type.to_uppercase()));
+            path_fields.insert("name".to_string(), json!(decode_segment(name)?));
+            match *method {
+                Method::PUT => "set_records",
+                Method::PATCH => "set_record_ttl",
+                Method::DELETE => "clear_records",
+                _ => return Err(anyhow::anyhow!("{} /{} cannot be scheduled", method, resource)),
+            }
+        } else {
+            Self::action(method, resource)?
+        };
+        
+        let mut data: serde_json::Value = if body.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_slice(body).map_err(|_| anyhow::anyhow!("Invalid JSON"))?
+        };
+        let object = data.as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("Request body must be a JSON object"))?;
+        object.extend(path_fields);
+        object.insert("action".to_string(), json!(action));
+        
+        serde_json::from_value(data).map_err(|e| anyhow::anyhow!("Invalid {} request: {}", action, e))
+    }
+    
+    /// The `action` tag of a request to a per-domain setting.
+    fn action(method: &Method, resource: &str) -> Result<&'static str> {
+        let action = match (method, resource) {
+            (&Method::PUT, "ip") => "set_ip",
+            (&Method::POST, "enable") => "enable",
+            (&Method::POST, "disable") => "disable",
+            (&Method::PUT, "bimi") => "set_bimi",
+            (&Method::DELETE, "bimi") => "clear_bimi",
+            (&Method::PUT, "dmarc") => "set_dmarc",
+            (&Method::DELETE, "dmarc") => "clear_dmarc",
+            (&Method::PUT, "mail-routing") => "set_mail_routing",
+            (&Method::DELETE, "mail-routing") => "clear_mail_routing",
+            (&Method::PATCH, "") => "set_ttl",
+            (&Method::PUT, "nameservers") => "set_nameservers",
+            (&Method::DELETE, "nameservers") => "clear_nameservers",
+            (&Method::PUT, "nameserver-set") => "assign_nameserver_set",
+            (&Method::DELETE, "nameserver-set") => "clear_nameserver_set",
+            (&Method::PUT, "routing-profile") => "assign_routing_profile",
+            (&Method::DELETE, "routing-profile") => "clear_routing_profile",
+            (&Method::PUT, "subdomains") => "set_subdomain_policy",
+            (&Method::DELETE, "subdomains") => "clear_subdomain_policy",
+            (&Method::PUT, "synthetic-records") => "set_synthetic_rules",
+            (&Method::DELETE, "synthetic-records") => "clear_synthetic_rules",
+            (&Method::PUT, "canaries") => "set_canaries",
+            (&Method::DELETE, "canaries") => "clear_canaries",
+            _ => return Err(anyhow::anyhow!("{} /{} cannot be scheduled", method, resource)),
+        };
+        Ok(action)
+    }
+    
+    /// Catches obviously bad input when the change is staged rather than hours later.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            ZoneMutation::SetIp { ip } => {
+                ip.parse::<std::net::Ipv4Addr>()
+                    .map_err(|_| anyhow::anyhow!("Invalid IPv4 address: {}", ip))?;
+            }
+            ZoneMutation::SetDmarc(policy) => policy.validate()?,
+            ZoneMutation::SetMailRouting(routing) => routing.validate()?,
+            ZoneMutation::SetTtl { ttl: Some(ttl) } => check_ttl(*ttl)?,
+            ZoneMutation::SetSyntheticRules { rules } => validate_rules(rules)?,
+            ZoneMutation::SetCanaries { canaries } => validate_canaries(canaries)?,
+            ZoneMutation::SetRecords { record_type, values, ttl, .. } => {
+                check_record_type(record_type)?;
+                if values.is_empty() || values.len() > MAX_RECORD_SET_VALUES {
+                    return Err(anyhow::anyhow!("{} record sets need between 1 and {} values", record_type, MAX_RECORD_SET_VALUES));
+                }
+                for value in values {
+                    parse_record_value(record_type, value)?;
+                }
+                if let Some(ttl) = ttl {
+                    check_ttl(*ttl)?;
+                }
+            }
+            ZoneMutation::SetRecordTtl { record_type, ttl, .. } => {
+                check_record_type(record_type)?;
+                check_ttl(*ttl)?;
+            }
+            ZoneMutation::ClearRecords { record_type, .. } => check_record_type(record_type)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn check_record_type(record_type: &str) -> Result<()> {
+    if !SERVED_RECORD_TYPES.contains(&record_type) {
+        return Err(anyhow::anyhow!("Only {} records can be set through the records API", SERVED_RECORD_TYPES.join(", ")));
+    }
+    Ok(())
+}
+
+/// Undoes the percent-encoding of a path segment, e.g. `%40` for `@`.
+fn decode_segment(segment: &str) -> Result<String> {
+    let invalid = || anyhow::anyhow!("Invalid percent-encoding in {}", segment);
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).ok_or_else(invalid)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::RwLock;
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RData, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+use crate::config::DnsConfig;
+use crate::dns_handler::CybertempHandler;
+use crate::dns_server::serve_udp;
+use crate::dns_stream::serve_tcp;
+use crate::domain_manager::DomainManager;
+use crate::domain_store::MemoryStore;
+use crate::response_cache::ResponseCache;
+
+const SELFTEST_DOMAIN: &str = "selftest.cybertemp.invalid";
+const SELFTEST_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+/// Stored record written through the domain manager and read back from the store.
+const SELFTEST_RECORD_NAME: &str = "www";
+const SELFTEST_RECORD_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 11);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Adds a synthetic domain and a record through the domain manager into a throwaway
+/// in-memory store, loads them back into a fresh manager as a restart would, then serves it
+/// on ephemeral loopback ports and checks real A/MX/TXT/NS answers. Needs no database or network.
+pub async fn run_selftest(config_path: &str, env: Option<&str>) -> Result<()> {
+    let config = DnsConfig::load(config_path, env)?;
+    let store = Arc::new(MemoryStore::new());
+    
+    let mut writer = DomainManager::new()
+        .with_nameservers(config.nameservers.clone(), config.nameserver_sets.clone())
+        .with_store(store.clone());
+    writer.add_domain(SELFTEST_DOMAIN, &SELFTEST_IP.to_string(), false).await?;
+    writer.set_records(SELFTEST_DOMAIN, "A", SELFTEST_RECORD_NAME, &[SELFTEST_RECORD_IP.to_string()], None, false, "selftest").await?;
+    println!("selftest: stored {} and {}.{}", SELFTEST_DOMAIN, SELFTEST_RECORD_NAME, SELFTEST_DOMAIN);
+    
+    let mut manager = DomainManager::new()
+        .with_nameservers(config.nameservers.clone(), config.nameserver_sets.clone())
+        .with_store(store);
+    manager.load_from_database().await?;
+    if manager.domain_count() != 1 {
+        return Err(anyhow::anyhow!("selftest failed: loaded {} domains from the store, expected 1", manager.domain_count()));
+    }
+    manager.mark_verified(SELFTEST_DOMAIN)?;
+    
+    let handler = CybertempHandler::new(config.clone(), Arc::new(RwLock::new(manager)))
+        .with_response_cache(Arc::new(ResponseCache::new(Duration::from_secs(config.response_cache_ttl_seconds))));
+    
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let server_addr = socket.local_addr()?;
+    let server = tokio::spawn(serve_udp(socket, handler.clone(), None));
+    println!("selftest: serving on udp://{}", server_addr);
+    
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let tcp_addr = listener.local_addr()?;
+    let tcp_server = tokio::spawn(serve_tcp(listener, handler, None, config.tcp_max_connections));
+    println!("selftest: serving on tcp://{}", tcp_addr);
+    
+    let client = UdpSocket::bind("127.0.0.1:0").await?;
+    let mail_server = crate::hostname_template::render(&config.mail_server, &config.template_context(), SELFTEST_DOMAIN);
+    let mut failures = 0;
+    
+    let checks: Vec<(&str, String, RecordType, Box<dyn Fn(&Message) -> Result<()>>)> = vec![
+        ("A", SELFTEST_DOMAIN.to_string(), RecordType::A, Box::new(|m: &Message| {
+            expect(m.answers().iter().any(|r| matches!(r.data(), Some(RData::A(a)) if a.0 == SELFTEST_IP)),
+                format!("no A record for {}", SELFTEST_IP))
+        })),
+        ("stored A", format!("{}.{}", SELFTEST_RECORD_NAME, SELFTEST_DOMAIN), RecordType::A, Box::new(|m: &Message| {
+            expect(m.answers().iter().any(|r| matches!(r.data(), Some(RData::A(a)) if a.0 == SELFTEST_RECORD_IP)),
+                format!("no stored A record for {}", SELFTEST_RECORD_IP))
+        })),
+        ("MX", SELFTEST_DOMAIN.to_string(), RecordType::MX, Box::new(|m: &Message| {
+            expect(m.answers().iter().any(|r| matches!(r.data(), Some(RData::MX(mx))
+                if mx.exchange().to_ascii().trim_end_matches('.') == mail_server && mx.preference() == config.mx_priority)),
+                format!("no MX {} {}", config.mx_priority, mail_server))
+        })),
+        ("TXT (SPF)", SELFTEST_DOMAIN.to_string(), RecordType::TXT, Box::new(|m: &Message| {
+            expect(txt_values(m).iter().any(|t| t.starts_with("v=spf1")), "no SPF record".to_string())
+        })),
+        ("TXT (DMARC)", format!("_dmarc.{}", SELFTEST_DOMAIN), RecordType::TXT, Box::new(|m: &Message| {
+            expect(txt_values(m).contains(&config.dmarc.to_txt()), "DMARC record missing or wrong".to_string())
+        })),
+        ("NS", SELFTEST_DOMAIN.to_string(), RecordType::NS, Box::new(|m: &Message| {
+            let mut served: Vec<String> = m.answers().iter()
+                .filter_map(|r| match r.data() {
+                    Some(RData::NS(ns)) => Some(ns.0.to_ascii().trim_end_matches('.').to_string()),
+                    _ => None,
+                })
+                .collect();
+            let mut expected = config.nameservers.clone();
+            served.sort();
+            expected.sort();
+            expect(served == expected, format!("expected {:?}, got {:?}", expected, served))
+        })),
+        ("unknown zone", "nonexistent.invalid".to_string(), RecordType::A, Box::new(|m: &Message| {
+            expect(m.answers().is_empty(), "answered for a zone we do not serve".to_string())
+        })),
+    ];
+    
+    for transport in ["udp", "tcp"] {
+        for (id, (label, qname, record_type, check)) in checks.iter().enumerate() {
+            let id = id as u16 + 1;
+            let response = match transport {
+                "udp" => query(&client, server_addr, id, qname, *record_type).await,
+                _ => query_tcp(tcp_addr, id, qname, *record_type).await,
+            };
+            let result = match response {
+                Ok(response) if response.response_code() != ResponseCode::NoError => {
+                    Err(anyhow::anyhow!("rcode {:?}", response.response_code()))
+                }
+                Ok(response) => check(&response),
+                Err(e) => Err(e),
+            };
+            
+            match result {
+                Ok(()) => println!("PASS {} {:<12} {}", transport, label, qname),
+                Err(e) => {
+                    failures += 1;
+                    println!("FAIL {} {:<12} {}: {}", transport, label, qname, e);
+                }
+            }
+        }
+    }
+    
+    server.abort();
+    tcp_server.abort();
+    
+    let total = checks.len() * 2;
+    if failures > 0 {
+        return Err(anyhow::anyhow!("selftest failed: {} of {} checks failed", failures, total));
+    }
+    
+    println!("selftest passed ({} checks)", total);
+    Ok(())
+}
+
+async fn query(client: &UdpSocket, server: SocketAddr, id: u16, qname: &str, record_type: RecordType) -> Result<Message> {
+    let mut request = Message::new();
+    request.set_id(id);
+    request.set_message_type(MessageType::Query);
+    request.set_op_code(OpCode::Query);
+    request.add_query(Query::query(Name::from_ascii(qname)?, record_type));
+    
+    client.send_to(&request.to_bytes()?, server).await?;
+    
+    let mut buf = [0u8; 4096];
+    let (len, _) = tokio::time::timeout(QUERY_TIMEOUT, client.recv_from(&mut buf)).await
+        .map_err(|_| anyhow::anyhow!("timed out after {:?}", QUERY_TIMEOUT))??;
+    let response = Message::from_bytes(&buf[..len])?;
+    
+    if response.id() != id {
+        return Err(anyhow::anyhow!("response id {} does not match query id {}", response.id(), id));
+    }
+    Ok(response)
+}
+
+/// Same as `query`, over a fresh TCP connection with the two-byte length prefix.
+async fn query_tcp(server: SocketAddr, id: u16, qname: &str, record_type: RecordType) -> Result<Message> {
+    let mut request = Message::new();
+    request.set_id(id);
+    request.set_message_type(MessageType::Query);
+    request.set_op_code(OpCode::Query);
+    request.add_query(Query::query(Name::from_ascii(qname)?, record_type));
+    let request = request.to_bytes()?;
+    
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        stream.write_u16(request.len() as u16).await?;
+        stream.write_all(&request).await?;
+        
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0u8; len as usize];
+        stream.read_exact(&mut buf).await?;
+        Ok::<_, anyhow::Error>(buf)
+    };
+    let buf = tokio::time::timeout(QUERY_TIMEOUT, exchange).await
+        .map_err(|_| anyhow::anyhow!("timed out after {:?}", QUERY_TIMEOUT))??;
+    let response = Message::from_bytes(&buf)?;
+    
+    if response.id() != id {
+        return Err(anyhow::anyhow!("response id {} does not match query id {}", response.id(), id));
+    }
+    Ok(response)
+}
+
+fn txt_values(message: &Message) -> Vec<String> {
+    message.answers().iter()
+        .filter_map(|r| match r.data() {
+            Some(RData::TXT(txt)) => Some(txt.iter().map(|s| String::from_utf8_lossy(s)).collect()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn expect(ok: bool, failure: String) -> Result<()> {
+    if ok {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(failure))
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use trust_dns_proto::op::{Message, Query, ResponseCode};
+use trust_dns_proto::rr::{RData, Record};
+
+use crate::config::DnsConfig;
+use crate::database::Database;
+use crate::dns_handler::CybertempHandler;
+use crate::domain_manager::{DomainManager, ReconcileReport};
+use crate::query_middleware::{QueryContext, QueryMiddleware};
+
+/// Mirrored queries waiting for the shadow handler; more are dropped rather than queued.
+const MIRROR_QUEUE: usize = 1024;
+
+/// A live question and the answer clients got for it.
+struct MirroredQuery {
+    query: Query,
+    live: Message,
+}
+
+/// Shadow mode counters for `GET /debug/runtime`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowStatus {
+    pub config_path: String,
+    /// Live answers compared against the shadow handler's.
+    pub compared: u64,
+    /// Comparisons where the two answers differed; each one is logged as a warning.
+    pub differing: u64,
+    /// Queries not mirrored because the queue was full.
+    pub dropped: u64,
+    /// Shadow lookups that failed outright.
+    pub failed: u64,
+}
+
+/// Answers every live question a second time with a handler built from a staging config and
+/// database, and logs where the two answers differ. Registered as the last middleware, so its
+/// `after` hook sees the answer every other hook has finished with. Lookups run on a single
+/// task off a bounded queue, so shadow mode never slows live answers down.
+pub struct ShadowMirror {
+    config_path: String,
+    manager: Arc<RwLock<DomainManager>>,
+    handler: CybertempHandler,
+    sender: Sender<MirroredQuery>,
+    receiver: Mutex<Option<Receiver<MirroredQuery>>>,
+    compared: AtomicU64,
+    differing: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl ShadowMirror {
+    /// Builds the shadow handler from the config at `config_path` and loads its domains from
+    /// that config's `database_url`.
+    pub async fn load(config_path: &str) -> Result<Self> {
+        let config = DnsConfig::load(config_path, None)
+            .map_err(|e| anyhow::anyhow!("Failed to load shadow config {}: {}", config_path, e))?;
+        let database = Database::new(&config.database_url).await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to the shadow database: {}", e))?;
+
+        let mut manager = DomainManager::new()
+            .with_database(Arc::new(database.with_serial_strategy(config.soa_serial_strategy)))
+            .with_nameservers(config.nameservers.clone(), config.nameserver_sets.clone())
+            .with_routing_profiles(config.routing_profiles.clone(), config.default_ip.clone())
+            .with_reserved_labels(config.reserved_labels.clone())
+            .with_serial_strategy(config.soa_serial_strategy);
+        manager.load_from_database().await?;
+        info!("Shadow mode answering from {} with {} domains", config_path, manager.domain_count());
+
+        let manager = Arc::new(RwLock::new(manager));
+        let (sender, receiver) = channel(MIRROR_QUEUE);
+        Ok(Self {
+            config_path: config_path.to_string(),
+            handler: CybertempHandler::new(config, manager.clone()),
+            manager,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            compared: AtomicU64::new(0),
+            differing: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        })
+    }
+
+    /// Picks up changes made to the staging database since the last call.
+    pub async fn reconcile(&self) -> Result<ReconcileReport> {
+        self.manager.write().await.reconcile_with_database().await
+    }
+
+    /// Compares mirrored queries until the server shuts down. Only the first call does anything.
+    pub async fn run(&self) {
+        let Some(mut receiver) = self.receiver.lock().unwrap_or_else(PoisonError::into_inner).take() else {
+            return;
+        };
+
+        while let Some(mirrored) = receiver.recv().await {
+            let name = mirrored.query.name().to_ascii();
+            let query_type = mirrored.query.query_type();
+            let shadow = match self.handler.lookup(&name, query_type).await {
+                Ok(shadow) => shadow,
+                Err(e) => {
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                    warn!("Shadow lookup of {} {} failed: {}", name, query_type, e);
+                    continue;
+                }
+            };
+
+            self.compared.fetch_add(1, Ordering::Relaxed);
+            let differences = differences(&mirrored.live, &shadow);
+            if !differences.is_empty() {
+                self.differing.fetch_add(1, Ordering::Relaxed);
+                warn!("Shadow answer for {} {} differs: {}", name, query_type, differences.join("; "));
+            }
+        }
+    }
+
+    pub fn status(&self) -> ShadowStatus {
+        ShadowStatus {
+            config_path: self.config_path.clone(),
+            compared: self.compared.load(Ordering::Relaxed),
+            differing: self.differing.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl QueryMiddleware for ShadowMirror {
+    fn after(&self, context: &QueryContext, response: &mut Message) {
+        // Internal lookups have no client, and refused clients were turned away before the
+        // zone lookup, which the shadow handler has no rate limit to repeat
+        if context.client.is_none() || response.response_code() == ResponseCode::Refused {
+            return;
+        }
+
+        let mirrored = MirroredQuery { query: context.query.clone(), live: response.clone() };
+        if self.sender.try_send(mirrored).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How the shadow answer differs from the live one: the response code, the AA bit, and the
+/// records only one of them has in each section.
+fn differences(live: &Message, shadow: &Message) -> Vec<String> {
+    let mut differences = Vec::new();
+    if live.response_code() != shadow.response_code() {
+        differences.push(format!("rcode {} -> {}", live.response_code(), shadow.response_code()));
+    }
+    if live.authoritative() != shadow.authoritative() {
+        differences.push(format!("aa {} -> {}", live.authoritative(), shadow.authoritative()));
+    }
+
+    for (section, live, shadow) in [
+        ("answer", live.answers(), shadow.answers()),
+        ("authority", live.name_servers(), shadow.name_servers()),
+        ("additional", live.additionals(), shadow.additionals()),
+    ] {
+        let live: BTreeSet<String> = live.iter().map(comparable).collect();
+        let shadow: BTreeSet<String> = shadow.iter().map(comparable).collect();
+        for record in live.difference(&shadow) {
+            differences.push(format!("{} -{}", section, record));
+        }
+        for record in shadow.difference(&live) {
+            differences.push(format!("{} +{}", section, record));
+        }
+    }
+    differences
+}
+
+/// A record in presentation format, lowercased since live answers echo the question's case.
+/// SOA serials are left out, as the staging database bumps its own.
+fn comparable(record: &Record) -> String {
+    match record.data() {
+        Some(RData::SOA(soa)) => format!(
+            "{} {} SOA {} {} {} {} {} {}",
+            record.name(), record.ttl(), soa.mname(), soa.rname(), soa.refresh(), soa.retry(), soa.expire(), soa.minimum()
+        ).to_lowercase(),
+        _ => record.to_string().to_lowercase(),
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use trust_dns_proto::rr::{RData, RecordType};
+
+use crate::config::RoutingProfile;
+use crate::dns_handler::CybertempHandler;
+use crate::domain_manager::{DomainRecord, VerificationStatus};
+
+const SMTP_PORT: u16 = 25;
+
+/// What is wrong with a mail server we hand out as an MX target address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpProblem {
+    /// The connection was refused, timed out or dropped before a greeting.
+    Unreachable,
+    /// The greeting was not a `220` reply.
+    BadBanner,
+    /// The greeting lacks the text expected for this kind of domain, e.g. a Discord
+    /// domain routed to the regular mail host.
+    UnexpectedBanner,
+    /// `EHLO` succeeded but did not offer `STARTTLS`.
+    NoStartTls,
+}
+
+/// One mail server address with a problem and the domains whose MX target resolves to it.
+#[derive(Debug, Clone, Serialize, Deserialize, async_graphql::SimpleObject)]
+pub struct MailServerProblem {
+    pub ip: String,
+    pub problem: SmtpProblem,
+    /// The greeting or connection error, for the first domain that hit the problem.
+    pub detail: String,
+    pub domains: Vec<String>,
+}
+
+/// What to expect from our mail servers.
+pub struct SmtpExpectations<'a> {
+    /// Name sent with `EHLO`.
+    pub helo: &'a str,
+    pub timeout: Duration,
+    /// Substring the greeting must contain for domains without a routing profile; empty accepts any.
+    pub banner: &'a str,
+    /// Profiles whose `smtp_banner` their domains' greetings must contain instead.
+    pub routing_profiles: &'a HashMap<String, RoutingProfile>,
+}
+
+struct Greeting {
+    banner: String,
+    starttls: bool,
+}
+
+/// Resolves the MX target of every served domain through `handler` and connects to port 25
+/// of each address, probing every address once per run.
+pub async fn check_mail_servers(
+    handler: &CybertempHandler,
+    domains: &[DomainRecord],
+    expect: &SmtpExpectations<'_>,
+) -> Vec<MailServerProblem> {
+    let mut greetings: HashMap<IpAddr, Result<Greeting, String>> = HashMap::new();
+    let mut found: BTreeMap<(IpAddr, SmtpProblem), MailServerProblem> = BTreeMap::new();
+    
+    let served = domains.iter()
+        .filter(|d| d.enabled && d.verification_status == VerificationStatus::Verified);
+    for domain in served {
+        for ip in mail_addresses(handler, &domain.domain).await {
+            let greeting = match greetings.entry(ip) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(probe(ip, expect).await.map_err(|e| e.to_string())),
+            };
+            
+            let expected_banner = domain.routing_profile()
+                .and_then(|profile| expect.routing_profiles.get(profile))
+                .map_or(expect.banner, |profile| profile.smtp_banner.as_str());
+            let (problem, detail) = match greeting {
+                Err(e) => (SmtpProblem::Unreachable, e.clone()),
+                Ok(greeting) if !greeting.banner.starts_with("220") => (SmtpProblem::BadBanner, greeting.banner.clone()),
+                Ok(greeting) if !greeting.banner.contains(expected_banner) => (SmtpProblem::UnexpectedBanner, greeting.banner.clone()),
+                Ok(greeting) if !greeting.starttls => (SmtpProblem::NoStartTls, greeting.banner.clone()),
+                Ok(_) => continue,
+            };
+            
+            found.entry((ip, problem))
+                .or_insert_with(|| MailServerProblem { ip: ip.to_string(), problem, detail, domains: Vec::new() })
+                .domains.push(domain.domain.clone());
+        }
+    }
+    
+    found.into_values()
+        .map(|mut problem| {
+            problem.domains.sort();
+            problem.domains.dedup();
+            problem
+        })
+        .collect()
+}
+
+/// Addresses we answer for the domain's MX targets.
+async fn mail_addresses(handler: &CybertempHandler, domain: &str) -> Vec<IpAddr> {
+    let Ok(response) = handler.lookup(domain, RecordType::MX).await else { return Vec::new() };
+    let targets: Vec<String> = response.answers().iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::MX(mx)) => Some(mx.exchange().to_ascii()),
+            _ => None,
+        })
+        .collect();
+    
+    let mut addresses = Vec::new();
+    for target in targets {
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            let Ok(response) = handler.lookup(&target, record_type).await else { continue };
+            addresses.extend(response.answers().iter().filter_map(|record| match record.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            }));
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Reads the greeting, sends `EHLO` to see whether `STARTTLS` is offered, then `QUIT`.
+async fn probe(ip: IpAddr, expect: &SmtpExpectations<'_>) -> Result<Greeting> {
+    tokio::time::timeout(expect.timeout, async {
+        let stream = TcpStream::connect(SocketAddr::new(ip, SMTP_PORT)).await?;
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        
+        let banner = read_reply(&mut reader).await?.join(" ");
+        if !banner.starts_with("220") {
+            return Ok(Greeting { banner, starttls: false });
+        }
+        
+        write.write_all(format!("EHLO {}\r\n", expect.helo).as_bytes()).await?;
+        let starttls = read_reply(&mut reader).await?.iter()
+            .any(|line| line.starts_with("250") && line.get(4..).unwrap_or("").trim().eq_ignore_ascii_case("STARTTLS"));
+        
+        // Best effort; the answer to QUIT changes nothing
+        let _ = write.write_all(b"QUIT\r\n").await;
+        Ok(Greeting { banner, starttls })
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("timed out after {:?}", expect.timeout))?
+}
+
+/// Lines of one (possibly multi-line, `250-...`) SMTP reply.
+async fn read_reply<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow::anyhow!("connection closed"));
+        }
+        let line = line.trim_end().to_string();
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line);
+        if last {
+            return Ok(lines);
+        }
+    }
+}
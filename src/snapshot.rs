@@ -0,0 +1,34 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::domain_manager::DomainRecord;
+
+/// The in-memory domain map as written to `snapshot_path`, enough to answer queries
+/// without the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub written_at: DateTime<Utc>,
+    pub domains: Vec<DomainRecord>,
+}
+
+/// Writes to a temporary file first and renames it over the old snapshot, so a crash
+/// mid-write never leaves a truncated file behind.
+pub async fn write_snapshot(path: &str, domains: Vec<DomainRecord>) -> Result<()> {
+    let snapshot = Snapshot { written_at: Utc::now(), domains };
+    let data = serde_json::to_vec(&snapshot)?;
+    
+    if let Some(dir) = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+pub async fn read_snapshot(path: &str) -> Result<Snapshot> {
+    let data = tokio::fs::read(path).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
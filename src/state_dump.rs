@@ -0,0 +1,37 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::db_breaker::BreakerStatus;
+use crate::domain_manager::DomainRecord;
+use crate::jobs::JobStats;
+
+/// How long a dump waits for the domain manager's read lock. A job stuck holding the write
+/// lock is exactly what a dump is for, so it is written without the domains rather than hang.
+pub const DUMP_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What SIGUSR1 writes to `state_dump_dir`.
+#[derive(Debug, Serialize)]
+pub struct StateDump {
+    pub dumped_at: DateTime<Utc>,
+    pub version: &'static str,
+    pub maintenance: bool,
+    pub serving_snapshot: bool,
+    pub response_cache_entries: usize,
+    pub database: BreakerStatus,
+    /// Every background job with its `last_started` heartbeat and whether it is `running`.
+    pub jobs: Vec<JobStats>,
+    /// `None` when the domain manager stayed locked for `DUMP_LOCK_TIMEOUT`.
+    pub domains: Option<Vec<DomainRecord>>,
+}
+
+/// Writes `dump` to a new `state-<timestamp>.json` in `dir`, returning its path.
+pub async fn write_state_dump(dir: &str, dump: &StateDump) -> Result<String> {
+    let data = serde_json::to_vec_pretty(dump)?;
+    tokio::fs::create_dir_all(dir).await?;
+    let path = Path::new(dir).join(format!("state-{}.json", dump.dumped_at.format("%Y%m%dT%H%M%S%.3fZ")));
+    tokio::fs::write(&path, data).await?;
+    Ok(path.display().to_string())
+}
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, DurationRound, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use crate::config::DnsConfig;
+use crate::database::Database;
+
+/// Width of the raw buckets in `query_stats_5m`.
+const BUCKET_SECONDS: i64 = 300;
+
+/// Queries for one domain in one bucket.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryCount {
+    pub domain: String,
+    pub bucket_start: DateTime<Utc>,
+    pub queries: i64,
+    /// Answered with NXDOMAIN or an empty answer section.
+    pub negative: i64,
+}
+
+/// Per-domain query counts for the open 5-minute buckets, until they are flushed to the database.
+#[derive(Default)]
+pub struct DomainQueryCounter {
+    buckets: Mutex<HashMap<(String, i64), (i64, i64)>>,
+}
+
+impl DomainQueryCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Counts one query for a managed domain.
+    pub fn record(&self, domain: &str, negative: bool) {
+        let bucket = Utc::now().timestamp() / BUCKET_SECONDS * BUCKET_SECONDS;
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let counts = buckets.entry((domain.to_string(), bucket)).or_insert((0, 0));
+        counts.0 += 1;
+        if negative {
+            counts.1 += 1;
+        }
+    }
+    
+    /// Open (domain, bucket) counters.
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// Removes and returns the buckets that ended before `now`; the open one keeps counting.
+    pub fn take_closed(&self, now: DateTime<Utc>) -> Vec<QueryCount> {
+        let open = now.timestamp() / BUCKET_SECONDS * BUCKET_SECONDS;
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let closed: Vec<(String, i64)> = buckets.keys().filter(|(_, start)| *start < open).cloned().collect();
+        
+        closed.into_iter()
+            .filter_map(|key| {
+                let (queries, negative) = buckets.remove(&key)?;
+                Some(QueryCount {
+                    domain: key.0,
+                    bucket_start: Utc.timestamp_opt(key.1, 0).single()?,
+                    queries,
+                    negative,
+                })
+            })
+            .collect()
+    }
+}
+
+/// How long each resolution is kept; `None` keeps it forever.
+#[derive(Debug, Clone, Copy)]
+pub struct StatsRetention {
+    pub raw: ChronoDuration,
+    pub hourly: ChronoDuration,
+    pub daily: Option<ChronoDuration>,
+}
+
+impl StatsRetention {
+    pub fn from_config(config: &DnsConfig) -> Result<Self> {
+        if config.stats_raw_retention_hours < 2 || config.stats_hourly_retention_days < 2 {
+            return Err(anyhow::anyhow!(
+                "stats_raw_retention_hours and stats_hourly_retention_days must be at least 2 to leave a full period to roll up"
+            ));
+        }
+        Ok(Self {
+            raw: ChronoDuration::hours(config.stats_raw_retention_hours as i64),
+            hourly: ChronoDuration::days(config.stats_hourly_retention_days as i64),
+            daily: (config.stats_daily_retention_days > 0)
+                .then(|| ChronoDuration::days(config.stats_daily_retention_days as i64)),
+        })
+    }
+}
+
+/// Where each resolution takes over, as `(raw_from, hourly_from)`: daily rows cover the time
+/// before `hourly_from`, hourly rows the time before `raw_from`, and 5-minute buckets the rest.
+/// These are the first periods still fully covered by the finer data.
+pub fn resolution_cutoffs(retention: &StatsRetention, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let hour = ChronoDuration::hours(1);
+    let day = ChronoDuration::days(1);
+    Ok((
+        (now - retention.raw).duration_trunc(hour)? + hour,
+        (now - retention.hourly).duration_trunc(day)? + day,
+    ))
+}
+
+/// Queries for one domain over a period.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueryTotal {
+    pub domain: String,
+    pub queries: i64,
+    pub negative: i64,
+}
+
+/// Per-domain query totals in `[from, to)`, each stretch of time read from the finest
+/// resolution still kept for it. Parts of the period in pruned ranges are counted in whole
+/// hours or days.
+pub async fn query_totals(
+    database: &Database,
+    retention: &StatsRetention,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<QueryTotal>> {
+    let (raw_from, hourly_from) = resolution_cutoffs(retention, Utc::now())?;
+    database.get_query_totals(from, to, raw_from, hourly_from).await
+}
+
+/// Rows written and deleted by one rollup run.
+#[derive(Debug, Default, Serialize)]
+pub struct RollupReport {
+    pub hourly_rows: u64,
+    pub daily_rows: u64,
+    pub pruned_raw: u64,
+    pub pruned_hourly: u64,
+    pub pruned_daily: u64,
+}
+
+/// Recomputes the hourly rollups from the 5-minute buckets and the daily rollups from the
+/// hourly ones, then prunes each resolution past its retention.
+///
+/// Only completed periods are rolled up, and by default only those still fully covered by the
+/// finer data, so a rollup can be rerun at any time and overwrites its rows with the same sums.
+/// `since` recomputes from an earlier point for backfills; periods whose finer data is already
+/// gone are left untouched, but one that was pruned halfway gets the partial sum.
+pub async fn rollup(database: &Database, retention: &StatsRetention, since: Option<DateTime<Utc>>) -> Result<RollupReport> {
+    let now = Utc::now();
+    let hour = ChronoDuration::hours(1);
+    let day = ChronoDuration::days(1);
+    
+    let (hourly_from, daily_from) = match since {
+        Some(since) => (since.duration_trunc(hour)?, since.duration_trunc(day)?),
+        None => resolution_cutoffs(retention, now)?,
+    };
+    
+    let mut report = RollupReport {
+        hourly_rows: database.rollup_query_stats_hourly(hourly_from, now.duration_trunc(hour)?).await?,
+        daily_rows: database.rollup_query_stats_daily(daily_from, now.duration_trunc(day)?).await?,
+        ..Default::default()
+    };
+    
+    let (raw, hourly, daily) = database.prune_query_stats(
+        now - retention.raw,
+        now - retention.hourly,
+        retention.daily.map(|daily| now - daily),
+    ).await?;
+    report.pruned_raw = raw;
+    report.pruned_hourly = hourly;
+    report.pruned_daily = daily;
+    
+    Ok(report)
+}
@@ -1,9 +1,13 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use tracing::{info, warn, error};
 
+use crate::abuse::AbuseState;
+use crate::blocklist::find_block;
+use crate::domain_name::domain_key;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SupabaseDomain {
     pub id: String,
@@ -19,12 +23,109 @@ pub struct SupabaseDomain {
     pub payment_status: String,
     pub amount_paid: Option<f64>,
     pub is_one_time_purchase: bool,
+    /// NS records seen at the last verification, as pushed by the sync.
+    #[serde(default)]
+    pub nameservers: Option<Vec<String>>,
+    #[serde(default)]
+    pub last_verified: Option<DateTime<Utc>>,
+}
+
+/// What the push writes for a domain, from the internal database.
+struct PushedState {
+    verified: bool,
+    discord: bool,
+    nameservers: Option<Vec<String>>,
+    last_verified: Option<DateTime<Utc>>,
+}
+
+impl PushedState {
+    fn from_domain(domain: &super::database::Domain) -> Self {
+        Self {
+            verified: domain.verified,
+            discord: domain.discord,
+            nameservers: domain.nameservers.clone(),
+            last_verified: domain.last_verified,
+        }
+    }
+
+    fn updates(&self) -> HashMap<&'static str, serde_json::Value> {
+        let mut updates = HashMap::new();
+        updates.insert("pending_ns_check", serde_json::Value::Bool(!self.verified));
+        updates.insert("discord", serde_json::Value::Bool(self.discord));
+        updates.insert("nameservers", serde_json::json!(self.nameservers));
+        updates.insert("last_verified", serde_json::json!(self.last_verified.map(|at| at.to_rfc3339())));
+        updates.insert("updated_at", serde_json::Value::String(Utc::now().to_rfc3339()));
+        updates
+    }
+}
+
+/// Who owns a domain and how it was paid for, as recorded in Supabase.
+#[derive(Debug, Serialize, Clone)]
+pub struct DomainOwner {
+    pub user_id: String,
+    /// From the Supabase auth user; `None` if the user no longer exists.
+    pub email: Option<String>,
+    pub added_at: DateTime<Utc>,
+    pub active: bool,
+    pub payment_status: String,
+    pub stripe_payment_id: Option<String>,
+    pub amount_paid: Option<f64>,
+    pub is_one_time_purchase: bool,
+}
+
+/// Everything a sync in both directions would change, computed without writing anything.
+#[derive(Debug, Serialize, Default)]
+pub struct SyncPlan {
+    /// Active Supabase domains that would be added to or updated in the internal database.
+    pub to_internal: Vec<InternalChange>,
+    /// Supabase rows whose fields would change, with the old and new values.
+    pub to_supabase: Vec<SupabaseChange>,
+    /// Supabase domains the pull leaves out.
+    pub skipped: Vec<SkippedDomain>,
+    /// Internal domains without a Supabase row; the sync only logs these.
+    pub missing_in_supabase: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InternalChange {
+    pub domain: String,
+    /// `add` for a new domain, `update` when its IP or Discord flag changes.
+    pub action: &'static str,
+    pub ip: String,
+    pub discord: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SupabaseChange {
+    pub domain: String,
+    pub id: String,
+    pub fields: BTreeMap<&'static str, FieldChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedDomain {
+    pub domain: String,
+    pub reason: String,
+}
+
+#[derive(Deserialize)]
+struct SupabaseUser {
+    email: Option<String>,
 }
 
 pub struct SupabaseClient {
     client: reqwest::Client,
     url: String,
     key: String,
+    /// A record for synced domains, and for those with the Discord flag.
+    default_ip: String,
+    discord_ip: String,
 }
 
 impl SupabaseClient {
@@ -33,9 +134,22 @@ impl SupabaseClient {
             client: reqwest::Client::new(),
             url,
             key,
+            default_ip: "45.134.39.50".to_string(),
+            discord_ip: "37.114.41.81".to_string(),
         }
     }
 
+    /// IPs synced domains get, from `default_ip` and the `discord` routing profile.
+    pub fn with_domain_ips(mut self, default_ip: String, discord_ip: String) -> Self {
+        self.default_ip = default_ip;
+        self.discord_ip = discord_ip;
+        self
+    }
+
+    fn domain_ip(&self, discord: bool) -> &str {
+        if discord { &self.discord_ip } else { &self.default_ip }
+    }
+
     pub fn is_configured(&self) -> bool {
         !self.url.is_empty() && !self.key.is_empty()
     }
@@ -46,18 +160,27 @@ impl SupabaseClient {
         }
 
         let domains = self.get_all_domains().await?;
+        let banned: HashSet<String> = database.get_abuse_cases(Some(AbuseState::Banned)).await?
+            .into_iter()
+            .map(|case| case.domain)
+            .collect();
+        let blocked = database.get_blocked_domains().await?;
         
         for supabase_domain in &domains {
+            if banned.contains(&domain_key(&supabase_domain.domain)) {
+                warn!("Skipping Supabase domain {}, it is banned for abuse", supabase_domain.domain);
+                continue;
+            }
+            if let Some(entry) = find_block(&blocked, &supabase_domain.domain) {
+                warn!("Skipping Supabase domain {}, it is blocked by {} pattern {}", supabase_domain.domain, entry.kind.as_str(), entry.pattern);
+                continue;
+            }
             if supabase_domain.active {
                 // Convert Cybertemp domain to our internal format
-                let ip = if supabase_domain.discord {
-                    "37.114.41.81".to_string()
-                } else {
-                    "45.134.39.50".to_string()
-                };
+                let ip = self.domain_ip(supabase_domain.discord);
                 
                 // Add to our internal PostgreSQL database
-                database.add_domain(&supabase_domain.domain, &ip, supabase_domain.discord).await?;
+                database.add_domain(&supabase_domain.domain, ip, supabase_domain.discord).await?;
                 
                 // Update pending_ns_check based on our verification status
                 if let Some(internal_domain) = database.get_domain(&supabase_domain.domain).await? {
@@ -74,6 +197,107 @@ impl SupabaseClient {
         Ok(())
     }
 
+    /// What `sync_from_supabase` followed by `sync_to_supabase` would change right now.
+    /// Supabase rows are compared against the internal state as it would be after the pull;
+    /// `updated_at` is left out since every push touches it.
+    pub async fn plan_sync(&self, database: &super::database::Database) -> Result<SyncPlan> {
+        let mut plan = SyncPlan::default();
+        if !self.is_configured() {
+            return Ok(plan);
+        }
+
+        let supabase_domains = self.get_all_domains().await?;
+        let banned: HashSet<String> = database.get_abuse_cases(Some(AbuseState::Banned)).await?
+            .into_iter()
+            .map(|case| case.domain)
+            .collect();
+        let blocked = database.get_blocked_domains().await?;
+
+        // What the push would write per internal domain, updated as the pull would leave it
+        let internal_domains = database.get_all_domains().await?;
+        let mut internal: HashMap<String, PushedState> = internal_domains.iter()
+            .map(|d| (d.domain.clone(), PushedState::from_domain(d)))
+            .collect();
+
+        for supabase_domain in &supabase_domains {
+            let name = domain_key(&supabase_domain.domain);
+            if banned.contains(&name) {
+                plan.skipped.push(SkippedDomain { domain: name, reason: "banned for abuse".to_string() });
+                continue;
+            }
+            if let Some(entry) = find_block(&blocked, &name) {
+                plan.skipped.push(SkippedDomain {
+                    domain: name,
+                    reason: format!("blocked by {} pattern {}", entry.kind.as_str(), entry.pattern),
+                });
+                continue;
+            }
+            if !supabase_domain.active {
+                continue;
+            }
+
+            let ip = self.domain_ip(supabase_domain.discord);
+            let action = match internal_domains.iter().find(|d| d.domain == name) {
+                None => Some("add"),
+                // inet columns read back as text carry their prefix length, e.g. `45.134.39.50/32`
+                Some(d) if d.ip_address.split('/').next() != Some(ip) || d.discord != supabase_domain.discord => Some("update"),
+                Some(_) => None,
+            };
+            if let Some(action) = action {
+                plan.to_internal.push(InternalChange { domain: name.clone(), action, ip: ip.to_string(), discord: supabase_domain.discord });
+            }
+            internal.entry(name)
+                .or_insert(PushedState { verified: false, discord: false, nameservers: None, last_verified: None })
+                .discord = supabase_domain.discord;
+        }
+
+        let supabase_domain_map: HashMap<String, &SupabaseDomain> = supabase_domains.iter()
+            .map(|d| (domain_key(&d.domain), d))
+            .collect();
+        let mut names: Vec<&String> = internal.keys().collect();
+        names.sort();
+        for name in names {
+            let state = &internal[name];
+            let Some(supabase_domain) = supabase_domain_map.get(name) else {
+                plan.missing_in_supabase.push(name.clone());
+                continue;
+            };
+
+            let pending_ns_check = !state.verified;
+            let mut fields = BTreeMap::new();
+            if supabase_domain.pending_ns_check != pending_ns_check {
+                fields.insert("pending_ns_check", FieldChange {
+                    from: serde_json::Value::Bool(supabase_domain.pending_ns_check),
+                    to: serde_json::Value::Bool(pending_ns_check),
+                });
+            }
+            if supabase_domain.discord != state.discord {
+                fields.insert("discord", FieldChange {
+                    from: serde_json::Value::Bool(supabase_domain.discord),
+                    to: serde_json::Value::Bool(state.discord),
+                });
+            }
+            if supabase_domain.nameservers != state.nameservers {
+                fields.insert("nameservers", FieldChange {
+                    from: serde_json::json!(supabase_domain.nameservers),
+                    to: serde_json::json!(state.nameservers),
+                });
+            }
+            // Compared to the second, since Supabase may store less precision than we do
+            if supabase_domain.last_verified.map(|at| at.timestamp()) != state.last_verified.map(|at| at.timestamp()) {
+                fields.insert("last_verified", FieldChange {
+                    from: serde_json::json!(supabase_domain.last_verified.map(|at| at.to_rfc3339())),
+                    to: serde_json::json!(state.last_verified.map(|at| at.to_rfc3339())),
+                });
+            }
+            if !fields.is_empty() {
+                plan.to_supabase.push(SupabaseChange { domain: name.clone(), id: supabase_domain.id.clone(), fields });
+            }
+        }
+
+        Ok(plan)
+    }
+
     pub async fn sync_to_supabase(&self, database: &super::database::Database) -> Result<()> {
         if !self.is_configured() {
             return Ok(());
@@ -91,11 +315,7 @@ impl SupabaseClient {
         for internal_domain in internal_domains {
             if let Some(supabase_domain) = supabase_domain_map.get(&internal_domain.domain) {
                 // Update existing Supabase domain
-                let mut updates = HashMap::new();
-                updates.insert("pending_ns_check", serde_json::Value::Bool(!internal_domain.verified));
-                updates.insert("discord", serde_json::Value::Bool(internal_domain.discord));
-                updates.insert("updated_at", serde_json::Value::String(Utc::now().to_rfc3339()));
-                
+                let updates = PushedState::from_domain(&internal_domain).updates();
                 self.update_domain(&supabase_domain.id, updates).await?;
             } else {
                 // This domain exists in our internal DB but not in Supabase
@@ -108,6 +328,96 @@ impl SupabaseClient {
         Ok(())
     }
 
+    /// Pushes one domain's state the way `sync_to_supabase` does for all of them, so a
+    /// verification change shows up without waiting for the next full sync.
+    pub async fn sync_domain_to_supabase(&self, database: &super::database::Database, domain: &str) -> Result<()> {
+        if !self.is_configured() {
+            return Ok(());
+        }
+
+        // Removed domains, e.g. after an expired grace period, have nothing left to push
+        let Some(internal_domain) = database.get_domain(domain).await? else {
+            return Ok(());
+        };
+        let Some(supabase_domain) = self.get_domain(&internal_domain.domain).await? else {
+            warn!("Domain {} exists in internal DB but not in Supabase", internal_domain.domain);
+            return Ok(());
+        };
+
+        let updates = PushedState::from_domain(&internal_domain).updates();
+        self.update_domain(&supabase_domain.id, updates).await?;
+        info!("Pushed verification state of {} to Supabase", internal_domain.domain);
+        Ok(())
+    }
+
+    pub async fn get_domain(&self, domain: &str) -> Result<Option<SupabaseDomain>> {
+        if !self.is_configured() {
+            return Ok(None);
+        }
+
+        let response = self.client
+            .get(format!("{}/rest/v1/domains?domain=eq.{}", self.url, domain))
+            .header("apikey", &self.key)
+            .header("Authorization", &format!("Bearer {}", self.key))
+            .header("Content-Type", "application/json")
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            error!("Supabase API error: {}", error_text);
+            return Err(anyhow::anyhow!("Supabase API error: {}", error_text));
+        }
+
+        let domains: Vec<SupabaseDomain> = response.json().await?;
+        Ok(domains.into_iter().next())
+    }
+
+    /// The domain's owner and payment details, or `None` if Supabase has no row for it.
+    pub async fn get_domain_owner(&self, domain: &str) -> Result<Option<DomainOwner>> {
+        let Some(supabase_domain) = self.get_domain(domain).await? else {
+            return Ok(None);
+        };
+        let email = self.get_user_email(&supabase_domain.user_id).await?;
+
+        Ok(Some(DomainOwner {
+            user_id: supabase_domain.user_id,
+            email,
+            added_at: supabase_domain.added_at,
+            active: supabase_domain.active,
+            payment_status: supabase_domain.payment_status,
+            stripe_payment_id: supabase_domain.stripe_payment_id,
+            amount_paid: supabase_domain.amount_paid,
+            is_one_time_purchase: supabase_domain.is_one_time_purchase,
+        }))
+    }
+
+    /// Looks the user up through the auth admin API, which needs the service role key.
+    pub async fn get_user_email(&self, user_id: &str) -> Result<Option<String>> {
+        if !self.is_configured() {
+            return Ok(None);
+        }
+
+        let response = self.client
+            .get(&format!("{}/auth/v1/admin/users/{}", self.url, user_id))
+            .header("apikey", &self.key)
+            .header("Authorization", &format!("Bearer {}", self.key))
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            error!("Supabase auth API error: {}", error_text);
+            return Err(anyhow::anyhow!("Supabase auth API error: {}", error_text));
+        }
+
+        let user: SupabaseUser = response.json().await?;
+        Ok(user.email)
+    }
+
     pub async fn get_all_domains(&self) -> Result<Vec<SupabaseDomain>> {
         if !self.is_configured() {
             return Ok(Vec::new());
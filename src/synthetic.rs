@@ -0,0 +1,110 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Most rules a zone can carry; every query below the zone without explicit records runs them all.
+const MAX_RULES: usize = 20;
+/// Compiled size limit per pattern, well above anything a hostname pattern needs.
+const MAX_PATTERN_SIZE: usize = 256 * 1024;
+/// Compiled patterns kept before the cache starts over, which only happens as rules are replaced.
+const MAX_CACHED_PATTERNS: usize = 10_000;
+/// Record types a rule can synthesize.
+const SYNTHETIC_TYPES: &[&str] = &["A", "AAAA", "CNAME", "MX", "TXT"];
+
+/// Answers every name below a zone matching `pattern` without a row per name, e.g.
+/// `^inbox-\d+\.(?P<domain>.+)$` → `A 192.0.2.1` for generated mail subdomains.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SyntheticRule {
+    /// Regex matched against the whole query name, lowercased and without the trailing dot.
+    pub pattern: String,
+    pub record_type: String,
+    /// Record value; `$name` and `${1}` expand to the pattern's capture groups.
+    pub value: String,
+    /// Defaults to the zone's TTL.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// MX preference.
+    #[serde(default)]
+    pub priority: u16,
+}
+
+impl SyntheticRule {
+    fn validate(&self) -> Result<()> {
+        compile(&self.pattern)?;
+        if !SYNTHETIC_TYPES.contains(&self.record_type.as_str()) {
+            return Err(anyhow::anyhow!("Rules can only synthesize {} records", SYNTHETIC_TYPES.join(", ")));
+        }
+        if self.value.trim().is_empty() {
+            return Err(anyhow::anyhow!("Rule for {} has an empty value", self.pattern));
+        }
+        if let Some(ttl) = self.ttl {
+            crate::validation::check_ttl(ttl)?;
+        }
+
+        // Values built from captures can only be checked once a query fills them in
+        let valid = self.value.contains('$') || match self.record_type.as_str() {
+            "A" => self.value.trim().parse::<std::net::Ipv4Addr>().is_ok(),
+            "AAAA" => self.value.trim().parse::<std::net::Ipv6Addr>().is_ok(),
+            "CNAME" | "MX" => trust_dns_proto::rr::Name::from_ascii(self.value.trim()).is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return Err(anyhow::anyhow!("Invalid {} value: {}", self.record_type, self.value));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a zone's rule list before it is stored.
+pub fn validate_rules(rules: &[SyntheticRule]) -> Result<()> {
+    if rules.is_empty() || rules.len() > MAX_RULES {
+        return Err(anyhow::anyhow!("Between 1 and {} rules are required", MAX_RULES));
+    }
+    rules.iter().try_for_each(SyntheticRule::validate)
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(MAX_PATTERN_SIZE)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid pattern {}: {}", pattern, e))
+}
+
+/// Rule patterns compiled once and shared by every query, since zones are cloned per lookup.
+#[derive(Default)]
+pub struct PatternCache {
+    patterns: Mutex<HashMap<String, Arc<Regex>>>,
+}
+
+impl PatternCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `rule`'s value with its captures filled in, if `name` matches its pattern.
+    pub fn expand(&self, rule: &SyntheticRule, name: &str) -> Option<String> {
+        let regex = self.get(&rule.pattern)?;
+        let captures = regex.captures(name)?;
+        let mut value = String::new();
+        captures.expand(rule.value.trim(), &mut value);
+        Some(value)
+    }
+
+    fn get(&self, pattern: &str) -> Option<Arc<Regex>> {
+        let mut patterns = self.patterns.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(regex) = patterns.get(pattern) {
+            return Some(regex.clone());
+        }
+
+        // Stored rules were validated, so this only fails for rows edited by hand
+        let regex = Arc::new(compile(pattern).ok()?);
+        if patterns.len() >= MAX_CACHED_PATTERNS {
+            patterns.clear();
+        }
+        patterns.insert(pattern.to_string(), regex.clone());
+        Some(regex)
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+use crate::config::DnsConfig;
+use crate::dns_handler::CybertempHandler;
+use crate::domain_manager::DomainManager;
+pub use crate::domain_store::MemoryStore;
+
+/// Exercises record synthesis without Postgres or sockets. The `DomainManager` persists to a
+/// `MemoryStore`; encoded `Message`s go straight to the handler.
+pub struct HandlerHarness {
+    config: DnsConfig,
+    domain_manager: Arc<RwLock<DomainManager>>,
+    store: Arc<MemoryStore>,
+    client: SocketAddr,
+}
+
+impl HandlerHarness {
+    pub fn new(config: DnsConfig) -> Self {
+        let store = Arc::new(MemoryStore::new());
+        let domain_manager = DomainManager::new()
+            .with_nameservers(config.nameservers.clone(), config.nameserver_sets.clone())
+            .with_store(store.clone());
+        
+        Self {
+            config,
+            domain_manager: Arc::new(RwLock::new(domain_manager)),
+            store,
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 5353)),
+        }
+    }
+    
+    /// Source address the handler sees, for per-client behaviour.
+    pub fn with_client(mut self, client: SocketAddr) -> Self {
+        self.client = client;
+        self
+    }
+    
+    /// Adds a domain that is already verified, so it is answered immediately.
+    pub async fn add_domain(&self, domain: &str, ip: &str, discord: bool) -> Result<()> {
+        let mut manager = self.domain_manager.write().await;
+        manager.add_domain(domain, ip, discord).await?;
+        manager.mark_verified(domain)
+    }
+    
+    /// Adds a domain still pending NS verification.
+    pub async fn add_unverified_domain(&self, domain: &str, ip: &str) -> Result<()> {
+        self.domain_manager.write().await.add_domain(domain, ip, false).await
+    }
+    
+    /// The domain manager, for setting BIMI/DMARC or disabling domains mid-test.
+    pub fn domain_manager(&self) -> Arc<RwLock<DomainManager>> {
+        self.domain_manager.clone()
+    }
+    
+    /// What the domain manager persists to, for changing state behind its back as another
+    /// node would and checking what it wrote.
+    pub fn store(&self) -> Arc<MemoryStore> {
+        self.store.clone()
+    }
+    
+    /// Runs a crafted request through the full encode/handle/decode path.
+    pub async fn handle(&self, request: &Message) -> Result<Message> {
+        let handler = CybertempHandler::new(self.config.clone(), self.domain_manager.clone());
+        let response = handler.handle_request(&request.to_bytes()?, self.client).await?;
+        Ok(Message::from_bytes(&response)?)
+    }
+    
+    /// Builds and handles a single-question query.
+    pub async fn query(&self, qname: &str, record_type: RecordType) -> Result<Message> {
+        let mut request = Message::new();
+        request.set_id(1);
+        request.set_message_type(MessageType::Query);
+        request.set_op_code(OpCode::Query);
+        request.add_query(Query::query(Name::from_ascii(qname)?, record_type));
+        self.handle(&request).await
+    }
+}
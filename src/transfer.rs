@@ -0,0 +1,83 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long a claim token can be redeemed for.
+pub const CLAIM_TOKEN_TTL_HOURS: i64 = 72;
+
+/// Body of `POST /domains/{domain}/transfer`.
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    /// Supabase user the domain moves to right away. Without it, a claim token is created for
+    /// the new owner to redeem with `POST /transfers/claim`.
+    #[serde(default)]
+    pub to_user: Option<String>,
+}
+
+/// Body of `POST /transfers/claim`, sent on behalf of the signed-in new owner.
+#[derive(Debug, Deserialize)]
+pub struct ClaimRequest {
+    pub token: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    /// A claim token is out and has not been redeemed.
+    Pending,
+    Completed,
+    /// Withdrawn, or replaced by a newer transfer of the same domain.
+    Cancelled,
+}
+
+impl TransferStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferStatus::Pending => "pending",
+            TransferStatus::Completed => "completed",
+            TransferStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::str::FromStr for TransferStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(TransferStatus::Pending),
+            "completed" => Ok(TransferStatus::Completed),
+            "cancelled" => Ok(TransferStatus::Cancelled),
+            other => Err(anyhow::anyhow!("Unknown transfer status: {}", other)),
+        }
+    }
+}
+
+/// A row of `domain_transfers`, the audit trail of ownership changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainTransfer {
+    pub id: i64,
+    pub domain: String,
+    pub from_user: String,
+    /// `None` until a claim token is redeemed.
+    pub to_user: Option<String>,
+    pub status: TransferStatus,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+    /// When an unredeemed claim token stops working.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A new claim token and the hash stored for it; the token itself is only shown once.
+pub fn new_claim_token() -> (String, String) {
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let hash = hash_claim_token(&token);
+    (token, hash)
+}
+
+pub fn hash_claim_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use trust_dns_proto::op::Message;
+
+/// Upper bounds of the advertised EDNS buffer size buckets: the classic 512, the DNS Flag
+/// Day 2020 default of 1232, sizes that fit an Ethernet MTU, and the old 4096 default.
+const BUFFER_SIZE_BOUNDS: [u16; 6] = [512, 1232, 1400, 1452, 2048, 4096];
+
+/// How a query reached us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    /// DNS over TLS.
+    Tls,
+    /// The DNS JSON API on `doh_port`.
+    Doh,
+}
+
+impl Transport {
+    const ALL: [Transport; 4] = [Transport::Udp, Transport::Tcp, Transport::Tls, Transport::Doh];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+            Transport::Doh => "doh",
+        }
+    }
+}
+
+#[derive(Default)]
+struct TransportCounters {
+    queries: AtomicU64,
+    /// Queries carrying an OPT record; the JSON API has none.
+    edns: AtomicU64,
+    dnssec_ok: AtomicU64,
+    /// UDP answers cut down to the header and question because they exceeded the client's buffer.
+    truncated: AtomicU64,
+    /// Advertised buffer sizes, one slot per bound plus one for anything larger.
+    buffer_sizes: [AtomicU64; BUFFER_SIZE_BOUNDS.len() + 1],
+    buffer_size_sum: AtomicU64,
+    /// Time from parsing a query to having its encoded answer, summed.
+    answer_micros: AtomicU64,
+}
+
+/// One transport's counts, as returned by `GET /stats/transport`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransportBreakdown {
+    pub transport: &'static str,
+    pub queries: u64,
+    pub edns: u64,
+    pub no_edns: u64,
+    pub dnssec_ok: u64,
+    pub truncated: u64,
+    /// Time spent answering, summed over `queries`.
+    pub answer_micros: u64,
+    pub mean_answer_micros: u64,
+    /// EDNS queries per advertised buffer size, keyed by bucket upper bound (`le`), not cumulative.
+    pub buffer_sizes: Vec<BufferSizeBucket>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BufferSizeBucket {
+    /// `None` for sizes above the largest bound.
+    pub le: Option<u16>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransportSnapshot {
+    pub since: DateTime<Utc>,
+    pub total_queries: u64,
+    pub transports: Vec<TransportBreakdown>,
+}
+
+/// Query counts per transport and EDNS use since startup, for deciding things like minimal
+/// responses or a smaller UDP payload limit.
+pub struct TransportStats {
+    counters: [TransportCounters; 4],
+    since: DateTime<Utc>,
+}
+
+impl TransportStats {
+    pub fn new() -> Self {
+        Self {
+            counters: Default::default(),
+            since: Utc::now(),
+        }
+    }
+
+    fn counters(&self, transport: Transport) -> &TransportCounters {
+        &self.counters[transport as usize]
+    }
+
+    /// Counts a parsed query and its OPT record, if any.
+    pub fn record(&self, transport: Transport, request: &Message) {
+        let counters = self.counters(transport);
+        counters.queries.fetch_add(1, Ordering::Relaxed);
+        let Some(edns) = request.extensions() else { return };
+
+        counters.edns.fetch_add(1, Ordering::Relaxed);
+        if edns.dnssec_ok() {
+            counters.dnssec_ok.fetch_add(1, Ordering::Relaxed);
+        }
+        let size = edns.max_payload();
+        let bucket = BUFFER_SIZE_BOUNDS.iter().position(|bound| size <= *bound).unwrap_or(BUFFER_SIZE_BOUNDS.len());
+        counters.buffer_sizes[bucket].fetch_add(1, Ordering::Relaxed);
+        counters.buffer_size_sum.fetch_add(u64::from(size), Ordering::Relaxed);
+    }
+
+    /// Counts a JSON API query, which has no EDNS.
+    pub fn record_json(&self) {
+        self.counters(Transport::Doh).queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_truncated(&self, transport: Transport) {
+        self.counters(transport).truncated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds the time it took to answer one query.
+    pub fn record_answer_time(&self, transport: Transport, elapsed: Duration) {
+        self.counters(transport).answer_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TransportSnapshot {
+        let transports: Vec<TransportBreakdown> = Transport::ALL.iter().map(|transport| {
+            let counters = self.counters(*transport);
+            let queries = counters.queries.load(Ordering::Relaxed);
+            let edns = counters.edns.load(Ordering::Relaxed);
+            let answer_micros = counters.answer_micros.load(Ordering::Relaxed);
+            TransportBreakdown {
+                transport: transport.as_str(),
+                queries,
+                edns,
+                no_edns: queries.saturating_sub(edns),
+                dnssec_ok: counters.dnssec_ok.load(Ordering::Relaxed),
+                truncated: counters.truncated.load(Ordering::Relaxed),
+                answer_micros,
+                mean_answer_micros: answer_micros / queries.max(1),
+                buffer_sizes: counters.buffer_sizes.iter().enumerate()
+                    .map(|(i, count)| BufferSizeBucket { le: BUFFER_SIZE_BOUNDS.get(i).copied(), count: count.load(Ordering::Relaxed) })
+                    .collect(),
+            }
+        }).collect();
+
+        TransportSnapshot {
+            since: self.since,
+            total_queries: transports.iter().map(|t| t.queries).sum(),
+            transports,
+        }
+    }
+
+    /// The counters in the Prometheus text format, appended to `GET /metrics`.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        metric(&mut out, "cybertemp_queries_total", "counter", "Parsed queries by transport.");
+        for transport in Transport::ALL {
+            let _ = writeln!(out, "cybertemp_queries_total{{transport=\"{}\"}} {}", transport.as_str(), self.counters(transport).queries.load(Ordering::Relaxed));
+        }
+        metric(&mut out, "cybertemp_edns_queries_total", "counter", "Queries with an EDNS OPT record by transport.");
+        for transport in Transport::ALL {
+            let _ = writeln!(out, "cybertemp_edns_queries_total{{transport=\"{}\"}} {}", transport.as_str(), self.counters(transport).edns.load(Ordering::Relaxed));
+        }
+        metric(&mut out, "cybertemp_dnssec_ok_queries_total", "counter", "Queries with the EDNS DO bit set by transport.");
+        for transport in Transport::ALL {
+            let _ = writeln!(out, "cybertemp_dnssec_ok_queries_total{{transport=\"{}\"}} {}", transport.as_str(), self.counters(transport).dnssec_ok.load(Ordering::Relaxed));
+        }
+        metric(&mut out, "cybertemp_truncated_responses_total", "counter", "UDP responses sent with TC because they exceeded the client's buffer.");
+        let _ = writeln!(out, "cybertemp_truncated_responses_total {}", self.counters(Transport::Udp).truncated.load(Ordering::Relaxed));
+
+        metric(&mut out, "cybertemp_answer_seconds_total", "counter", "Time spent answering queries by transport.");
+        for transport in Transport::ALL {
+            let micros = self.counters(transport).answer_micros.load(Ordering::Relaxed);
+            let _ = writeln!(out, "cybertemp_answer_seconds_total{{transport=\"{}\"}} {:.6}", transport.as_str(), micros as f64 / 1_000_000.0);
+        }
+
+        metric(&mut out, "cybertemp_edns_buffer_size_bytes", "histogram", "Advertised EDNS UDP buffer sizes by transport.");
+        for transport in [Transport::Udp, Transport::Tcp, Transport::Tls] {
+            let counters = self.counters(transport);
+            let mut cumulative = 0;
+            for (i, count) in counters.buffer_sizes.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let le = BUFFER_SIZE_BOUNDS.get(i).map(|bound| bound.to_string()).unwrap_or_else(|| "+Inf".to_string());
+                let _ = writeln!(out, "cybertemp_edns_buffer_size_bytes_bucket{{transport=\"{}\",le=\"{}\"}} {}", transport.as_str(), le, cumulative);
+            }
+            let _ = writeln!(out, "cybertemp_edns_buffer_size_bytes_sum{{transport=\"{}\"}} {}", transport.as_str(), counters.buffer_size_sum.load(Ordering::Relaxed));
+            let _ = writeln!(out, "cybertemp_edns_buffer_size_bytes_count{{transport=\"{}\"}} {}", transport.as_str(), cumulative);
+        }
+
+        out
+    }
+}
+
+impl Default for TransportStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
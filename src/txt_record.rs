@@ -0,0 +1,94 @@
+use anyhow::Result;
+
+/// Longest character-string in a TXT record; longer values go out as several in one record.
+const CHUNK_LENGTH: usize = 255;
+/// Longest TXT value accepted, comfortably above a 4096-bit DKIM key.
+pub const MAX_TXT_LENGTH: usize = 4000;
+
+/// We store TXT values unquoted, while providers and zone files give `"v=spf1 ..."` or split
+/// `"a" "b"` strings, with `\"` and `\\` escapes inside the quotes.
+pub fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if !value.starts_with('"') {
+        return value.to_string();
+    }
+
+    let mut out = String::new();
+    let mut quoted = false;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => out.extend(chars.next()),
+            _ if quoted => out.push(c),
+            // Whitespace between the quoted strings
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `value` split into character-strings of at most 255 bytes, without splitting a UTF-8
+/// character. Resolvers join them back together.
+pub fn chunks(value: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = value;
+    while rest.len() > CHUNK_LENGTH {
+        let mut end = CHUNK_LENGTH;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+    chunks.push(rest.to_string());
+    chunks
+}
+
+/// Checks an unquoted TXT value before it is stored.
+pub fn validate(value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Err(anyhow::anyhow!("TXT values cannot be empty"));
+    }
+    if value.len() > MAX_TXT_LENGTH {
+        return Err(anyhow::anyhow!("TXT values are limited to {} bytes", MAX_TXT_LENGTH));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(anyhow::anyhow!("TXT values cannot contain control characters"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_split_at_255_bytes() {
+        assert_eq!(chunks(&"a".repeat(255)), vec!["a".repeat(255)]);
+        assert_eq!(chunks(&"a".repeat(256)), vec!["a".repeat(255), "a".to_string()]);
+
+        let long = chunks(&"a".repeat(MAX_TXT_LENGTH));
+        assert_eq!(long.len(), 16);
+        assert!(long.iter().all(|chunk| chunk.len() <= CHUNK_LENGTH));
+        assert_eq!(long.concat(), "a".repeat(MAX_TXT_LENGTH));
+    }
+
+    #[test]
+    fn chunks_keep_multibyte_characters_whole() {
+        // "é" takes bytes 254 and 255, so it cannot end the first chunk
+        let value = format!("{}é{}", "a".repeat(254), "b".repeat(10));
+        let split = chunks(&value);
+        assert_eq!(split, vec!["a".repeat(254), format!("é{}", "b".repeat(10))]);
+        assert_eq!(split.concat(), value);
+    }
+
+    #[test]
+    fn unquote_joins_strings_and_unescapes() {
+        assert_eq!(unquote("v=spf1 -all"), "v=spf1 -all");
+        assert_eq!(unquote(r#""a" "b""#), "ab");
+        assert_eq!(unquote(r#"  "v=DKIM1; " "p=abc"  "#), "v=DKIM1; p=abc");
+        assert_eq!(unquote(r#""say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(unquote(r#""back\\slash""#), r"back\slash");
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::database::Database;
+use crate::stats_history::QueryTotal;
+
+/// How far back the uptime stored on each domain looks.
+pub const UPTIME_WINDOW_DAYS: i64 = 30;
+
+/// One domain's usage over an export period.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DomainUsage {
+    pub domain: String,
+    pub queries: i64,
+    pub negative_queries: i64,
+    /// Seconds the domain was verified, i.e. answered for.
+    pub verified_seconds: i64,
+    /// Seconds covered by verification checks; time before a domain's first check is unknown.
+    pub observed_seconds: i64,
+    /// `verified_seconds` out of `observed_seconds`; `None` without any check.
+    pub uptime_percent: Option<f64>,
+}
+
+/// Verified and observed seconds in `[from, to)`. Each check's status holds until the next
+/// check; `checks` are `(checked_at, verified)` in time order and may start before `from`.
+pub fn verification_uptime(checks: &[(DateTime<Utc>, bool)], from: DateTime<Utc>, to: DateTime<Utc>) -> (i64, i64) {
+    let mut verified = 0;
+    let mut observed = 0;
+    for (i, (checked_at, is_verified)) in checks.iter().enumerate() {
+        let start = (*checked_at).max(from);
+        let end = checks.get(i + 1).map(|(next, _)| *next).unwrap_or(to).min(to);
+        if end <= start {
+            continue;
+        }
+        
+        let seconds = (end - start).num_seconds();
+        observed += seconds;
+        if *is_verified {
+            verified += seconds;
+        }
+    }
+    (verified, observed)
+}
+
+/// `verified` out of `observed` seconds as a percentage; `None` when nothing was observed.
+pub fn uptime_percent(verified: i64, observed: i64) -> Option<f64> {
+    (observed > 0).then(|| verified as f64 * 100.0 / observed as f64)
+}
+
+/// One stretch of time a domain spent in the same verification status.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusPeriod {
+    pub status: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+/// Merges consecutive checks with the same status into periods within `[from, to)`. `checks`
+/// are `(checked_at, status)` in time order, as for `verification_uptime`.
+pub fn status_periods(checks: &[(DateTime<Utc>, String)], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<StatusPeriod> {
+    let mut periods: Vec<StatusPeriod> = Vec::new();
+    for (i, (checked_at, status)) in checks.iter().enumerate() {
+        let start = (*checked_at).max(from);
+        let end = checks.get(i + 1).map(|(next, _)| *next).unwrap_or(to).min(to);
+        if end <= start {
+            continue;
+        }
+        
+        match periods.last_mut() {
+            Some(last) if last.status == *status => last.to = end,
+            _ => periods.push(StatusPeriod { status: status.clone(), from: start, to: end }),
+        }
+    }
+    periods
+}
+
+/// A domain's verification uptime over the last `UPTIME_WINDOW_DAYS`, with the periods behind it.
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainUptime {
+    pub domain: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub verified_seconds: i64,
+    pub observed_seconds: i64,
+    pub uptime_percent: Option<f64>,
+    pub periods: Vec<StatusPeriod>,
+}
+
+/// Reads a domain's verification history for the window ending at `now`.
+pub async fn domain_uptime(database: &Database, domain: &str, now: DateTime<Utc>) -> Result<DomainUptime> {
+    let from = now - ChronoDuration::days(UPTIME_WINDOW_DAYS);
+    let checks: Vec<(DateTime<Utc>, String)> = database.get_verification_checks(from, now, Some(domain)).await?
+        .into_iter()
+        .map(|(_, status, checked_at)| (checked_at, status))
+        .collect();
+    
+    let flags: Vec<(DateTime<Utc>, bool)> = checks.iter()
+        .map(|(checked_at, status)| (*checked_at, status == "Verified"))
+        .collect();
+    let (verified, observed) = verification_uptime(&flags, from, now);
+    
+    Ok(DomainUptime {
+        domain: domain.to_lowercase(),
+        from,
+        to: now,
+        verified_seconds: verified,
+        observed_seconds: observed,
+        uptime_percent: uptime_percent(verified, observed),
+        periods: status_periods(&checks, from, now),
+    })
+}
+
+/// Joins query totals and verification checks (`(domain, status, checked_at)` ordered by
+/// domain, then time) into one row per domain, including `domains` without either.
+pub fn usage_report(
+    totals: Vec<QueryTotal>,
+    checks: Vec<(String, String, DateTime<Utc>)>,
+    domains: Vec<String>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<DomainUsage> {
+    let mut rows: BTreeMap<String, DomainUsage> = BTreeMap::new();
+    for domain in domains {
+        rows.insert(domain.clone(), DomainUsage { domain, ..Default::default() });
+    }
+    for total in totals {
+        let row = rows.entry(total.domain.clone())
+            .or_insert_with(|| DomainUsage { domain: total.domain.clone(), ..Default::default() });
+        row.queries = total.queries;
+        row.negative_queries = total.negative;
+    }
+    
+    let mut by_domain: BTreeMap<String, Vec<(DateTime<Utc>, bool)>> = BTreeMap::new();
+    for (domain, status, checked_at) in checks {
+        by_domain.entry(domain).or_default().push((checked_at, status == "Verified"));
+    }
+    for (domain, checks) in by_domain {
+        let (verified, observed) = verification_uptime(&checks, from, to);
+        let row = rows.entry(domain.clone()).or_insert_with(|| DomainUsage { domain, ..Default::default() });
+        row.verified_seconds = verified;
+        row.observed_seconds = observed;
+        row.uptime_percent = uptime_percent(verified, observed);
+    }
+    
+    rows.into_values().collect()
+}
+
+/// RFC 4180 CSV with a header row; a missing uptime is an empty field.
+pub fn to_csv(rows: &[DomainUsage]) -> String {
+    let mut csv = String::from("domain,queries,negative_queries,verified_seconds,observed_seconds,uptime_percent\r\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\r\n",
+            csv_field(&row.domain),
+            row.queries,
+            row.negative_queries,
+            row.verified_seconds,
+            row.observed_seconds,
+            row.uptime_percent.map(|uptime| format!("{:.3}", uptime)).unwrap_or_default(),
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
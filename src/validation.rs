@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::txt_record;
+
+/// Labels our own answers, mail setup and client autoconfiguration depend on. A customer
+/// record at (or below) one of these could redirect a zone's mail or its policy lookups.
+pub const DEFAULT_RESERVED_LABELS: &[&str] = &[
+    "_dmarc",
+    "default._bimi",
+    "_mta-sts",
+    "mta-sts",
+    "_smtp._tls",
+    "autodiscover",
+    "autoconfig",
+    "mail",
+];
+
+/// The reserved label a record name (relative to its zone, `@` for the apex) falls on or below.
+pub fn reserved_label<'a>(name: &str, reserved: &'a [String]) -> Option<&'a str> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    reserved.iter()
+        .map(String::as_str)
+        .find(|label| name == *label || name.ends_with(&format!(".{}", label)))
+}
+
+/// Refuses record names on reserved labels; records that must live there opt in explicitly.
+pub fn check_record_name(name: &str, reserved: &[String]) -> Result<()> {
+    match reserved_label(name, reserved) {
+        Some(label) => Err(anyhow::anyhow!(
+            "Record name {} is on the reserved label {}; set allow_reserved on the record to create it anyway",
+            name, label
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Longest TTL accepted for overrides; resolvers cap cached answers at about a week anyway.
+pub const MAX_TTL: u32 = 604_800;
+
+/// Refuses TTL overrides of zero, which would stop resolvers caching the answers at all.
+pub fn check_ttl(ttl: u32) -> Result<()> {
+    if ttl == 0 || ttl > MAX_TTL {
+        return Err(anyhow::anyhow!("TTL must be between 1 and {} seconds", MAX_TTL));
+    }
+    Ok(())
+}
+
+/// Most values one record set (type and name) can hold, whatever the type.
+pub const MAX_RECORD_SET_VALUES: usize = 20;
+
+/// Checks a record value given in zone-file presentation form and splits it into the stored
+/// value and priority. MX and SRV values lead with their priority, e.g. `10 mail.example.net`
+/// or `10 5 5060 sip.example.net`; TXT values may be quoted.
+pub fn parse_record_value(record_type: &str, value: &str) -> Result<(String, i32)> {
+    if record_type == "TXT" {
+        let value = txt_record::unquote(value);
+        txt_record::validate(&value)?;
+        return Ok((value, 0));
+    }
+
+    let value = value.trim();
+    let invalid = || anyhow::anyhow!("Invalid {} value: {}", record_type, value);
+    match record_type {
+        "A" => value.parse::<Ipv4Addr>().map(|ip| (ip.to_string(), 0)).map_err(|_| invalid()),
+        "AAAA" => value.parse::<Ipv6Addr>().map(|ip| (ip.to_string(), 0)).map_err(|_| invalid()),
+        "CNAME" => Ok((check_target(value).ok_or_else(invalid)?, 0)),
+        "MX" => {
+            let (preference, host) = value.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let preference: u16 = preference.parse().map_err(|_| invalid())?;
+            Ok((check_target(host.trim()).ok_or_else(invalid)?, i32::from(preference)))
+        }
+        "SRV" => {
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            let [priority, weight, port, target] = parts[..] else { return Err(invalid()) };
+            let [priority, weight, port] = [priority, weight, port].map(|n| n.parse::<u16>().ok());
+            let (Some(priority), Some(weight), Some(port)) = (priority, weight, port) else { return Err(invalid()) };
+            let target = check_target(target).ok_or_else(invalid)?;
+            Ok((format!("{} {} {}", weight, port, target), i32::from(priority)))
+        }
+        other => Err(anyhow::anyhow!("{} records cannot be set; use A, AAAA, CNAME, MX, SRV or TXT", other)),
+    }
+}
+
+/// A stored value back in presentation form, with the priority of MX and SRV records leading.
+pub fn format_record_value(record_type: &str, value: &str, priority: i32) -> String {
+    match record_type {
+        "MX" | "SRV" => format!("{} {}", priority, value),
+        _ => value.to_string(),
+    }
+}
+
+/// A hostname record target, lowercased without its trailing dot.
+fn check_target(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+    trust_dns_proto::rr::Name::from_ascii(&host).ok()?;
+    (!host.is_empty()).then_some(host)
+}
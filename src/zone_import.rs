@@ -0,0 +1,311 @@
+use anyhow::Result;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::config::DnsConfig;
+use crate::database::Database;
+use crate::validation::reserved_label;
+
+/// Record types we can take over from another provider; everything else is reported and skipped.
+const IMPORTABLE_TYPES: &[&str] = &["A", "AAAA", "CNAME", "MX", "TXT", "SRV", "CAA"];
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const CLOUDFLARE_PAGE_SIZE: u32 = 100;
+
+/// A record pulled from another DNS provider, with its owner relative to the zone (`@` for the apex).
+#[derive(Debug, Clone)]
+pub struct ImportedRecord {
+    pub record_type: String,
+    pub name: String,
+    pub value: String,
+    pub ttl: i32,
+    pub priority: i32,
+}
+
+/// Everything fetched for one zone, before it is written anywhere.
+#[derive(Debug, Default)]
+pub struct ZoneImport {
+    pub zone: String,
+    pub records: Vec<ImportedRecord>,
+    pub skipped: Vec<String>,
+}
+
+impl ZoneImport {
+    /// The first apex A record, used as the domain IP when the domain is created.
+    pub fn apex_ip(&self) -> Option<&str> {
+        self.records.iter()
+            .find(|r| r.record_type == "A" && r.name == "@")
+            .map(|r| r.value.as_str())
+    }
+    
+    /// Sorts a provider record into `records` or `skipped`. `name` may be fully qualified.
+    fn push(&mut self, record_type: &str, name: &str, value: &str, ttl: i32, priority: i32) {
+        let name = relative_name(name, &self.zone);
+        if !IMPORTABLE_TYPES.contains(&record_type) {
+            self.skipped.push(format!("{} {}", record_type, name));
+            return;
+        }
+        let value = match record_type {
+            "CNAME" | "MX" | "SRV" => value.trim_end_matches('.').to_string(),
+            "TXT" => crate::txt_record::unquote(value),
+            _ => value.to_string(),
+        };
+        self.records.push(ImportedRecord {
+            record_type: record_type.to_string(),
+            name,
+            value,
+            ttl,
+            priority,
+        });
+    }
+    
+    /// Adds a record given in zone-file presentation form, where MX and SRV values lead with their priority.
+    #[cfg(feature = "route53")]
+    fn push_presentation(&mut self, record_type: &str, name: &str, value: &str, ttl: i32) {
+        let (priority, value) = match record_type {
+            "MX" | "SRV" => match value.split_once(' ') {
+                Some((priority, rest)) => (priority.parse().unwrap_or(0), rest),
+                None => (0, value),
+            },
+            _ => (0, value),
+        };
+        self.push(record_type, name, value, ttl, priority);
+    }
+}
+
+/// Route53 returns owner names with special characters octal-escaped, e.g. `\052.example.com` for `*`.
+#[cfg(feature = "route53")]
+fn unescape_route53_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let digits: String = chars.clone().take(3).collect();
+        if digits.len() == 3 && digits.chars().all(|d| ('0'..='7').contains(&d)) {
+            out.push(u8::from_str_radix(&digits, 8).unwrap_or(b'?') as char);
+            chars.nth(2);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// `www.example.com` -> `www`, `example.com` -> `@`, anything outside the zone unchanged.
+fn relative_name(name: &str, zone: &str) -> String {
+    let name = name.trim_end_matches('.').to_lowercase();
+    if name == zone {
+        return "@".to_string();
+    }
+    name.strip_suffix(&format!(".{}", zone))
+        .map(str::to_string)
+        .unwrap_or(name)
+}
+
+/// What to do with a fetched zone, from the `migrate` CLI flags.
+#[derive(Debug, Default)]
+pub struct ImportOptions {
+    /// Domain IP when the zone has no apex A record.
+    pub ip: Option<String>,
+    /// Create the domain if needed and mark it pending NS verification.
+    pub pending: bool,
+    /// Import records on reserved labels (`mail`, `_dmarc`, ...) instead of skipping them.
+    pub allow_reserved: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<CloudflareError>,
+    result: Option<T>,
+    result_info: Option<CloudflareResultInfo>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct CloudflareResultInfo {
+    total_pages: u32,
+}
+
+#[derive(Deserialize)]
+struct CloudflareZone {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct CloudflareRecord {
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    content: String,
+    ttl: i32,
+    priority: Option<i32>,
+}
+
+/// Pulls every record of `zone` from the Cloudflare API using an API token with `Zone.DNS:Read`.
+pub async fn fetch_cloudflare_zone(token: &str, zone: &str, default_ttl: i32) -> Result<ZoneImport> {
+    let client = reqwest::Client::new();
+    let zone = zone.trim_end_matches('.').to_lowercase();
+    
+    let zones: Vec<CloudflareZone> = cloudflare_get(
+        &client,
+        token,
+        &format!("{}/zones?name={}", CLOUDFLARE_API, zone),
+    ).await?.0;
+    let zone_id = zones.into_iter().next()
+        .ok_or_else(|| anyhow::anyhow!("Zone {} not found in this Cloudflare account", zone))?
+        .id;
+    
+    let mut import = ZoneImport { zone, ..Default::default() };
+    let mut page = 1;
+    loop {
+        let (records, total_pages): (Vec<CloudflareRecord>, u32) = cloudflare_get(
+            &client,
+            token,
+            &format!("{}/zones/{}/dns_records?page={}&per_page={}", CLOUDFLARE_API, zone_id, page, CLOUDFLARE_PAGE_SIZE),
+        ).await?;
+        
+        for record in records {
+            // Cloudflare reports "automatic" TTL as 1
+            let ttl = if record.ttl <= 1 { default_ttl } else { record.ttl };
+            import.push(&record.record_type, &record.name, &record.content, ttl, record.priority.unwrap_or(0));
+        }
+        
+        if page >= total_pages {
+            break;
+        }
+        page += 1;
+    }
+    
+    Ok(import)
+}
+
+async fn cloudflare_get<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    token: &str,
+    url: &str,
+) -> Result<(T, u32)> {
+    let response: CloudflareResponse<T> = client
+        .get(url)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    
+    if !response.success {
+        let errors: Vec<String> = response.errors.iter()
+            .map(|e| format!("{} ({})", e.message, e.code))
+            .collect();
+        return Err(anyhow::anyhow!("Cloudflare API error: {}", errors.join(", ")));
+    }
+    let total_pages = response.result_info.map(|info| info.total_pages).unwrap_or(1);
+    let result = response.result
+        .ok_or_else(|| anyhow::anyhow!("Cloudflare API returned no result"))?;
+    Ok((result, total_pages))
+}
+
+/// Lists every record set in a Route53 hosted zone, one record per value, using the default
+/// AWS credential chain. Alias record sets have no values of their own and are skipped.
+#[cfg(feature = "route53")]
+pub async fn fetch_route53_zone(hosted_zone_id: &str) -> Result<ZoneImport> {
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let client = aws_sdk_route53::Client::new(&aws_config);
+    
+    let hosted_zone = client.get_hosted_zone().id(hosted_zone_id).send().await?;
+    let zone = hosted_zone.hosted_zone()
+        .ok_or_else(|| anyhow::anyhow!("Hosted zone {} not found", hosted_zone_id))?
+        .name()
+        .trim_end_matches('.')
+        .to_lowercase();
+    
+    let mut import = ZoneImport { zone, ..Default::default() };
+    let mut request = client.list_resource_record_sets().hosted_zone_id(hosted_zone_id);
+    loop {
+        let page = request.send().await?;
+        
+        for set in page.resource_record_sets() {
+            let record_type = set.r#type().as_str();
+            let name = unescape_route53_name(set.name());
+            if set.alias_target().is_some() {
+                import.skipped.push(format!("{} {} (alias)", record_type, relative_name(&name, &import.zone)));
+                continue;
+            }
+            let ttl = set.ttl().unwrap_or(0) as i32;
+            for value in set.resource_records() {
+                import.push_presentation(record_type, &name, value.value(), ttl);
+            }
+        }
+        
+        if !page.is_truncated() {
+            break;
+        }
+        request = client.list_resource_record_sets()
+            .hosted_zone_id(hosted_zone_id)
+            .set_start_record_name(page.next_record_name().map(str::to_string))
+            .set_start_record_type(page.next_record_type().cloned())
+            .set_start_record_identifier(page.next_record_identifier().map(str::to_string));
+    }
+    
+    Ok(import)
+}
+
+#[cfg(not(feature = "route53"))]
+pub async fn fetch_route53_zone(_hosted_zone_id: &str) -> Result<ZoneImport> {
+    Err(anyhow::anyhow!("Route53 import needs a build with --features route53"))
+}
+
+/// Writes a fetched zone into the records table, creating or resetting the domain per `options`.
+pub async fn apply_import(config: &DnsConfig, import: &ZoneImport, options: &ImportOptions, actor: &str) -> Result<()> {
+    let (records, reserved): (Vec<ImportedRecord>, Vec<ImportedRecord>) = import.records.iter()
+        .cloned()
+        .partition(|r| options.allow_reserved || reserved_label(&r.name, &config.reserved_labels).is_none());
+    
+    for record in &records {
+        println!("  {:<6} {:<30} {:>6} {}", record.record_type, record.name, record.ttl, record.value);
+    }
+    for skipped in &import.skipped {
+        println!("  skipped unsupported record: {}", skipped);
+    }
+    for record in &reserved {
+        println!("  skipped reserved record: {} {} (pass --allow-reserved to import it)", record.record_type, record.name);
+    }
+    println!(
+        "{}: {} records to import, {} skipped",
+        import.zone,
+        records.len(),
+        import.skipped.len() + reserved.len()
+    );
+    
+    if options.dry_run {
+        return Ok(());
+    }
+    
+    let database = Database::new(&config.database_url).await?.with_serial_strategy(config.soa_serial_strategy);
+    let existing = database.get_domain(&import.zone).await?;
+    
+    if options.pending {
+        if existing.is_none() {
+            let ip = options.ip.as_deref()
+                .or(import.apex_ip())
+                .ok_or_else(|| anyhow::anyhow!("{} has no apex A record, pass --ip", import.zone))?;
+            database.add_domain(&import.zone, ip, false).await?;
+        }
+        database.mark_pending_verification(&import.zone).await?;
+    } else if existing.is_none() {
+        return Err(anyhow::anyhow!("Domain {} is not managed here, pass --pending to add it", import.zone));
+    }
+    
+    database.import_records(&import.zone, &records, actor).await?;
+    info!("Imported {} records into {}", records.len(), import.zone);
+    Ok(())
+}